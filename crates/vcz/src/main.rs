@@ -3,12 +3,23 @@ use tokio::join;
 use tracing::Level;
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::{fmt::time::OffsetTime, FmtSubscriber};
-use vincenzo::{args::Args, daemon::Daemon};
+use vincenzo::{
+    args::{Args, Command},
+    create,
+    daemon::Daemon,
+};
 
 use vcz_ui::{action::Action, app::App};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    if let Some(Command::Create(create_args)) = args.command {
+        create::run_cli(create_args)?;
+        return Ok(());
+    }
+
     let tmp = std::env::temp_dir();
     let time = std::time::SystemTime::now();
     let timestamp =
@@ -41,11 +52,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut daemon = Daemon::new();
 
     // Start and run the terminal UI
-    let mut fr = App::new();
+    let mut fr = App::new().daemon_addr(args.daemon_addr);
     let fr_tx = fr.tx.clone();
 
-    let args = Args::parse();
-
     // If the user passed a magnet through the CLI,
     // start this torrent immediately
     if let Some(magnet) = args.magnet {
@@ -1,27 +1,36 @@
 use clap::Parser;
-use futures::SinkExt;
-use tokio::net::{TcpListener, TcpStream};
-use tokio_util::codec::Framed;
+use futures::{SinkExt, StreamExt};
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
 use vincenzo::{
-    args::Args,
+    args::{Args, Command},
     config::Config,
+    create,
     daemon::Daemon,
-    daemon_wire::{DaemonCodec, Message},
+    daemon_wire::{self, tls::ClientTls, Message},
+    torrent::TorrentOptions,
 };
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+
+    if let Some(Command::Create(create_args)) = args.command {
+        create::run_cli(create_args)?;
+        return Ok(());
+    }
+
     let config = Config::load()?;
 
-    let daemon_addr = config.daemon_addr;
+    let daemon_addr =
+        args.daemon_addr.clone().unwrap_or_else(|| config.client_addr());
 
-    let is_daemon_running = TcpListener::bind(daemon_addr).await.is_err();
+    let tls = ClientTls::from_config(&config)?;
+
+    let socket = daemon_wire::connect(&daemon_addr, tls.as_ref()).await;
 
     // if the daemon is not running, run it
-    if !is_daemon_running {
+    let Ok(mut socket) = socket else {
         let subscriber = FmtSubscriber::builder()
             .with_max_level(Level::INFO)
             .without_time()
@@ -33,23 +42,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let mut daemon = Daemon::new();
 
         daemon.run().await?;
-    }
+
+        return Ok(());
+    };
 
     // Now that the daemon is running on a process,
     // the user can send commands using CLI flags,
     // using a different terminal, and we want
-    // to listen to these flags and send messages to Daemon.
+    // to listen to these flags and send messages to Daemon,
+    // through the connection we just made.
     //
-    // 1. Create a TCP connection to Daemon
-    let socket = TcpStream::connect(daemon_addr).await?;
-
-    let mut socket = Framed::new(socket, DaemonCodec);
-
-    // 2. Fire the corresponding message of a CLI flag.
+    // Fire the corresponding message of a CLI flag.
     //
     // add a a new torrent to Daemon
     if let Some(magnet) = args.magnet {
-        socket.send(Message::NewTorrent(magnet)).await?;
+        let save_path = match args.save_path {
+            Some(path) => {
+                Some(std::path::absolute(path)?.to_string_lossy().into_owned())
+            }
+            None => None,
+        };
+        let options = TorrentOptions {
+            save_path,
+            paused: args.paused,
+            ..Default::default()
+        };
+        socket.send(Message::NewTorrent(magnet, options)).await?;
     }
 
     if args.stats {
@@ -67,5 +85,48 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    if let Some([id, dir]) = args.move_storage.as_deref() {
+        let info_hash = parse_info_hash(id)?;
+        let new_path = std::path::absolute(dir)?;
+        socket.send(Message::MoveStorage { info_hash, new_path }).await?;
+    }
+
+    if let Some(id) = args.export_torrent {
+        let info_hash = parse_info_hash(&id)?;
+        socket.send(Message::ExportTorrent(info_hash)).await?;
+
+        // the daemon also sends the state of the torrents every second
+        while let Some(msg) = socket.next().await {
+            let Message::TorrentFile(torrent) = msg? else { continue };
+            let torrent = torrent.ok_or_else(|| {
+                format!("the torrent {id} does not exist or has no info yet")
+            })?;
+            let path = format!("{id}.torrent");
+            std::fs::write(&path, torrent)?;
+            println!("{path}");
+            break;
+        }
+    }
+
+    if let Some(id) = args.export_magnet {
+        let info_hash = parse_info_hash(&id)?;
+        socket.send(Message::ExportMagnet(info_hash)).await?;
+
+        while let Some(msg) = socket.next().await {
+            let Message::MagnetLink(magnet) = msg? else { continue };
+            let magnet = magnet
+                .ok_or_else(|| format!("the torrent {id} does not exist"))?;
+            println!("{magnet}");
+            break;
+        }
+    }
+
     Ok(())
 }
+
+/// Parse the hex string of an info hash.
+fn parse_info_hash(id: &str) -> Result<[u8; 20], Box<dyn std::error::Error>> {
+    hex::decode(id)?
+        .try_into()
+        .map_err(|_| format!("{id} is not a valid info hash").into())
+}
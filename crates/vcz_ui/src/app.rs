@@ -1,14 +1,13 @@
 use futures::{SinkExt, Stream, StreamExt};
 use tokio::{
-    net::TcpStream,
     select, spawn,
     sync::mpsc::{self, unbounded_channel, UnboundedReceiver, UnboundedSender},
 };
-use tokio_util::codec::Framed;
 use tracing::debug;
 use vincenzo::{
     config::Config,
-    daemon_wire::{DaemonCodec, Message},
+    daemon_wire::{self, tls::ClientTls, DaemonAddr, Message},
+    torrent::TorrentOptions,
 };
 
 use crate::{
@@ -20,6 +19,9 @@ use crate::{
 
 pub struct App {
     pub is_detached: bool,
+    /// Address of the daemon, when `None` the one of the configuration file
+    /// is used.
+    pub daemon_addr: Option<DaemonAddr>,
     pub tx: UnboundedSender<Action>,
     should_quit: bool,
     rx: Option<UnboundedReceiver<Action>>,
@@ -32,12 +34,24 @@ impl App {
         self
     }
 
+    pub fn daemon_addr(mut self, v: Option<DaemonAddr>) -> Self {
+        self.daemon_addr = v;
+        self
+    }
+
     pub fn new() -> Self {
         let (tx, rx) = unbounded_channel();
 
         let page = Box::new(TorrentList::new(tx.clone()));
 
-        App { should_quit: false, tx, rx: Some(rx), page, is_detached: false }
+        App {
+            should_quit: false,
+            tx,
+            rx: Some(rx),
+            page,
+            is_detached: false,
+            daemon_addr: None,
+        }
     }
 
     pub async fn run(&mut self) -> Result<(), Error> {
@@ -47,11 +61,14 @@ impl App {
         let tx = self.tx.clone();
         let mut rx = std::mem::take(&mut self.rx).unwrap();
 
-        let daemon_addr = Config::load().unwrap().daemon_addr;
-        let socket = TcpStream::connect(daemon_addr).await.unwrap();
+        let config = Config::load().unwrap();
+        let daemon_addr =
+            self.daemon_addr.clone().unwrap_or_else(|| config.client_addr());
+        let tls = ClientTls::from_config(&config).unwrap();
+        let socket =
+            daemon_wire::connect(&daemon_addr, tls.as_ref()).await.unwrap();
 
         // spawn event loop to listen to messages sent by the daemon
-        let socket = Framed::new(socket, DaemonCodec);
         let (mut sink, stream) = socket.split();
         let _tx = self.tx.clone();
 
@@ -88,8 +105,12 @@ impl App {
                 }
 
                 if let Action::NewTorrent(magnet) = action {
-                    let _ =
-                        sink.send(Message::NewTorrent(magnet.to_owned())).await;
+                    let _ = sink
+                        .send(Message::NewTorrent(
+                            magnet.to_owned(),
+                            TorrentOptions::default(),
+                        ))
+                        .await;
                 }
             }
 
@@ -1,12 +1,15 @@
+use clap::Parser;
 use tracing::debug;
-use vincenzo::error::Error;
+use vincenzo::{args::Args, error::Error};
 
 use vcz_ui::app::App;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    let args = Args::parse();
+
     // Start and run the terminal UI
-    let mut app = App::new();
+    let mut app = App::new().daemon_addr(args.daemon_addr);
 
     // UI is detached from the Daemon
     app.is_detached = true;
@@ -218,6 +218,10 @@ impl<'a> Page for TorrentList<'a> {
                 status_txt.push(download_and_rate);
             }
 
+            if let Some(error) = &ctx.error {
+                status_txt.push(format!(" - {error}").into());
+            }
+
             let s = ctx.stats.seeders.to_string();
             let l = ctx.stats.leechers.to_string();
             let sl = format!("Seeders {s} Leechers {l}").into();
@@ -1,4 +1,8 @@
-use clap::Parser;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::daemon_wire::DaemonAddr;
 
 #[derive(Parser, Debug, Default)]
 #[clap(name = "Vincenzo", author = "Gabriel Lombardo")]
@@ -8,6 +12,15 @@ pub struct Args {
     #[clap(short, long)]
     pub magnet: Option<String>,
 
+    /// Directory where the torrent of `--magnet` is saved, instead of the
+    /// download dir.
+    #[clap(long, requires = "magnet")]
+    pub save_path: Option<PathBuf>,
+
+    /// Add the torrent of `--magnet` paused.
+    #[clap(long, requires = "magnet")]
+    pub paused: bool,
+
     /// Print all torrent status on stdout
     #[clap(short, long)]
     pub stats: bool,
@@ -16,10 +29,70 @@ pub struct Args {
     #[clap(short, long)]
     pub pause: Option<String>,
 
+    /// Write the .torrent file of a torrent, given a hash string of its id,
+    /// to `<id>.torrent` on the current directory.
+    #[clap(long)]
+    pub export_torrent: Option<String>,
+
+    /// Print the magnet link of a torrent given a hash string of its id
+    #[clap(long)]
+    pub export_magnet: Option<String>,
+
+    /// Move the files of a torrent, given a hash string of its id, to a
+    /// directory on the machine of the daemon.
+    #[clap(long, num_args = 2, value_names = ["ID", "DIR"])]
+    pub move_storage: Option<Vec<String>>,
+
     /// Stop all torrents and gracefully shutdown
     #[clap(short, long)]
     pub quit: bool,
+
+    /// Address of the daemon, either `ip:port` or `unix:/path/to/socket`.
+    /// Defaults to the one in the configuration file.
+    #[clap(long)]
+    pub daemon_addr: Option<DaemonAddr>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
     //     /// If the program should quit after all torrents are fully
     // downloaded     #[clap(short, long)]
     //     pub quit_after_complete: bool,
 }
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Create a .torrent file from a file or a directory, and print its
+    /// magnet link.
+    Create(CreateArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CreateArgs {
+    /// File or directory to be shared.
+    pub path: PathBuf,
+
+    /// Where to write the .torrent file, defaults to `<name>.torrent`.
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Announce URL of a tracker, can be used multiple times.
+    #[clap(short, long = "tracker")]
+    pub trackers: Vec<String>,
+
+    /// Piece length in bytes, a power of two. Picked from the size of the
+    /// files if not set.
+    #[clap(long)]
+    pub piece_length: Option<u32>,
+
+    /// Free-form comment of the torrent.
+    #[clap(short, long)]
+    pub comment: Option<String>,
+
+    /// Only get peers from the trackers (BEP 27).
+    #[clap(long)]
+    pub private: bool,
+
+    /// URL of a web seed, can be used multiple times.
+    #[clap(short, long = "web-seed")]
+    pub web_seeds: Vec<String>,
+}
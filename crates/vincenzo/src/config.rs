@@ -1,14 +1,87 @@
 //! Config file
-use std::{net::SocketAddr, sync::LazyLock};
+use std::{net::SocketAddr, path::PathBuf, sync::LazyLock};
 
 use serde::{Deserialize, Serialize};
 
-use crate::error::Error;
+use crate::{daemon_wire::DaemonAddr, disk::Preallocation, error::Error};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub download_dir: String,
+    /// Directories where torrents can be saved or moved to, besides
+    /// `download_dir` and `incomplete_dir`, with their subdirectories.
+    pub allowed_save_dirs: Vec<PathBuf>,
+    /// Where the torrents are downloaded, they are moved to `download_dir`
+    /// when they are complete.
+    pub incomplete_dir: Option<String>,
+    /// If the files have the `.part` suffix while they are being downloaded.
+    pub part_suffix: bool,
     pub daemon_addr: SocketAddr,
+    /// If the daemon should listen for TCP connections on `daemon_addr`.
+    pub daemon_tcp: bool,
+    /// Path of an optional Unix domain socket that the daemon will listen
+    /// on, in addition to TCP.
+    pub daemon_unix_socket: Option<PathBuf>,
+    /// Permission bits of the Unix domain socket file, e.g `0o660` to only
+    /// allow the owner and group of the socket to control the daemon.
+    pub daemon_unix_socket_mode: u32,
+    /// PEM certificate chain of the daemon. When both this and
+    /// `daemon_tls_key` are set, the TCP listener only accepts TLS.
+    pub daemon_tls_cert: Option<PathBuf>,
+    /// PEM private key of `daemon_tls_cert`.
+    pub daemon_tls_key: Option<PathBuf>,
+    /// PEM CA used by the daemon to verify client certificates, when set,
+    /// clients without a certificate signed by it are rejected.
+    pub daemon_tls_client_ca: Option<PathBuf>,
+    /// PEM CA pinned by clients to verify the daemon certificate. When set,
+    /// clients connect to the daemon over TLS.
+    pub daemon_tls_ca: Option<PathBuf>,
+    /// PEM certificate chain that clients present to the daemon.
+    pub daemon_tls_client_cert: Option<PathBuf>,
+    /// PEM private key of `daemon_tls_client_cert`.
+    pub daemon_tls_client_key: Option<PathBuf>,
+    /// Name that the daemon certificate must be valid for, defaults to the
+    /// IP of `daemon_addr`.
+    pub daemon_tls_server_name: Option<String>,
+    /// Address of the optional Transmission RPC listener.
+    pub rpc_addr: Option<SocketAddr>,
+    /// Username of the HTTP basic authentication of the RPC, it must be set
+    /// together with `rpc_password`.
+    pub rpc_username: Option<String>,
+    /// Password of the HTTP basic authentication of the RPC.
+    pub rpc_password: Option<String>,
+    /// Addresses that can use the RPC, `*` matches any number of an IPv4
+    /// address, e.g `192.168.*.*`. Defaults to loopback only.
+    pub rpc_whitelist: Vec<String>,
+    /// Directory where `.magnet` and `.torrent` files are picked up and
+    /// added automatically.
+    pub watch_dir: Option<PathBuf>,
+    /// Where the files of `watch_dir` are moved after being added, if not
+    /// set, they are renamed to `.added` in place.
+    pub watch_processed_dir: Option<PathBuf>,
+    /// Directory where the info of the torrents is cached as .torrent files,
+    /// so that torrents added again do not need to download it from peers.
+    pub metadata_dir: Option<PathBuf>,
+    /// Memory in MiB used by the blocks that were downloaded but not written
+    /// to disk yet, shared by all torrents.
+    pub disk_cache_size: u64,
+    /// Memory in MiB used by the pieces that were read to be uploaded,
+    /// shared by all torrents.
+    pub disk_read_cache_size: u64,
+    /// Maximum of files that the disk keeps open, shared by all torrents.
+    pub max_open_files: usize,
+    /// Threads that hash the downloaded pieces, by default, one per CPU.
+    pub hashing_threads: Option<usize>,
+    /// How the files of the torrents are allocated on disk.
+    pub preallocation: Preallocation,
+    /// Executable that runs when a torrent is added, see [`crate::hooks`].
+    pub on_added: Option<PathBuf>,
+    /// Executable that runs when a torrent is fully downloaded.
+    pub on_complete: Option<PathBuf>,
+    /// Executable that runs when a torrent stops because of an error.
+    pub on_error: Option<PathBuf>,
+    /// Seconds that a hook can run before being killed.
+    pub hook_timeout: u64,
     pub quit_after_complete: bool,
 }
 
@@ -24,19 +97,86 @@ static CONFIG: LazyLock<config::Config> = LazyLock::new(|| {
         .map(|v| format!("{v}/vincenzo/config"))
         .unwrap_or(format!("{home}/.config/vincenzo/config"));
 
+    let metadata_dir = std::env::var("XDG_CACHE_HOME")
+        .map(|v| format!("{v}/vincenzo/metadata"))
+        .unwrap_or(format!("{home}/.cache/vincenzo/metadata"));
+
     config::Config::builder()
         .add_source(config::File::with_name(&config_file).required(false))
         .add_source(config::Environment::default())
         .set_default("download_dir", download_dir)
         .unwrap()
+        .set_default("allowed_save_dirs", Vec::<String>::new())
+        .unwrap()
         .set_default("daemon_addr", "127.0.0.1:3030")
         .unwrap()
+        .set_default("daemon_tcp", true)
+        .unwrap()
+        .set_default("metadata_dir", metadata_dir)
+        .unwrap()
+        .set_default("daemon_unix_socket_mode", 0o600)
+        .unwrap()
+        .set_default("hook_timeout", 60)
+        .unwrap()
+        .set_default("rpc_whitelist", vec!["127.0.0.1", "::1"])
+        .unwrap()
+        .set_default("disk_cache_size", 64)
+        .unwrap()
+        .set_default("disk_read_cache_size", 64)
+        .unwrap()
+        .set_default("max_open_files", 512)
+        .unwrap()
+        .set_default("preallocation", "none")
+        .unwrap()
+        .set_default("part_suffix", false)
+        .unwrap()
         .set_default("quit_after_complete", false)
         .unwrap()
         .build()
         .unwrap()
 });
 
+/// The defaults of [`Config::load`], without the directories guessed from
+/// $HOME.
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            download_dir: String::new(),
+            allowed_save_dirs: Vec::new(),
+            incomplete_dir: None,
+            part_suffix: false,
+            daemon_addr: SocketAddr::from(([127, 0, 0, 1], 3030)),
+            daemon_tcp: true,
+            daemon_unix_socket: None,
+            daemon_unix_socket_mode: 0o600,
+            daemon_tls_cert: None,
+            daemon_tls_key: None,
+            daemon_tls_client_ca: None,
+            daemon_tls_ca: None,
+            daemon_tls_client_cert: None,
+            daemon_tls_client_key: None,
+            daemon_tls_server_name: None,
+            rpc_addr: None,
+            rpc_username: None,
+            rpc_password: None,
+            rpc_whitelist: vec!["127.0.0.1".to_owned(), "::1".to_owned()],
+            watch_dir: None,
+            watch_processed_dir: None,
+            metadata_dir: None,
+            disk_cache_size: 64,
+            disk_read_cache_size: 64,
+            max_open_files: 512,
+            hashing_threads: None,
+            preallocation: Preallocation::None,
+            on_added: None,
+            on_complete: None,
+            on_error: None,
+            hook_timeout: 60,
+            quit_after_complete: false,
+        }
+    }
+}
+
 impl Config {
     /// Try to load the configuration. Environmental variables have priviledge
     /// over values from the configuration file. If both are not set, it will
@@ -47,6 +187,24 @@ impl Config {
             .try_deserialize::<Self>()
             .map_err(|_| Error::ConfigDeserializeError)
     }
+
+    /// Address that clients should use to reach the daemon, the Unix socket
+    /// is preferred when it is configured.
+    pub fn client_addr(&self) -> DaemonAddr {
+        match &self.daemon_unix_socket {
+            Some(path) => DaemonAddr::Unix(path.clone()),
+            None => DaemonAddr::Tcp(self.daemon_addr),
+        }
+    }
+
+    /// Directories where torrents can be saved or moved to, the
+    /// `allowed_save_dirs`, `download_dir` and `incomplete_dir`.
+    pub fn save_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = self.allowed_save_dirs.clone();
+        dirs.push(PathBuf::from(&self.download_dir));
+        dirs.extend(self.incomplete_dir.as_ref().map(PathBuf::from));
+        dirs
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,500 @@
+//! Create .torrent files from local files and directories.
+//!
+//! ```no_run
+//! use vincenzo::create::TorrentBuilder;
+//!
+//! let torrent = TorrentBuilder::new("/data/dataset")
+//!     .trackers(vec!["udp://tracker.opentrackr.org:1337/announce".to_owned()])
+//!     .comment("our dataset")
+//!     .private(true)
+//!     .build()
+//!     .unwrap();
+//!
+//! std::fs::write("dataset.torrent", &torrent.bytes).unwrap();
+//! println!("{}", torrent.magnet);
+//! ```
+use std::{
+    fs::File as FsFile,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bendy::encoding::ToBencode;
+
+use crate::{
+    args::CreateArgs,
+    error::Error,
+    extensions::core::BLOCK_LEN,
+    metainfo::{File, Info, MetaInfo},
+};
+
+/// The smallest piece length that is picked automatically.
+pub const MIN_PIECE_LENGTH: u32 = BLOCK_LEN;
+
+/// The largest piece length that is picked automatically.
+pub const MAX_PIECE_LENGTH: u32 = 16 * 1024 * 1024;
+
+/// How many pieces the automatic piece length aims for.
+const TARGET_PIECES: u64 = 1500;
+
+/// A .torrent that was created by [`TorrentBuilder`].
+#[derive(Debug, Clone)]
+pub struct CreatedTorrent {
+    pub metainfo: MetaInfo,
+    pub info_hash: [u8; 20],
+    /// The bencoded .torrent file.
+    pub bytes: Vec<u8>,
+    pub magnet: String,
+}
+
+/// Builds a [`MetaInfo`] from a file or a directory on disk.
+#[derive(Debug, Clone, Default)]
+pub struct TorrentBuilder {
+    path: PathBuf,
+    piece_length: Option<u32>,
+    trackers: Vec<String>,
+    comment: Option<String>,
+    creation_date: Option<u32>,
+    private: bool,
+    web_seeds: Vec<String>,
+    threads: Option<usize>,
+}
+
+impl TorrentBuilder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), ..Default::default() }
+    }
+
+    /// Use this piece length instead of picking one from the size of the
+    /// files. It must be a power of two and at least 16 KiB.
+    pub fn piece_length(mut self, v: u32) -> Self {
+        self.piece_length = Some(v);
+        self
+    }
+
+    /// Trackers of the torrent, each one in its own tier.
+    pub fn trackers(mut self, v: Vec<String>) -> Self {
+        self.trackers = v;
+        self
+    }
+
+    pub fn comment(mut self, v: impl Into<String>) -> Self {
+        self.comment = Some(v.into());
+        self
+    }
+
+    /// Unix timestamp of the creation date, defaults to now.
+    pub fn creation_date(mut self, v: u32) -> Self {
+        self.creation_date = Some(v);
+        self
+    }
+
+    pub fn private(mut self, v: bool) -> Self {
+        self.private = v;
+        self
+    }
+
+    /// Web seeds, written on the `url-list` of the torrent.
+    pub fn web_seeds(mut self, v: Vec<String>) -> Self {
+        self.web_seeds = v;
+        self
+    }
+
+    /// How many threads are used to hash the pieces, defaults to the
+    /// available parallelism.
+    pub fn threads(mut self, v: usize) -> Self {
+        self.threads = Some(v);
+        self
+    }
+
+    /// Pick a piece length that results in around [`TARGET_PIECES`] pieces.
+    pub fn auto_piece_length(total_size: u64) -> u32 {
+        let piece_length = (total_size / TARGET_PIECES).next_power_of_two();
+        piece_length.clamp(MIN_PIECE_LENGTH as u64, MAX_PIECE_LENGTH as u64)
+            as u32
+    }
+
+    /// Walk the files, hash the pieces, and encode the torrent.
+    pub fn build(self) -> Result<CreatedTorrent, Error> {
+        let name = self
+            .path
+            .file_name()
+            .and_then(|v| v.to_str())
+            .ok_or_else(|| {
+                Error::CreateTorrent(format!(
+                    "`{}` does not have a valid name",
+                    self.path.display()
+                ))
+            })?
+            .to_owned();
+
+        let is_dir = self.path.is_dir();
+
+        // paths relative to `self.path`, sorted to be deterministic.
+        let mut files = Vec::new();
+        if is_dir {
+            Self::walk(&self.path, &mut Vec::new(), &mut files)?;
+            files.sort();
+        } else {
+            files.push(Vec::new());
+        }
+
+        let mut disk_files = Vec::with_capacity(files.len());
+        let mut total_size = 0;
+
+        for path in files {
+            let full_path =
+                path.iter().fold(self.path.clone(), |acc, v| acc.join(v));
+            let len = std::fs::metadata(&full_path)?.len();
+            total_size += len;
+            disk_files.push((path, full_path, len));
+        }
+
+        if total_size == 0 {
+            return Err(Error::CreateTorrent(
+                "there is nothing to share".to_owned(),
+            ));
+        }
+
+        let piece_length = match self.piece_length {
+            Some(v) if v < MIN_PIECE_LENGTH || !v.is_power_of_two() => {
+                return Err(Error::CreateTorrent(format!(
+                    "the piece length {v} must be a power of two, of at least {MIN_PIECE_LENGTH}"
+                )));
+            }
+            Some(v) => v,
+            None => Self::auto_piece_length(total_size),
+        };
+
+        let reader = PieceReader {
+            files: disk_files.iter().map(|(_, p, l)| (p.clone(), *l)).collect(),
+            piece_length,
+            total_size,
+        };
+        let pieces = reader.hash_pieces(self.threads)?;
+
+        let (file_length, files) = if is_dir {
+            let files = disk_files
+                .into_iter()
+                .map(|(path, _, length)| File {
+                    length,
+                    path,
+                    ..Default::default()
+                })
+                .collect();
+            (None, Some(files))
+        } else {
+            (Some(total_size), None)
+        };
+
+        let info = Info {
+            piece_length,
+            pieces,
+            name,
+            file_length,
+            files,
+            private: self.private.then_some(1),
+            ..Default::default()
+        };
+
+        let creation_date = self.creation_date.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|v| v.as_secs() as u32)
+                .unwrap_or_default()
+        });
+
+        let metainfo = MetaInfo {
+            announce: self.trackers.first().cloned().unwrap_or_default(),
+            announce_list: (self.trackers.len() > 1).then(|| {
+                self.trackers.iter().map(|v| vec![v.clone()]).collect()
+            }),
+            info,
+            comment: self.comment,
+            creation_date: Some(creation_date),
+            http_seeds: None,
+            url_list: (!self.web_seeds.is_empty()).then_some(self.web_seeds),
+            piece_layers: None,
+        };
+
+        let bytes = metainfo.to_bencode().map_err(|_| Error::BencodeError)?;
+        let info_hash = MetaInfo::info_hash(&bytes)?;
+        let magnet = metainfo.to_magnet_uri(&info_hash.into());
+
+        Ok(CreatedTorrent { metainfo, info_hash, bytes, magnet })
+    }
+
+    /// Recursively collect the paths of all files inside `dir`.
+    fn walk(
+        dir: &Path,
+        prefix: &mut Vec<String>,
+        files: &mut Vec<Vec<String>>,
+    ) -> Result<(), Error> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|v| {
+                Error::CreateTorrent(format!("{v:?} is not valid UTF-8"))
+            })?;
+            let file_type = entry.file_type()?;
+
+            prefix.push(name);
+            if file_type.is_dir() {
+                Self::walk(&entry.path(), prefix, files)?;
+            } else if file_type.is_file() {
+                files.push(prefix.clone());
+            }
+            prefix.pop();
+        }
+        Ok(())
+    }
+}
+
+/// Reads the pieces of a list of files as if they were one contiguous file.
+struct PieceReader {
+    files: Vec<(PathBuf, u64)>,
+    piece_length: u32,
+    total_size: u64,
+}
+
+impl PieceReader {
+    fn pieces(&self) -> usize {
+        self.total_size.div_ceil(self.piece_length as u64) as usize
+    }
+
+    /// Hash all pieces, splitting them in contiguous ranges, one per thread.
+    fn hash_pieces(&self, threads: Option<usize>) -> Result<Vec<u8>, Error> {
+        let pieces = self.pieces();
+        let threads = threads
+            .or_else(|| thread::available_parallelism().ok().map(|v| v.get()))
+            .unwrap_or(1)
+            .clamp(1, pieces);
+        let per_thread = pieces.div_ceil(threads);
+
+        let hashes = thread::scope(|s| {
+            let handles: Vec<_> = (0..threads)
+                .map(|t| {
+                    let range =
+                        t * per_thread..((t + 1) * per_thread).min(pieces);
+                    s.spawn(move || self.hash_range(range))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("hashing thread panicked"))
+                .collect::<Result<Vec<_>, Error>>()
+        })?;
+
+        Ok(hashes.concat())
+    }
+
+    fn hash_range(
+        &self,
+        range: std::ops::Range<usize>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut hashes = Vec::with_capacity(range.len() * 20);
+        let mut buf = vec![0u8; self.piece_length as usize];
+
+        for piece in range {
+            let begin = piece as u64 * self.piece_length as u64;
+            let len = (self.total_size - begin).min(self.piece_length as u64)
+                as usize;
+
+            self.read_at(begin, &mut buf[..len])?;
+            hashes.extend_from_slice(
+                &sha1_smol::Sha1::from(&buf[..len]).digest().bytes(),
+            );
+        }
+
+        Ok(hashes)
+    }
+
+    /// Fill `buf` with the bytes starting at `offset`, which may span
+    /// multiple files.
+    fn read_at(
+        &self,
+        mut offset: u64,
+        mut buf: &mut [u8],
+    ) -> Result<(), Error> {
+        let mut file_begin = 0;
+
+        for (path, len) in &self.files {
+            let file_end = file_begin + len;
+
+            if offset < file_end && !buf.is_empty() {
+                let n = ((file_end - offset) as usize).min(buf.len());
+                let mut file = FsFile::open(path).map_err(|_| {
+                    Error::FileOpenError(path.display().to_string())
+                })?;
+
+                file.seek(SeekFrom::Start(offset - file_begin))?;
+                file.read_exact(&mut buf[..n])?;
+
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+
+            file_begin = file_end;
+        }
+
+        Ok(())
+    }
+}
+
+/// Create the torrent described by the `create` subcommand, write it to
+/// disk, and print its magnet link.
+pub fn run_cli(args: CreateArgs) -> Result<CreatedTorrent, Error> {
+    let mut builder = TorrentBuilder::new(&args.path)
+        .trackers(args.trackers)
+        .web_seeds(args.web_seeds)
+        .private(args.private);
+
+    if let Some(piece_length) = args.piece_length {
+        builder = builder.piece_length(piece_length);
+    }
+    if let Some(comment) = args.comment {
+        builder = builder.comment(comment);
+    }
+
+    let torrent = builder.build()?;
+
+    let output = args.output.unwrap_or_else(|| {
+        format!("{}.torrent", torrent.metainfo.info.name).into()
+    });
+
+    std::fs::write(&output, &torrent.bytes)?;
+
+    println!("Created {}", output.display());
+    println!("{}", torrent.magnet);
+
+    Ok(torrent)
+}
+
+#[cfg(test)]
+mod tests {
+    use bendy::decoding::FromBencode;
+    use rand::{distributions::Alphanumeric, Rng};
+
+    use super::*;
+
+    fn tmp_dir() -> PathBuf {
+        let mut rng = rand::thread_rng();
+        let name: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        std::env::temp_dir().join(name)
+    }
+
+    #[test]
+    fn auto_piece_length() {
+        assert_eq!(TorrentBuilder::auto_piece_length(10), MIN_PIECE_LENGTH);
+        assert_eq!(
+            TorrentBuilder::auto_piece_length(1500 * 1024 * 1024),
+            1024 * 1024
+        );
+        assert_eq!(
+            TorrentBuilder::auto_piece_length(u64::MAX / 2),
+            MAX_PIECE_LENGTH
+        );
+    }
+
+    #[test]
+    fn create_multi_file_torrent() {
+        let dir = tmp_dir().join("dataset");
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+
+        // 3 files that do not align with the piece length.
+        let a: Vec<u8> = (0..BLOCK_LEN + 10).map(|v| v as u8).collect();
+        let c: Vec<u8> = (0..7).collect();
+        let d: Vec<u8> = (0..BLOCK_LEN * 2).map(|v| (v * 3) as u8).collect();
+        std::fs::write(dir.join("a.bin"), &a).unwrap();
+        std::fs::write(dir.join("b").join("c.bin"), &c).unwrap();
+        std::fs::write(dir.join("d.bin"), &d).unwrap();
+
+        let torrent = TorrentBuilder::new(&dir)
+            .trackers(vec![
+                "udp://tracker.opentrackr.org:1337/announce".to_owned(),
+                "udp://open.stealth.si:80/announce".to_owned(),
+            ])
+            .web_seeds(vec!["http://localhost/".to_owned()])
+            .comment("bla")
+            .creation_date(10)
+            .private(true)
+            .threads(2)
+            .build()
+            .unwrap();
+
+        let info = &torrent.metainfo.info;
+        assert_eq!(info.name, "dataset");
+        assert_eq!(info.private, Some(1));
+        assert_eq!(
+            info.files.as_ref().unwrap(),
+            &vec![
+                File {
+                    length: a.len() as u64,
+                    path: vec!["a.bin".to_owned()],
+                    ..Default::default()
+                },
+                File {
+                    length: c.len() as u64,
+                    path: vec!["b".to_owned(), "c.bin".to_owned()],
+                    ..Default::default()
+                },
+                File {
+                    length: d.len() as u64,
+                    path: vec!["d.bin".to_owned()],
+                    ..Default::default()
+                },
+            ]
+        );
+
+        // hash the concatenated files to compare
+        let all = [a, c, d].concat();
+        let expected: Vec<u8> = all
+            .chunks(BLOCK_LEN as usize)
+            .flat_map(|v| sha1_smol::Sha1::from(v).digest().bytes())
+            .collect();
+        assert_eq!(info.piece_length, BLOCK_LEN);
+        assert_eq!(info.pieces, expected);
+
+        // the bytes decode to the same metainfo
+        let decoded = MetaInfo::from_bencode(&torrent.bytes).unwrap();
+        assert_eq!(decoded, torrent.metainfo);
+        assert_eq!(decoded.comment.as_deref(), Some("bla"));
+        assert_eq!(
+            decoded.url_list,
+            Some(vec!["http://localhost/".to_owned()])
+        );
+        assert_eq!(decoded.trackers().len(), 2);
+
+        assert!(torrent.magnet.starts_with(&format!(
+            "magnet:?xt=urn:btih:{}",
+            hex::encode(torrent.info_hash)
+        )));
+
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn create_single_file_torrent() {
+        let dir = tmp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("foo.txt");
+        std::fs::write(&path, b"hello").unwrap();
+
+        let torrent = TorrentBuilder::new(&path).build().unwrap();
+        let info = &torrent.metainfo.info;
+
+        assert_eq!(info.name, "foo.txt");
+        assert_eq!(info.file_length, Some(5));
+        assert_eq!(info.files, None);
+        assert_eq!(
+            info.pieces,
+            sha1_smol::Sha1::from(b"hello").digest().bytes()
+        );
+        assert!(TorrentBuilder::new(&path).piece_length(1000).build().is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
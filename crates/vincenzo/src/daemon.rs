@@ -3,7 +3,10 @@
 use futures::{SinkExt, StreamExt};
 use hashbrown::HashMap;
 use std::{
+    fs::Permissions,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::{Component, Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
@@ -11,7 +14,8 @@ use tokio_util::codec::Framed;
 use tracing::{error, info, trace, warn};
 
 use tokio::{
-    net::{TcpListener, TcpStream},
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
     select, spawn,
     sync::{mpsc, oneshot, RwLock},
     time::interval,
@@ -19,19 +23,25 @@ use tokio::{
 
 use crate::{
     config::Config,
-    daemon_wire::{DaemonCodec, Message},
+    daemon_wire::{tls, DaemonCodec, Message},
     disk::{Disk, DiskMsg},
     error::Error,
+    hooks::{HookEnv, HookEvent, Hooks},
     magnet::Magnet,
-    torrent::{Torrent, TorrentMsg, TorrentState, TorrentStatus},
+    metainfo::MetaInfo,
+    rpc::Rpc,
+    torrent::{
+        Torrent, TorrentMsg, TorrentOptions, TorrentState, TorrentStatus,
+    },
     utils::to_human_readable,
+    watch::WatchDir,
 };
 
 /// The daemon is the highest-level entity in the library.
 /// It owns [`Disk`] and [`Torrent`]s, which owns Peers.
 ///
-/// The communication with the daemon happens via TCP with messages
-/// documented at [`DaemonCodec`].
+/// The communication with the daemon happens via TCP, or optionally a Unix
+/// domain socket, with messages documented at [`DaemonCodec`].
 ///
 /// The daemon is decoupled from the UI and can even run on different machines,
 /// and so, they need a way to communicate. We use TCP, so we can benefit
@@ -45,6 +55,12 @@ pub struct Daemon {
     pub ctx: Arc<DaemonCtx>,
     /// key: info_hash
     pub torrent_txs: HashMap<[u8; 20], mpsc::Sender<TorrentMsg>>,
+    /// Hooks of the configuration, given to each new [`Torrent`].
+    hooks: Hooks,
+    /// Where the [`Torrent`]s cache their info, from the configuration.
+    metadata_dir: Option<PathBuf>,
+    /// Where the [`Torrent`]s can be saved, see [`Daemon::check_save_path`].
+    allowed_save_dirs: Vec<PathBuf>,
     rx: mpsc::Receiver<DaemonMsg>,
 }
 
@@ -63,15 +79,30 @@ pub struct DaemonCtx {
 pub enum DaemonMsg {
     /// Tell Daemon to add a new torrent and it will immediately
     /// announce to a tracker, connect to the peers, and start the download.
-    NewTorrent(Magnet),
+    NewTorrent(Magnet, TorrentOptions),
+    /// Add a new torrent from the bytes of its .torrent file, the info of
+    /// the file is used instead of being downloaded from peers.
+    NewTorrentFile(Vec<u8>, TorrentOptions),
     /// Message that the Daemon will send to all connectors when the state
     /// of a torrent updates (every 1 second).
     TorrentState(TorrentState),
     /// Ask the Daemon to send a [`TorrentState`] of the torrent with the given
     /// hash_info.
     RequestTorrentState([u8; 20], oneshot::Sender<Option<TorrentState>>),
-    /// Pause/Resume a torrent.
+    /// Pause/Resume a torrent, a torrent with an error is retried.
     TogglePause([u8; 20]),
+    /// Stop a torrent and forget about it, the downloaded files are kept.
+    RemoveTorrent([u8; 20]),
+    /// Ask the Daemon for the .torrent file of the torrent with the given
+    /// info_hash, `None` if the torrent does not exist or if its info was
+    /// not downloaded yet.
+    ExportTorrent([u8; 20], oneshot::Sender<Option<Vec<u8>>>),
+    /// Ask the Daemon for a magnet link of the torrent with the given
+    /// info_hash, `None` if the torrent does not exist.
+    ExportMagnet([u8; 20], oneshot::Sender<Option<String>>),
+    /// Move the files of a torrent to the directory `new_path`, the torrent
+    /// keeps running from there.
+    MoveStorage { info_hash: [u8; 20], new_path: PathBuf },
     /// Gracefully shutdown the Daemon
     Quit,
     /// Print the status of all Torrents to stdout
@@ -90,6 +121,9 @@ impl Daemon {
             rx,
             disk_tx: None,
             torrent_txs: HashMap::new(),
+            hooks: Hooks::default(),
+            metadata_dir: None,
+            allowed_save_dirs: Vec::new(),
             ctx: Arc::new(DaemonCtx {
                 tx,
                 torrent_states: RwLock::new(HashMap::new()),
@@ -113,50 +147,144 @@ impl Daemon {
     /// can also be fired internaly (via CLI flags).
     pub async fn run(&mut self) -> Result<(), Error> {
         let config = Config::load()?;
-        let socket = TcpListener::bind(config.daemon_addr).await.unwrap();
+        self.hooks = Hooks::from(&config);
+        self.metadata_dir.clone_from(&config.metadata_dir);
+        self.allowed_save_dirs = config.save_dirs();
 
         let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(300);
         self.disk_tx = Some(disk_tx);
 
-        let mut disk = Disk::new(disk_rx, config.download_dir);
+        let mut disk = Disk::new(disk_rx, config.download_dir.clone())
+            .cache_size(config.disk_cache_size * 1024 * 1024)
+            .read_cache_size(config.disk_read_cache_size * 1024 * 1024)
+            .max_open_files(config.max_open_files)
+            .hashing_threads(config.hashing_threads)
+            .preallocation(config.preallocation)
+            .incomplete_dir(config.incomplete_dir.clone())
+            .part_suffix(config.part_suffix);
 
         spawn(async move {
             let _ = disk.run().await;
         });
 
-        let ctx = self.ctx.clone();
-
-        info!("Daemon listening on: {}", config.daemon_addr);
+        let mut handles = Vec::new();
 
         // Listen to remote TCP messages
-        let handle = spawn(async move {
-            loop {
-                match socket.accept().await {
-                    Ok((socket, addr)) => {
-                        info!("Connected with remote: {addr}");
-
-                        let ctx = ctx.clone();
-
-                        spawn(async move {
-                            let socket = Framed::new(socket, DaemonCodec);
-                            let _ = Self::listen_remote_msgs(socket, ctx).await;
-                        });
+        if config.daemon_tcp {
+            let socket = TcpListener::bind(config.daemon_addr).await?;
+            let acceptor = tls::acceptor(&config)?;
+            let ctx = self.ctx.clone();
+
+            if acceptor.is_some() {
+                info!("Daemon listening on: {} (TLS)", config.daemon_addr);
+            } else {
+                info!("Daemon listening on: {}", config.daemon_addr);
+            }
+
+            handles.push(spawn(async move {
+                loop {
+                    match socket.accept().await {
+                        Ok((socket, addr)) => {
+                            info!("Connected with remote: {addr}");
+                            let Some(acceptor) = acceptor.clone() else {
+                                Self::spawn_remote(socket, ctx.clone());
+                                continue;
+                            };
+                            let ctx = ctx.clone();
+                            // do the handshake on its own task, to not block
+                            // other connections
+                            spawn(async move {
+                                match acceptor.accept(socket).await {
+                                    Ok(socket) => {
+                                        Self::spawn_remote(socket, ctx);
+                                    }
+                                    Err(e) => {
+                                        warn!("TLS handshake with {addr} failed: {e}");
+                                    }
+                                }
+                            });
+                        }
+                        Err(e) => {
+                            error!("Could not connect with remote: {e:#?}");
+                        }
                     }
-                    Err(e) => {
-                        error!("Could not connect with remote: {e:#?}");
+                }
+            }));
+        }
+
+        // Listen to local messages on the Unix socket, access control is
+        // done by the permissions of the socket file.
+        if let Some(path) = &config.daemon_unix_socket {
+            let socket = Self::bind_unix(path, config.daemon_unix_socket_mode)?;
+            let ctx = self.ctx.clone();
+
+            info!("Daemon listening on: unix:{}", path.display());
+
+            handles.push(spawn(async move {
+                loop {
+                    match socket.accept().await {
+                        Ok((socket, _)) => {
+                            info!("Connected with local unix socket");
+                            Self::spawn_remote(socket, ctx.clone());
+                        }
+                        Err(e) => {
+                            error!("Could not connect with remote: {e:#?}");
+                        }
                     }
                 }
-            }
-        });
+            }));
+        }
 
         let ctx = self.ctx.clone();
 
+        // Listen to the Transmission RPC
+        if let Some(addr) = config.rpc_addr {
+            let rpc = Rpc::new(self.ctx.clone(), config.download_dir.clone())
+                .whitelist(config.rpc_whitelist.clone())
+                .allowed_save_dirs(config.save_dirs());
+            let rpc =
+                match (&config.rpc_username, &config.rpc_password) {
+                    (Some(username), Some(password)) => {
+                        rpc.auth(username.clone(), password.clone())
+                    }
+                    (None, None) => rpc,
+                    _ => return Err(Error::RpcConfigInvalid(
+                        "rpc_username and rpc_password must be set together"
+                            .to_owned(),
+                    )),
+                };
+            handles.push(spawn(async move {
+                if let Err(e) = rpc.run(addr).await {
+                    error!("Transmission RPC stopped: {e}");
+                }
+            }));
+        }
+
+        // Add the torrents dropped on the watch directory
+        if let Some(dir) = config.watch_dir.clone() {
+            let watch = WatchDir::new(
+                dir,
+                config.watch_processed_dir.clone(),
+                self.ctx.tx.clone(),
+            );
+            handles.push(spawn(async move {
+                if let Err(e) = watch.run().await {
+                    error!("Watch directory stopped: {e}");
+                }
+            }));
+        }
+
         // Listen to internal mpsc messages
         loop {
             select! {
                 Some(msg) = self.rx.recv() => {
                     match msg {
                         DaemonMsg::TorrentState(torrent_state) => {
+                            // a removed torrent may still send its last state
+                            if !self.torrent_txs.contains_key(&torrent_state.info_hash) {
+                                continue;
+                            }
+
                             let mut torrent_states = self.ctx.torrent_states.write().await;
 
                             torrent_states.insert(torrent_state.info_hash, torrent_state.clone());
@@ -167,12 +295,29 @@ impl Daemon {
 
                             drop(torrent_states);
                         }
-                        DaemonMsg::NewTorrent(magnet) => {
-                            let _ = self.new_torrent(magnet).await;
+                        DaemonMsg::NewTorrent(magnet, options) => {
+                            let _ = self.new_torrent(magnet, options).await;
+                        }
+                        DaemonMsg::NewTorrentFile(torrent, options) => {
+                            if let Err(e) = self.new_torrent_file(torrent, options).await {
+                                warn!("Could not add the .torrent file: {e}");
+                            }
                         }
                         DaemonMsg::TogglePause(info_hash) => {
                             let _ = self.toggle_pause(info_hash).await;
                         }
+                        DaemonMsg::RemoveTorrent(info_hash) => {
+                            let _ = self.remove_torrent(info_hash).await;
+                        }
+                        DaemonMsg::MoveStorage { info_hash, new_path } => {
+                            let _ = self.move_storage(info_hash, new_path).await;
+                        }
+                        DaemonMsg::ExportTorrent(info_hash, recipient) => {
+                            self.export(info_hash, recipient, TorrentMsg::ExportTorrent, Option::flatten);
+                        }
+                        DaemonMsg::ExportMagnet(info_hash, recipient) => {
+                            self.export(info_hash, recipient, TorrentMsg::ExportMagnet, |v| v);
+                        }
                         DaemonMsg::RequestTorrentState(info_hash, recipient) => {
                             let torrent_states = self.ctx.torrent_states.read().await;
                             let torrent_state = torrent_states.get(&info_hash);
@@ -206,7 +351,12 @@ impl Daemon {
                         }
                         DaemonMsg::Quit => {
                             let _ = self.quit().await;
-                            handle.abort();
+                            for handle in &handles {
+                                handle.abort();
+                            }
+                            if let Some(path) = &config.daemon_unix_socket {
+                                let _ = std::fs::remove_file(path);
+                            }
                             break;
                         }
                     }
@@ -217,13 +367,64 @@ impl Daemon {
         Ok(())
     }
 
-    /// Listen to messages sent remotely via TCP,
+    /// Bind the Unix socket at `path` with the permissions `mode`.
+    ///
+    /// The socket is bound inside of a private directory and then moved to
+    /// `path`, so that clients can't connect before it has its permissions.
+    /// A stale socket left by a previous run is removed, but not the socket
+    /// of a running daemon, nor a file that is not a socket.
+    fn bind_unix(path: &Path, mode: u32) -> Result<UnixListener, Error> {
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            let is_live = std::os::unix::net::UnixStream::connect(path).is_ok();
+            if !metadata.file_type().is_socket() || is_live {
+                return Err(Error::PathExists(path.display().to_string()));
+            }
+            std::fs::remove_file(path)?;
+        }
+
+        let name = path.file_name().ok_or(Error::PathInvalid)?;
+        let mut private = path.with_file_name(".");
+        private.as_mut_os_string().push(name);
+        private.as_mut_os_string().push(format!(".{}", std::process::id()));
+
+        let _ = std::fs::remove_dir_all(&private);
+        std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+        let tmp = private.join("socket");
+        let socket =
+            std::os::unix::net::UnixListener::bind(&tmp).and_then(|socket| {
+                std::fs::set_permissions(&tmp, Permissions::from_mode(mode))?;
+                std::fs::rename(&tmp, path)?;
+                Ok(socket)
+            });
+        let _ = std::fs::remove_dir_all(&private);
+
+        let socket = socket?;
+        socket.set_nonblocking(true)?;
+        Ok(UnixListener::from_std(socket)?)
+    }
+
+    /// Spawn a task to listen to the messages of a newly connected client.
+    fn spawn_remote<T>(socket: T, ctx: Arc<DaemonCtx>)
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        spawn(async move {
+            let socket = Framed::new(socket, DaemonCodec);
+            let _ = Self::listen_remote_msgs(socket, ctx).await;
+        });
+    }
+
+    /// Listen to messages sent remotely via TCP or a Unix socket,
     /// A UI can be a standalone binary that is executing on another machine,
     /// and wants to control the daemon using the [`DaemonCodec`] protocol.
-    async fn listen_remote_msgs(
-        socket: Framed<TcpStream, DaemonCodec>,
+    async fn listen_remote_msgs<T>(
+        socket: Framed<T, DaemonCodec>,
         ctx: Arc<DaemonCtx>,
-    ) -> Result<(), Error> {
+    ) -> Result<(), Error>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send,
+    {
         trace!("daemon listen_msgs");
 
         let mut draw_interval = interval(Duration::from_secs(1));
@@ -237,11 +438,11 @@ impl Daemon {
                 // or locally on the same binary (i.e CLI).
                 Some(Ok(msg)) = stream.next() => {
                     match msg {
-                        Message::NewTorrent(magnet_link) => {
+                        Message::NewTorrent(magnet_link, options) => {
                             trace!("daemon received NewTorrent {magnet_link}");
                             let magnet = Magnet::new(&magnet_link);
                             if let Ok(magnet) = magnet {
-                                let _ = ctx.tx.send(DaemonMsg::NewTorrent(magnet)).await;
+                                let _ = ctx.tx.send(DaemonMsg::NewTorrent(magnet, options)).await;
                             }
                         }
                         Message::RequestTorrentState(info_hash) => {
@@ -256,6 +457,30 @@ impl Daemon {
                             trace!("daemon received TogglePause {id:?}");
                             let _ = ctx.tx.send(DaemonMsg::TogglePause(id)).await;
                         }
+                        Message::RemoveTorrent(id) => {
+                            trace!("daemon received RemoveTorrent {id:?}");
+                            let _ = ctx.tx.send(DaemonMsg::RemoveTorrent(id)).await;
+                        }
+                        Message::MoveStorage { info_hash, new_path } => {
+                            trace!("daemon received MoveStorage {info_hash:?} {new_path:?}");
+                            let _ = ctx.tx.send(DaemonMsg::MoveStorage { info_hash, new_path }).await;
+                        }
+                        Message::ExportTorrent(info_hash) => {
+                            trace!("daemon received ExportTorrent {info_hash:?}");
+                            let (tx, rx) = oneshot::channel();
+                            let _ = ctx.tx.send(DaemonMsg::ExportTorrent(info_hash, tx)).await;
+                            let r = rx.await?;
+
+                            let _ = sink.send(Message::TorrentFile(r)).await;
+                        }
+                        Message::ExportMagnet(info_hash) => {
+                            trace!("daemon received ExportMagnet {info_hash:?}");
+                            let (tx, rx) = oneshot::channel();
+                            let _ = ctx.tx.send(DaemonMsg::ExportMagnet(info_hash, tx)).await;
+                            let r = rx.await?;
+
+                            let _ = sink.send(Message::MagnetLink(r)).await;
+                        }
                         Message::Quit => {
                             info!("Daemon is quitting");
                             let _ = ctx.tx.send(DaemonMsg::Quit).await;
@@ -288,6 +513,73 @@ impl Daemon {
         Ok(())
     }
 
+    /// Move the files of the torrent to the directory `new_path`, while the
+    /// torrent is moving, its status is [`TorrentStatus::Moving`].
+    pub async fn move_storage(
+        &self,
+        info_hash: [u8; 20],
+        new_path: PathBuf,
+    ) -> Result<(), Error> {
+        let tx = self
+            .torrent_txs
+            .get(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?;
+
+        Self::check_save_path(&new_path, &self.allowed_save_dirs)?;
+        tx.send(TorrentMsg::MoveStorage(new_path)).await?;
+
+        Ok(())
+    }
+
+    /// Stop the torrent and remove it from the daemon, the files that were
+    /// downloaded are kept.
+    pub async fn remove_torrent(
+        &mut self,
+        info_hash: [u8; 20],
+    ) -> Result<(), Error> {
+        let tx = self
+            .torrent_txs
+            .remove(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?;
+
+        self.ctx.torrent_states.write().await.remove(&info_hash);
+        info!("Removed torrent {}", hex::encode(info_hash));
+
+        tx.send(TorrentMsg::Quit).await?;
+
+        Ok(())
+    }
+
+    /// Ask the torrent with the given info_hash to export itself with the
+    /// message built by `msg`, and send the answer to `recipient` after
+    /// mapping it with `f`. The torrent answers on its own task, so that the
+    /// Daemon is not blocked waiting for it.
+    fn export<T, R>(
+        &self,
+        info_hash: [u8; 20],
+        recipient: oneshot::Sender<Option<R>>,
+        msg: impl FnOnce(oneshot::Sender<T>) -> TorrentMsg,
+        f: impl FnOnce(Option<T>) -> Option<R> + Send + 'static,
+    ) where
+        T: Send + 'static,
+        R: Send + 'static,
+    {
+        let Some(tx) = self.torrent_txs.get(&info_hash).cloned() else {
+            let _ = recipient.send(None);
+            return;
+        };
+        let (otx, orx) = oneshot::channel();
+        let msg = msg(otx);
+
+        spawn(async move {
+            let r = match tx.send(msg).await {
+                Ok(_) => orx.await.ok(),
+                Err(_) => None,
+            };
+            let _ = recipient.send(f(r));
+        });
+    }
+
     /// Sends a Draw message to the [`UI`] with the updated state of a torrent.
     async fn draw<T>(sink: &mut T, ctx: Arc<DaemonCtx>) -> Result<(), Error>
     where
@@ -306,6 +598,47 @@ impl Daemon {
         Ok(())
     }
 
+    /// Check that a torrent can be saved at `path`, which must be absolute,
+    /// without `..`, and inside one of the `allowed_dirs`. The paths are
+    /// compared after their symlinks are resolved, so a symlink inside of an
+    /// allowed dir can't point outside of it.
+    pub fn check_save_path(
+        path: &Path,
+        allowed_dirs: &[PathBuf],
+    ) -> Result<(), Error> {
+        let allowed = path.is_absolute()
+            && !path.components().any(|c| c == Component::ParentDir)
+            && Self::resolve_path(path).is_some_and(|path| {
+                allowed_dirs.iter().any(|dir| {
+                    Self::resolve_path(dir)
+                        .is_some_and(|dir| path.starts_with(dir))
+                })
+            });
+
+        if !allowed {
+            warn!("the save path {path:?} is not allowed");
+            return Err(Error::SavePathNotAllowed(path.display().to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Canonicalize the nearest ancestor of `path` that exists and join the
+    /// rest of the path to it, `None` if the ancestor can't be resolved, e.g
+    /// a dangling symlink.
+    fn resolve_path(path: &Path) -> Option<PathBuf> {
+        let ancestor = path
+            .ancestors()
+            .find(|ancestor| std::fs::symlink_metadata(ancestor).is_ok())?;
+        let resolved = ancestor.canonicalize().ok()?;
+        let rest = path.strip_prefix(ancestor).ok()?;
+
+        if rest.as_os_str().is_empty() {
+            return Some(resolved);
+        }
+        Some(resolved.join(rest))
+    }
+
     /// Create a new [`Torrent`] given a magnet link URL
     /// and run the torrent's event loop.
     ///
@@ -316,10 +649,43 @@ impl Daemon {
     /// # Panic
     ///
     /// This fn will panic if it is being called BEFORE run
-    pub async fn new_torrent(&mut self, magnet: Magnet) -> Result<(), Error> {
+    pub async fn new_torrent(
+        &mut self,
+        magnet: Magnet,
+        options: TorrentOptions,
+    ) -> Result<(), Error> {
+        self.add_torrent(magnet, options, None).await
+    }
+
+    /// Create a new [`Torrent`] from the bytes of its .torrent file.
+    pub async fn new_torrent_file(
+        &mut self,
+        torrent: Vec<u8>,
+        options: TorrentOptions,
+    ) -> Result<(), Error> {
+        let magnet = MetaInfo::magnet(&torrent)?;
+        self.add_torrent(magnet, options, Some(torrent)).await
+    }
+
+    /// Create a new [`Torrent`], with the .torrent file of the magnet if it
+    /// is known.
+    async fn add_torrent(
+        &mut self,
+        magnet: Magnet,
+        options: TorrentOptions,
+        metainfo: Option<Vec<u8>>,
+    ) -> Result<(), Error> {
         trace!("magnet: {}", *magnet);
         let info_hash = magnet.parse_xt();
 
+        // the directories of the torrent are created from the save path
+        if let Some(save_path) = &options.save_path {
+            Self::check_save_path(
+                Path::new(save_path),
+                &self.allowed_save_dirs,
+            )?;
+        }
+
         let mut torrent_states = self.ctx.torrent_states.write().await;
 
         if torrent_states.get(&info_hash).is_some() {
@@ -330,6 +696,8 @@ impl Daemon {
         let torrent_state = TorrentState {
             name: magnet.parse_dn(),
             info_hash,
+            labels: options.labels.clone(),
+            save_path: options.save_path.clone(),
             ..Default::default()
         };
 
@@ -339,14 +707,48 @@ impl Daemon {
         // disk_tx is not None at this point, this is safe
         // (if calling after run)
         let disk_tx = self.disk_tx.clone().unwrap();
-        let mut torrent = Torrent::new(disk_tx, self.ctx.tx.clone(), magnet);
+        let mut torrent = Torrent::with_options(
+            disk_tx,
+            self.ctx.tx.clone(),
+            magnet,
+            options,
+        );
+        torrent.hooks = self.hooks.clone();
+        torrent.metadata_dir.clone_from(&self.metadata_dir);
 
         self.torrent_txs.insert(info_hash, torrent.ctx.tx.clone());
         info!("Downloading torrent: {}", torrent.name);
 
+        self.hooks.spawn(
+            HookEvent::Added,
+            HookEnv {
+                info_hash,
+                name: torrent.name.clone(),
+                ..Default::default()
+            },
+        );
+
         spawn(async move {
-            torrent.start_and_run(None).await?;
-            Ok::<(), Error>(())
+            let r = async {
+                if let Some(metainfo) = metainfo {
+                    torrent.set_metainfo(&metainfo).await?;
+                }
+                torrent.start_and_run(None).await
+            }
+            .await;
+            if let Err(e) = r {
+                error!("Torrent {:?} stopped: {e}", torrent.name);
+                torrent.hooks.spawn(
+                    HookEvent::Error,
+                    HookEnv {
+                        info_hash,
+                        name: torrent.name.clone(),
+                        size: torrent.size,
+                        error: Some(e.to_string()),
+                        save_path: torrent.base_path.clone(),
+                    },
+                );
+            }
         });
 
         Ok(())
@@ -367,3 +769,119 @@ impl Daemon {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{distributions::Alphanumeric, Rng};
+    use tokio::net::UnixStream;
+
+    use super::*;
+
+    // torrents are only saved at absolute paths, inside the allowed dirs.
+    #[tokio::test]
+    async fn save_path_must_be_allowed() {
+        let allowed = [PathBuf::from("/mnt/a")];
+        let check = |path: &str, allowed: &[PathBuf]| {
+            Daemon::check_save_path(Path::new(path), allowed).is_ok()
+        };
+        assert!(!check("/anywhere", &[]));
+        assert!(check("/mnt/a/b", &allowed));
+        assert!(!check("relative", &[]));
+        assert!(!check("/mnt/a/../../etc", &allowed));
+        assert!(!check("/mnt/ab", &allowed));
+
+        // a symlink can't escape from the allowed dir
+        let mut rng = rand::thread_rng();
+        let dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let dir = std::env::temp_dir().join(dir);
+        let allowed_dir = dir.join("allowed");
+        std::fs::create_dir_all(&allowed_dir).unwrap();
+        std::fs::create_dir(dir.join("outside")).unwrap();
+        std::os::unix::fs::symlink(
+            dir.join("outside"),
+            allowed_dir.join("link"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            dir.join("missing"),
+            allowed_dir.join("dangling"),
+        )
+        .unwrap();
+        let allowed_dirs = [allowed_dir.clone()];
+        let check = |path: PathBuf| {
+            Daemon::check_save_path(&path, &allowed_dirs).is_ok()
+        };
+        assert!(check(allowed_dir.join("new").join("x")));
+        assert!(!check(allowed_dir.join("link")));
+        assert!(!check(allowed_dir.join("link").join("x")));
+        assert!(!check(allowed_dir.join("dangling").join("x")));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // nothing is created for a torrent that is rejected
+        let mut daemon = Daemon::new();
+        daemon.allowed_save_dirs = allowed.to_vec();
+        let magnet = Magnet::new(
+            "magnet:?xt=urn:btih:9999999999999999999999999999999999999999",
+        )
+        .unwrap();
+        let options = TorrentOptions {
+            save_path: Some("/etc".to_owned()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            daemon.new_torrent(magnet, options).await,
+            Err(Error::SavePathNotAllowed(_))
+        ));
+        assert!(daemon.ctx.torrent_states.read().await.is_empty());
+    }
+
+    // the daemon is controlled by a client over the Unix socket, which only
+    // replaces the socket of a previous run, not a live one.
+    #[tokio::test]
+    async fn serve_unix_socket() {
+        let mut rng = rand::thread_rng();
+        let dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let dir = std::env::temp_dir().join(dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("vcz.sock");
+
+        // a file that is not a socket is not removed
+        std::fs::write(&path, b"").unwrap();
+        assert!(Daemon::bind_unix(&path, 0o600).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        // a stale socket is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let socket = Daemon::bind_unix(&path, 0o600).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // and the socket of a running daemon is not
+        assert!(Daemon::bind_unix(&path, 0o600).is_err());
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let ctx = Arc::new(DaemonCtx {
+            tx,
+            torrent_states: RwLock::new(HashMap::new()),
+        });
+        // the daemon was probed above, so there is more than one client
+        spawn(async move {
+            while let Ok((socket, _)) = socket.accept().await {
+                Daemon::spawn_remote(socket, ctx.clone());
+            }
+        });
+
+        let client = UnixStream::connect(&path).await.unwrap();
+        let mut client = Framed::new(client, DaemonCodec);
+        client.send(Message::TogglePause([7; 20])).await.unwrap();
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(DaemonMsg::TogglePause(v)) if v == [7; 20]
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
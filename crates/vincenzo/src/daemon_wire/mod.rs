@@ -1,11 +1,94 @@
 //! Framed messages sent to/from Daemon
+pub mod tls;
+
 use bytes::{Buf, BufMut, BytesMut};
 use speedy::{BigEndian, Readable, Writable};
-use std::io::Cursor;
-use tokio::io;
-use tokio_util::codec::{Decoder, Encoder};
+use std::{
+    ffi::OsString,
+    fmt,
+    io::Cursor,
+    net::SocketAddr,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::PathBuf,
+    str::FromStr,
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite},
+    net::{TcpStream, UnixStream},
+};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use crate::{
+    error::Error,
+    torrent::{TorrentOptions, TorrentState},
+};
+
+use self::tls::ClientTls;
+
+/// Address where a client can reach the Daemon.
+///
+/// Parsed from either an `ip:port` string, for TCP, or a
+/// `unix:/path/to/socket` string, for a Unix domain socket.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DaemonAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for DaemonAddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(Error::DaemonAddrInvalid(s.to_owned()));
+            }
+            return Ok(Self::Unix(path.into()));
+        }
+        s.parse()
+            .map(Self::Tcp)
+            .map_err(|_| Error::DaemonAddrInvalid(s.to_owned()))
+    }
+}
+
+impl fmt::Display for DaemonAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
 
-use crate::torrent::TorrentState;
+/// Any transport that can carry the [`DaemonCodec`] protocol.
+pub trait DaemonIo: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> DaemonIo for T {}
+
+/// Connect to the Daemon listening on `addr`, regardless of the transport.
+///
+/// TCP connections are wrapped in TLS when `tls` is given, Unix sockets
+/// never use TLS.
+pub async fn connect(
+    addr: &DaemonAddr,
+    tls: Option<&ClientTls>,
+) -> Result<Framed<Box<dyn DaemonIo>, DaemonCodec>, Error> {
+    let socket: Box<dyn DaemonIo> = match (addr, tls) {
+        (DaemonAddr::Tcp(addr), Some(tls)) => {
+            let socket = TcpStream::connect(addr).await?;
+            let socket =
+                tls.connector().connect(tls.server_name(*addr), socket).await?;
+            Box::new(socket)
+        }
+        (DaemonAddr::Tcp(addr), None) => {
+            Box::new(TcpStream::connect(addr).await?)
+        }
+        (DaemonAddr::Unix(path), _) => {
+            Box::new(UnixStream::connect(path).await?)
+        }
+    };
+    Ok(Framed::new(socket, DaemonCodec))
+}
 
 /// Messages of [`DaemonCodec`], check the struct documentation
 /// to read how to send messages.
@@ -22,10 +105,10 @@ pub enum Message {
     ///
     /// Quit does not have a message_id, only the u32 len.
     Quit,
-    /// Add a new torrent given a magnet link.
+    /// Add a new torrent given a magnet link, and its options.
     ///
-    /// <len=1+magnet_link_len><id=1><magnet_link>
-    NewTorrent(String),
+    /// <len=5+magnet_link_len+options_len><id=1><magnet_link_len><magnet_link><options>
+    NewTorrent(String, TorrentOptions),
     /// Every second, the Daemon will send information about all torrents
     /// to all listeners
     ///
@@ -38,6 +121,35 @@ pub enum Message {
     RequestTorrentState([u8; 20]),
     /// Print the status of all Torrents to stdout
     PrintTorrentStatus,
+    /// Stop the torrent with the given info_hash and remove it from the
+    /// Daemon.
+    RemoveTorrent([u8; 20]),
+    /// Ask the Daemon for the .torrent file of the torrent with the given
+    /// info_hash, answered with [`Message::TorrentFile`].
+    ///
+    /// <len=21><id=7><info_hash>
+    ExportTorrent([u8; 20]),
+    /// The bencoded .torrent file asked with [`Message::ExportTorrent`], or
+    /// an empty payload if the torrent does not exist or does not have the
+    /// info yet.
+    ///
+    /// <len=1+torrent_len><id=8><torrent>
+    TorrentFile(Option<Vec<u8>>),
+    /// Ask the Daemon for a magnet link of the torrent with the given
+    /// info_hash, answered with [`Message::MagnetLink`].
+    ///
+    /// <len=21><id=9><info_hash>
+    ExportMagnet([u8; 20]),
+    /// The magnet link asked with [`Message::ExportMagnet`], or an empty
+    /// payload if the torrent does not exist.
+    ///
+    /// <len=1+magnet_link_len><id=10><magnet_link>
+    MagnetLink(Option<String>),
+    /// Move the files of the torrent with the given info_hash to the
+    /// directory `new_path`, on the machine of the Daemon.
+    ///
+    /// <len=21+new_path_len><id=11><info_hash><new_path>
+    MoveStorage { info_hash: [u8; 20], new_path: PathBuf },
 }
 
 #[repr(u8)]
@@ -48,6 +160,12 @@ pub enum MessageId {
     GetTorrentState = 3,
     TogglePause = 4,
     PrintTorrentStatus = 5,
+    RemoveTorrent = 6,
+    ExportTorrent = 7,
+    TorrentFile = 8,
+    ExportMagnet = 9,
+    MagnetLink = 10,
+    MoveStorage = 11,
 }
 
 impl TryFrom<u8> for MessageId {
@@ -61,6 +179,12 @@ impl TryFrom<u8> for MessageId {
             k if k == GetTorrentState as u8 => Ok(GetTorrentState),
             k if k == PrintTorrentStatus as u8 => Ok(PrintTorrentStatus),
             k if k == TogglePause as u8 => Ok(TogglePause),
+            k if k == RemoveTorrent as u8 => Ok(RemoveTorrent),
+            k if k == ExportTorrent as u8 => Ok(ExportTorrent),
+            k if k == TorrentFile as u8 => Ok(TorrentFile),
+            k if k == ExportMagnet as u8 => Ok(ExportMagnet),
+            k if k == MagnetLink as u8 => Ok(MagnetLink),
+            k if k == MoveStorage as u8 => Ok(MoveStorage),
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "Unknown message id",
@@ -80,17 +204,22 @@ impl TryFrom<u8> for MessageId {
 ///
 /// # Example
 ///
-/// You are sending a magnet of 18 bytes: "magnet:blabla"
+/// You are sending a magnet of 13 bytes: "magnet:blabla", with the default
+/// options.
 ///
 /// ```
 /// use bytes::{Buf, BufMut, BytesMut};
-/// use vincenzo::daemon_wire::MessageId;
+/// use speedy::{BigEndian, Writable};
+/// use vincenzo::{daemon_wire::MessageId, torrent::TorrentOptions};
 ///
 /// let mut buf = BytesMut::new();
 /// let magnet = "magnet:blabla".to_owned();
+/// let options = TorrentOptions::default()
+///     .write_to_vec_with_ctx(BigEndian {})
+///     .unwrap();
 ///
 /// // len is: 1 byte of the message_id + the payload len
-/// let msg_len = 1 + magnet.len() as u32;
+/// let msg_len = 1 + 4 + magnet.len() as u32 + options.len() as u32;
 ///
 /// // len>
 /// buf.put_u32(msg_len);
@@ -99,12 +228,14 @@ impl TryFrom<u8> for MessageId {
 /// buf.put_u8(MessageId::NewTorrent as u8);
 ///
 /// // payload
+/// buf.put_u32(magnet.len() as u32);
 /// buf.extend_from_slice(magnet.as_bytes());
+/// buf.extend_from_slice(&options);
 ///
 /// // result
-/// // len  msg_id  payload
-/// // 19     1    "magnet:blabla"
-/// // u32    u8    (dynamic size)
+/// // len  msg_id  magnet_len  magnet           options
+/// // x      1        13       "magnet:blabla"  (dynamic size)
+/// // u32    u8       u32      (dynamic size)
 /// ```
 #[derive(Debug)]
 pub struct DaemonCodec;
@@ -119,12 +250,15 @@ impl Encoder<Message> for DaemonCodec {
         buf: &mut BytesMut,
     ) -> Result<(), Self::Error> {
         match item {
-            Message::NewTorrent(magnet) => {
-                let msg_len = 1 + magnet.len() as u32;
+            Message::NewTorrent(magnet, options) => {
+                let options = options.write_to_vec_with_ctx(BigEndian {})?;
+                let msg_len = 5 + magnet.len() as u32 + options.len() as u32;
 
                 buf.put_u32(msg_len);
                 buf.put_u8(MessageId::NewTorrent as u8);
+                buf.put_u32(magnet.len() as u32);
                 buf.extend_from_slice(magnet.as_bytes());
+                buf.extend_from_slice(&options);
             }
             Message::TorrentState(torrent_info) => {
                 let info_bytes = match torrent_info {
@@ -151,6 +285,53 @@ impl Encoder<Message> for DaemonCodec {
                 buf.put_u8(MessageId::TogglePause as u8);
                 buf.extend_from_slice(&info_hash);
             }
+            Message::RemoveTorrent(info_hash) => {
+                let msg_len = 1 + info_hash.len() as u32;
+
+                buf.put_u32(msg_len);
+                buf.put_u8(MessageId::RemoveTorrent as u8);
+                buf.extend_from_slice(&info_hash);
+            }
+            Message::ExportTorrent(info_hash) => {
+                let msg_len = 1 + info_hash.len() as u32;
+
+                buf.put_u32(msg_len);
+                buf.put_u8(MessageId::ExportTorrent as u8);
+                buf.extend_from_slice(&info_hash);
+            }
+            Message::TorrentFile(torrent) => {
+                let torrent = torrent.unwrap_or_default();
+                let msg_len = 1 + torrent.len() as u32;
+
+                buf.put_u32(msg_len);
+                buf.put_u8(MessageId::TorrentFile as u8);
+                buf.extend_from_slice(&torrent);
+            }
+            Message::ExportMagnet(info_hash) => {
+                let msg_len = 1 + info_hash.len() as u32;
+
+                buf.put_u32(msg_len);
+                buf.put_u8(MessageId::ExportMagnet as u8);
+                buf.extend_from_slice(&info_hash);
+            }
+            Message::MagnetLink(magnet) => {
+                let magnet = magnet.unwrap_or_default();
+                let msg_len = 1 + magnet.len() as u32;
+
+                buf.put_u32(msg_len);
+                buf.put_u8(MessageId::MagnetLink as u8);
+                buf.extend_from_slice(magnet.as_bytes());
+            }
+            Message::MoveStorage { info_hash, new_path } => {
+                let new_path = new_path.as_os_str().as_bytes();
+                let msg_len =
+                    1 + info_hash.len() as u32 + new_path.len() as u32;
+
+                buf.put_u32(msg_len);
+                buf.put_u8(MessageId::MoveStorage as u8);
+                buf.extend_from_slice(&info_hash);
+                buf.extend_from_slice(new_path);
+            }
             Message::PrintTorrentStatus => {
                 let msg_len = 1;
 
@@ -213,10 +394,24 @@ impl Decoder for DaemonCodec {
         // so all calls to `remaining` and `get_*` will start from the payload.
         let msg = match msg_id {
             MessageId::NewTorrent => {
-                let mut payload = vec![0u8; buf.remaining()];
-                buf.copy_to_slice(&mut payload);
+                let magnet_len = buf.get_u32() as usize;
+                let mut magnet = vec![0u8; magnet_len];
+                buf.copy_to_slice(&mut magnet);
 
-                Message::NewTorrent(String::from_utf8(payload).unwrap())
+                let mut options = vec![0u8; msg_len - 5 - magnet_len];
+                buf.copy_to_slice(&mut options);
+
+                let magnet = String::from_utf8(magnet).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "The magnet link is not valid UTF-8",
+                    )
+                })?;
+                let options = TorrentOptions::read_from_buffer_with_ctx(
+                    BigEndian {},
+                    &options,
+                )?;
+                Message::NewTorrent(magnet, options)
             }
             MessageId::TorrentState => {
                 let mut info: Option<TorrentState> = None;
@@ -239,12 +434,60 @@ impl Decoder for DaemonCodec {
                 Message::TogglePause(payload)
             }
             MessageId::PrintTorrentStatus => Message::PrintTorrentStatus,
+            MessageId::RemoveTorrent => {
+                let mut payload = [0u8; 20_usize];
+                buf.copy_to_slice(&mut payload);
+
+                Message::RemoveTorrent(payload)
+            }
             MessageId::GetTorrentState => {
                 let mut payload = [0u8; 20_usize];
                 buf.copy_to_slice(&mut payload);
 
                 Message::RequestTorrentState(payload)
             }
+            MessageId::ExportTorrent => {
+                let mut payload = [0u8; 20_usize];
+                buf.copy_to_slice(&mut payload);
+
+                Message::ExportTorrent(payload)
+            }
+            MessageId::TorrentFile => {
+                let mut payload = vec![0u8; msg_len - 1];
+                buf.copy_to_slice(&mut payload);
+
+                Message::TorrentFile((!payload.is_empty()).then_some(payload))
+            }
+            MessageId::ExportMagnet => {
+                let mut payload = [0u8; 20_usize];
+                buf.copy_to_slice(&mut payload);
+
+                Message::ExportMagnet(payload)
+            }
+            MessageId::MagnetLink => {
+                let mut payload = vec![0u8; msg_len - 1];
+                buf.copy_to_slice(&mut payload);
+
+                let magnet = String::from_utf8(payload).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "The magnet link is not valid UTF-8",
+                    )
+                })?;
+                Message::MagnetLink((!magnet.is_empty()).then_some(magnet))
+            }
+            MessageId::MoveStorage => {
+                let mut info_hash = [0u8; 20_usize];
+                buf.copy_to_slice(&mut info_hash);
+
+                let mut new_path = vec![0u8; msg_len - 21];
+                buf.copy_to_slice(&mut new_path);
+
+                Message::MoveStorage {
+                    info_hash,
+                    new_path: OsString::from_vec(new_path).into(),
+                }
+            }
         };
 
         Ok(Some(msg))
@@ -253,14 +496,40 @@ impl Decoder for DaemonCodec {
 
 #[cfg(test)]
 mod tests {
-    use crate::torrent::TorrentStatus;
+    use crate::{
+        disk::{PieceStrategy, Preallocation},
+        torrent::TorrentStatus,
+    };
 
     use super::*;
 
+    #[test]
+    fn parse_daemon_addr() {
+        assert_eq!(
+            "127.0.0.1:3030".parse::<DaemonAddr>().unwrap(),
+            DaemonAddr::Tcp("127.0.0.1:3030".parse().unwrap())
+        );
+        assert_eq!(
+            "unix:/run/vincenzo.sock".parse::<DaemonAddr>().unwrap(),
+            DaemonAddr::Unix("/run/vincenzo.sock".into())
+        );
+        assert!("unix:".parse::<DaemonAddr>().is_err());
+        assert!("localhost".parse::<DaemonAddr>().is_err());
+    }
+
     #[test]
     fn new_torrent() {
         let mut buf = BytesMut::new();
-        let msg = Message::NewTorrent("magnet:blabla".to_owned());
+        let options = TorrentOptions {
+            save_path: Some("/mnt/other".to_owned()),
+            paused: true,
+            files: Some(vec![0, 2]),
+            labels: vec!["linux".to_owned()],
+            piece_strategy: PieceStrategy::Sequential,
+            preallocation: Some(Preallocation::Full),
+        };
+        let msg =
+            Message::NewTorrent("magnet:blabla".to_owned(), options.clone());
         DaemonCodec.encode(msg, &mut buf).unwrap();
 
         println!("encoded {buf:?}");
@@ -270,8 +539,9 @@ mod tests {
         println!("decoded {msg:?}");
 
         match msg {
-            Message::NewTorrent(magnet) => {
+            Message::NewTorrent(magnet, decoded) => {
                 assert_eq!(magnet, "magnet:blabla".to_owned());
+                assert_eq!(decoded, options);
             }
             _ => panic!(),
         }
@@ -290,8 +560,13 @@ mod tests {
             downloaded: 999,
             download_rate: 111,
             uploaded: 44,
+            upload_rate: 4,
             size: 9,
             info_hash: [0u8; 20],
+            labels: vec!["linux".to_owned()],
+            save_path: None,
+            error: Some("No space left on device".to_owned()),
+            moved: 0,
         };
 
         let a = info.write_to_vec_with_ctx(BigEndian {}).unwrap();
@@ -343,4 +618,52 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn export_torrent_and_magnet() {
+        let msgs = [
+            Message::ExportTorrent([4u8; 20]),
+            Message::TorrentFile(Some(b"d4:infod4:name3:fooee".to_vec())),
+            Message::TorrentFile(None),
+            Message::ExportMagnet([5u8; 20]),
+            Message::MagnetLink(Some("magnet:?xt=urn:btih:aa".to_owned())),
+            Message::MagnetLink(None),
+        ];
+
+        let mut buf = BytesMut::new();
+        for msg in &msgs {
+            DaemonCodec.encode(msg.clone(), &mut buf).unwrap();
+        }
+
+        for msg in msgs {
+            assert_eq!(DaemonCodec.decode(&mut buf).unwrap().unwrap(), msg);
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn move_storage() {
+        let mut buf = BytesMut::new();
+        let msg = Message::MoveStorage {
+            info_hash: [6u8; 20],
+            new_path: "/mnt/other disk/torrents".into(),
+        };
+        DaemonCodec.encode(msg.clone(), &mut buf).unwrap();
+
+        let decoded = DaemonCodec.decode(&mut buf).unwrap().unwrap();
+
+        assert_eq!(decoded, msg);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn remove_torrent() {
+        let mut buf = BytesMut::new();
+        let msg = Message::RemoveTorrent([3u8; 20]);
+        DaemonCodec.encode(msg.clone(), &mut buf).unwrap();
+
+        let decoded = DaemonCodec.decode(&mut buf).unwrap().unwrap();
+
+        assert_eq!(decoded, msg);
+    }
 }
@@ -0,0 +1,359 @@
+//! TLS for the TCP transport of the [`DaemonCodec`](super::DaemonCodec).
+//!
+//! The daemon uses the certificate and key from the configuration file, and
+//! may require clients to present a certificate signed by a given CA.
+//! Clients pin the CA that signed the certificate of the daemon, instead of
+//! trusting the certificate store of the system.
+use std::{fs::File, io::BufReader, net::SocketAddr, path::Path, sync::Arc};
+
+use tokio_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        server::WebPkiClientVerifier,
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+use crate::{config::Config, error::Error};
+
+/// Build the [`TlsAcceptor`] of the daemon listener, or `None` if TLS is not
+/// enabled in the configuration. A certificate without a key, or the
+/// opposite, or a client CA without both, is an error instead of a fallback
+/// to plain TCP.
+pub fn acceptor(config: &Config) -> Result<Option<TlsAcceptor>, Error> {
+    let (cert, key) = match (&config.daemon_tls_cert, &config.daemon_tls_key) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) if config.daemon_tls_client_ca.is_some() => {
+            return Err(Error::TlsConfigInvalid(
+                "daemon_tls_client_ca needs daemon_tls_cert and daemon_tls_key"
+                    .to_owned(),
+            ))
+        }
+        (None, None) => return Ok(None),
+        _ => {
+            return Err(Error::TlsConfigInvalid(
+                "daemon_tls_cert and daemon_tls_key must be set together"
+                    .to_owned(),
+            ))
+        }
+    };
+
+    let builder = ServerConfig::builder();
+
+    let builder = match &config.daemon_tls_client_ca {
+        Some(ca) => {
+            let verifier =
+                WebPkiClientVerifier::builder(Arc::new(root_store(ca)?))
+                    .build()
+                    .map_err(|e| Error::TlsConfigInvalid(e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let server_config =
+        builder.with_single_cert(load_certs(cert)?, load_key(key)?)?;
+
+    Ok(Some(TlsAcceptor::from(Arc::new(server_config))))
+}
+
+/// What a client needs to connect to a daemon that uses TLS.
+#[derive(Clone)]
+pub struct ClientTls {
+    connector: TlsConnector,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl ClientTls {
+    /// Build the TLS configuration of a client, or `None` if the client
+    /// should not use TLS, which is when `daemon_tls_ca` is not set.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, Error> {
+        let Some(ca) = &config.daemon_tls_ca else {
+            return Ok(None);
+        };
+
+        let builder =
+            ClientConfig::builder().with_root_certificates(root_store(ca)?);
+
+        let client_config = match (
+            &config.daemon_tls_client_cert,
+            &config.daemon_tls_client_key,
+        ) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_key(key)?)?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(Error::TlsConfigInvalid(
+                    "daemon_tls_client_cert and daemon_tls_client_key must be set together".to_owned(),
+                ))
+            }
+        };
+
+        let server_name = config
+            .daemon_tls_server_name
+            .clone()
+            .map(ServerName::try_from)
+            .transpose()
+            .map_err(|e| Error::TlsConfigInvalid(e.to_string()))?;
+
+        Ok(Some(Self {
+            connector: TlsConnector::from(Arc::new(client_config)),
+            server_name,
+        }))
+    }
+
+    pub fn connector(&self) -> &TlsConnector {
+        &self.connector
+    }
+
+    /// Name that the certificate of the daemon must be valid for, if not
+    /// configured, it is the IP of the daemon.
+    pub fn server_name(&self, addr: SocketAddr) -> ServerName<'static> {
+        self.server_name
+            .clone()
+            .unwrap_or_else(|| ServerName::IpAddress(addr.ip().into()))
+    }
+}
+
+/// Read all PEM certificates of a file.
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let mut reader = BufReader::new(open(path)?);
+    let certs =
+        rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+
+    if certs.is_empty() {
+        return Err(Error::TlsConfigInvalid(format!(
+            "no certificates found in `{}`",
+            path.display()
+        )));
+    }
+
+    Ok(certs)
+}
+
+/// Read the first PEM private key of a file.
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, Error> {
+    let mut reader = BufReader::new(open(path)?);
+
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| {
+        Error::TlsConfigInvalid(format!(
+            "no private key found in `{}`",
+            path.display()
+        ))
+    })
+}
+
+fn root_store(path: &Path) -> Result<RootCertStore, Error> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+
+    Ok(roots)
+}
+
+fn open(path: &Path) -> Result<File, Error> {
+    File::open(path)
+        .map_err(|_| Error::FileOpenError(path.display().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use futures::{SinkExt, StreamExt};
+    use rand::{distributions::Alphanumeric, Rng};
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedKey, IsCa, KeyPair,
+    };
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    use super::*;
+    use crate::daemon_wire::{self, DaemonAddr, DaemonCodec, Message};
+
+    struct Pki {
+        dir: PathBuf,
+        ca: CertifiedKey,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let mut rng = rand::thread_rng();
+            let name: String =
+                (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+            let dir = std::env::temp_dir().join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let mut params = CertificateParams::new(vec![]).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let key_pair = KeyPair::generate().unwrap();
+            let cert = params.self_signed(&key_pair).unwrap();
+
+            let pki = Self { dir, ca: CertifiedKey { cert, key_pair } };
+            std::fs::write(pki.path("ca.pem"), pki.ca.cert.pem()).unwrap();
+            pki
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.dir.join(name)
+        }
+
+        /// Issue a certificate signed by the CA and write it to
+        /// `{name}.pem` and `{name}.key`.
+        fn issue(&self, name: &str, san: &str) {
+            let params = CertificateParams::new(vec![san.to_owned()]).unwrap();
+            let key_pair = KeyPair::generate().unwrap();
+            let cert = params
+                .signed_by(&key_pair, &self.ca.cert, &self.ca.key_pair)
+                .unwrap();
+
+            std::fs::write(self.path(&format!("{name}.pem")), cert.pem())
+                .unwrap();
+            std::fs::write(
+                self.path(&format!("{name}.key")),
+                key_pair.serialize_pem(),
+            )
+            .unwrap();
+        }
+
+        fn config(&self) -> Config {
+            Config {
+                daemon_addr: "127.0.0.1:0".parse().unwrap(),
+                daemon_tls_cert: Some(self.path("daemon.pem")),
+                daemon_tls_key: Some(self.path("daemon.key")),
+                daemon_tls_ca: Some(self.path("ca.pem")),
+                ..Config::default()
+            }
+        }
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Accept one TLS connection and echo back the first message.
+    async fn echo_daemon(acceptor: TlsAcceptor) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let Ok(socket) = acceptor.accept(socket).await else {
+                return;
+            };
+            let mut socket = Framed::new(socket, DaemonCodec);
+            if let Some(Ok(msg)) = socket.next().await {
+                socket.send(msg).await.unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn connect_with_pinned_ca() {
+        let pki = Pki::new();
+        pki.issue("daemon", "127.0.0.1");
+        let config = pki.config();
+
+        let acceptor = acceptor(&config).unwrap().unwrap();
+        let tls = ClientTls::from_config(&config).unwrap().unwrap();
+        let addr = echo_daemon(acceptor).await;
+
+        let mut socket =
+            daemon_wire::connect(&DaemonAddr::Tcp(addr), Some(&tls))
+                .await
+                .unwrap();
+
+        socket.send(Message::PrintTorrentStatus).await.unwrap();
+        let msg = socket.next().await.unwrap().unwrap();
+
+        assert_eq!(msg, Message::PrintTorrentStatus);
+    }
+
+    #[tokio::test]
+    async fn reject_untrusted_daemon() {
+        let pki = Pki::new();
+        pki.issue("daemon", "127.0.0.1");
+        let other = Pki::new();
+        let mut config = pki.config();
+
+        // the client pins a CA that did not sign the daemon certificate
+        let acceptor = acceptor(&config).unwrap().unwrap();
+        config.daemon_tls_ca = Some(other.path("ca.pem"));
+        let tls = ClientTls::from_config(&config).unwrap().unwrap();
+        let addr = echo_daemon(acceptor).await;
+
+        let r = daemon_wire::connect(&DaemonAddr::Tcp(addr), Some(&tls)).await;
+
+        assert!(r.is_err());
+    }
+
+    #[tokio::test]
+    async fn verify_client_certificate() {
+        let pki = Pki::new();
+        pki.issue("daemon", "localhost");
+        pki.issue("client", "laptop");
+        let mut config = pki.config();
+        config.daemon_tls_client_ca = Some(pki.path("ca.pem"));
+        config.daemon_tls_server_name = Some("localhost".to_owned());
+
+        // without a client certificate the handshake fails
+        let acceptor = acceptor(&config).unwrap().unwrap();
+        let tls = ClientTls::from_config(&config).unwrap().unwrap();
+        let addr = echo_daemon(acceptor.clone()).await;
+
+        let mut socket =
+            daemon_wire::connect(&DaemonAddr::Tcp(addr), Some(&tls))
+                .await
+                .unwrap();
+        let _ = socket.send(Message::PrintTorrentStatus).await;
+        assert!(!matches!(socket.next().await, Some(Ok(_))));
+
+        // with a client certificate signed by the CA it succeeds
+        config.daemon_tls_client_cert = Some(pki.path("client.pem"));
+        config.daemon_tls_client_key = Some(pki.path("client.key"));
+        let tls = ClientTls::from_config(&config).unwrap().unwrap();
+        let addr = echo_daemon(acceptor).await;
+
+        let mut socket =
+            daemon_wire::connect(&DaemonAddr::Tcp(addr), Some(&tls))
+                .await
+                .unwrap();
+        socket.send(Message::PrintTorrentStatus).await.unwrap();
+        let msg = socket.next().await.unwrap().unwrap();
+
+        assert_eq!(msg, Message::PrintTorrentStatus);
+    }
+
+    #[test]
+    fn reject_half_configuration() {
+        let pki = Pki::new();
+        pki.issue("daemon", "127.0.0.1");
+        pki.issue("client", "laptop");
+
+        // a certificate without a key does not fall back to plain TCP
+        let mut config = pki.config();
+        config.daemon_tls_key = None;
+        assert!(matches!(acceptor(&config), Err(Error::TlsConfigInvalid(_))));
+
+        // neither does a client CA without a certificate
+        let mut config = pki.config();
+        config.daemon_tls_cert = None;
+        config.daemon_tls_key = None;
+        config.daemon_tls_client_ca = Some(pki.path("ca.pem"));
+        assert!(matches!(acceptor(&config), Err(Error::TlsConfigInvalid(_))));
+
+        let mut config = pki.config();
+        config.daemon_tls_client_key = Some(pki.path("client.key"));
+        assert!(matches!(
+            ClientTls::from_config(&config),
+            Err(Error::TlsConfigInvalid(_))
+        ));
+    }
+}
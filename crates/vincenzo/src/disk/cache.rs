@@ -0,0 +1,393 @@
+//! Caches of the [`Disk`](super::Disk), each one with a memory budget shared
+//! by all torrents.
+//!
+//! The [`WriteCache`] has the blocks that were downloaded, but not written to
+//! disk yet. Blocks are kept in memory until their piece is complete, so that
+//! the piece can be hashed without reading it back from disk. When the cache
+//! uses more memory than its capacity, the blocks of the pieces that least
+//! recently received a block are handed back to the disk to be written, and
+//! these pieces are hashed from disk once they are complete.
+//!
+//! The [`ReadCache`] has whole pieces that were read to be uploaded, so that
+//! the blocks of a piece requested by peers are read from disk at once.
+
+use std::collections::BTreeMap;
+
+use hashbrown::HashMap;
+
+use crate::extensions::core::{Block, BlockInfo};
+
+/// Default capacity of the caches, in bytes.
+pub const DEFAULT_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Statistics of a [`WriteCache`] or [`ReadCache`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    /// Blocks read and pieces hashed from memory.
+    pub hits: u64,
+    /// Blocks read and pieces hashed from disk, because they were not in the
+    /// cache.
+    pub misses: u64,
+    /// How many times pieces were removed to free memory. The blocks removed
+    /// from the write cache are written to disk.
+    pub evictions: u64,
+    /// Bytes in the cache.
+    pub used: u64,
+    /// Maximum bytes of the blocks in the cache.
+    pub capacity: u64,
+}
+
+#[derive(Debug, Default)]
+struct CachedPiece {
+    blocks: Vec<Block>,
+    /// If blocks of this piece were written to disk before it was complete.
+    flushed: bool,
+    /// When the piece last received a block, pieces that are not receiving
+    /// blocks are flushed first.
+    last_write: u64,
+}
+
+/// Blocks of the incomplete pieces of all torrents, with a memory budget.
+#[derive(Debug)]
+pub struct WriteCache {
+    capacity: u64,
+    used: u64,
+    /// Incremented on every write, to order the pieces by their last write.
+    clock: u64,
+    /// k: (info_hash, piece)
+    pieces: HashMap<([u8; 20], usize), CachedPiece>,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl Default for WriteCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_SIZE)
+    }
+}
+
+impl WriteCache {
+    /// A cache that uses at most `capacity` bytes for blocks.
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            used: 0,
+            clock: 0,
+            pieces: HashMap::new(),
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Add a block, a block that is already in the cache is replaced.
+    pub fn insert(&mut self, info_hash: [u8; 20], block: Block) {
+        self.clock += 1;
+        let piece = self.pieces.entry((info_hash, block.index)).or_default();
+        piece.last_write = self.clock;
+        self.used += block.block.len() as u64;
+
+        match piece.blocks.iter_mut().find(|b| b.begin == block.begin) {
+            Some(b) => {
+                self.used -= b.block.len() as u64;
+                *b = block;
+            }
+            None => piece.blocks.push(block),
+        }
+    }
+
+    /// If the blocks use more memory than the capacity.
+    pub fn is_full(&self) -> bool {
+        self.used > self.capacity
+    }
+
+    /// Remove the blocks of the piece that least recently received a block,
+    /// which must be written to disk by the caller.
+    pub fn evict(&mut self) -> Option<([u8; 20], Vec<Block>)> {
+        let (key, piece) = self
+            .pieces
+            .iter_mut()
+            .filter(|(_, p)| !p.blocks.is_empty())
+            .min_by_key(|(_, p)| p.last_write)?;
+
+        let blocks = std::mem::take(&mut piece.blocks);
+        piece.flushed = true;
+        self.used -= blocks.iter().map(|b| b.block.len() as u64).sum::<u64>();
+        self.evictions += 1;
+
+        Some((key.0, blocks))
+    }
+
+    /// Remove the blocks of a complete piece, sorted by their offset.
+    ///
+    /// The bool is false if blocks of the piece were flushed before, in that
+    /// case, the piece must be read from disk after the blocks are written.
+    pub fn take(
+        &mut self,
+        info_hash: [u8; 20],
+        piece: usize,
+    ) -> (Vec<Block>, bool) {
+        let Some(mut cached) = self.pieces.remove(&(info_hash, piece)) else {
+            self.misses += 1;
+            return (Vec::new(), false);
+        };
+
+        self.used -=
+            cached.blocks.iter().map(|b| b.block.len() as u64).sum::<u64>();
+        cached.blocks.sort();
+
+        if cached.flushed {
+            self.misses += 1;
+        } else {
+            self.hits += 1;
+        }
+
+        (cached.blocks, !cached.flushed)
+    }
+
+    /// The bytes of `block_info`, if they are in a single block of the cache.
+    pub fn get(
+        &mut self,
+        info_hash: [u8; 20],
+        block_info: &BlockInfo,
+    ) -> Option<Vec<u8>> {
+        let begin = block_info.begin as usize;
+        let end = begin + block_info.len as usize;
+
+        let bytes = self
+            .pieces
+            .get(&(info_hash, block_info.index as usize))
+            .and_then(|piece| {
+                piece.blocks.iter().find_map(|b| {
+                    let b_begin = b.begin as usize;
+                    let b_end = b_begin + b.block.len();
+                    (b_begin <= begin && end <= b_end).then(|| {
+                        b.block[begin - b_begin..end - b_begin].to_vec()
+                    })
+                })
+            });
+
+        match bytes {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+
+        bytes
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            used: self.used,
+            capacity: self.capacity,
+        }
+    }
+}
+
+/// Pieces read from disk, the least recently used pieces are removed when
+/// the cache is full.
+#[derive(Debug)]
+pub struct ReadCache {
+    capacity: u64,
+    used: u64,
+    /// Incremented on every access, to order the pieces by their last use.
+    clock: u64,
+    /// k: (info_hash, piece), v: (bytes, last use)
+    pieces: HashMap<([u8; 20], usize), (Vec<u8>, u64)>,
+    /// The pieces ordered by their last use.
+    lru: BTreeMap<u64, ([u8; 20], usize)>,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl Default for ReadCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_SIZE)
+    }
+}
+
+impl ReadCache {
+    /// A cache that uses at most `capacity` bytes for pieces.
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            used: 0,
+            clock: 0,
+            pieces: HashMap::new(),
+            lru: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Add the bytes of an entire piece, removing the least recently used
+    /// pieces until it fits. Pieces larger than the capacity are not added.
+    pub fn insert(
+        &mut self,
+        info_hash: [u8; 20],
+        piece: usize,
+        bytes: Vec<u8>,
+    ) {
+        self.remove(info_hash, piece);
+
+        if bytes.len() as u64 > self.capacity {
+            return;
+        }
+
+        while self.used + bytes.len() as u64 > self.capacity {
+            let Some((_, (info_hash, piece))) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some((bytes, _)) = self.pieces.remove(&(info_hash, piece)) {
+                self.used -= bytes.len() as u64;
+                self.evictions += 1;
+            }
+        }
+
+        self.clock += 1;
+        self.used += bytes.len() as u64;
+        self.lru.insert(self.clock, (info_hash, piece));
+        self.pieces.insert((info_hash, piece), (bytes, self.clock));
+    }
+
+    /// Remove a piece, when its bytes on disk change.
+    pub fn remove(&mut self, info_hash: [u8; 20], piece: usize) {
+        if let Some((bytes, last_use)) = self.pieces.remove(&(info_hash, piece))
+        {
+            self.used -= bytes.len() as u64;
+            self.lru.remove(&last_use);
+        }
+    }
+
+    /// The bytes of `block_info`, if its piece is in the cache.
+    pub fn get(
+        &mut self,
+        info_hash: [u8; 20],
+        block_info: &BlockInfo,
+    ) -> Option<Vec<u8>> {
+        let key = (info_hash, block_info.index as usize);
+        let begin = block_info.begin as usize;
+        let end = begin + block_info.len as usize;
+
+        let Some((bytes, last_use)) = self.pieces.get_mut(&key) else {
+            self.misses += 1;
+            return None;
+        };
+        let Some(bytes) = bytes.get(begin..end) else {
+            self.misses += 1;
+            return None;
+        };
+
+        self.clock += 1;
+        self.lru.remove(last_use);
+        self.lru.insert(self.clock, key);
+        *last_use = self.clock;
+        self.hits += 1;
+
+        Some(bytes.to_vec())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            used: self.used,
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(index: usize, begin: u32, len: usize) -> Block {
+        Block { index, begin, block: vec![begin as u8; len] }
+    }
+
+    #[test]
+    fn evict_least_recently_written() {
+        let mut cache = WriteCache::new(12);
+
+        cache.insert([0; 20], block(0, 0, 4));
+        cache.insert([1; 20], block(0, 0, 4));
+        cache.insert([0; 20], block(0, 4, 4));
+
+        // duplicated blocks are replaced
+        cache.insert([0; 20], block(0, 4, 4));
+        assert_eq!(cache.stats().used, 12);
+        assert!(!cache.is_full());
+
+        cache.insert([0; 20], block(0, 8, 4));
+        assert!(cache.is_full());
+
+        // the piece of the torrent 1 did not receive blocks for longer
+        let (info_hash, blocks) = cache.evict().unwrap();
+        assert_eq!(info_hash, [1; 20]);
+        assert_eq!(blocks, vec![block(0, 0, 4)]);
+        assert!(!cache.is_full());
+        assert_eq!(cache.stats().evictions, 1);
+
+        // the flushed piece must be hashed from disk
+        cache.insert([1; 20], block(0, 4, 4));
+        assert_eq!(cache.take([1; 20], 0), (vec![block(0, 4, 4)], false));
+        assert_eq!(
+            cache.take([0; 20], 0),
+            (vec![block(0, 0, 4), block(0, 4, 4), block(0, 8, 4)], true)
+        );
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.used), (1, 1, 0));
+        assert_eq!(cache.evict(), None);
+    }
+
+    #[test]
+    fn get_bytes_of_blocks() {
+        let mut cache = WriteCache::default();
+        cache.insert([0; 20], block(3, 8, 4));
+
+        let info = BlockInfo { index: 3, begin: 9, len: 2 };
+        assert_eq!(cache.get([0; 20], &info), Some(vec![8, 8]));
+
+        let info = BlockInfo { index: 3, begin: 10, len: 4 };
+        assert_eq!(cache.get([0; 20], &info), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+    }
+
+    #[test]
+    fn read_least_recently_used() {
+        let mut cache = ReadCache::new(8);
+        let info = |index| BlockInfo { index, begin: 1, len: 2 };
+
+        cache.insert([0; 20], 0, vec![0, 1, 2, 3]);
+        cache.insert([0; 20], 1, vec![4, 5, 6, 7]);
+        assert_eq!(cache.get([0; 20], &info(0)), Some(vec![1, 2]));
+
+        // the piece 1 was used less recently than the piece 0
+        cache.insert([1; 20], 0, vec![8, 9, 10, 11]);
+        assert_eq!(cache.get([0; 20], &info(1)), None);
+        assert_eq!(cache.get([0; 20], &info(0)), Some(vec![1, 2]));
+        assert_eq!(cache.get([1; 20], &info(0)), Some(vec![9, 10]));
+
+        // blocks out of the piece are not in the cache
+        let outside = BlockInfo { index: 0, begin: 3, len: 2 };
+        assert_eq!(cache.get([0; 20], &outside), None);
+
+        // pieces larger than the cache are never added
+        cache.insert([2; 20], 0, vec![0; 9]);
+        assert_eq!(cache.get([2; 20], &info(0)), None);
+
+        cache.remove([0; 20], 0);
+        let stats = cache.stats();
+        assert_eq!((stats.used, stats.evictions), (4, 1));
+        assert_eq!((stats.hits, stats.misses), (3, 3));
+    }
+}
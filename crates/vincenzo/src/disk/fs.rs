@@ -0,0 +1,811 @@
+//! Storage of torrents in files, under the download directory.
+
+use std::{
+    ffi::{CString, OsString},
+    future::Future,
+    io::SeekFrom,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, PermissionsExt},
+        io::AsRawFd,
+    },
+    path::{Path, PathBuf},
+};
+
+use hashbrown::HashMap;
+use tokio::{
+    fs::{
+        create_dir_all, metadata, remove_dir_all, remove_file, rename,
+        set_permissions, symlink, symlink_metadata, File, OpenOptions,
+    },
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tracing::debug;
+
+use crate::error::Error;
+
+use super::{
+    pool::FilePool,
+    storage::{Layout, Preallocation, Storage, StorageFile},
+};
+
+/// A torrent that is stored in files.
+#[derive(Debug, Clone)]
+struct FsTorrent {
+    /// The root directory of the torrent, or the file itself on single file
+    /// torrents.
+    base: PathBuf,
+    /// Where the torrent is moved when it is complete, the download dir,
+    /// unless it was moved somewhere else.
+    dir: PathBuf,
+    /// If the files have the `.part` suffix, until the torrent is complete.
+    part: bool,
+    layout: Layout,
+}
+
+impl FsTorrent {
+    /// Path of a file of the torrent, with the `.part` suffix while it is
+    /// being downloaded.
+    fn file_path(&self, file: &StorageFile) -> PathBuf {
+        let mut path = FsStorage::file_path(&self.base, file);
+        if self.part {
+            path.as_mut_os_string().push(".part");
+        }
+        path
+    }
+}
+
+/// Stores the torrents in their files, in "download_dir/name_of_torrent".
+///
+/// If there is an `incomplete_dir`, the torrents are downloaded there, and
+/// moved to the `download_dir` when they are complete.
+#[derive(Debug)]
+pub struct FsStorage {
+    pub download_dir: String,
+    /// Where the torrents are while they are being downloaded.
+    incomplete_dir: Option<String>,
+    /// If the files have the `.part` suffix while they are being downloaded.
+    part_suffix: bool,
+    /// Files that are kept open to read and write blocks.
+    files: FilePool,
+    /// How the files of the torrents are allocated.
+    preallocation: Preallocation,
+    /// Torrents that are allocated differently than `preallocation`.
+    /// k: info_hash
+    torrent_preallocation: HashMap<[u8; 20], Preallocation>,
+    /// Torrents that are saved in another directory than `download_dir`.
+    /// k: info_hash
+    torrent_dirs: HashMap<[u8; 20], PathBuf>,
+    /// k: info_hash
+    torrents: HashMap<[u8; 20], FsTorrent>,
+}
+
+impl FsStorage {
+    pub fn new(download_dir: String) -> Self {
+        Self {
+            download_dir,
+            incomplete_dir: None,
+            part_suffix: false,
+            files: FilePool::default(),
+            preallocation: Preallocation::default(),
+            torrent_preallocation: HashMap::new(),
+            torrent_dirs: HashMap::new(),
+            torrents: HashMap::new(),
+        }
+    }
+
+    /// Download the torrents in `dir`, and move them to the download dir when
+    /// they are complete.
+    pub fn incomplete_dir(mut self, dir: Option<String>) -> Self {
+        self.incomplete_dir = dir;
+        self
+    }
+
+    /// Add the `.part` suffix to the files while they are being downloaded.
+    pub fn part_suffix(mut self, part_suffix: bool) -> Self {
+        self.part_suffix = part_suffix;
+        self
+    }
+
+    /// Set how the files of the torrents are allocated.
+    pub fn preallocation(mut self, preallocation: Preallocation) -> Self {
+        self.preallocation = preallocation;
+        self
+    }
+
+    /// Set the maximum of files that are kept open.
+    pub fn max_open_files(mut self, max: usize) -> Self {
+        self.files = FilePool::new(max);
+        self
+    }
+
+    /// How many files are open.
+    pub fn open_files(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Open a file given a path, the path is absolute
+    /// and does not consider the base path of the torrent,
+    /// if this behaviour is wanted, you can get the base path
+    /// of the torrent using `base_path`.
+    pub async fn open_file(path: impl AsRef<Path>) -> Result<File, Error> {
+        let path = path.as_ref().to_owned();
+
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .await
+            .map_err(|_| {
+                Error::FileOpenError(path.to_str().unwrap().to_owned())
+            })
+    }
+
+    /// Bytes that are free on the filesystem of `path`, for unprivileged
+    /// users.
+    pub fn free_space(path: &Path) -> Result<u64, Error> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| Error::FolderOpenError(path.display().to_string()))?;
+
+        // SAFETY: the path is a valid C string, and `stat` is only read
+        // after statvfs succeeds.
+        unsafe {
+            let mut stat: libc::statvfs = std::mem::zeroed();
+            if libc::statvfs(c_path.as_ptr(), &mut stat) != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+        }
+    }
+
+    /// Allocate the file up to `length`, it is never truncated.
+    async fn preallocate(
+        file: File,
+        length: u64,
+        preallocation: Preallocation,
+    ) -> Result<(), Error> {
+        match preallocation {
+            Preallocation::None => {}
+            Preallocation::Sparse => {
+                if file.metadata().await?.len() < length {
+                    file.set_len(length).await?;
+                }
+            }
+            Preallocation::Full if length > 0 => {
+                let file = file.into_std().await;
+
+                tokio::task::spawn_blocking(move || {
+                    // SAFETY: the file descriptor is open until the end of
+                    // the closure.
+                    let r = unsafe {
+                        libc::posix_fallocate(
+                            file.as_raw_fd(),
+                            0,
+                            length as libc::off_t,
+                        )
+                    };
+                    match r {
+                        0 => Ok(()),
+                        e => Err(std::io::Error::from_raw_os_error(e)),
+                    }
+                })
+                .await
+                .map_err(std::io::Error::other)??;
+            }
+            Preallocation::Full => {}
+        }
+        Ok(())
+    }
+
+    fn file_path(base: &Path, file: &StorageFile) -> PathBuf {
+        let mut path = base.to_owned();
+        path.extend(&file.path);
+        path
+    }
+
+    /// Where the files of a torrent are. A torrent that already has files in
+    /// its final directory was completed before, and it stays there.
+    async fn locate(&self, info_hash: [u8; 20], layout: &Layout) -> FsTorrent {
+        let dir = match self.torrent_dirs.get(&info_hash) {
+            Some(dir) => dir.clone(),
+            None => PathBuf::from(&self.download_dir),
+        };
+        let complete = FsTorrent {
+            base: dir.join(&layout.name),
+            dir,
+            part: false,
+            layout: layout.clone(),
+        };
+
+        if self.incomplete_dir.is_none() && !self.part_suffix {
+            return complete;
+        }
+
+        for file in &layout.files {
+            if file.padding || file.symlink.is_some() {
+                continue;
+            }
+            if symlink_metadata(complete.file_path(file)).await.is_ok() {
+                return complete;
+            }
+        }
+
+        let incomplete_dir = match &self.incomplete_dir {
+            Some(dir) => Path::new(dir),
+            None => &complete.dir,
+        };
+
+        FsTorrent {
+            base: incomplete_dir.join(&layout.name),
+            dir: complete.dir.clone(),
+            part: self.part_suffix,
+            layout: layout.clone(),
+        }
+    }
+
+    /// Move a file or directory, when `to` is on another filesystem, it is
+    /// copied and then deleted, and `progress` is called with the bytes that
+    /// were copied so far.
+    ///
+    /// The copy is made with a temporary name, next to `to`, and renamed
+    /// when it is done, so that `to` never has a partial copy.
+    pub async fn move_path(
+        from: &Path,
+        to: &Path,
+        progress: impl Fn(u64) + Send + 'static,
+    ) -> Result<(), Error> {
+        match rename(from, to).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+                debug!("{from:?} and {to:?} are on different filesystems");
+                let (from, to) = (from.to_owned(), to.to_owned());
+
+                let mut name = OsString::from(".");
+                name.push(to.file_name().unwrap_or_default());
+                name.push(".tmp");
+                let tmp = to.with_file_name(name);
+
+                tokio::task::spawn_blocking(move || {
+                    // a copy of a move that was interrupted before
+                    let _ = Self::remove_path(&tmp);
+
+                    if let Err(e) =
+                        Self::copy_path(&from, &tmp, &mut 0, &progress)
+                    {
+                        let _ = Self::remove_path(&tmp);
+                        return Err(e);
+                    }
+                    std::fs::rename(&tmp, &to)?;
+                    Self::remove_path(&from)
+                })
+                .await
+                .map_err(std::io::Error::other)??;
+
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Remove a file, directory or symlink, and everything under it.
+    fn remove_path(path: &Path) -> std::io::Result<()> {
+        match std::fs::symlink_metadata(path)?.is_dir() {
+            true => std::fs::remove_dir_all(path),
+            false => std::fs::remove_file(path),
+        }
+    }
+
+    /// Copy a file, directory or symlink, and everything under it. `copied`
+    /// is incremented with the bytes of each file.
+    fn copy_path(
+        from: &Path,
+        to: &Path,
+        copied: &mut u64,
+        progress: &impl Fn(u64),
+    ) -> std::io::Result<()> {
+        let file_type = std::fs::symlink_metadata(from)?.file_type();
+
+        if file_type.is_dir() {
+            std::fs::create_dir_all(to)?;
+            for entry in std::fs::read_dir(from)? {
+                let entry = entry?;
+                let to = to.join(entry.file_name());
+                Self::copy_path(&entry.path(), &to, copied, progress)?;
+            }
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(from)?, to)?;
+        } else {
+            *copied += std::fs::copy(from, to)?;
+            progress(*copied);
+        }
+
+        Ok(())
+    }
+
+    /// Complete the files of a torrent, see [`Storage::complete`].
+    async fn complete_files(
+        mut torrent: FsTorrent,
+        to: PathBuf,
+    ) -> Result<(), Error> {
+        if torrent.part {
+            for file in &torrent.layout.files {
+                if file.padding || file.symlink.is_some() {
+                    continue;
+                }
+                let from = torrent.file_path(file);
+                if metadata(&from).await.is_ok() {
+                    rename(from, Self::file_path(&torrent.base, file)).await?;
+                }
+            }
+            torrent.part = false;
+        }
+
+        if torrent.base != to {
+            debug!("moving {:?} to {to:?}", torrent.base);
+            create_dir_all(&torrent.dir).await?;
+            Self::move_path(&torrent.base, &to, |_| {}).await?;
+            torrent.base = to;
+        }
+
+        for file in &torrent.layout.files {
+            let path = torrent.file_path(file);
+
+            if file.executable && file.symlink.is_none() {
+                let mut permissions = metadata(&path).await?.permissions();
+                permissions.set_mode(permissions.mode() | 0o111);
+                set_permissions(&path, permissions).await?;
+            }
+
+            if let Some(target) = &file.symlink {
+                // the target is relative to the root of the torrent
+                let mut link = PathBuf::new();
+                for _ in 1..file.path.len() {
+                    link.push("..");
+                }
+                link.extend(target);
+
+                if let Some(parent) = path.parent() {
+                    create_dir_all(parent).await?;
+                }
+                if symlink_metadata(&path).await.is_err() {
+                    symlink(link, &path).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn write_file(
+        file: &mut File,
+        offset: u64,
+        bytes: &[u8],
+    ) -> std::io::Result<()> {
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(bytes).await?;
+        // wait for the write, the file is not closed after it
+        file.flush().await
+    }
+
+    async fn read_file(
+        file: &mut File,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> std::io::Result<()> {
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        let mut read = 0;
+        while read < buf.len() {
+            match file.read(&mut buf[read..]).await? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Storage for FsStorage {
+    async fn create(
+        &mut self,
+        info_hash: [u8; 20],
+        layout: &Layout,
+    ) -> Result<(), Error> {
+        let torrent = self.locate(info_hash, layout).await;
+        let preallocation = self
+            .torrent_preallocation
+            .get(&info_hash)
+            .copied()
+            .unwrap_or(self.preallocation);
+
+        // create "skeleton" of the torrent, empty files and directories
+        for file in &layout.files {
+            // padding files are only zeros that are never written, and
+            // symlinks are created after the download is complete.
+            if file.padding || file.symlink.is_some() {
+                continue;
+            }
+
+            let path = torrent.file_path(file);
+
+            if let Some(dir) = path.parent() {
+                create_dir_all(dir).await?;
+            }
+            let fs_file = Self::open_file(path).await?;
+            Self::preallocate(fs_file, file.length, preallocation).await?;
+        }
+
+        self.torrents.insert(info_hash, torrent);
+
+        Ok(())
+    }
+
+    fn set_preallocation(
+        &mut self,
+        info_hash: [u8; 20],
+        preallocation: Preallocation,
+    ) {
+        self.torrent_preallocation.insert(info_hash, preallocation);
+    }
+
+    fn set_dir(&mut self, info_hash: [u8; 20], dir: &Path) {
+        self.torrent_dirs.insert(info_hash, dir.to_owned());
+    }
+
+    /// The files that were already allocated, of a torrent that was
+    /// downloaded before, do not need more space.
+    async fn check_space(
+        &mut self,
+        info_hash: [u8; 20],
+        layout: &Layout,
+    ) -> Result<(), Error> {
+        let torrent = self.locate(info_hash, layout).await;
+        let dir = torrent.base.parent().unwrap_or(&torrent.base);
+        create_dir_all(dir).await?;
+        let free = Self::free_space(dir)?;
+
+        let mut needed = 0;
+        for file in &layout.files {
+            if file.padding || file.symlink.is_some() {
+                continue;
+            }
+            let allocated = match metadata(torrent.file_path(file)).await {
+                Ok(m) => m.blocks() * 512,
+                Err(_) => 0,
+            };
+            needed += file.length.saturating_sub(allocated);
+        }
+
+        if needed > free {
+            return Err(Error::NoSpace(needed, free));
+        }
+
+        Ok(())
+    }
+
+    async fn read(
+        &mut self,
+        info_hash: [u8; 20],
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let torrent =
+            self.torrents.get(&info_hash).ok_or(Error::TorrentDoesNotExist)?;
+        let mut buf = vec![0; len];
+
+        for (file, file_offset, range) in torrent.layout.spans(offset, len) {
+            let path = torrent.file_path(file);
+            let buf = &mut buf[range];
+            let file = self.files.get(info_hash, &path).await?;
+
+            // the file may have been moved or deleted while it was open
+            if let Err(e) = Self::read_file(file, file_offset, buf).await {
+                debug!("could not read from {path:?}: {e}, opening it again");
+                self.files.close(&path);
+                let file = self.files.get(info_hash, &path).await?;
+                Self::read_file(file, file_offset, buf).await?;
+            }
+        }
+
+        Ok(buf)
+    }
+
+    async fn write(
+        &mut self,
+        info_hash: [u8; 20],
+        offset: u64,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        let torrent =
+            self.torrents.get(&info_hash).ok_or(Error::TorrentDoesNotExist)?;
+
+        for (file, file_offset, range) in
+            torrent.layout.spans(offset, bytes.len())
+        {
+            let path = torrent.file_path(file);
+            let bytes = &bytes[range];
+            let file = self.files.get(info_hash, &path).await?;
+
+            if let Err(e) = Self::write_file(file, file_offset, bytes).await {
+                debug!("could not write to {path:?}: {e}, opening it again");
+                self.files.close(&path);
+                let file = self.files.get(info_hash, &path).await?;
+                Self::write_file(file, file_offset, bytes).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move the torrent out of the incomplete dir and remove the `.part`
+    /// suffix of its files. Then, set the executable bit of files and create
+    /// the symlinks of the torrent, BEP 47.
+    fn complete(
+        &mut self,
+        info_hash: [u8; 20],
+    ) -> Result<impl Future<Output = Result<(), Error>> + Send + 'static, Error>
+    {
+        let torrent = self
+            .torrents
+            .get(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?
+            .clone();
+        let to = torrent.dir.join(&torrent.layout.name);
+
+        // the files are opened again, at their final location
+        if torrent.part || torrent.base != to {
+            self.files.close_torrent(info_hash);
+        }
+
+        Ok(Self::complete_files(torrent, to))
+    }
+
+    fn completed(&mut self, info_hash: [u8; 20]) {
+        let Some(torrent) = self.torrents.get_mut(&info_hash) else {
+            return;
+        };
+        torrent.part = false;
+        torrent.base = torrent.dir.join(&torrent.layout.name);
+    }
+
+    /// The root of the torrent, which is "download_dir/name_of_torrent",
+    /// unless it is incomplete or was moved.
+    fn base_path(&self, info_hash: [u8; 20]) -> Option<&Path> {
+        self.torrents.get(&info_hash).map(|t| t.base.as_path())
+    }
+
+    fn close(&mut self, info_hash: [u8; 20]) {
+        self.files.close_torrent(info_hash);
+    }
+
+    fn move_to(
+        &mut self,
+        info_hash: [u8; 20],
+        dir: &Path,
+        progress: impl Fn(u64) + Send + 'static,
+    ) -> Result<impl Future<Output = Result<(), Error>> + Send + 'static, Error>
+    {
+        let torrent =
+            self.torrents.get(&info_hash).ok_or(Error::TorrentDoesNotExist)?;
+        let from = torrent.base.clone();
+        let to = dir.join(&torrent.layout.name);
+        let dir = dir.to_owned();
+
+        // the files are opened again, at the new location
+        self.files.close_torrent(info_hash);
+
+        Ok(async move {
+            if to == from {
+                return Ok(());
+            }
+            // a rename would replace a file that is already there
+            if symlink_metadata(&to).await.is_ok() {
+                return Err(Error::PathExists(to.display().to_string()));
+            }
+            create_dir_all(dir).await?;
+            Self::move_path(&from, &to, progress).await
+        })
+    }
+
+    fn moved(&mut self, info_hash: [u8; 20], dir: &Path) {
+        let Some(torrent) = self.torrents.get_mut(&info_hash) else {
+            return;
+        };
+        let to = dir.join(&torrent.layout.name);
+
+        if to != torrent.base {
+            torrent.base = to;
+            // an incomplete torrent stays where it was moved to
+            torrent.dir = dir.to_owned();
+        }
+    }
+
+    async fn delete(&mut self, info_hash: [u8; 20]) -> Result<(), Error> {
+        self.files.close_torrent(info_hash);
+        let Some(torrent) = self.torrents.remove(&info_hash) else {
+            return Ok(());
+        };
+
+        match metadata(&torrent.base).await {
+            Ok(m) if m.is_dir() => remove_dir_all(&torrent.base).await?,
+            Ok(_) => remove_file(&torrent.base).await?,
+            // nothing was written yet
+            Err(_) => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn preallocate_files() {
+        let mut rng = rand::thread_rng();
+        let dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let dir = std::env::temp_dir().join(dir);
+
+        let file = |path: &str, offset, length| StorageFile {
+            path: vec![path.to_owned()],
+            offset,
+            length,
+            ..Default::default()
+        };
+        let layout = |name: &str| Layout {
+            name: name.to_owned(),
+            files: vec![file("a", 0, 100_000), file("b", 100_000, 0)],
+        };
+
+        let mut storage = FsStorage::new(dir.to_string_lossy().into_owned())
+            .preallocation(Preallocation::Sparse);
+        storage.set_preallocation([1; 20], Preallocation::Full);
+        storage.set_preallocation([2; 20], Preallocation::None);
+
+        storage.create([0; 20], &layout("sparse")).await.unwrap();
+        storage.create([1; 20], &layout("full")).await.unwrap();
+        storage.create([2; 20], &layout("none")).await.unwrap();
+
+        let sparse = std::fs::metadata(dir.join("sparse").join("a")).unwrap();
+        assert_eq!(sparse.len(), 100_000);
+
+        let full = std::fs::metadata(dir.join("full").join("a")).unwrap();
+        assert_eq!(full.len(), 100_000);
+        assert!(full.blocks() * 512 >= 100_000);
+        assert_eq!(
+            std::fs::metadata(dir.join("full").join("b")).unwrap().len(),
+            0
+        );
+
+        let none = std::fs::metadata(dir.join("none").join("a")).unwrap();
+        assert_eq!(none.len(), 0);
+
+        // the allocated files need no more space, but a torrent larger than
+        // any disk does
+        storage.check_space([1; 20], &layout("full")).await.unwrap();
+        let huge = Layout {
+            name: "huge".to_owned(),
+            files: vec![file("a", 0, u64::MAX / 2)],
+        };
+        assert!(matches!(
+            storage.check_space([3; 20], &huge).await,
+            Err(Error::NoSpace(needed, _)) if needed == u64::MAX / 2
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn move_to_download_dir_on_complete() {
+        let mut rng = rand::thread_rng();
+        let dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let dir = std::env::temp_dir().join(dir);
+        let download_dir = dir.join("complete");
+        let incomplete_dir = dir.join("incomplete");
+
+        let file = |path: &str, offset| StorageFile {
+            path: vec![path.to_owned()],
+            offset,
+            length: 3,
+            ..Default::default()
+        };
+        let layout = Layout {
+            name: "t".to_owned(),
+            files: vec![file("a", 0), file("b", 3)],
+        };
+
+        let storage = || {
+            FsStorage::new(download_dir.to_string_lossy().into_owned())
+                .incomplete_dir(Some(
+                    incomplete_dir.to_string_lossy().into_owned(),
+                ))
+                .part_suffix(true)
+        };
+        let mut storage_a = storage();
+
+        storage_a.create([0; 20], &layout).await.unwrap();
+        storage_a.write([0; 20], 0, b"abcdef").await.unwrap();
+
+        assert!(incomplete_dir.join("t").join("a.part").is_file());
+        assert!(incomplete_dir.join("t").join("b.part").is_file());
+        assert!(!download_dir.join("t").exists());
+
+        storage_a.complete([0; 20]).unwrap().await.unwrap();
+        storage_a.completed([0; 20]);
+
+        assert!(!incomplete_dir.join("t").exists());
+        assert_eq!(
+            std::fs::read(download_dir.join("t").join("a")).unwrap(),
+            b"abc"
+        );
+        assert_eq!(
+            std::fs::read(download_dir.join("t").join("b")).unwrap(),
+            b"def"
+        );
+        assert_eq!(
+            storage_a.base_path([0; 20]),
+            Some(download_dir.join("t").as_path())
+        );
+
+        // the torrent is seeded from the download dir
+        assert_eq!(storage_a.read([0; 20], 2, 2).await.unwrap(), b"cd");
+
+        // and it stays there when it is added again
+        let mut storage_b = storage();
+        storage_b.create([0; 20], &layout).await.unwrap();
+        assert_eq!(
+            storage_b.base_path([0; 20]),
+            Some(download_dir.join("t").as_path())
+        );
+        assert!(!incomplete_dir.join("t").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn save_in_the_dir_of_the_torrent() {
+        let mut rng = rand::thread_rng();
+        let dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let dir = std::env::temp_dir().join(dir);
+
+        let layout = Layout {
+            name: "t".to_owned(),
+            files: vec![StorageFile {
+                path: vec!["a".to_owned()],
+                length: 3,
+                ..Default::default()
+            }],
+        };
+
+        let mut storage = FsStorage::new(
+            dir.join("downloads").to_string_lossy().into_owned(),
+        );
+        storage.set_dir([1; 20], &dir.join("other"));
+
+        storage.create([0; 20], &layout).await.unwrap();
+        storage.create([1; 20], &layout).await.unwrap();
+        storage.write([1; 20], 0, b"abc").await.unwrap();
+
+        assert_eq!(
+            storage.base_path([0; 20]),
+            Some(dir.join("downloads").join("t").as_path())
+        );
+        assert_eq!(
+            storage.base_path([1; 20]),
+            Some(dir.join("other").join("t").as_path())
+        );
+        assert_eq!(
+            std::fs::read(dir.join("other").join("t").join("a")).unwrap(),
+            b"abc"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
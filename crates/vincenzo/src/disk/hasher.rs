@@ -0,0 +1,226 @@
+//! Pool of threads that hash the pieces of the torrents.
+//!
+//! Hashing a piece of a few MiB takes a while, and the event loop of the
+//! [`Disk`](super::Disk) can't read or write blocks in the meantime. The
+//! pieces are sent to the workers of a [`Hasher`], which send the results back
+//! to the disk over a channel.
+
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use tokio::sync::{mpsc::Sender, oneshot};
+use tracing::debug;
+
+use crate::{error::Error, merkle};
+
+/// What the bytes of a piece must hash to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PieceCheck {
+    /// The hash of the piece on Info.pieces, v2 torrents do not have it.
+    pub sha1: Option<[u8; 20]>,
+    /// The merkle tree of the file of the piece, on hybrid and v2 torrents.
+    pub v2: Option<V2Check>,
+}
+
+/// How a piece of a hybrid or v2 torrent is verified with the merkle tree of its
+/// file, only the first `len` bytes of the piece are in the file, the rest is
+/// padding.
+#[derive(Debug, Clone, PartialEq)]
+pub enum V2Check {
+    /// Files that are not larger than a piece are verified with the root of
+    /// their tree.
+    FileRoot { root: [u8; 32], len: usize },
+    /// Pieces of larger files are verified with the hash of the piece, from
+    /// the piece layers.
+    Piece { hash: [u8; 32], len: usize, piece_length: u32 },
+    /// The hash of the piece is not on the piece layers yet, so the piece
+    /// can't be verified.
+    Unknown,
+}
+
+impl PieceCheck {
+    /// Verify the `bytes` of a piece, a piece without any hash to verify it
+    /// against is not valid.
+    pub fn verify(&self, bytes: &[u8]) -> Result<(), Error> {
+        if let Some(sha1) = self.sha1 {
+            if sha1_smol::Sha1::from(bytes).digest().bytes() != sha1 {
+                return Err(Error::PieceInvalid);
+            }
+        }
+
+        let valid = match &self.v2 {
+            None => self.sha1.is_some(),
+            Some(V2Check::Unknown) => false,
+            Some(V2Check::FileRoot { root, len }) => {
+                let data = &bytes[..(*len).min(bytes.len())];
+                merkle::file_root(data) == Some(*root)
+            }
+            Some(V2Check::Piece { hash, len, piece_length }) => {
+                let data = &bytes[..(*len).min(bytes.len())];
+                merkle::piece_hash(data, *piece_length) == *hash
+            }
+        };
+
+        if !valid {
+            return Err(Error::PieceInvalid);
+        }
+
+        Ok(())
+    }
+}
+
+/// A piece that was hashed by a worker.
+#[derive(Debug)]
+pub struct Hashed {
+    pub info_hash: [u8; 20],
+    pub index: usize,
+    pub result: Result<(), Error>,
+    /// Who asked to validate the piece, the result of pieces that were
+    /// downloaded goes to the torrent instead.
+    pub recipient: Option<oneshot::Sender<Result<(), Error>>>,
+}
+
+#[derive(Debug)]
+struct Job {
+    info_hash: [u8; 20],
+    index: usize,
+    bytes: Vec<u8>,
+    check: PieceCheck,
+    recipient: Option<oneshot::Sender<Result<(), Error>>>,
+}
+
+/// Workers that hash pieces in their own threads, the threads stop when the
+/// hasher is dropped.
+#[derive(Debug)]
+pub struct Hasher {
+    threads: usize,
+    jobs: mpsc::Sender<Job>,
+    results: Sender<Hashed>,
+}
+
+impl Hasher {
+    /// Spawn `threads` workers, or one per CPU, that send the pieces they
+    /// hashed to `results`.
+    pub fn new(threads: Option<usize>, results: Sender<Hashed>) -> Self {
+        let threads = threads
+            .or_else(|| thread::available_parallelism().ok().map(|v| v.get()))
+            .unwrap_or(1)
+            .max(1);
+
+        let (jobs, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for i in 0..threads {
+            let rx = rx.clone();
+            let results = results.clone();
+
+            thread::Builder::new()
+                .name(format!("hasher-{i}"))
+                .spawn(move || loop {
+                    // the lock is only held while waiting for a job
+                    let Ok(job) = rx.lock().unwrap().recv() else { break };
+
+                    let hashed = Hashed {
+                        info_hash: job.info_hash,
+                        index: job.index,
+                        result: job.check.verify(&job.bytes),
+                        recipient: job.recipient,
+                    };
+
+                    // the disk was dropped
+                    if results.blocking_send(hashed).is_err() {
+                        break;
+                    }
+                })
+                .expect("failed to spawn a hashing thread");
+        }
+
+        debug!("spawned {threads} hashing threads");
+
+        Self { threads, jobs, results }
+    }
+
+    /// The same hasher with a different number of threads.
+    pub fn threads(self, threads: Option<usize>) -> Self {
+        Self::new(threads, self.results)
+    }
+
+    /// How many pieces can wait to be hashed, before the disk waits for the
+    /// workers.
+    pub fn max_pending(&self) -> usize {
+        self.threads * 2
+    }
+
+    /// Send a piece to be verified by one of the workers.
+    pub fn hash(
+        &self,
+        info_hash: [u8; 20],
+        index: usize,
+        bytes: Vec<u8>,
+        check: PieceCheck,
+        recipient: Option<oneshot::Sender<Result<(), Error>>>,
+    ) {
+        let job = Job { info_hash, index, bytes, check, recipient };
+        // the workers only stop after the hasher is dropped
+        let _ = self.jobs.send(job);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn hash_in_workers() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let hasher = Hasher::new(Some(2), tx);
+
+        let piece = vec![7; 100];
+        let check = PieceCheck {
+            sha1: Some(sha1_smol::Sha1::from(&piece).digest().bytes()),
+            v2: None,
+        };
+
+        hasher.hash([0; 20], 0, piece.clone(), check.clone(), None);
+        hasher.hash([0; 20], 1, vec![8; 100], check.clone(), None);
+
+        let (otx, orx) = oneshot::channel();
+        hasher.hash([1; 20], 2, piece, check, Some(otx));
+
+        let mut results = Vec::new();
+        for _ in 0..3 {
+            let hashed = rx.recv().await.unwrap();
+            results.push((hashed.index, hashed.result.is_ok()));
+            if let Some(recipient) = hashed.recipient {
+                recipient.send(hashed.result).unwrap();
+            }
+        }
+        results.sort();
+
+        assert_eq!(results, [(0, true), (1, false), (2, true)]);
+        assert!(orx.await.unwrap().is_ok());
+
+        // the threads stop with the hasher
+        drop(hasher);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[test]
+    fn verify_v2_pieces() {
+        let piece = vec![7; 100];
+        let root = merkle::file_root(&piece).unwrap();
+        let check = |v2| PieceCheck { sha1: None, v2 };
+
+        let v2 = V2Check::FileRoot { root, len: 100 };
+        assert!(check(Some(v2.clone())).verify(&piece).is_ok());
+        assert!(check(Some(v2)).verify(&[8; 100]).is_err());
+
+        // a piece without any hash, or with an unknown one, is not valid
+        assert!(check(Some(V2Check::Unknown)).verify(&piece).is_err());
+        assert!(check(None).verify(&piece).is_err());
+    }
+}
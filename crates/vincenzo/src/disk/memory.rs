@@ -0,0 +1,135 @@
+//! Storage of torrents in memory, which is lost when the program exits.
+
+use std::{future::Future, path::Path};
+
+use hashbrown::HashMap;
+
+use crate::error::Error;
+
+use super::storage::{Layout, Storage};
+
+/// Stores the bytes of each torrent in a buffer, which grows as the pieces
+/// are written.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    /// k: info_hash
+    torrents: HashMap<[u8; 20], (Layout, Vec<u8>)>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The bytes of a torrent that were written, padding files are zeros.
+    pub fn bytes(&self, info_hash: [u8; 20]) -> Option<&[u8]> {
+        self.torrents.get(&info_hash).map(|(_, bytes)| bytes.as_slice())
+    }
+}
+
+impl Storage for MemoryStorage {
+    async fn create(
+        &mut self,
+        info_hash: [u8; 20],
+        layout: &Layout,
+    ) -> Result<(), Error> {
+        self.torrents.insert(info_hash, (layout.clone(), Vec::new()));
+        Ok(())
+    }
+
+    async fn read(
+        &mut self,
+        info_hash: [u8; 20],
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let (_, bytes) =
+            self.torrents.get(&info_hash).ok_or(Error::TorrentDoesNotExist)?;
+        let mut buf = vec![0; len];
+
+        let start = (offset as usize).min(bytes.len());
+        let end = (offset as usize + len).min(bytes.len());
+        buf[..end - start].copy_from_slice(&bytes[start..end]);
+
+        Ok(buf)
+    }
+
+    async fn write(
+        &mut self,
+        info_hash: [u8; 20],
+        offset: u64,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        let (layout, buf) = self
+            .torrents
+            .get_mut(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?;
+
+        for (_, _, range) in layout.spans(offset, bytes.len()) {
+            let start = offset as usize + range.start;
+            let end = offset as usize + range.end;
+
+            if buf.len() < end {
+                buf.resize(end, 0);
+            }
+            buf[start..end].copy_from_slice(&bytes[range]);
+        }
+
+        Ok(())
+    }
+
+    fn move_to(
+        &mut self,
+        info_hash: [u8; 20],
+        _dir: &Path,
+        _progress: impl Fn(u64) + Send + 'static,
+    ) -> Result<impl Future<Output = Result<(), Error>> + Send + 'static, Error>
+    {
+        if !self.torrents.contains_key(&info_hash) {
+            return Err(Error::TorrentDoesNotExist);
+        }
+        Ok(async { Ok(()) })
+    }
+
+    async fn delete(&mut self, info_hash: [u8; 20]) -> Result<(), Error> {
+        self.torrents.remove(&info_hash);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::storage::StorageFile;
+
+    #[tokio::test]
+    async fn padding_is_not_written() {
+        let file = |offset: u64, length, padding| StorageFile {
+            path: vec![offset.to_string()],
+            offset,
+            length,
+            padding,
+            ..Default::default()
+        };
+        let layout = Layout {
+            name: "memory".to_owned(),
+            files: vec![file(0, 3, false), file(3, 2, true), file(5, 3, false)],
+        };
+
+        let mut storage = MemoryStorage::new();
+        storage.create([0; 20], &layout).await.unwrap();
+
+        storage.write([0; 20], 1, &[1, 2, 3, 4, 5, 6]).await.unwrap();
+        assert_eq!(storage.bytes([0; 20]).unwrap(), [0, 1, 2, 0, 0, 5, 6]);
+
+        // bytes that were not written are zeros
+        assert_eq!(storage.read([0; 20], 5, 4).await.unwrap(), [5, 6, 0, 0]);
+        assert_eq!(
+            storage.hash([0; 20], 0, 2).await.unwrap(),
+            sha1_smol::Sha1::from([0, 1]).digest().bytes()
+        );
+
+        storage.delete([0; 20]).await.unwrap();
+        assert!(storage.read([0; 20], 0, 1).await.is_err());
+    }
+}
@@ -0,0 +1,3025 @@
+//! Disk is responsible for file I/O of all Torrents.
+//!
+//! The bytes of the torrents are kept in a [`Storage`], which is
+//! [`FsStorage`] by default.
+pub mod cache;
+pub mod fs;
+pub mod hasher;
+pub mod memory;
+pub mod pool;
+pub mod storage;
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use hashbrown::{HashMap, HashSet};
+use rand::seq::SliceRandom;
+use speedy::{Readable, Writable};
+use tokio::{
+    fs::File,
+    io::AsyncSeekExt,
+    select,
+    sync::{
+        mpsc::{self, Receiver},
+        oneshot::Sender,
+    },
+};
+use tracing::{debug, warn};
+
+use crate::{
+    error::Error,
+    extensions::core::{Block, BlockInfo, BLOCK_LEN},
+    metainfo,
+    peer::{PeerCtx, PeerMsg},
+    torrent::{TorrentCtx, TorrentMsg},
+};
+
+pub use self::{
+    fs::FsStorage,
+    memory::MemoryStorage,
+    storage::{Layout, Preallocation, Storage},
+};
+
+use self::{
+    cache::{CacheStats, ReadCache, WriteCache},
+    hasher::{Hashed, Hasher, PieceCheck, V2Check},
+};
+
+#[derive(Debug)]
+pub enum DiskMsg {
+    /// After the client downloaded the Info from peers, this message will be
+    /// sent, to create the skeleton of the torrent on disk (empty files
+    /// and folders), and to add the torrent ctx.
+    NewTorrent(Arc<TorrentCtx>),
+    /// The Peer does not have an ID until the handshake (that is why it's an
+    /// option), when that happens, this message will be sent immediately
+    /// to add the peer context.
+    NewPeer(Arc<PeerCtx>),
+    ReadBlock {
+        info_hash: [u8; 20],
+        block_info: BlockInfo,
+        recipient: Sender<Vec<u8>>,
+    },
+    /// Validate that the hash of a piece on the storage matches the hash on
+    /// Info.pieces, the piece is hashed by the workers of the [`Hasher`],
+    /// and the result is sent to `recipient`.
+    ValidatePiece {
+        info_hash: [u8; 20],
+        recipient: Sender<Result<(), Error>>,
+        piece: usize,
+    },
+    OpenFile(String, Sender<File>),
+    /// Write the given block to disk, the Disk struct will get the seeked file
+    /// automatically.
+    WriteBlock {
+        info_hash: [u8; 20],
+        block: Block,
+    },
+    /// Request block infos that the peer has, that we do not have ir nor
+    /// requested it.
+    RequestBlocks {
+        info_hash: [u8; 20],
+        peer_id: [u8; 20],
+        recipient: Sender<VecDeque<BlockInfo>>,
+        qnt: usize,
+    },
+    /// When a peer is Choked, or receives an error and must close the
+    /// connection, the outgoing/pending blocks of this peer must be
+    /// appended back to the list of available block_infos.
+    ReturnBlockInfos([u8; 20], VecDeque<BlockInfo>),
+    /// Statistics of the write and read caches.
+    CacheStats(Sender<(CacheStats, CacheStats)>),
+    /// Close the open files of a torrent, when it is paused or removed. They
+    /// are opened again when they are used.
+    CloseFiles([u8; 20]),
+    /// Move the files of a torrent to `dir`, outside of the event loop. The
+    /// I/O of the torrent waits until they are moved, and the torrent
+    /// receives [`TorrentMsg::MoveProgress`] until [`TorrentMsg::StorageMoved`]
+    /// or [`TorrentMsg::DiskError`].
+    MoveStorage {
+        info_hash: [u8; 20],
+        dir: PathBuf,
+    },
+    /// Try again the I/O of a torrent that failed, i.e. after the disk was
+    /// remounted. The torrent receives [`TorrentMsg::DiskReady`] or another
+    /// [`TorrentMsg::DiskError`].
+    Retry([u8; 20]),
+    Quit,
+}
+
+impl DiskMsg {
+    /// The torrent of a message that uses its storage, which can't be handled
+    /// while the files of the torrent are moved.
+    fn storage_of(&self) -> Option<[u8; 20]> {
+        match self {
+            DiskMsg::ReadBlock { info_hash, .. }
+            | DiskMsg::ValidatePiece { info_hash, .. }
+            | DiskMsg::WriteBlock { info_hash, .. }
+            | DiskMsg::MoveStorage { info_hash, .. } => Some(*info_hash),
+            DiskMsg::CloseFiles(info_hash) | DiskMsg::Retry(info_hash) => {
+                Some(*info_hash)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The files of a torrent that were moved by a task of the [`Disk`].
+#[derive(Debug)]
+struct Moved {
+    info_hash: [u8; 20],
+    to: MovedTo,
+    result: Result<(), Error>,
+}
+
+/// Where the files of a torrent were moved.
+#[derive(Debug)]
+enum MovedTo {
+    /// To a directory, by [`DiskMsg::MoveStorage`].
+    Dir(PathBuf),
+    /// To their final location, after the wanted pieces were verified, by
+    /// [`Storage::complete`].
+    Complete,
+}
+
+/// The algorithm that determines how pieces are downloaded.
+/// The recommended is Random. But Sequential is used for streaming.
+///
+/// The default algorithm to use is random-first until we have
+/// a complete piece, after that, we switch to rarest-first.
+#[derive(
+    Clone, Copy, Hash, PartialEq, Eq, Default, Debug, Readable, Writable,
+)]
+pub enum PieceStrategy {
+    /// Random-first, select random pieces to download
+    #[default]
+    Random,
+    /// Rarest-first, give priority to the rarest pieces.
+    Rarest,
+    /// Sequential downloads, useful in streaming.
+    Sequential,
+}
+
+// A cache of the Info of a torrent,
+// used to avoid the cost of doing read locks all the time.
+#[derive(Debug, Clone)]
+struct TorrentInfo {
+    total_size: u64,
+    piece_length: u32,
+    pieces: u32,
+    layout: Layout,
+    /// The files of hybrid and v2 torrents, to verify the pieces with the
+    /// merkle tree of each file.
+    v2_files: Vec<V2File>,
+}
+
+/// A file of a hybrid or v2 torrent, which starts at the beginning of a
+/// piece.
+#[derive(Debug, Clone)]
+struct V2File {
+    first_piece: u32,
+    length: u64,
+    pieces_root: [u8; 32],
+}
+
+/// The Disk struct responsabilities:
+/// - Create the layout of torrents in the [`Storage`]
+/// - Read/Write blocks to the [`Storage`]
+/// - Store block infos of all torrents
+/// - Validate hash of pieces
+#[derive(Debug)]
+pub struct Disk<S: Storage = FsStorage> {
+    /// k: info_hash
+    pub torrent_ctxs: HashMap<[u8; 20], Arc<TorrentCtx>>,
+    /// k: peer_id
+    pub peer_ctxs: HashMap<[u8; 20], Arc<PeerCtx>>,
+    /// k: info_hash
+    /// The sequence in which pieces will be downloaded,
+    /// based on `PieceOrder`.
+    /// k: info_hash
+    pub pieces: HashMap<[u8; 20], Vec<u32>>,
+    /// How many pieces were downloaded.
+    /// k: info_hash
+    pub downloaded_pieces_len: HashMap<[u8; 20], u32>,
+    /// How many bytes downloaded for each piece.
+    /// k: info_hash
+    pub downloaded_pieces: HashMap<[u8; 20], Vec<u64>>,
+    /// k: info_hash
+    pub piece_strategy: HashMap<[u8; 20], PieceStrategy>,
+    /// Where the bytes of the torrents are stored.
+    pub storage: S,
+    /// Blocks of the pieces that are being downloaded, that were not written
+    /// to disk yet.
+    cache: WriteCache,
+    /// Pieces that were read to be uploaded to peers.
+    read_cache: ReadCache,
+    /// Workers that hash the pieces, outside of the event loop.
+    hasher: Hasher,
+    /// The pieces that were hashed by the workers of `hasher`.
+    hashed_rx: Receiver<Hashed>,
+    /// How many pieces were sent to `hasher` and not received yet.
+    hashing: usize,
+    /// k: info_hash
+    torrent_info: HashMap<[u8; 20], TorrentInfo>,
+    /// The block infos of each piece of a torrent, ordered from 0 to last.
+    /// where the index of the VecDeque is a piece.
+    /// k: info_hash
+    pieces_blocks: HashMap<[u8; 20], Vec<VecDeque<BlockInfo>>>,
+    /// Blocks that could not be written, they are written again when the
+    /// torrent is retried.
+    /// k: info_hash
+    unwritten: HashMap<[u8; 20], Vec<Block>>,
+    /// Torrents whose files are being moved, with the messages that wait for
+    /// the move to be done.
+    /// k: info_hash
+    moving: HashMap<[u8; 20], Vec<DiskMsg>>,
+    /// Messages of torrents that were moved, handled before new messages.
+    parked: VecDeque<DiskMsg>,
+    /// Torrents whose files are at their final location.
+    /// k: info_hash
+    completed: HashSet<[u8; 20]>,
+    moved_tx: mpsc::Sender<Moved>,
+    /// The torrents that were moved by the tasks of `MoveStorage`.
+    moved_rx: Receiver<Moved>,
+    rx: Receiver<DiskMsg>,
+}
+
+impl Disk<FsStorage> {
+    /// A disk that stores the torrents in files, in
+    /// "download_dir/name_of_torrent".
+    pub fn new(rx: Receiver<DiskMsg>, download_dir: String) -> Self {
+        Self::with_storage(rx, FsStorage::new(download_dir))
+    }
+
+    /// Set the maximum of files that are kept open.
+    pub fn max_open_files(self, max: usize) -> Self {
+        Self { storage: self.storage.max_open_files(max), ..self }
+    }
+
+    /// Set how the files of the torrents are allocated.
+    pub fn preallocation(self, preallocation: Preallocation) -> Self {
+        Self { storage: self.storage.preallocation(preallocation), ..self }
+    }
+
+    /// Download the torrents in `dir`, and move them to the download dir when
+    /// they are complete.
+    pub fn incomplete_dir(self, dir: Option<String>) -> Self {
+        Self { storage: self.storage.incomplete_dir(dir), ..self }
+    }
+
+    /// Add the `.part` suffix to the files while they are being downloaded.
+    pub fn part_suffix(self, part_suffix: bool) -> Self {
+        Self { storage: self.storage.part_suffix(part_suffix), ..self }
+    }
+
+    /// Open a file given a path, the path is absolute
+    /// and does not consider the base path of the torrent,
+    /// if this behaviour is wanted, you can get the base path
+    /// of the torrent using `base_path`.
+    pub async fn open_file(path: impl AsRef<Path>) -> Result<File, Error> {
+        FsStorage::open_file(path).await
+    }
+
+    /// Get the base path of a torrent directory.
+    /// Which is always "download_dir/name_of_torrent".
+    pub fn base_path(&self, info_hash: [u8; 20]) -> Result<PathBuf, Error> {
+        self.storage
+            .base_path(info_hash)
+            .map(Path::to_owned)
+            .ok_or(Error::TorrentDoesNotExist)
+    }
+
+    /// Return a seeked tokio::fs::File, given a `BlockInfo`.
+    ///
+    /// # Use cases:
+    /// - After we receive a Piece msg, we need to
+    /// map the block to a fs::File to be able to write to disk.
+    ///
+    /// - When a leecher sends a Request msg, we need
+    /// to get the corresponding file seeked on the right offset
+    /// of the block info.
+    pub async fn get_file_from_block_info(
+        &self,
+        block_info: &BlockInfo,
+        info_hash: [u8; 20],
+    ) -> Result<(File, metainfo::File), Error> {
+        let torrent =
+            self.torrent_ctxs.get(&info_hash).ok_or(Error::InfoHashInvalid)?;
+
+        let info = torrent.info.read().await;
+
+        let absolute_offset = block_info.index as u64
+            * info.piece_length as u64
+            + block_info.begin as u64;
+
+        let mut path = self.base_path(info_hash)?;
+
+        if let Some(files) = &info.files {
+            let mut accumulated_length = 0_u64;
+
+            for file_info in files.iter() {
+                if accumulated_length + file_info.length > absolute_offset {
+                    path.extend(&file_info.path);
+
+                    let mut file = Self::open_file(&path).await?;
+
+                    let file_relative_offset =
+                        absolute_offset - accumulated_length;
+
+                    file.seek(SeekFrom::Start(file_relative_offset)).await?;
+
+                    return Ok((file, file_info.clone()));
+                }
+                accumulated_length += file_info.length;
+            }
+
+            Err(Error::FileOpenError("Offset exceeds file sizes".to_owned()))
+        } else {
+            let mut file = Self::open_file(path).await?;
+            file.seek(SeekFrom::Start(absolute_offset)).await?;
+
+            let file_info = metainfo::File {
+                path: vec![info.name.to_owned()],
+                length: info.file_length.unwrap(),
+                ..Default::default()
+            };
+
+            Ok((file, file_info))
+        }
+    }
+}
+
+impl<S: Storage> Disk<S> {
+    pub fn with_storage(rx: Receiver<DiskMsg>, storage: S) -> Self {
+        let (hashed_tx, hashed_rx) = mpsc::channel::<Hashed>(100);
+        let (moved_tx, moved_rx) = mpsc::channel::<Moved>(10);
+
+        Self {
+            rx,
+            storage,
+            cache: WriteCache::default(),
+            read_cache: ReadCache::default(),
+            hasher: Hasher::new(None, hashed_tx),
+            hashed_rx,
+            hashing: 0,
+            peer_ctxs: HashMap::new(),
+            torrent_ctxs: HashMap::new(),
+            downloaded_pieces_len: HashMap::new(),
+            piece_strategy: HashMap::default(),
+            downloaded_pieces: HashMap::new(),
+            pieces_blocks: HashMap::default(),
+            torrent_info: HashMap::default(),
+            pieces: HashMap::default(),
+            unwritten: HashMap::default(),
+            moving: HashMap::default(),
+            parked: VecDeque::new(),
+            completed: HashSet::new(),
+            moved_tx,
+            moved_rx,
+        }
+    }
+
+    /// Set the maximum memory, in bytes, used by the blocks that were not
+    /// written to disk yet.
+    pub fn cache_size(mut self, bytes: u64) -> Self {
+        self.cache = WriteCache::new(bytes);
+        self
+    }
+
+    /// Set the maximum memory, in bytes, used by the pieces that were read
+    /// to be uploaded.
+    pub fn read_cache_size(mut self, bytes: u64) -> Self {
+        self.read_cache = ReadCache::new(bytes);
+        self
+    }
+
+    /// Set how many threads hash the pieces, by default, one per CPU.
+    pub fn hashing_threads(self, threads: Option<usize>) -> Self {
+        Self { hasher: self.hasher.threads(threads), ..self }
+    }
+
+    #[tracing::instrument(skip(self), name = "disk::run")]
+    pub async fn run(&mut self) -> Result<(), Error> {
+        debug!("disk started event loop");
+        loop {
+            let msg = match self.parked.pop_front() {
+                Some(msg) => Some(msg),
+                None => select! {
+                    Some(hashed) = self.hashed_rx.recv() => {
+                        self.piece_hashed(hashed).await;
+                        continue;
+                    }
+                    Some(moved) = self.moved_rx.recv() => {
+                        self.storage_moved(moved).await;
+                        continue;
+                    }
+                    msg = self.rx.recv() => msg,
+                },
+            };
+            let Some(msg) = msg else { break };
+
+            if let Some(parked) =
+                msg.storage_of().and_then(|h| self.moving.get_mut(&h))
+            {
+                parked.push(msg);
+                continue;
+            }
+
+            match msg {
+                DiskMsg::NewTorrent(torrent) => {
+                    debug!("NewTorrent");
+                    let tx = torrent.tx.clone();
+                    let info_hash = torrent.info_hash;
+                    match self.new_torrent(torrent).await {
+                        Ok(()) => self.send_base_path(info_hash).await,
+                        Err(e) => {
+                            warn!("could not create the torrent: {e}");
+                            let _ = tx
+                                .send(TorrentMsg::DiskError(e.to_string()))
+                                .await;
+                        }
+                    }
+                }
+                DiskMsg::ReadBlock { block_info, recipient, info_hash } => {
+                    debug!("ReadBlock");
+
+                    let len = block_info.len;
+
+                    // the recipient is dropped on errors, and the peer does
+                    // not upload the block.
+                    match self.read_block(info_hash, block_info).await {
+                        Ok(bytes) => {
+                            let _ = recipient.send(bytes);
+                        }
+                        // a request of a peer, and not an error of the disk
+                        Err(
+                            e @ (Error::PieceOutOfBounds(_)
+                            | Error::BlockOutOfBounds(..)),
+                        ) => {
+                            warn!("could not read block: {e}");
+                            continue;
+                        }
+                        Err(e) => {
+                            self.torrent_error(info_hash, e).await;
+                            continue;
+                        }
+                    }
+
+                    // increment uploaded count
+                    if let Some(ctx) = self.torrent_ctxs.get(&info_hash) {
+                        let _ = ctx
+                            .tx
+                            .send(TorrentMsg::IncrementUploaded(len))
+                            .await;
+                    }
+                }
+                DiskMsg::WriteBlock { block, info_hash } => {
+                    debug!("WriteBlock");
+                    match self.write_block(info_hash, block).await {
+                        Ok(()) => {}
+                        // a block of a peer, and not an error of the disk
+                        Err(e @ Error::PieceOutOfBounds(_)) => {
+                            warn!("could not write block: {e}");
+                        }
+                        Err(e) => self.torrent_error(info_hash, e).await,
+                    }
+                }
+                DiskMsg::OpenFile(path, tx) => {
+                    debug!("OpenFile");
+                    match FsStorage::open_file(&path).await {
+                        Ok(file) => {
+                            let _ = tx.send(file);
+                        }
+                        Err(e) => warn!("could not open {path:?}: {e}"),
+                    }
+                }
+                DiskMsg::RequestBlocks {
+                    qnt,
+                    recipient,
+                    info_hash,
+                    peer_id,
+                } => {
+                    debug!("RequestBlocks");
+                    let infos = self
+                        .request_blocks(info_hash, peer_id, qnt)
+                        .await
+                        .unwrap_or_default();
+                    debug!("disk sending {}", infos.len());
+                    let _ = recipient.send(infos);
+                }
+                DiskMsg::ValidatePiece { info_hash, recipient, piece } => {
+                    debug!("ValidatePiece");
+                    if let Err(e) = self
+                        .validate_piece(info_hash, piece, Some(recipient))
+                        .await
+                    {
+                        self.torrent_error(info_hash, e).await;
+                    }
+                }
+                DiskMsg::NewPeer(peer) => {
+                    debug!("NewPeer");
+                    let info_hash = peer.info_hash;
+                    if let Err(e) = self.new_peer(peer).await {
+                        self.torrent_error(info_hash, e).await;
+                    }
+                }
+                DiskMsg::ReturnBlockInfos(info_hash, block_infos) => {
+                    debug!("ReturnBlockInfos");
+                    // the torrent was not created, or was removed
+                    let Some(pieces_blocks) =
+                        self.pieces_blocks.get_mut(&info_hash)
+                    else {
+                        continue;
+                    };
+                    for block in block_infos {
+                        // get vector of piece_blocks for each
+                        // piece of the blocks.
+                        if let Some(piece) =
+                            pieces_blocks.get_mut(block.index as usize)
+                        {
+                            piece.push_back(block);
+                        }
+                    }
+                }
+                DiskMsg::CacheStats(tx) => {
+                    debug!("CacheStats");
+                    let _ =
+                        tx.send((self.cache.stats(), self.read_cache.stats()));
+                }
+                DiskMsg::CloseFiles(info_hash) => {
+                    debug!("CloseFiles");
+                    self.storage.close(info_hash);
+                }
+                DiskMsg::MoveStorage { info_hash, dir } => {
+                    debug!("MoveStorage");
+                    let Some(progress_tx) =
+                        self.torrent_ctxs.get(&info_hash).map(|t| t.tx.clone())
+                    else {
+                        continue;
+                    };
+                    let progress = move |bytes| {
+                        let _ = progress_tx
+                            .try_send(TorrentMsg::MoveProgress(bytes));
+                    };
+                    let task =
+                        match self.storage.move_to(info_hash, &dir, progress) {
+                            Ok(task) => task,
+                            Err(e) => {
+                                self.torrent_error(info_hash, e).await;
+                                continue;
+                            }
+                        };
+                    // the blocks that are still in the write cache are
+                    // written to the new location.
+                    self.spawn_move(info_hash, MovedTo::Dir(dir), task);
+                }
+                DiskMsg::Retry(info_hash) => {
+                    debug!("Retry");
+                    let Some(tx) =
+                        self.torrent_ctxs.get(&info_hash).map(|t| t.tx.clone())
+                    else {
+                        continue;
+                    };
+                    let msg = match self.retry(info_hash).await {
+                        Ok(()) => TorrentMsg::DiskReady,
+                        Err(e) => {
+                            warn!("could not retry the torrent: {e}");
+                            TorrentMsg::DiskError(e.to_string())
+                        }
+                    };
+                    let _ = tx.send(msg).await;
+                }
+                DiskMsg::Quit => {
+                    debug!("Quit");
+                    self.wait_hashes().await;
+                    self.wait_moves().await;
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The files of a torrent were moved, or could not be moved, and its I/O
+    /// can be done again. The messages of the torrent that were parked
+    /// during the move are handled before new messages.
+    async fn storage_moved(&mut self, moved: Moved) {
+        let Moved { info_hash, to, result } = moved;
+        let parked = self.moving.remove(&info_hash).unwrap_or_default();
+        self.parked.extend(parked);
+
+        if result.is_ok() {
+            match &to {
+                MovedTo::Dir(dir) => self.storage.moved(info_hash, dir),
+                MovedTo::Complete => {
+                    self.storage.completed(info_hash);
+                    self.completed.insert(info_hash);
+                }
+            }
+        }
+
+        // the blocks that were evicted from the cache during the move
+        let blocks = self.unwritten.remove(&info_hash).unwrap_or_default();
+        let result = result.and(self.write_blocks(info_hash, blocks).await);
+
+        if let Err(e) = result {
+            self.torrent_error(info_hash, e).await;
+            return;
+        }
+
+        self.send_base_path(info_hash).await;
+        let Some(tx) = self.torrent_ctxs.get(&info_hash).map(|t| t.tx.clone())
+        else {
+            return;
+        };
+
+        match to {
+            MovedTo::Dir(dir) => {
+                let _ = tx.send(TorrentMsg::StorageMoved(dir)).await;
+                // the pieces may have been verified during the move
+                self.complete(info_hash).await;
+            }
+            MovedTo::Complete => {
+                let _ = tx.send(TorrentMsg::StorageComplete).await;
+            }
+        }
+    }
+
+    /// Move the files of a torrent with the `task` of the [`Storage`],
+    /// outside of the event loop. The messages of the torrent are parked
+    /// until it is done, see [`Self::storage_moved`].
+    fn spawn_move(
+        &mut self,
+        info_hash: [u8; 20],
+        to: MovedTo,
+        task: impl Future<Output = Result<(), Error>> + Send + 'static,
+    ) {
+        self.moving.insert(info_hash, Vec::new());
+        let moved_tx = self.moved_tx.clone();
+        tokio::spawn(async move {
+            let result = task.await;
+            let _ = moved_tx.send(Moved { info_hash, to, result }).await;
+        });
+    }
+
+    /// Move the files of a torrent to their final location, when its wanted
+    /// pieces are verified. The torrent receives
+    /// [`TorrentMsg::DownloadComplete`], and then
+    /// [`TorrentMsg::StorageComplete`] when the files are moved. A torrent
+    /// that is moving is completed after the move.
+    async fn complete(&mut self, info_hash: [u8; 20]) {
+        if self.moving.contains_key(&info_hash)
+            || self.completed.contains(&info_hash)
+        {
+            return;
+        }
+        let Some(torrent_ctx) = self.torrent_ctxs.get(&info_hash).cloned()
+        else {
+            return;
+        };
+
+        let info = torrent_ctx.info.read().await;
+        let bitfield = torrent_ctx.bitfield.read().await;
+        let verified = match torrent_ctx.selects_files() {
+            false => bitfield.all(),
+            true => info
+                .selected_pieces(|i| torrent_ctx.is_file_selected(i))
+                .iter_ones()
+                .all(|piece| bitfield.get(piece).is_some_and(|b| *b)),
+        };
+        drop(bitfield);
+        drop(info);
+
+        if !verified {
+            return;
+        }
+
+        let _ = torrent_ctx.tx.send(TorrentMsg::DownloadComplete).await;
+
+        match self.storage.complete(info_hash) {
+            Ok(task) => self.spawn_move(info_hash, MovedTo::Complete, task),
+            Err(e) => self.torrent_error(info_hash, e).await,
+        }
+    }
+
+    /// Tell the torrent where its files are, after they are created, moved or
+    /// completed.
+    async fn send_base_path(&self, info_hash: [u8; 20]) {
+        let (Some(ctx), Some(path)) = (
+            self.torrent_ctxs.get(&info_hash),
+            self.storage.base_path(info_hash),
+        ) else {
+            return;
+        };
+        let _ = ctx.tx.send(TorrentMsg::BasePath(path.to_owned())).await;
+    }
+
+    /// Wait until the files of all torrents are moved.
+    async fn wait_moves(&mut self) {
+        while !self.moving.is_empty() {
+            let Some(moved) = self.moved_rx.recv().await else { break };
+            self.storage_moved(moved).await;
+        }
+    }
+
+    /// An error of one torrent does not stop the other torrents, the torrent
+    /// stops its I/O until it is retried, see [`DiskMsg::Retry`].
+    async fn torrent_error(&self, info_hash: [u8; 20], e: Error) {
+        warn!("disk error on torrent {info_hash:?}: {e}");
+        if let Some(ctx) = self.torrent_ctxs.get(&info_hash) {
+            let _ = ctx.tx.send(TorrentMsg::DiskError(e.to_string())).await;
+        }
+    }
+
+    /// Try again the I/O of a torrent that failed. A torrent that could not
+    /// be created is created again, otherwise its files are reopened and
+    /// the blocks that could not be written are written.
+    async fn retry(&mut self, info_hash: [u8; 20]) -> Result<(), Error> {
+        let torrent_ctx = self
+            .torrent_ctxs
+            .get(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?
+            .clone();
+
+        // the blocks are the last thing to be created
+        let (layout, pieces) = match self.torrent_info.get(&info_hash) {
+            Some(info) if self.pieces_blocks.contains_key(&info_hash) => {
+                (info.layout.clone(), info.pieces as usize)
+            }
+            _ => return self.new_torrent(torrent_ctx).await,
+        };
+
+        // the open files may be of a disk that was unmounted
+        self.storage.close(info_hash);
+        self.storage.create(info_hash, &layout).await?;
+
+        let blocks = self.unwritten.remove(&info_hash).unwrap_or_default();
+        self.write_blocks(info_hash, blocks).await?;
+
+        // the pieces that were downloaded were not hashed, because they
+        // could not be written or read. The pieces that are still being
+        // hashed may be hashed again, see `piece_hashed`.
+        for index in 0..pieces {
+            if torrent_ctx.bitfield.read().await.get(index).is_some_and(|b| *b)
+            {
+                continue;
+            }
+            let size = self.piece_size(info_hash, index)? as u64;
+            let downloaded = self
+                .downloaded_pieces
+                .get(&info_hash)
+                .and_then(|d| d.get(index))
+                .copied()
+                .unwrap_or_default();
+
+            if downloaded >= size {
+                self.validate_piece(info_hash, index, None).await?;
+            }
+        }
+
+        self.complete(info_hash).await;
+
+        Ok(())
+    }
+
+    /// Initialize necessary data.
+    ///
+    /// # Important
+    /// Must only be called after torrent has Info downloaded
+    #[tracing::instrument(skip(self, torrent_ctx), name = "new_torrent")]
+    pub async fn new_torrent(
+        &mut self,
+        torrent_ctx: Arc<TorrentCtx>,
+    ) -> Result<(), Error> {
+        let info_hash = torrent_ctx.info_hash;
+        debug!("new_torrent {info_hash:?}");
+
+        // all paths of the torrent are built from the info, so they must be
+        // safe before anything is created.
+        torrent_ctx.info.write().await.sanitize()?;
+
+        self.torrent_ctxs.insert(info_hash, torrent_ctx);
+
+        let torrent_ctx = &self.torrent_ctxs[&info_hash];
+        let info = torrent_ctx.info.read().await;
+
+        // the files of v2 only torrents must be aligned by the torrent first
+        if info.files.is_none() && info.file_length.is_none() {
+            return Err(Error::InfoInvalid);
+        }
+
+        let v2_files = Self::v2_files(&info)?;
+
+        let layout = Layout::from_info(&info);
+        let options = &torrent_ctx.options;
+
+        if let Some(preallocation) = options.preallocation {
+            self.storage.set_preallocation(info_hash, preallocation);
+        }
+        if let Some(save_path) = &options.save_path {
+            self.storage.set_dir(info_hash, Path::new(save_path));
+        }
+
+        // fail before the download starts, and not in the middle of it
+        self.storage.check_space(info_hash, &layout).await?;
+
+        // create a cache of the info to avoid
+        // calling a read lock everytime.
+        self.torrent_info.insert(
+            info_hash,
+            TorrentInfo {
+                total_size: info.get_size(),
+                piece_length: info.piece_length,
+                pieces: info.pieces(),
+                layout: layout.clone(),
+                v2_files,
+            },
+        );
+
+        self.storage.create(info_hash, &layout).await?;
+
+        let pieces_len = info.pieces();
+
+        let piece_order = options.piece_strategy;
+        self.piece_strategy.insert(info_hash, piece_order);
+
+        let mut r: Vec<u32> = (0..pieces_len).collect();
+        let downloaded_pieces = vec![0; pieces_len as usize];
+
+        // only the pieces of the selected files are requested
+        if torrent_ctx.selects_files() {
+            let selected =
+                info.selected_pieces(|i| torrent_ctx.is_file_selected(i));
+            r.retain(|piece| selected[*piece as usize]);
+        }
+
+        if piece_order != PieceStrategy::Sequential {
+            r.shuffle(&mut rand::thread_rng());
+        }
+
+        // each index of Vec is a piece index, that is a VecDeque of blocks
+        let mut pieces_blocks: Vec<VecDeque<BlockInfo>> =
+            Vec::with_capacity(r.len());
+        debug!("self.pieces {:?}", r);
+        self.pieces.insert(info_hash, r);
+
+        self.downloaded_pieces.insert(info_hash, downloaded_pieces);
+
+        // generate all block_infos of this torrent
+        for block in info.get_block_infos()? {
+            // and place each block_info into it's corresponding
+            // piece, which is the index of `pieces_blocks`
+            match pieces_blocks.get_mut(block.index as usize) {
+                None => {
+                    pieces_blocks.push(vec![block].into());
+                }
+                Some(g) => {
+                    g.push_back(block);
+                }
+            };
+        }
+        self.pieces_blocks.insert(info_hash, pieces_blocks);
+        self.downloaded_pieces_len.insert(info_hash, 0);
+
+        // tell all peers that the Info is downloaded, and
+        // everything is ready to start the download.
+        // peers that were disconnected are ignored.
+        for peer in &self.peer_ctxs {
+            let _ = peer.1.tx.send(PeerMsg::HaveInfo).await;
+        }
+
+        Ok(())
+    }
+
+    /// Match the files of a hybrid or v2 torrent with the files of its file
+    /// tree. Every file of the tree must start at the beginning of a piece,
+    /// with the same length, otherwise the v1 and v2 metadata disagree and
+    /// the info is invalid.
+    fn v2_files(info: &metainfo::Info) -> Result<Vec<V2File>, Error> {
+        let Some(file_tree) = &info.file_tree else { return Ok(Vec::new()) };
+        let mut tree_files: HashMap<Vec<String>, metainfo::TreeFile> =
+            file_tree
+                .files()
+                .into_iter()
+                .map(|f| (f.path.clone(), f))
+                .collect();
+
+        let v1_files = match &info.files {
+            Some(files) => files.clone(),
+            None => vec![metainfo::File {
+                path: vec![info.name.clone()],
+                length: info.file_length.unwrap_or_default(),
+                ..Default::default()
+            }],
+        };
+
+        let piece_length = info.piece_length as u64;
+        let mut offset = 0_u64;
+        let mut v2_files = Vec::new();
+
+        for file in v1_files {
+            let tree_file = match tree_files.remove(&file.path) {
+                Some(tree_file) => tree_file,
+                None if file.attr.padding => {
+                    offset += file.length;
+                    continue;
+                }
+                None => return Err(Error::InfoInvalid),
+            };
+
+            if tree_file.length != file.length {
+                return Err(Error::InfoInvalid);
+            }
+
+            if let Some(pieces_root) = tree_file.pieces_root {
+                // hybrid torrents align the files with pad files
+                if piece_length == 0 || !offset.is_multiple_of(piece_length) {
+                    return Err(Error::InfoInvalid);
+                }
+                v2_files.push(V2File {
+                    first_piece: (offset / piece_length) as u32,
+                    length: file.length,
+                    pieces_root,
+                });
+            }
+            offset += file.length;
+        }
+
+        // empty files may be only on the file tree
+        if tree_files.values().any(|f| f.length > 0) {
+            return Err(Error::InfoInvalid);
+        }
+
+        Ok(v2_files)
+    }
+
+    /// Add a new peer to `peer_ctxs`.
+    pub async fn new_peer(
+        &mut self,
+        peer_ctx: Arc<PeerCtx>,
+    ) -> Result<(), Error> {
+        self.peer_ctxs.insert(peer_ctx.id, peer_ctx);
+        Ok(())
+    }
+
+    /// The function will get the next available piece
+    /// based on the following criteria:
+    ///
+    /// - it will respect `PieceStrategy`.
+    /// - the peer must have the piece (the bit is set to 1 on it's bitfield).
+    /// - the local peer (client) doesn't have the piece downloaded.
+    ///
+    /// # Return
+    /// if `Disk` does not have the peer_ctx of the given peer_id, or the
+    /// torrent was not created, it will return None.
+    async fn next_piece(
+        &self,
+        info_hash: [u8; 20],
+        peer_id: [u8; 20],
+    ) -> Option<(usize, u32)> {
+        let peer_ctx = self.peer_ctxs.get(&peer_id)?;
+        let peer_pieces = peer_ctx.pieces.read().await;
+        let downloaded_pieces = self.downloaded_pieces.get(&info_hash)?;
+        self.pieces
+            .get(&info_hash)?
+            .iter()
+            .enumerate()
+            .find(|(_, piece)| {
+                let piece = **piece as usize;
+                let (Some(has_piece), Some(downloaded), Ok(size)) = (
+                    peer_pieces.get(piece),
+                    downloaded_pieces.get(piece),
+                    self.piece_size(info_hash, piece),
+                ) else {
+                    return false;
+                };
+                *has_piece && *downloaded < size as u64
+            })
+            .map(|(i, x)| (i, x.to_owned()))
+    }
+
+    /// Change the piece download algorithm to rarest-first.
+    ///
+    /// The rarest-first strategy actually begins in random-first,
+    /// until the first piece is downloaded, after that, it finally
+    /// switches to rarest-first.
+    ///
+    /// The function will get the pieces of all peers, and see
+    /// which pieces are the most rare, and reorder the piece
+    /// vector of Disk, where the most rare are the ones to the right.
+    async fn rarest_first(&mut self, info_hash: [u8; 20]) -> Result<(), Error> {
+        // get all peers of the given torrent `info_hash`
+        let peer_ctxs: Vec<Arc<PeerCtx>> = self
+            .peer_ctxs
+            .values()
+            .filter(|&v| v.info_hash == info_hash)
+            .cloned()
+            .collect();
+
+        debug!("calculating score of {:?} peers", peer_ctxs.len());
+
+        // the pieces stay in random order, until there are peers to score
+        // them.
+        if peer_ctxs.is_empty() {
+            return Ok(());
+        }
+
+        // pieces of the local peer
+        let pieces = self
+            .pieces
+            .get_mut(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?;
+
+        // vec of pieces scores/occurences, where index = piece.
+        let mut score: Vec<u32> = vec![0u32; pieces.len()];
+
+        // traverse pieces of the peers
+        for ctx in peer_ctxs {
+            let pieces =
+                ctx.pieces.read().await.clone().into_iter().enumerate();
+            for (i, item) in pieces {
+                // increment each occurence of a piece
+                if item {
+                    if let Some(item) = score.get_mut(i) {
+                        *item += 1;
+                    }
+                }
+            }
+        }
+        debug!("pieces random {pieces:?}");
+        debug!("len of pieces random {:?}", pieces.len());
+        debug!("score {score:?}");
+
+        while !score.is_empty() {
+            // get the rarest, the piece with the least occurences
+            let (rarest_idx, _) = score.iter().enumerate().min().unwrap();
+
+            // get the last
+            let (last_idx, _) = score.iter().enumerate().last().unwrap();
+
+            pieces.swap(last_idx, rarest_idx);
+
+            // remove the rarest from the score,
+            // as it's already used.
+            score.remove(rarest_idx);
+        }
+
+        debug!("pieces changed to rarest {pieces:?}");
+        if let Some(piece_order) = self.piece_strategy.get_mut(&info_hash) {
+            if *piece_order == PieceStrategy::Random {
+                *piece_order = PieceStrategy::Rarest;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Request blocks of the given `peer_id` that has not been requested
+    /// nor downloaded yet, given a `qnt` quantity.
+    #[tracing::instrument(skip_all)]
+    pub async fn request_blocks(
+        &mut self,
+        info_hash: [u8; 20],
+        peer_id: [u8; 20],
+        qnt: usize,
+    ) -> Result<VecDeque<BlockInfo>, Error> {
+        let mut result: VecDeque<BlockInfo> = VecDeque::new();
+
+        for _ in 0..qnt {
+            let next_piece = self.next_piece(info_hash, peer_id).await;
+
+            if let Some(piece) = next_piece {
+                let pieces_blocks = self
+                    .pieces_blocks
+                    .get_mut(&info_hash)
+                    .ok_or(Error::TorrentDoesNotExist)?;
+                let blocks = pieces_blocks.get_mut(piece.1 as usize);
+
+                if let Some(blocks) = blocks {
+                    if blocks.is_empty() {
+                        debug!(
+                            "piece {} is empty, removing by index {}",
+                            piece.1, piece.0
+                        );
+                        // pieces_blocks.remove(piece.1 as usize);
+                        self.pieces
+                            .get_mut(&info_hash)
+                            .ok_or(Error::TorrentDoesNotExist)?
+                            .remove(piece.0);
+                    }
+                    // how many blocks are left to request
+                    let left = qnt - result.len();
+                    result.extend(blocks.drain(0..left.min(blocks.len())));
+                }
+            }
+
+            if result.len() >= qnt {
+                break;
+            };
+        }
+        debug!("result len {:?}", result.len());
+
+        Ok(result)
+    }
+
+    pub async fn read_block(
+        &mut self,
+        info_hash: [u8; 20],
+        block_info: BlockInfo,
+    ) -> Result<Vec<u8>, Error> {
+        let BlockInfo { index, begin, len } = block_info;
+
+        // the block is requested by a peer, it must be checked before
+        // allocating it.
+        let piece_size = self.piece_size(info_hash, index as usize)?;
+        if len > BLOCK_LEN || begin as u64 + len as u64 > piece_size as u64 {
+            return Err(Error::BlockOutOfBounds(index, begin, len));
+        }
+
+        // how many bytes to read, after offset (begin)
+        let buf = vec![0; len as usize];
+
+        if self.is_padding(info_hash, &block_info) {
+            return Ok(buf);
+        }
+
+        if let Some(bytes) = self.cache.get(info_hash, &block_info) {
+            return Ok(bytes);
+        }
+
+        if let Some(bytes) = self.read_cache.get(info_hash, &block_info) {
+            return Ok(bytes);
+        }
+
+        // peers usually request all blocks of a piece, in order, so the
+        // entire piece is read ahead when they request the first block.
+        if block_info.begin == 0 {
+            let index = block_info.index as usize;
+            let piece = self.read_piece(info_hash, index).await?;
+            let bytes =
+                piece.get(..block_info.len as usize).map(<[u8]>::to_vec);
+            self.read_cache.insert(info_hash, index, piece);
+
+            return bytes.ok_or(Error::FileOpenError(
+                "Offset exceeds file sizes".to_owned(),
+            ));
+        }
+
+        let torrent_info = self
+            .torrent_info
+            .get(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?;
+        let offset = block_info.index as u64 * torrent_info.piece_length as u64
+            + block_info.begin as u64;
+
+        self.storage.read(info_hash, offset, buf.len()).await
+    }
+
+    /// The essence of the entire Disk struct is in this function,
+    /// It will first try to write the block to the `cache`.
+    ///
+    /// # When a full piece is downloaded
+    ///
+    /// It is only after all blocks of the piece has been downloaded on `cache`,
+    /// that the function will write all the bytes into disk. If the cache is
+    /// full, the blocks of the pieces that are not receiving blocks are
+    /// written before, and these pieces are read back from disk to be hashed.
+    ///
+    /// Whenever a full piece is downloaded, this function will send it to the
+    /// `hasher`, to validate the full piece hash outside of the event loop,
+    /// see `piece_hashed`.
+    ///
+    /// If the download algorithm of the pieces is set to "Random", and this
+    /// function has downloaded it's first full piece, it will change the
+    /// algorithm to rarest-first.
+    ///
+    /// The block is checked before the piece is changed, the errors after it
+    /// are of the storage, and the piece is hashed when the torrent is
+    /// retried, see [`DiskMsg::Retry`].
+    #[tracing::instrument(skip(self, block))]
+    pub async fn write_block(
+        &mut self,
+        info_hash: [u8; 20],
+        block: Block,
+    ) -> Result<(), Error> {
+        // Write the block's data to the correct position in the file
+        let len = block.block.len();
+        let index = block.index;
+
+        let piece_size = self.piece_size(info_hash, index)? as u64;
+        let torrent_tx = self
+            .torrent_ctxs
+            .get(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?
+            .tx
+            .clone();
+        let downloaded_piece_bytes = self
+            .downloaded_pieces
+            .get_mut(&info_hash)
+            .and_then(|pieces| pieces.get_mut(index))
+            .ok_or(Error::TorrentDoesNotExist)?;
+
+        *downloaded_piece_bytes += len as u64;
+
+        // Check if the entire piece of the `block` has been downloaded
+        let piece_downloaded = *downloaded_piece_bytes >= piece_size;
+
+        self.cache.insert(info_hash, block);
+
+        while self.cache.is_full() {
+            let Some((info_hash, blocks)) = self.cache.evict() else { break };
+            debug!("cache is full, writing {} blocks", blocks.len());
+            // the evicted blocks may be of another torrent
+            if let Err(e) = self.write_blocks(info_hash, blocks).await {
+                self.torrent_error(info_hash, e).await;
+            }
+        }
+
+        let _ =
+            torrent_tx.send(TorrentMsg::IncrementDownloaded(len as u32)).await;
+
+        if !piece_downloaded {
+            return Ok(());
+        }
+
+        if let Some(pieces_len) = self.downloaded_pieces_len.get_mut(&info_hash)
+        {
+            *pieces_len += 1;
+        }
+
+        // without peers, the pieces stay random until the next piece
+        if self.piece_strategy.get(&info_hash) == Some(&PieceStrategy::Random) {
+            debug!("piece downloaded, and piece order is random, switching to rarest-first");
+            self.rarest_first(info_hash).await?;
+        }
+
+        // write the piece and validate that the downloaded pieces hash
+        // matches the hash of the info.
+        let (blocks, in_memory) = self.cache.take(info_hash, index);
+        let bytes: Vec<u8> = match in_memory {
+            true => blocks.iter().flat_map(|b| b.block.clone()).collect(),
+            false => Vec::new(),
+        };
+        self.write_blocks(info_hash, blocks).await?;
+
+        let bytes = match in_memory {
+            true => bytes,
+            false => self.read_piece(info_hash, index).await?,
+        };
+        self.hash_piece(info_hash, index, bytes, None).await?;
+
+        Ok(())
+    }
+
+    /// Send the `bytes` of a piece to be validated by the workers of the
+    /// `hasher`. If too many pieces are waiting to be hashed, this function
+    /// waits for the workers, to bound the memory of the pending pieces.
+    async fn hash_piece(
+        &mut self,
+        info_hash: [u8; 20],
+        index: usize,
+        bytes: Vec<u8>,
+        recipient: Option<Sender<Result<(), Error>>>,
+    ) -> Result<(), Error> {
+        while self.hashing >= self.hasher.max_pending() {
+            let Some(hashed) = self.hashed_rx.recv().await else { break };
+            self.piece_hashed(hashed).await;
+        }
+
+        let check = match self.piece_check(info_hash, index).await {
+            Ok(check) => check,
+            Err(e) => {
+                let Some(recipient) = recipient else { return Err(e) };
+                let _ = recipient.send(Err(e));
+                return Ok(());
+            }
+        };
+
+        self.hasher.hash(info_hash, index, bytes, check, recipient);
+        self.hashing += 1;
+
+        Ok(())
+    }
+
+    /// Handle a piece that was hashed by the workers. If a downloaded piece
+    /// is valid, the bitfield of the torrent is updated and the torrent will
+    /// send a Have msg to all peers that don't have this piece. This is the
+    /// only place where a torrent is completed, see [`Self::complete`].
+    async fn piece_hashed(&mut self, hashed: Hashed) {
+        self.hashing = self.hashing.saturating_sub(1);
+        let Hashed { info_hash, index, result, recipient } = hashed;
+
+        if let Some(recipient) = recipient {
+            let _ = recipient.send(result);
+            return;
+        }
+
+        // the torrent was removed while the piece was hashed
+        let Some(torrent_ctx) = self.torrent_ctxs.get(&info_hash).cloned()
+        else {
+            return;
+        };
+
+        match result {
+            Ok(_) => {
+                debug!("Piece {index} is valid.");
+
+                // the piece was hashed again by a retry
+                let mut bitfield = torrent_ctx.bitfield.write().await;
+                if bitfield.get(index).is_some_and(|b| *b) {
+                    return;
+                }
+                bitfield.set(index, true);
+                drop(bitfield);
+
+                let _ = torrent_ctx
+                    .tx
+                    .send(TorrentMsg::DownloadedPiece(index))
+                    .await;
+
+                self.complete(info_hash).await;
+            }
+            Err(_) => {
+                warn!("Piece {index} is corrupted.");
+            }
+        }
+    }
+
+    /// Wait until all pieces that were sent to the `hasher` are handled.
+    pub async fn wait_hashes(&mut self) {
+        while self.hashing > 0 {
+            let Some(hashed) = self.hashed_rx.recv().await else { break };
+            self.piece_hashed(hashed).await;
+        }
+    }
+
+    /// Given a piece, find it's corresponding file.
+    /// The file will NOT be seeked.
+    pub async fn get_file_from_piece(
+        &self,
+        piece: u32,
+        info_hash: [u8; 20],
+    ) -> Result<metainfo::File, Error> {
+        let torrent =
+            self.torrent_ctxs.get(&info_hash).ok_or(Error::InfoHashInvalid)?;
+
+        let info = torrent.info.read().await;
+        let piece_len = info.piece_length;
+
+        // multi file torrent
+        if let Some(files) = &info.files {
+            let mut acc = 0;
+            let file_info = files.iter().find(|f| {
+                let pieces = f.pieces(piece_len) as u64 + acc;
+                acc = pieces;
+                piece as u64 >= pieces
+            });
+
+            let file = file_info.ok_or(Error::FileOpenError("".to_owned()))?;
+
+            return Ok(file.clone());
+        }
+
+        // single file torrent
+        let file_info = metainfo::File {
+            path: vec![info.name.to_owned()],
+            length: info.file_length.unwrap(),
+            ..Default::default()
+        };
+
+        Ok(file_info)
+    }
+
+    /// Given a `BlockInfo`, find the corresponding `Block`
+    /// by reading the storage.
+    pub async fn get_block_from_block_info(
+        &mut self,
+        block_info: &BlockInfo,
+        info_hash: [u8; 20],
+    ) -> Result<Block, Error> {
+        // todo: try to get the block from cache first,
+        // if not in cache, read from the storage.
+        let torrent_info = self
+            .torrent_info
+            .get(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?;
+        let offset = block_info.index as u64 * torrent_info.piece_length as u64
+            + block_info.begin as u64;
+
+        let buf = match self.is_padding(info_hash, block_info) {
+            true => vec![0; block_info.len as usize],
+            false => {
+                self.storage
+                    .read(info_hash, offset, block_info.len as usize)
+                    .await?
+            }
+        };
+
+        let block = Block {
+            index: block_info.index as usize,
+            begin: block_info.begin,
+            block: buf,
+        };
+
+        Ok(block)
+    }
+
+    /// Validate a piece that is on the storage, the piece is read and hashed
+    /// by the workers of the `hasher`, like the downloaded pieces. The
+    /// result is sent to the `recipient`, if there is one, otherwise it is
+    /// handled by [`Self::piece_hashed`].
+    #[tracing::instrument(skip(self, info_hash, recipient))]
+    pub async fn validate_piece(
+        &mut self,
+        info_hash: [u8; 20],
+        index: usize,
+        recipient: Option<Sender<Result<(), Error>>>,
+    ) -> Result<(), Error> {
+        let bytes = match self.read_piece(info_hash, index).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                let Some(recipient) = recipient else { return Err(e) };
+                let _ = recipient.send(Err(e));
+                return Ok(());
+            }
+        };
+        self.hash_piece(info_hash, index, bytes, recipient).await
+    }
+
+    /// What a piece must hash to, the hash on Info.pieces and, on hybrid and
+    /// v2 torrents, the merkle tree of its file. Pieces of files larger than
+    /// a piece can only be verified with the tree after we have the hash of
+    /// the piece, from the piece layers, until then they are not valid.
+    async fn piece_check(
+        &self,
+        info_hash: [u8; 20],
+        index: usize,
+    ) -> Result<PieceCheck, Error> {
+        let torrent_ctx = self
+            .torrent_ctxs
+            .get(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?;
+        let torrent_info = self
+            .torrent_info
+            .get(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?;
+
+        // v2 torrents do not have the sha1 of the pieces
+        let sha1: Option<[u8; 20]> = torrent_ctx
+            .info
+            .read()
+            .await
+            .pieces
+            .get(index * 20..index * 20 + 20)
+            .and_then(|hash| hash.try_into().ok());
+
+        let piece_length = torrent_info.piece_length;
+
+        let Some(file) = torrent_info.v2_files.iter().find(|f| {
+            let pieces = f.length.div_ceil(piece_length as u64) as usize;
+            let first = f.first_piece as usize;
+            (first..first + pieces).contains(&index)
+        }) else {
+            return Ok(PieceCheck { sha1, v2: None });
+        };
+
+        let piece_in_file = (index - file.first_piece as usize) as u64;
+
+        // the end of the last piece of a file is padding
+        let len = (file.length - piece_in_file * piece_length as u64)
+            .min(piece_length as u64) as usize;
+
+        if file.length <= piece_length as u64 {
+            let v2 = V2Check::FileRoot { root: file.pieces_root, len };
+            return Ok(PieceCheck { sha1, v2: Some(v2) });
+        }
+
+        let piece_layers = torrent_ctx.piece_layers.read().await;
+        let begin = piece_in_file as usize * 32;
+
+        let v2 = match piece_layers
+            .get(&file.pieces_root)
+            .and_then(|layer| layer.get(begin..begin + 32))
+        {
+            Some(hash) if hash != [0; 32] => V2Check::Piece {
+                hash: hash.try_into().unwrap(),
+                len,
+                piece_length,
+            },
+            _ => V2Check::Unknown,
+        };
+
+        Ok(PieceCheck { sha1, v2: Some(v2) })
+    }
+
+    /// Write blocks to the storage, contiguous blocks are written at once.
+    async fn write_blocks(
+        &mut self,
+        info_hash: [u8; 20],
+        mut blocks: Vec<Block>,
+    ) -> Result<(), Error> {
+        // the files are being moved, the blocks are written after the move
+        if self.moving.contains_key(&info_hash) {
+            self.unwritten.entry(info_hash).or_default().extend(blocks);
+            return Ok(());
+        }
+        let Some(torrent_info) = self.torrent_info.get(&info_hash) else {
+            return Ok(());
+        };
+        let piece_length = torrent_info.piece_length as u64;
+        blocks.sort();
+
+        // pieces read before are not the same anymore
+        for block in &blocks {
+            self.read_cache.remove(info_hash, block.index);
+        }
+
+        let mut offset = 0;
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut result = Ok(());
+
+        for block in &blocks {
+            let block_offset =
+                block.index as u64 * piece_length + block.begin as u64;
+
+            if block_offset != offset + bytes.len() as u64 {
+                result = self.storage.write(info_hash, offset, &bytes).await;
+                if result.is_err() {
+                    break;
+                }
+                bytes.clear();
+                offset = block_offset;
+            }
+            bytes.extend_from_slice(&block.block);
+        }
+
+        if result.is_ok() {
+            result = self.storage.write(info_hash, offset, &bytes).await;
+        }
+
+        // the downloaded blocks are not lost, and the torrent does not need
+        // to download them again.
+        if result.is_err() {
+            self.unwritten.entry(info_hash).or_default().extend(blocks);
+        }
+
+        result
+    }
+
+    /// Read an entire piece from the storage.
+    async fn read_piece(
+        &mut self,
+        info_hash: [u8; 20],
+        index: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let torrent_info = self
+            .torrent_info
+            .get(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?;
+        let offset = index as u64 * torrent_info.piece_length as u64;
+        let len = self.piece_size(info_hash, index)? as usize;
+
+        self.storage.read(info_hash, offset, len).await
+    }
+
+    /// If the block is inside of a padding file, BEP 47.
+    fn is_padding(&self, info_hash: [u8; 20], block_info: &BlockInfo) -> bool {
+        let Some(torrent_info) = self.torrent_info.get(&info_hash) else {
+            return false;
+        };
+        let offset = block_info.index as u64 * torrent_info.piece_length as u64
+            + block_info.begin as u64;
+
+        // blocks do not cross files
+        torrent_info
+            .layout
+            .files
+            .iter()
+            .rev()
+            .find(|f| f.offset <= offset)
+            .is_some_and(|f| f.padding)
+    }
+
+    /// Get the correct piece size, the last piece of a torrent
+    /// might be smaller than the other pieces.
+    fn piece_size(
+        &self,
+        info_hash: [u8; 20],
+        piece_index: usize,
+    ) -> Result<u32, Error> {
+        let v = self
+            .torrent_info
+            .get(&info_hash)
+            .ok_or(Error::TorrentDoesNotExist)?;
+        if piece_index >= v.pieces as usize {
+            return Err(Error::PieceOutOfBounds(piece_index));
+        }
+        if piece_index == v.pieces as usize - 1 {
+            let remainder = v.total_size % v.piece_length as u64;
+            if remainder != 0 {
+                return Ok(remainder as u32);
+            }
+        }
+        Ok(v.piece_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, path::Path};
+
+    use bendy::decoding::FromBencode;
+    use rand::{distributions::Alphanumeric, Rng};
+
+    use crate::{
+        bitfield::Bitfield,
+        daemon::DaemonMsg,
+        extensions::core::{Block, BLOCK_LEN},
+        magnet::Magnet,
+        merkle,
+        metainfo::{self, Info},
+        torrent::Torrent,
+    };
+
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::{
+        fs,
+        io::AsyncWriteExt,
+        sync::{mpsc, oneshot, RwLock},
+    };
+
+    // when we send the msg `NewTorrent` the `Disk` must create
+    // the "skeleton" of the torrent tree. Empty folders and empty files.
+    #[tokio::test]
+    async fn create_file_tree() {
+        let original_hook = std::panic::take_hook();
+        let name = "bla".to_owned();
+        let magnet = format!("magnet:?xt=urn:btih:9999999999999999999999999999999999999999&amp;dn={name}&amp;tr=udp%3A%2F%2Ftracker.coppersurfer.tk%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.openbittorrent.com%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&amp;tr=udp%3A%2F%2Fbt.xxx-tracker.com%3A2710%2Fannounce&amp;tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Feddie4.nl%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&amp;tr=udp%3A%2F%2Fp4p.arenabg.com%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.tiny-vps.com%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce");
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(1000);
+
+        let (daemon_tx, _daemon_rx) = mpsc::channel::<DaemonMsg>(1000);
+
+        let magnet = Magnet::new(&magnet).unwrap();
+        let torrent = Torrent::new(disk_tx.clone(), daemon_tx, magnet);
+        let torrent_ctx = torrent.ctx.clone();
+
+        let mut rng = rand::thread_rng();
+        let download_dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+
+        let dd = download_dir.clone();
+        std::panic::set_hook(Box::new(move |panic| {
+            let _ = std::fs::remove_dir_all(&dd);
+            original_hook(panic);
+        }));
+
+        let mut disk = Disk::new(disk_rx, download_dir.clone());
+
+        let info = Info {
+            file_length: None,
+            name,
+            piece_length: BLOCK_LEN,
+            pieces: vec![],
+            files: Some(vec![
+                metainfo::File {
+                    length: BLOCK_LEN as u64 * 2,
+                    path: vec!["foo.txt".to_owned()],
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: BLOCK_LEN as u64 * 2,
+                    path: vec!["bar".to_owned(), "baz.txt".to_owned()],
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: BLOCK_LEN as u64 * 2,
+                    path: vec![
+                        "bar".to_owned(),
+                        "buzz".to_owned(),
+                        "bee.txt".to_owned(),
+                    ],
+                    ..Default::default()
+                },
+            ]),
+            private: None,
+            meta_version: None,
+            file_tree: None,
+        };
+
+        disk.torrent_ctxs.insert(torrent_ctx.info_hash, torrent_ctx.clone());
+
+        let mut info_ctx = torrent.ctx.info.write().await;
+        *info_ctx = info.clone();
+        drop(info_ctx);
+
+        disk.new_torrent(torrent_ctx.clone()).await.unwrap();
+
+        let mut path = PathBuf::new();
+        path.push(&download_dir);
+        path.push(&info.name);
+        path.push("foo.txt");
+
+        let result = Disk::open_file(path).await;
+        assert!(result.is_ok());
+
+        let mut path = PathBuf::new();
+        path.push(&download_dir);
+        path.push(&info.name);
+        path.push("bar/baz.txt");
+        let result = Disk::open_file(path).await;
+        assert!(result.is_ok());
+
+        let mut path = PathBuf::new();
+        path.push(&download_dir);
+        path.push(&info.name);
+        path.push("bar/buzz/bee.txt");
+        let result = Disk::open_file(path).await;
+        assert!(result.is_ok());
+
+        assert!(Path::new(&format!("{download_dir}/bla/foo.txt")).is_file());
+        assert!(Path::new(&format!("{download_dir}/bla/bar/baz.txt")).is_file());
+        assert!(Path::new(&format!("{download_dir}/bla/bar/buzz/bee.txt"))
+            .is_file());
+
+        tokio::fs::remove_dir_all(download_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_file_from_block_info() {
+        //
+        // Complex multi file torrent, 64 blocks per piece
+        //
+        let original_hook = std::panic::take_hook();
+        let mut rng = rand::thread_rng();
+        let download_dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let name = "name_of_torrent_folder".to_owned();
+        let file_a = "file_a";
+        let file_b = "file_b";
+        let file_c = "file_c";
+
+        let dd = download_dir.clone();
+        std::panic::set_hook(Box::new(move |panic| {
+            let _ = std::fs::remove_dir_all(&dd);
+            original_hook(panic);
+        }));
+
+        let info = Info {
+            name: name.clone(),
+            piece_length: 1048576,
+            file_length: None,
+            pieces: vec![0u8; 660],
+            files: Some(vec![
+                metainfo::File {
+                    length: 26384160,
+                    path: vec![file_a.to_owned()],
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: 8281625,
+                    path: vec![file_b.to_owned()],
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: 46,
+                    path: vec![file_c.to_owned()],
+                    ..Default::default()
+                },
+            ]),
+            private: None,
+            meta_version: None,
+            file_tree: None,
+        };
+
+        let magnet = format!("magnet:?xt=urn:btih:9999999999999999999999999999999999999999&amp;dn={name}&amp;tr=udp%3A%2F%2Ftracker.coppersurfer.tk%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.openbittorrent.com%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&amp;tr=udp%3A%2F%2Fbt.xxx-tracker.com%3A2710%2Fannounce&amp;tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Feddie4.nl%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&amp;tr=udp%3A%2F%2Fp4p.arenabg.com%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.tiny-vps.com%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce");
+
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(3);
+
+        let (fr_tx, _) = mpsc::channel::<DaemonMsg>(300);
+        let magnet = Magnet::new(&magnet).unwrap();
+        let torrent = Torrent::new(disk_tx.clone(), fr_tx, magnet);
+        let torrent_ctx = torrent.ctx.clone();
+        let info_hash: [u8; 20] = torrent_ctx.info_hash;
+        let mut disk = Disk::new(disk_rx, download_dir.clone());
+
+        let mut info_ctx = torrent.ctx.info.write().await;
+        *info_ctx = info.clone();
+        drop(info_ctx);
+
+        disk.new_torrent(torrent_ctx).await.unwrap();
+
+        // write 0s to all files with their sizes
+        for file in &info.files.clone().unwrap() {
+            let mut path = PathBuf::new();
+
+            path.push(download_dir.clone());
+            path.push(name.clone());
+            path.push(file.path[0].clone());
+
+            let mut fs_file = fs::File::create(path).await.unwrap();
+
+            fs_file.write_all(&vec![0_u8; file.length as usize]).await.unwrap();
+        }
+
+        let (_, meta_file) = disk
+            .get_file_from_block_info(
+                &BlockInfo { index: 0, begin: 0, len: BLOCK_LEN },
+                info_hash,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(meta_file, info.files.as_ref().unwrap()[0]);
+
+        // first block of the last piece
+        let block = BlockInfo { index: 25, begin: 0, len: 16384 };
+
+        let (_, meta_file) =
+            disk.get_file_from_block_info(&block, info_hash).await.unwrap();
+
+        assert_eq!(meta_file, info.files.as_ref().unwrap()[0]);
+
+        // last block of the first file
+        let last_block = BlockInfo { index: 25, begin: 163840, len: 5920 };
+
+        let (_, meta_file) = disk
+            .get_file_from_block_info(&last_block, info_hash)
+            .await
+            .unwrap();
+
+        assert_eq!(meta_file, info.files.as_ref().unwrap()[0]);
+
+        // first block of second file
+        let block = BlockInfo { index: 25, begin: 169760, len: BLOCK_LEN };
+
+        let (_, meta_file) =
+            disk.get_file_from_block_info(&block, info_hash).await.unwrap();
+
+        assert_eq!(meta_file, info.files.as_ref().unwrap()[1]);
+
+        // second block of second file
+        let block =
+            BlockInfo { index: 25, begin: 169760 + BLOCK_LEN, len: BLOCK_LEN };
+
+        let (_, meta_file) =
+            disk.get_file_from_block_info(&block, info_hash).await.unwrap();
+
+        assert_eq!(meta_file, info.files.as_ref().unwrap()[1]);
+
+        // last of second file
+        let block = BlockInfo { index: 33, begin: 49152, len: 13625 };
+
+        let (_, meta_file) =
+            disk.get_file_from_block_info(&block, info_hash).await.unwrap();
+
+        assert_eq!(meta_file, info.files.as_ref().unwrap()[1]);
+
+        // last file of torrent
+        let block = BlockInfo { index: 33, begin: 62777, len: 46 };
+
+        let (_, meta_file) =
+            disk.get_file_from_block_info(&block, info_hash).await.unwrap();
+
+        assert_eq!(meta_file, info.files.as_ref().unwrap()[2]);
+
+        tokio::fs::remove_dir_all(download_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_out_of_order() {
+        let name = "arch";
+
+        let info = Info {
+            file_length: None,
+            name: name.to_owned(),
+            piece_length: 3,
+            pieces: vec![
+                25, 24, 125, 201, 141, 206, 82, 250, 76, 78, 142, 5, 179, 65,
+                169, 183, 122, 81, 253, 38, 138, 247, 91, 50, 219, 108, 241,
+                131, 238, 114, 179, 138, 39, 171, 85, 195, 131, 111, 27, 237,
+            ],
+            files: Some(vec![
+                metainfo::File {
+                    length: 3,
+                    path: vec!["out.txt".to_owned()],
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: 3,
+                    path: vec!["last.txt".to_owned()],
+                    ..Default::default()
+                },
+            ]),
+            private: None,
+            meta_version: None,
+            file_tree: None,
+        };
+
+        let magnet = format!("magnet:?xt=urn:btih:9999999999999999999999999999999999999999&amp;dn={name}&amp;tr=udp%3A%2F%2Ftracker.coppersurfer.tk%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.openbittorrent.com%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&amp;tr=udp%3A%2F%2Fbt.xxx-tracker.com%3A2710%2Fannounce&amp;tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Feddie4.nl%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&amp;tr=udp%3A%2F%2Fp4p.arenabg.com%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.tiny-vps.com%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce");
+        let (disk_tx, _) = mpsc::channel::<DiskMsg>(3);
+
+        let (_, rx) = mpsc::channel(5);
+        let mut disk = Disk::with_storage(rx, MemoryStorage::new());
+
+        let (fr_tx, _) = mpsc::channel::<DaemonMsg>(300);
+        let magnet = Magnet::new(&magnet).unwrap();
+        let torrent = Torrent::new(disk_tx, fr_tx, magnet);
+        let info_hash = torrent.ctx.info_hash;
+
+        let mut info_t = torrent.ctx.info.write().await;
+        *info_t = info.clone();
+        drop(info_t);
+
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+
+        *disk.piece_strategy.get_mut(&info_hash).unwrap() =
+            PieceStrategy::Sequential;
+
+        let mut p = torrent.ctx.bitfield.write().await;
+        *p = Bitfield::from_vec(vec![255]);
+        drop(info);
+        drop(p);
+
+        //
+        //  WRITE
+        //
+
+        // second file
+        let block =
+            Block { index: 1, begin: 2, block: "9".as_bytes().to_owned() };
+        disk.write_block(info_hash, block).await.unwrap();
+        let block =
+            Block { index: 1, begin: 1, block: "w".as_bytes().to_owned() };
+        disk.write_block(info_hash, block).await.unwrap();
+        let block =
+            Block { index: 1, begin: 0, block: "x".as_bytes().to_owned() };
+        disk.write_block(info_hash, block).await.unwrap();
+
+        // first file
+        let block =
+            Block { index: 0, begin: 2, block: "3".as_bytes().to_owned() };
+        disk.write_block(info_hash, block).await.unwrap();
+        let block =
+            Block { index: 0, begin: 1, block: "1".as_bytes().to_owned() };
+        disk.write_block(info_hash, block).await.unwrap();
+        let block =
+            Block { index: 0, begin: 0, block: "2".as_bytes().to_owned() };
+        disk.write_block(info_hash, block).await.unwrap();
+
+        // out.txt and then last.txt
+        assert_eq!(
+            disk.storage.bytes(info_hash).unwrap(),
+            [50, 49, 51, 120, 119, 57]
+        );
+    }
+
+    // padding files are never written, and the executable bits and
+    // symlinks are applied when the download is complete, BEP 47.
+    #[tokio::test]
+    async fn padding_and_file_attrs() {
+        let name = "attrs";
+        let a = vec![1, 2, 3, 4, 5, 6];
+        let b = vec![7; 12];
+
+        let mut piece = a.clone();
+        piece.extend_from_slice(&[0; 6]);
+        let mut pieces =
+            sha1_smol::Sha1::from(&piece).digest().bytes().to_vec();
+        pieces.extend(sha1_smol::Sha1::from(&b).digest().bytes());
+
+        let info = Info {
+            name: name.to_owned(),
+            piece_length: 12,
+            pieces,
+            files: Some(vec![
+                metainfo::File {
+                    length: 6,
+                    path: vec!["a.sh".to_owned()],
+                    attr: "x".into(),
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: 6,
+                    path: vec![".pad".to_owned(), "6".to_owned()],
+                    attr: "p".into(),
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: 12,
+                    path: vec!["b.txt".to_owned()],
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: 0,
+                    path: vec!["dir".to_owned(), "link".to_owned()],
+                    attr: "l".into(),
+                    symlink_path: Some(vec!["b.txt".to_owned()]),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+
+        let magnet = format!("magnet:?xt=urn:btih:9999999999999999999999999999999999999999&dn={name}");
+        let mut rng = rand::thread_rng();
+        let download_dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let download_dir = std::env::temp_dir().join(download_dir);
+
+        let (disk_tx, _) = mpsc::channel::<DiskMsg>(3);
+        let (_, rx) = mpsc::channel(5);
+        let mut disk =
+            Disk::new(rx, download_dir.to_string_lossy().into_owned());
+
+        let (fr_tx, _) = mpsc::channel::<DaemonMsg>(300);
+        let magnet = Magnet::new(&magnet).unwrap();
+        let torrent = Torrent::new(disk_tx, fr_tx, magnet);
+        *torrent.ctx.info.write().await = info;
+        *torrent.ctx.bitfield.write().await = Bitfield::repeat(false, 2);
+
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+        let info_hash = torrent.ctx.info_hash;
+        let base = disk.base_path(info_hash).unwrap();
+        *disk.piece_strategy.get_mut(&info_hash).unwrap() =
+            PieceStrategy::Sequential;
+
+        let blocks = [
+            Block { index: 0, begin: 0, block: a.clone() },
+            Block { index: 0, begin: 6, block: vec![0; 6] },
+            Block { index: 1, begin: 0, block: b.clone() },
+        ];
+        for block in blocks {
+            disk.write_block(info_hash, block).await.unwrap();
+        }
+        disk.wait_hashes().await;
+        // the attributes are applied when the torrent is completed
+        disk.wait_moves().await;
+
+        assert!(torrent.ctx.bitfield.read().await.all());
+        assert!(!base.join(".pad").exists());
+
+        // padding is read as zeros, without a file
+        let block_info = BlockInfo { index: 0, begin: 6, len: 6 };
+        assert_eq!(
+            disk.read_block(info_hash, block_info).await.unwrap(),
+            [0; 6]
+        );
+        assert!(!base.join(".pad").exists());
+
+        let mode = fs::metadata(base.join("a.sh")).await.unwrap().permissions();
+        assert_eq!(mode.mode() & 0o111, 0o111);
+
+        let link = base.join("dir").join("link");
+        assert_eq!(
+            fs::read_link(&link).await.unwrap(),
+            Path::new("..").join("b.txt")
+        );
+        assert_eq!(fs::read(&link).await.unwrap(), b);
+
+        tokio::fs::remove_dir_all(&download_dir).await.unwrap();
+    }
+
+    // if we can write, read blocks, and then validate the hash of the pieces
+    #[tokio::test]
+    async fn read_write_blocks_and_validate_pieces() {
+        let name = "qwerty";
+
+        let info = Info {
+            file_length: None,
+            name: name.to_owned(),
+            piece_length: 12,
+            pieces: vec![
+                38, 217, 37, 110, 112, 21, 210, 221, 197, 150, 173, 23, 34,
+                152, 198, 113, 27, 205, 25, 45, 194, 40, 51, 230, 54, 11, 105,
+                175, 141, 19, 33, 54, 17, 20, 203, 34, 160, 241, 116, 6, 203,
+                60, 156, 40, 208, 56, 192, 60, 224, 249, 43, 30, 49, 0, 62, 13,
+                220, 56, 176, 42,
+            ],
+            files: Some(vec![
+                metainfo::File {
+                    length: 12,
+                    path: vec!["foo.txt".to_owned()],
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: 12,
+                    path: vec!["bar".to_owned(), "baz.txt".to_owned()],
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: 12,
+                    path: vec![
+                        "bar".to_owned(),
+                        "buzz".to_owned(),
+                        "bee.txt".to_owned(),
+                    ],
+                    ..Default::default()
+                },
+            ]),
+            private: None,
+            meta_version: None,
+            file_tree: None,
+        };
+
+        let magnet = format!("magnet:?xt=urn:btih:9999999999999999999999999999999999999999&amp;dn={name}&amp;tr=udp%3A%2F%2Ftracker.coppersurfer.tk%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.openbittorrent.com%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&amp;tr=udp%3A%2F%2Fbt.xxx-tracker.com%3A2710%2Fannounce&amp;tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Feddie4.nl%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&amp;tr=udp%3A%2F%2Fp4p.arenabg.com%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.tiny-vps.com%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce");
+        let (disk_tx, _) = mpsc::channel::<DiskMsg>(3);
+
+        let (_, rx) = mpsc::channel(5);
+        let mut disk = Disk::with_storage(rx, MemoryStorage::new());
+
+        let (fr_tx, _) = mpsc::channel::<DaemonMsg>(300);
+        let magnet = Magnet::new(&magnet).unwrap();
+        let torrent = Torrent::new(disk_tx, fr_tx, magnet);
+        let mut info_t = torrent.ctx.info.write().await;
+        *info_t = info.clone();
+        drop(info_t);
+
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+
+        let info_hash = torrent.ctx.info_hash;
+        *disk.piece_strategy.get_mut(&info_hash).unwrap() =
+            PieceStrategy::Sequential;
+
+        let mut p = torrent.ctx.bitfield.write().await;
+        *p = Bitfield::from_vec(vec![255, 255, 255, 255]);
+        drop(info);
+        drop(p);
+
+        //
+        //  WRITE BLOCKS
+        //
+
+        // write a block before reading it
+        // write entire first file (foo.txt)
+        let block = Block {
+            index: 0,
+            begin: 0,
+            block: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+        };
+
+        let result = disk.write_block(info_hash, block.clone()).await;
+        assert!(result.is_ok());
+
+        // validate that the first file contains the bytes that we wrote
+        let block_info =
+            BlockInfo { index: 0, begin: 0, len: block.block.len() as u32 };
+        let result = disk.read_block(info_hash, block_info).await;
+        assert_eq!(result.unwrap(), block.block);
+
+        // write a block before reading it
+        // write entire second file (/bar/baz.txt)
+        let block = Block {
+            index: 1,
+            begin: 0,
+            block: vec![13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24],
+        };
+
+        let result = disk.write_block(info_hash, block.clone()).await;
+        assert!(result.is_ok());
+
+        // validate that the second file contains the bytes that we wrote
+        let block_info = BlockInfo { index: 1, begin: 0, len: 12 };
+        let result = disk.read_block(info_hash, block_info).await;
+        assert_eq!(result.unwrap(), block.block);
+
+        // write a block before reading it
+        // write entire third file (/bar/buzz/bee.txt)
+        let block = Block {
+            index: 2,
+            begin: 0,
+            block: vec![25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36],
+        };
+
+        let result = disk.write_block(info_hash, block.clone()).await;
+        assert!(result.is_ok());
+
+        // validate that the third file contains the bytes that we wrote
+        let block_info = BlockInfo { index: 2, begin: 0, len: 12 };
+        let result = disk.read_block(info_hash, block_info).await;
+        assert_eq!(result.unwrap(), block.block);
+
+        //
+        //  READ BLOCKS with offsets
+        //
+
+        let block_info = BlockInfo { index: 0, begin: 1, len: 3 };
+
+        // read piece 1 block from first file
+        let result = disk.read_block(info_hash, block_info).await;
+        assert_eq!(result.unwrap(), vec![2, 3, 4]);
+
+        let block_info = BlockInfo { index: 0, begin: 9, len: 3 };
+
+        // read piece 0 block from first file
+        let result = disk.read_block(info_hash, block_info).await;
+        assert_eq!(result.unwrap(), vec![10, 11, 12]);
+
+        // last three bytes of file
+        let block_info = BlockInfo { index: 1, begin: 9, len: 3 };
+
+        // read piece 2 block from second file
+        let result = disk.read_block(info_hash, block_info).await;
+        assert_eq!(result.unwrap(), vec![22, 23, 24]);
+
+        let block_info = BlockInfo { index: 1, begin: 1, len: 6 };
+
+        // read piece 2 block from second file
+        let result = disk.read_block(info_hash, block_info).await;
+        assert_eq!(result.unwrap(), vec![14, 15, 16, 17, 18, 19]);
+
+        let block_info = BlockInfo { index: 2, begin: 0, len: 6 };
+
+        // read piece 3 block from third file
+        let result = disk.read_block(info_hash, block_info).await;
+        assert_eq!(result.unwrap(), vec![25, 26, 27, 28, 29, 30]);
+    }
+
+    #[tokio::test]
+    async fn seek_files() {
+        let name = "seekfiles";
+
+        let info = Info {
+            piece_length: 32768,
+            pieces: vec![0; 5480], // 274 pieces * 20 bytes each
+            name: "name".to_string(),
+            file_length: None,
+            files: Some(vec![
+                // 308 blocks
+                metainfo::File {
+                    length: 5034059,
+                    path: vec!["dir".to_string(), "file_a.pdf".to_string()],
+                    ..Default::default()
+                },
+                // 1 block
+                metainfo::File {
+                    length: 62,
+                    path: vec!["file_1.txt".to_string()],
+                    ..Default::default()
+                },
+                // 1 block
+                metainfo::File {
+                    length: 237,
+                    path: vec!["file_2.txt".to_string()],
+                    ..Default::default()
+                },
+            ]),
+            private: None,
+            meta_version: None,
+            file_tree: None,
+        };
+
+        let magnet = format!("magnet:?xt=urn:btih:9999999999999999999999999999999999999999&amp;dn={name}&amp;tr=udp%3A%2F%2Ftracker.coppersurfer.tk%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.openbittorrent.com%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&amp;tr=udp%3A%2F%2Fbt.xxx-tracker.com%3A2710%2Fannounce&amp;tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Feddie4.nl%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&amp;tr=udp%3A%2F%2Fp4p.arenabg.com%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.tiny-vps.com%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce");
+        let (disk_tx, _) = mpsc::channel::<DiskMsg>(3);
+
+        let (_, rx) = mpsc::channel(5);
+        let mut disk = Disk::with_storage(rx, MemoryStorage::new());
+
+        let (fr_tx, _) = mpsc::channel::<DaemonMsg>(300);
+        let magnet = Magnet::new(&magnet).unwrap();
+        let torrent = Torrent::new(disk_tx, fr_tx, magnet);
+        let mut info_t = torrent.ctx.info.write().await;
+        *info_t = info.clone();
+        drop(info_t);
+
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+
+        let info_hash = torrent.ctx.info_hash;
+        *disk.piece_strategy.get_mut(&info_hash).unwrap() =
+            PieceStrategy::Sequential;
+
+        let mut p = torrent.ctx.bitfield.write().await;
+        *p = Bitfield::from_vec(vec![
+            255, 255, 255, 255, 255, 255, 255, 255, 255,
+        ]);
+        drop(info);
+        drop(p);
+
+        //
+        //  WRITE BLOCKS
+        //
+
+        // write a block before reading it
+        let block = Block { index: 0, begin: 0, block: vec![0; 5034059] };
+
+        let result = disk.write_block(info_hash, block.clone()).await;
+        assert!(result.is_ok());
+
+        // write a block before reading it
+        let block = Block { index: 153, begin: 20555, block: vec![0; 62] };
+
+        let result = disk.write_block(info_hash, block.clone()).await;
+        assert!(result.is_ok());
+
+        // let block_info = Block {
+        //     index: 153,
+        //     begin: 20617,
+        //     block: vec![0; 237],
+        // };
+        let result = disk.write_block(info_hash, block.clone()).await;
+        assert!(result.is_ok());
+    }
+
+    // blocks of a torrent with a few terabytes must be mapped to the right
+    // file and offset, without writing anything.
+    #[tokio::test]
+    async fn multi_terabyte_offsets() {
+        const TIB: u64 = 1024 * 1024 * 1024 * 1024;
+        let piece_length = 16 * 1024 * 1024;
+        let name = "big".to_owned();
+        let files = vec![
+            metainfo::File {
+                length: 2 * TIB,
+                path: vec!["a.bin".to_owned()],
+                ..Default::default()
+            },
+            metainfo::File {
+                length: TIB + 1,
+                path: vec!["b.bin".to_owned()],
+                ..Default::default()
+            },
+            metainfo::File {
+                length: 3,
+                path: vec!["c.bin".to_owned()],
+                ..Default::default()
+            },
+        ];
+        let info = Info {
+            name: name.clone(),
+            piece_length,
+            pieces: vec![0; 196_609 * 20],
+            file_length: None,
+            files: Some(files.clone()),
+            private: None,
+            meta_version: None,
+            file_tree: None,
+        };
+
+        let mut rng = rand::thread_rng();
+        let download_dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(3);
+        let (daemon_tx, _) = mpsc::channel::<DaemonMsg>(3);
+        let magnet = Magnet::new(&format!(
+            "magnet:?xt=urn:btih:9999999999999999999999999999999999999999&dn={name}"
+        ))
+        .unwrap();
+        let torrent = Torrent::new(disk_tx, daemon_tx, magnet);
+        let info_hash = torrent.ctx.info_hash;
+        *torrent.ctx.info.write().await = info.clone();
+
+        // `new_torrent` would generate all the block infos of the torrent,
+        // only the caches that are used here are created.
+        let mut disk = Disk::new(disk_rx, download_dir.clone());
+        disk.torrent_ctxs.insert(info_hash, torrent.ctx.clone());
+        disk.torrent_info.insert(
+            info_hash,
+            TorrentInfo {
+                total_size: info.get_size(),
+                piece_length,
+                pieces: info.pieces(),
+                layout: Layout::from_info(&info),
+                v2_files: Vec::new(),
+            },
+        );
+        // the files are created empty
+        disk.storage
+            .create(info_hash, &Layout::from_info(&info))
+            .await
+            .unwrap();
+
+        assert_eq!(disk.piece_size(info_hash, 0).unwrap(), piece_length);
+        assert_eq!(disk.piece_size(info_hash, 196_608).unwrap(), 4);
+
+        let cases = [
+            // last block of the first file
+            (131_071, piece_length - BLOCK_LEN, &files[0], 2 * TIB - 16384),
+            (131_072, 0, &files[1], 0),
+            (196_608, 0, &files[1], TIB),
+            (196_608, 1, &files[2], 0),
+        ];
+
+        for (index, begin, file, offset) in cases {
+            let block_info = BlockInfo { index, begin, len: 1 };
+            let (mut fs_file, meta_file) = disk
+                .get_file_from_block_info(&block_info, info_hash)
+                .await
+                .unwrap();
+
+            assert_eq!(&meta_file, file);
+            assert_eq!(fs_file.stream_position().await.unwrap(), offset);
+        }
+
+        tokio::fs::remove_dir_all(&download_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn hostile_paths_stay_in_download_dir() {
+        let mut rng = rand::thread_rng();
+        let download_dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let download_dir = std::env::temp_dir().join(download_dir);
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(1000);
+        let (daemon_tx, _daemon_rx) = mpsc::channel::<DaemonMsg>(1000);
+        let mut disk =
+            Disk::new(disk_rx, download_dir.to_string_lossy().into_owned());
+
+        let bytes =
+            include_bytes!("../../../../test-files/hostile/absolute.torrent");
+        let info_hash = metainfo::MetaInfo::info_hash(bytes).unwrap();
+        let info = metainfo::MetaInfo::from_bencode(bytes).unwrap().info;
+        let magnet = format!("magnet:?xt=urn:btih:{}", hex::encode(info_hash));
+        let torrent = Torrent::new(
+            disk_tx.clone(),
+            daemon_tx.clone(),
+            Magnet::new(&magnet).unwrap(),
+        );
+        *torrent.ctx.info.write().await = info;
+
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+
+        let base = disk.base_path(info_hash).unwrap();
+        assert_eq!(base, download_dir.join("_tmp"));
+        assert!(base.join("_etc").join("cron.d").join("evil").is_file());
+        assert!(base.join("C:_Windows").join("evil.dll").is_file());
+        assert_eq!(std::fs::read_dir(&download_dir).unwrap().count(), 1);
+
+        // torrents with paths that can't be made safe are not created
+        let bytes =
+            include_bytes!("../../../../test-files/hostile/traversal.torrent");
+        let info_hash = metainfo::MetaInfo::info_hash(bytes).unwrap();
+        let info = metainfo::MetaInfo::from_bencode(bytes).unwrap().info;
+        let magnet = format!("magnet:?xt=urn:btih:{}", hex::encode(info_hash));
+        let torrent =
+            Torrent::new(disk_tx, daemon_tx, Magnet::new(&magnet).unwrap());
+        *torrent.ctx.info.write().await = info;
+
+        assert!(matches!(
+            disk.new_torrent(torrent.ctx.clone()).await,
+            Err(Error::UnsafePath(_))
+        ));
+        assert!(!disk.torrent_ctxs.contains_key(&info_hash));
+        assert_eq!(std::fs::read_dir(&download_dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(download_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_and_read_caches() {
+        let mut rng = rand::thread_rng();
+        let download_dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let download_dir = std::env::temp_dir().join(download_dir);
+
+        // two pieces of 12 bytes, the first crosses both files
+        let data: Vec<u8> = (0..24).collect();
+        let info = Info {
+            name: "cache".to_owned(),
+            piece_length: 12,
+            pieces: data
+                .chunks(12)
+                .flat_map(|p| sha1_smol::Sha1::from(p).digest().bytes())
+                .collect(),
+            files: Some(vec![
+                metainfo::File {
+                    length: 8,
+                    path: vec!["a".to_owned()],
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: 16,
+                    path: vec!["b".to_owned()],
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel::<DaemonMsg>(10);
+        let magnet = Magnet::new(
+            "magnet:?xt=urn:btih:9999999999999999999999999999999999999999",
+        )
+        .unwrap();
+        let mut torrent = Torrent::new(disk_tx, daemon_tx, magnet);
+        let info_hash = torrent.ctx.info_hash;
+        *torrent.ctx.info.write().await = info;
+        *torrent.ctx.bitfield.write().await = Bitfield::repeat(false, 2);
+
+        // only one block of 6 bytes fits in the cache
+        let mut disk =
+            Disk::new(disk_rx, download_dir.to_string_lossy().into_owned())
+                .cache_size(6);
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+        *disk.piece_strategy.get_mut(&info_hash).unwrap() =
+            PieceStrategy::Sequential;
+
+        let block = |index: usize, begin: u32| Block {
+            index,
+            begin,
+            block: data[index * 12 + begin as usize..][..6].to_vec(),
+        };
+
+        disk.write_block(info_hash, block(0, 0)).await.unwrap();
+        assert_eq!(disk.cache.stats().used, 6);
+
+        // the first block is written to disk, to make space for this one
+        disk.write_block(info_hash, block(1, 6)).await.unwrap();
+        assert_eq!(disk.cache.stats().evictions, 1);
+        assert_eq!(
+            fs::read(download_dir.join("cache").join("a")).await.unwrap(),
+            data[..6]
+        );
+
+        // the blocks are still read before they are written
+        let block_info = BlockInfo { index: 1, begin: 7, len: 2 };
+        assert_eq!(
+            disk.read_block(info_hash, block_info).await.unwrap(),
+            data[19..21]
+        );
+
+        // and the pieces are validated, even if they are read from disk
+        disk.write_block(info_hash, block(0, 6)).await.unwrap();
+        disk.write_block(info_hash, block(1, 0)).await.unwrap();
+        disk.wait_hashes().await;
+
+        assert!(torrent.ctx.bitfield.read().await.all());
+        let mut downloaded = Vec::new();
+        while let Ok(msg) = torrent.rx.try_recv() {
+            if let TorrentMsg::DownloadedPiece(piece) = msg {
+                downloaded.push(piece);
+            }
+        }
+        // the pieces are hashed in parallel
+        downloaded.sort();
+        assert_eq!(downloaded, [0, 1]);
+
+        let mut files =
+            fs::read(download_dir.join("cache").join("a")).await.unwrap();
+        files.extend(
+            fs::read(download_dir.join("cache").join("b")).await.unwrap(),
+        );
+        assert_eq!(files, data);
+
+        let stats = disk.cache.stats();
+        assert_eq!((stats.used, stats.capacity), (0, 6));
+        assert!(stats.misses >= 1 && stats.hits >= 1);
+
+        // the entire piece is read when the first block is requested
+        let first = BlockInfo { index: 1, begin: 0, len: 6 };
+        assert_eq!(
+            disk.read_block(info_hash, first).await.unwrap(),
+            data[12..18]
+        );
+
+        // both files are kept open, until the torrent is paused or removed
+        assert_eq!(disk.storage.open_files(), 2);
+        disk.storage.close(info_hash);
+        assert_eq!(disk.storage.open_files(), 0);
+        fs::remove_dir_all(&download_dir).await.unwrap();
+
+        let second = BlockInfo { index: 1, begin: 6, len: 6 };
+        assert_eq!(
+            disk.read_block(info_hash, second).await.unwrap(),
+            data[18..24]
+        );
+        assert_eq!(disk.read_cache.stats().hits, 1);
+    }
+
+    // pieces are hashed by the workers while the disk keeps handling
+    // messages, and the result is sent back to who asked.
+    #[tokio::test]
+    async fn validate_pieces_in_workers() {
+        let data: Vec<u8> = (0..24).collect();
+        let info = Info {
+            name: "hashing".to_owned(),
+            piece_length: 12,
+            pieces: data
+                .chunks(12)
+                .flat_map(|p| sha1_smol::Sha1::from(p).digest().bytes())
+                .collect(),
+            file_length: Some(24),
+            ..Default::default()
+        };
+
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel::<DaemonMsg>(10);
+        let magnet = Magnet::new(
+            "magnet:?xt=urn:btih:9999999999999999999999999999999999999999",
+        )
+        .unwrap();
+        let torrent = Torrent::new(disk_tx.clone(), daemon_tx, magnet);
+        let info_hash = torrent.ctx.info_hash;
+        *torrent.ctx.info.write().await = info;
+
+        let mut disk = Disk::with_storage(disk_rx, MemoryStorage::new())
+            .hashing_threads(Some(2));
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+
+        // the second piece is corrupted
+        disk.storage.write(info_hash, 0, &data[..12]).await.unwrap();
+        disk.storage.write(info_hash, 12, &[0; 12]).await.unwrap();
+
+        let handle = tokio::spawn(async move {
+            disk.run().await.unwrap();
+            disk
+        });
+
+        let mut results = Vec::new();
+        for piece in [0, 1, 2] {
+            let (tx, rx) = oneshot::channel();
+            disk_tx
+                .send(DiskMsg::ValidatePiece {
+                    info_hash,
+                    recipient: tx,
+                    piece,
+                })
+                .await
+                .unwrap();
+            results.push(rx);
+        }
+
+        assert!(results.remove(0).await.unwrap().is_ok());
+        assert!(matches!(
+            results.remove(0).await.unwrap(),
+            Err(Error::PieceInvalid)
+        ));
+        // there is no third piece
+        assert!(results.remove(0).await.unwrap().is_err());
+
+        disk_tx.send(DiskMsg::Quit).await.unwrap();
+        let disk = handle.await.unwrap();
+        assert_eq!(disk.hashing, 0);
+    }
+
+    // the torrent is only completed after its pieces are verified, and the
+    // complete hook of the torrent only runs after the disk has the files at
+    // their final location.
+    #[tokio::test]
+    async fn complete_answers_the_torrent() {
+        let mut rng = rand::thread_rng();
+        let dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let dir = std::env::temp_dir().join(dir);
+        let download_dir = dir.join("complete");
+        let incomplete_dir = dir.join("incomplete");
+
+        let data: Vec<u8> = (0..12).collect();
+        let info = Info {
+            name: "complete".to_owned(),
+            piece_length: 12,
+            pieces: sha1_smol::Sha1::from(&data).digest().bytes().to_vec(),
+            file_length: Some(12),
+            ..Default::default()
+        };
+
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel::<DaemonMsg>(10);
+        let magnet = Magnet::new(
+            "magnet:?xt=urn:btih:9999999999999999999999999999999999999999",
+        )
+        .unwrap();
+        let mut torrent = Torrent::new(disk_tx, daemon_tx, magnet);
+        let info_hash = torrent.ctx.info_hash;
+        *torrent.ctx.info.write().await = info;
+        *torrent.ctx.bitfield.write().await = Bitfield::repeat(false, 1);
+
+        let mut disk =
+            Disk::new(disk_rx, download_dir.to_string_lossy().into_owned())
+                .incomplete_dir(Some(
+                    incomplete_dir.to_string_lossy().into_owned(),
+                ))
+                .part_suffix(true);
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+        *disk.piece_strategy.get_mut(&info_hash).unwrap() =
+            PieceStrategy::Sequential;
+
+        // all bytes were downloaded, but the piece is corrupted
+        let block = Block { index: 0, begin: 0, block: vec![0; 12] };
+        disk.write_block(info_hash, block).await.unwrap();
+        disk.wait_hashes().await;
+
+        while let Ok(msg) = torrent.rx.try_recv() {
+            assert!(!matches!(
+                msg,
+                TorrentMsg::DownloadComplete | TorrentMsg::StorageComplete
+            ));
+        }
+        assert!(incomplete_dir.join("complete.part").is_file());
+
+        let block = Block { index: 0, begin: 0, block: data.clone() };
+        disk.write_block(info_hash, block).await.unwrap();
+        disk.wait_hashes().await;
+
+        // the files are moved outside of the event loop
+        assert!(disk.moving.contains_key(&info_hash));
+        disk.wait_moves().await;
+
+        let mut msgs = Vec::new();
+        while let Ok(msg) = torrent.rx.try_recv() {
+            match msg {
+                TorrentMsg::DownloadComplete => msgs.push("download"),
+                TorrentMsg::StorageComplete => msgs.push("storage"),
+                _ => {}
+            }
+        }
+        assert_eq!(msgs, ["download", "storage"]);
+        assert_eq!(torrent.ctx.bitfield.read().await.count_ones(), 1);
+        assert_eq!(
+            fs::read(download_dir.join("complete")).await.unwrap(),
+            data
+        );
+        assert!(!incomplete_dir.join("complete.part").exists());
+        assert_eq!(
+            disk.base_path(info_hash).unwrap(),
+            download_dir.join("complete")
+        );
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    // the files are moved outside of the event loop, and the I/O of the
+    // torrent waits for them.
+    #[tokio::test]
+    async fn move_storage_parks_the_torrent() {
+        let mut rng = rand::thread_rng();
+        let download_dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let download_dir = std::env::temp_dir().join(download_dir);
+        let moved_dir = download_dir.join("moved");
+
+        let data: Vec<u8> = (0..12).collect();
+        let info = Info {
+            name: "move".to_owned(),
+            piece_length: 12,
+            pieces: sha1_smol::Sha1::from(&data).digest().bytes().to_vec(),
+            file_length: Some(12),
+            ..Default::default()
+        };
+
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel::<DaemonMsg>(10);
+        let magnet = Magnet::new(
+            "magnet:?xt=urn:btih:9999999999999999999999999999999999999999",
+        )
+        .unwrap();
+        let mut torrent = Torrent::new(disk_tx.clone(), daemon_tx, magnet);
+        let info_hash = torrent.ctx.info_hash;
+        *torrent.ctx.info.write().await = info;
+
+        let mut disk =
+            Disk::new(disk_rx, download_dir.to_string_lossy().into_owned());
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+        disk.storage.write(info_hash, 0, &data).await.unwrap();
+        let handle = tokio::spawn(async move { disk.run().await });
+
+        disk_tx
+            .send(DiskMsg::MoveStorage { info_hash, dir: moved_dir.clone() })
+            .await
+            .unwrap();
+        let (tx, rx) = oneshot::channel();
+        disk_tx
+            .send(DiskMsg::ValidatePiece { info_hash, recipient: tx, piece: 0 })
+            .await
+            .unwrap();
+
+        let mut base_path = None;
+        loop {
+            match torrent.rx.recv().await.unwrap() {
+                TorrentMsg::StorageMoved(dir) => {
+                    assert_eq!(dir, moved_dir);
+                    break;
+                }
+                TorrentMsg::MoveProgress(_) => {}
+                TorrentMsg::BasePath(path) => base_path = Some(path),
+                msg => panic!("unexpected {msg:?}"),
+            }
+        }
+        // the hooks get the new location
+        assert_eq!(base_path, Some(moved_dir.join("move")));
+        // the piece is read from the new location
+        assert!(rx.await.unwrap().is_ok());
+        assert_eq!(fs::read(moved_dir.join("move")).await.unwrap(), data);
+        assert!(!download_dir.join("move").exists());
+
+        disk_tx.send(DiskMsg::Quit).await.unwrap();
+        handle.await.unwrap().unwrap();
+        fs::remove_dir_all(&download_dir).await.unwrap();
+    }
+
+    // the pieces of v2 only torrents are verified with the merkle tree of
+    // their files, larger files only after the hashes of the pieces are known.
+    #[tokio::test]
+    async fn validate_v2_only_pieces() {
+        async fn validate(
+            disk: &mut Disk<MemoryStorage>,
+            info_hash: [u8; 20],
+            index: usize,
+        ) -> Result<(), Error> {
+            let (tx, rx) = oneshot::channel();
+            disk.validate_piece(info_hash, index, Some(tx)).await?;
+            disk.wait_hashes().await;
+            rx.await.unwrap()
+        }
+
+        let a: Vec<u8> = (0..BLOCK_LEN + 100).map(|i| i as u8).collect();
+        let b = vec![7; 10];
+        let root = merkle::file_root(&a).unwrap();
+        let tree = BTreeMap::from([
+            (
+                "a".to_owned(),
+                metainfo::FileTree::File {
+                    length: a.len() as u64,
+                    pieces_root: Some(root),
+                },
+            ),
+            (
+                "b".to_owned(),
+                metainfo::FileTree::File {
+                    length: 10,
+                    pieces_root: merkle::file_root(&b),
+                },
+            ),
+        ]);
+        let mut info = Info {
+            name: "v2".to_owned(),
+            piece_length: BLOCK_LEN,
+            meta_version: Some(2),
+            file_tree: Some(metainfo::FileTree::Dir(tree)),
+            ..Default::default()
+        };
+        info.align_v2_files();
+
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel::<DaemonMsg>(10);
+        let magnet = Magnet::new(
+            "magnet:?xt=urn:btih:9999999999999999999999999999999999999999",
+        )
+        .unwrap();
+        let torrent = Torrent::new(disk_tx, daemon_tx, magnet);
+        let info_hash = torrent.ctx.info_hash;
+        *torrent.ctx.info.write().await = info;
+
+        let mut disk = Disk::with_storage(disk_rx, MemoryStorage::new());
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+
+        disk.storage.write(info_hash, 0, &a).await.unwrap();
+        disk.storage.write(info_hash, BLOCK_LEN as u64 * 2, &b).await.unwrap();
+
+        // the hashes of the pieces of `a` are not known yet
+        assert!(validate(&mut disk, info_hash, 0).await.is_err());
+        assert!(validate(&mut disk, info_hash, 1).await.is_err());
+        assert!(validate(&mut disk, info_hash, 2).await.is_ok());
+
+        torrent
+            .ctx
+            .piece_layers
+            .write()
+            .await
+            .insert(root, merkle::piece_layer(&a, BLOCK_LEN).concat());
+
+        assert!(validate(&mut disk, info_hash, 0).await.is_ok());
+        assert!(validate(&mut disk, info_hash, 1).await.is_ok());
+
+        disk.storage
+            .write(info_hash, BLOCK_LEN as u64 * 2, &[8])
+            .await
+            .unwrap();
+        assert!(validate(&mut disk, info_hash, 2).await.is_err());
+    }
+
+    // an I/O error only stops the torrent that failed, and the torrent can
+    // be retried after the disk is back, without downloading again.
+    #[tokio::test]
+    async fn retry_torrent_after_disk_error() {
+        let mut rng = rand::thread_rng();
+        let download_dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let download_dir = std::env::temp_dir().join(download_dir);
+
+        let data: Vec<u8> = (0..12).collect();
+        let info = Info {
+            name: "broken".to_owned(),
+            piece_length: 12,
+            pieces: sha1_smol::Sha1::from(&data).digest().bytes().to_vec(),
+            file_length: Some(12),
+            ..Default::default()
+        };
+
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel::<DaemonMsg>(10);
+        let magnet = Magnet::new(
+            "magnet:?xt=urn:btih:9999999999999999999999999999999999999999",
+        )
+        .unwrap();
+        let mut torrent = Torrent::new(disk_tx.clone(), daemon_tx, magnet);
+        let info_hash = torrent.ctx.info_hash;
+        *torrent.ctx.info.write().await = info;
+        *torrent.ctx.bitfield.write().await = Bitfield::repeat(false, 1);
+
+        let mut disk =
+            Disk::new(disk_rx, download_dir.to_string_lossy().into_owned());
+        disk.new_torrent(torrent.ctx.clone()).await.unwrap();
+
+        // the disk is unmounted, and the files can't be opened
+        disk.storage.close(info_hash);
+        fs::remove_dir_all(&download_dir).await.unwrap();
+        fs::write(&download_dir, b"").await.unwrap();
+
+        let handle = tokio::spawn(async move {
+            disk.run().await.unwrap();
+            disk
+        });
+
+        // requests of blocks out of the torrent are not errors of the disk
+        for block_info in [
+            BlockInfo { index: 1, begin: 0, len: 6 },
+            BlockInfo { index: 0, begin: 6, len: 12 },
+            BlockInfo { index: 0, begin: 0, len: BLOCK_LEN + 1 },
+            BlockInfo { index: 0, begin: u32::MAX, len: u32::MAX },
+        ] {
+            let (recipient, rx) = oneshot::channel();
+            disk_tx
+                .send(DiskMsg::ReadBlock { block_info, recipient, info_hash })
+                .await
+                .unwrap();
+            assert!(rx.await.is_err());
+        }
+        let (tx, rx) = oneshot::channel();
+        disk_tx.send(DiskMsg::CacheStats(tx)).await.unwrap();
+        assert!(rx.await.is_ok());
+        assert!(torrent.rx.try_recv().is_err());
+
+        // a peer that has the piece, to score the pieces after it
+        let (peer_tx, _peer_rx) = mpsc::channel(10);
+        let peer = Arc::new(PeerCtx {
+            direction: crate::peer::Direction::Outbound,
+            tx: peer_tx,
+            pieces: RwLock::new(Bitfield::repeat(true, 1)),
+            id: [1; 20],
+            remote_addr: "10.0.0.2:6881".parse().unwrap(),
+            local_addr: "127.0.0.1:6881".parse().unwrap(),
+            info_hash,
+        });
+        disk_tx.send(DiskMsg::NewPeer(peer)).await.unwrap();
+
+        // a block out of the torrent is not an error of the disk
+        let block = Block { index: 1, begin: 0, block: vec![0; 6] };
+        disk_tx.send(DiskMsg::WriteBlock { info_hash, block }).await.unwrap();
+
+        for begin in [0, 6] {
+            let block = Block {
+                index: 0,
+                begin,
+                block: data[begin as usize..][..6].to_vec(),
+            };
+            disk_tx
+                .send(DiskMsg::WriteBlock { info_hash, block })
+                .await
+                .unwrap();
+        }
+
+        loop {
+            match torrent.rx.recv().await.unwrap() {
+                TorrentMsg::DiskError(_) => break,
+                TorrentMsg::IncrementDownloaded(_) => {}
+                msg => panic!("unexpected {msg:?}"),
+            }
+        }
+
+        // the event loop is still running
+        let (tx, rx) = oneshot::channel();
+        disk_tx.send(DiskMsg::CacheStats(tx)).await.unwrap();
+        assert!(rx.await.is_ok());
+
+        // the disk is mounted again
+        fs::remove_file(&download_dir).await.unwrap();
+        disk_tx.send(DiskMsg::Retry(info_hash)).await.unwrap();
+
+        let (mut ready, mut downloaded) = (false, false);
+        while !ready || !downloaded {
+            match torrent.rx.recv().await.unwrap() {
+                TorrentMsg::DiskReady => ready = true,
+                TorrentMsg::DownloadedPiece(0) => downloaded = true,
+                msg => panic!("unexpected {msg:?}"),
+            }
+        }
+
+        assert!(torrent.ctx.bitfield.read().await.all());
+        assert_eq!(fs::read(download_dir.join("broken")).await.unwrap(), data);
+
+        disk_tx.send(DiskMsg::Quit).await.unwrap();
+        let disk = handle.await.unwrap();
+        assert!(disk.unwritten.is_empty());
+        assert_eq!(disk.downloaded_pieces[&info_hash], [12]);
+        assert_eq!(disk.piece_strategy[&info_hash], PieceStrategy::Rarest);
+        fs::remove_dir_all(&download_dir).await.unwrap();
+    }
+}
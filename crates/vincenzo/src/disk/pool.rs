@@ -0,0 +1,147 @@
+//! Pool of the open files of all torrents.
+//!
+//! Opening a file for every block that is read or written is expensive on
+//! torrents with many files, so the [`Disk`](super::Disk) keeps the files
+//! open, up to a maximum, closing the least recently used ones first.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use hashbrown::HashMap;
+use tokio::fs::File;
+
+use crate::error::Error;
+
+use super::FsStorage;
+
+/// Default maximum of open files.
+pub const DEFAULT_MAX_OPEN_FILES: usize = 512;
+
+#[derive(Debug)]
+struct OpenFile {
+    file: File,
+    info_hash: [u8; 20],
+    last_use: u64,
+}
+
+/// Open files of the torrents, the least recently used files are closed when
+/// the pool is full.
+#[derive(Debug)]
+pub struct FilePool {
+    max_open: usize,
+    /// Incremented on every access, to order the files by their last use.
+    clock: u64,
+    files: HashMap<PathBuf, OpenFile>,
+    /// The paths of the files ordered by their last use.
+    lru: BTreeMap<u64, PathBuf>,
+}
+
+impl Default for FilePool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_OPEN_FILES)
+    }
+}
+
+impl FilePool {
+    /// A pool with at most `max_open` open files, at least one file is
+    /// always kept open.
+    pub fn new(max_open: usize) -> Self {
+        Self {
+            max_open: max_open.max(1),
+            clock: 0,
+            files: HashMap::new(),
+            lru: BTreeMap::new(),
+        }
+    }
+
+    /// Get the open file of `path`, the file is opened if it is not in the
+    /// pool.
+    pub async fn get(
+        &mut self,
+        info_hash: [u8; 20],
+        path: &Path,
+    ) -> Result<&mut File, Error> {
+        self.clock += 1;
+
+        if let Some(open) = self.files.get_mut(path) {
+            self.lru.remove(&open.last_use);
+            self.lru.insert(self.clock, path.to_owned());
+            open.last_use = self.clock;
+            return Ok(&mut self.files.get_mut(path).unwrap().file);
+        }
+
+        while self.files.len() >= self.max_open {
+            let Some((_, path)) = self.lru.pop_first() else { break };
+            self.files.remove(&path);
+        }
+
+        let file = FsStorage::open_file(path).await?;
+        self.lru.insert(self.clock, path.to_owned());
+        let open = OpenFile { file, info_hash, last_use: self.clock };
+
+        self.files.insert(path.to_owned(), open);
+
+        Ok(&mut self.files.get_mut(path).unwrap().file)
+    }
+
+    /// Close the file of `path`, it will be opened again when it is used.
+    pub fn close(&mut self, path: &Path) {
+        if let Some(open) = self.files.remove(path) {
+            self.lru.remove(&open.last_use);
+        }
+    }
+
+    /// Close all files of a torrent.
+    pub fn close_torrent(&mut self, info_hash: [u8; 20]) {
+        self.files.retain(|_, open| open.info_hash != info_hash);
+        self.lru.retain(|_, path| self.files.contains_key(path));
+    }
+
+    /// How many files are open.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn close_least_recently_used() {
+        let mut rng = rand::thread_rng();
+        let dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let dir = std::env::temp_dir().join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut pool = FilePool::new(2);
+        pool.get([0; 20], &dir.join("a")).await.unwrap();
+        pool.get([1; 20], &dir.join("b")).await.unwrap();
+        pool.get([0; 20], &dir.join("a")).await.unwrap();
+
+        // "b" was used less recently than "a"
+        pool.get([0; 20], &dir.join("c")).await.unwrap();
+        assert_eq!(pool.len(), 2);
+        assert!(pool.files.contains_key(&dir.join("a")));
+        assert!(pool.files.contains_key(&dir.join("c")));
+
+        pool.close(&dir.join("c"));
+        pool.get([1; 20], &dir.join("b")).await.unwrap();
+        pool.close_torrent([0; 20]);
+
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.lru.len(), 1);
+        assert!(pool.files.contains_key(&dir.join("b")));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
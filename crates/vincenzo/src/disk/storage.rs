@@ -0,0 +1,292 @@
+//! Where the [`Disk`](super::Disk) stores the bytes of the torrents.
+//!
+//! The disk sees a torrent as a sequence of bytes, and the [`Storage`] maps
+//! them to files, memory or anything else. The disk takes care of the caches,
+//! the pieces to download and their validation.
+
+use std::{future::Future, ops::Range, path::Path};
+
+use serde::{Deserialize, Serialize};
+use speedy::{Readable, Writable};
+
+use crate::{error::Error, metainfo::Info};
+
+/// A file of a torrent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StorageFile {
+    /// Path of the file, relative to the root of the torrent. Empty on single
+    /// file torrents, where the root is the file itself.
+    pub path: Vec<String>,
+    /// Where the file starts, in the bytes of the torrent.
+    pub offset: u64,
+    pub length: u64,
+    /// Padding files are never written, BEP 47.
+    pub padding: bool,
+    pub executable: bool,
+    /// Target of a symlink, relative to the root of the torrent, BEP 47.
+    pub symlink: Option<Vec<String>>,
+}
+
+/// How the files of a torrent are allocated when they are created.
+#[derive(
+    Serialize,
+    Deserialize,
+    Readable,
+    Writable,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Preallocation {
+    /// The files are empty, and grow as the pieces are written.
+    #[default]
+    None,
+    /// The files have their full size, but the blocks of the disk are only
+    /// allocated when the pieces are written.
+    Sparse,
+    /// All blocks of the files are allocated before the download, which
+    /// avoids fragmentation, and running out of space in the middle of it.
+    Full,
+}
+
+/// How the bytes of a torrent are split in files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Layout {
+    /// Name of the root directory of the torrent, or of the file on single
+    /// file torrents.
+    pub name: String,
+    pub files: Vec<StorageFile>,
+}
+
+impl Layout {
+    pub fn from_info(info: &Info) -> Self {
+        let Some(files) = &info.files else {
+            return Self {
+                name: info.name.clone(),
+                files: vec![StorageFile {
+                    length: info.file_length.unwrap_or_default(),
+                    ..Default::default()
+                }],
+            };
+        };
+
+        let mut offset = 0;
+        let files = files
+            .iter()
+            .map(|f| {
+                let file = StorageFile {
+                    path: f.path.clone(),
+                    offset,
+                    length: f.length,
+                    padding: f.attr.padding,
+                    executable: f.attr.executable,
+                    symlink: f
+                        .attr
+                        .symlink
+                        .then(|| f.symlink_path.clone())
+                        .flatten(),
+                };
+                offset += f.length;
+                file
+            })
+            .collect();
+
+        Self { name: info.name.clone(), files }
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.files.last().map(|f| f.offset + f.length).unwrap_or_default()
+    }
+
+    /// The files that have bytes in `offset..offset + len`, with the offset
+    /// in the file, and the range of these bytes. Padding files are skipped.
+    pub fn spans(
+        &self,
+        offset: u64,
+        len: usize,
+    ) -> Vec<(&StorageFile, u64, Range<usize>)> {
+        let end = offset + len as u64;
+
+        self.files
+            .iter()
+            .filter(|f| !f.padding)
+            .filter_map(|f| {
+                let start = offset.max(f.offset);
+                let stop = end.min(f.offset + f.length);
+                (start < stop).then(|| {
+                    (
+                        f,
+                        start - f.offset,
+                        (start - offset) as usize..(stop - offset) as usize,
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// A backend where the bytes of torrents are stored.
+///
+/// All offsets are in the bytes of the torrent, where the first byte of a
+/// piece is at `piece * piece_length`. Writes never touch padding files.
+pub trait Storage: std::fmt::Debug + Send {
+    /// Prepare the storage for a new torrent, i.e. creating its empty files
+    /// and directories.
+    fn create(
+        &mut self,
+        info_hash: [u8; 20],
+        layout: &Layout,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Allocate the files of a torrent differently than the default, it must
+    /// be called before the torrent is created.
+    fn set_preallocation(
+        &mut self,
+        _info_hash: [u8; 20],
+        _preallocation: Preallocation,
+    ) {
+    }
+
+    /// Store a torrent in `dir` instead of the default location, it must be
+    /// called before the torrent is created.
+    fn set_dir(&mut self, _info_hash: [u8; 20], _dir: &Path) {}
+
+    /// Check if there is space for the bytes of the torrent, before it is
+    /// created, so that the download does not fail in the middle.
+    fn check_space(
+        &mut self,
+        _info_hash: [u8; 20],
+        _layout: &Layout,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Read `len` bytes at `offset`. Bytes that were not written yet are
+    /// zeros.
+    fn read(
+        &mut self,
+        info_hash: [u8; 20],
+        offset: u64,
+        len: usize,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> + Send;
+
+    /// Write `bytes` at `offset`, which are whole pieces, or contiguous
+    /// blocks of a piece.
+    fn write(
+        &mut self,
+        info_hash: [u8; 20],
+        offset: u64,
+        bytes: &[u8],
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// SHA-1 of the `len` bytes at `offset`, which are a piece.
+    fn hash(
+        &mut self,
+        info_hash: [u8; 20],
+        offset: u64,
+        len: usize,
+    ) -> impl Future<Output = Result<[u8; 20], Error>> + Send {
+        async move {
+            let bytes = self.read(info_hash, offset, len).await?;
+            Ok(sha1_smol::Sha1::from(bytes).digest().bytes())
+        }
+    }
+
+    /// Start completing a torrent whose wanted pieces were verified, i.e. to
+    /// move its files to their final location and apply their attributes.
+    /// Like [`Storage::move_to`], the returned future does not borrow the
+    /// storage, the torrent is not used until it is done, and then
+    /// [`Storage::completed`] is called if it succeeded.
+    fn complete(
+        &mut self,
+        _info_hash: [u8; 20],
+    ) -> Result<impl Future<Output = Result<(), Error>> + Send + 'static, Error>
+    {
+        Ok(async { Ok(()) })
+    }
+
+    /// The torrent was completed by the future of [`Storage::complete`].
+    fn completed(&mut self, _info_hash: [u8; 20]) {}
+
+    /// Where the bytes of a torrent are, if the storage has a path for them.
+    fn base_path(&self, _info_hash: [u8; 20]) -> Option<&Path> {
+        None
+    }
+
+    /// Release the resources used by a torrent, such as open files, they are
+    /// acquired again when the torrent is used.
+    fn close(&mut self, _info_hash: [u8; 20]) {}
+
+    /// Start moving the bytes of a torrent to `dir`. The returned future
+    /// moves them without borrowing the storage, so that the other torrents
+    /// are not blocked, and `progress` is called with the bytes that were
+    /// moved so far. The torrent is not used until the future is done, and
+    /// then [`Storage::moved`] is called if it succeeded.
+    fn move_to(
+        &mut self,
+        info_hash: [u8; 20],
+        dir: &Path,
+        progress: impl Fn(u64) + Send + 'static,
+    ) -> Result<impl Future<Output = Result<(), Error>> + Send + 'static, Error>;
+
+    /// The bytes of a torrent were moved to `dir`, by the future of
+    /// [`Storage::move_to`].
+    fn moved(&mut self, _info_hash: [u8; 20], _dir: &Path) {}
+
+    /// Delete the bytes of a torrent.
+    fn delete(
+        &mut self,
+        info_hash: [u8; 20],
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metainfo::{File, FileAttr};
+
+    use super::*;
+
+    #[test]
+    fn spans_of_files() {
+        let file = |path: &str, length, attr: &str| File {
+            path: vec![path.to_owned()],
+            length,
+            attr: FileAttr::from(attr),
+            ..Default::default()
+        };
+        let info = Info {
+            name: "a".to_owned(),
+            files: Some(vec![
+                file("a", 3, ""),
+                file(".pad", 2, "p"),
+                file("b", 0, ""),
+                file("c", 5, "x"),
+            ]),
+            ..Default::default()
+        };
+
+        let layout = Layout::from_info(&info);
+        assert_eq!(layout.total_size(), 10);
+        assert_eq!(layout.files[3].offset, 5);
+        assert!(layout.files[3].executable);
+
+        let spans: Vec<_> = layout
+            .spans(1, 6)
+            .into_iter()
+            .map(|(f, offset, range)| (f.path[0].as_str(), offset, range))
+            .collect();
+        assert_eq!(spans, [("a", 1, 0..2), ("c", 0, 4..6)]);
+
+        let info = Info {
+            name: "single".to_owned(),
+            file_length: Some(4),
+            ..Default::default()
+        };
+        let layout = Layout::from_info(&info);
+        assert_eq!(layout.spans(2, 8)[0].2, 0..2);
+    }
+}
@@ -63,6 +63,16 @@ pub enum Error {
     TorrentDoesNotExist,
     #[error("The piece downloaded does not have a valid hash")]
     PieceInvalid,
+    #[error("The piece {0} is not a piece of the torrent")]
+    PieceOutOfBounds(usize),
+    #[error("The block of {2} bytes at {1} of the piece {0} is not a block of the torrent")]
+    BlockOutOfBounds(u32, u32, u32),
+    #[error("The info downloaded from peers does not match the info hash")]
+    InfoInvalid,
+    #[error("The torrent has an unsafe file path `{0}`")]
+    UnsafePath(String),
+    #[error("Not enough space on disk, the torrent needs {0} bytes but only {1} bytes are free")]
+    NoSpace(u64, u64),
     #[error("The peer ID does not exist on this torrent")]
     PeerIdInvalid,
     #[error("Disk does not have the provided info_hash")]
@@ -75,6 +85,12 @@ pub enum Error {
         "Your magnet does not have an info_hash, are you sure you copied the entire magnet link?"
     )]
     MagnetNoInfoHash,
+    #[error("The info hash `{0}` of the magnet is invalid, it must be 40 hex or 32 base32 characters on `btih`, or a SHA-256 multihash on `btmh`")]
+    MagnetInfoHashInvalid(String),
+    #[error("The peer `{0}` of the magnet is invalid, it must be `host:port`")]
+    MagnetPeerInvalid(String),
+    #[error("The file selection `{0}` of the magnet is invalid, it must be a list of indices or ranges, i.e. `0,2,4-6`")]
+    MagnetSelectOnlyInvalid(String),
     #[error("Could not send message to Disk")]
     SendErrorDisk(#[from] mpsc::error::SendError<DiskMsg>),
     #[error("Could not receive message from oneshot")]
@@ -87,6 +103,10 @@ pub enum Error {
     SendErrorTorrent(#[from] mpsc::error::SendError<TorrentMsg>),
     #[error("The given PATH is invalid")]
     PathInvalid,
+    #[error("The path `{0}` already exists")]
+    PathExists(String),
+    #[error("The save path `{0}` is not allowed")]
+    SavePathNotAllowed(String),
     #[error("Could not send message to TCP socket")]
     SendErrorTcp,
     #[error("Tried to load $HOME but could not find it. Please make sure you have a $HOME env and that this program has the permission to create dirs.")]
@@ -97,4 +117,20 @@ pub enum Error {
     NoDuplicateTorrent,
     #[error("No peers in the torrent")]
     NoPeers,
+    #[error("The daemon address `{0}` is invalid, use `ip:port` or `unix:/path/to/socket`")]
+    DaemonAddrInvalid(String),
+    #[error("TLS error: {0}")]
+    Tls(#[from] tokio_rustls::rustls::Error),
+    #[error("The TLS configuration is invalid: {0}")]
+    TlsConfigInvalid(String),
+    #[error("The RPC configuration is invalid: {0}")]
+    RpcConfigInvalid(String),
+    #[error("Could not create the torrent: {0}")]
+    CreateTorrent(String),
+    #[error("The web seed `{0}` is invalid, only http urls are supported")]
+    WebSeedUrlInvalid(String),
+    #[error("The web seed sent an invalid response: {0}")]
+    WebSeedResponse(String),
+    #[error("The web seed is busy, retry after {0} seconds")]
+    WebSeedBusy(u64),
 }
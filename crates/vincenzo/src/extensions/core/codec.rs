@@ -5,10 +5,10 @@ use tokio::{io, sync::oneshot};
 use tokio_util::codec::{Decoder, Encoder};
 use tracing::{debug, trace, warn};
 
-use super::{Block, BlockInfo, Message};
+use super::{Block, BlockInfo, HashRequest, Message};
 use crate::{
     bitfield::Bitfield, disk::DiskMsg, error::Error,
-    extensions::extended::ExtensionTrait, peer::Peer,
+    extensions::extended::ExtensionTrait, merkle, peer::Peer,
 };
 
 /// Core messages exchanged after a successful handshake.
@@ -26,6 +26,12 @@ pub enum Core {
     Piece(Block),
     Cancel(BlockInfo),
     Extended(u8, Vec<u8>),
+    /// Request hashes of the merkle tree of a file, BEP 52.
+    HashRequest(HashRequest),
+    /// The requested hashes followed by their uncle hashes.
+    Hashes(HashRequest, Vec<[u8; 32]>),
+    /// The peer can't answer a hash request.
+    HashReject(HashRequest),
     KeepAlive,
 }
 
@@ -43,6 +49,9 @@ pub enum CoreId {
     Piece = 7,
     Cancel = 8,
     Extended = 20,
+    HashRequest = 21,
+    Hashes = 22,
+    HashReject = 23,
 }
 
 impl TryFrom<u8> for CoreId {
@@ -61,6 +70,9 @@ impl TryFrom<u8> for CoreId {
             k if k == Piece as u8 => Ok(Piece),
             k if k == Cancel as u8 => Ok(Cancel),
             k if k == Extended as u8 => Ok(Extended),
+            k if k == HashRequest as u8 => Ok(HashRequest),
+            k if k == Hashes as u8 => Ok(Hashes),
+            k if k == HashReject as u8 => Ok(HashReject),
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "Unknown message id",
@@ -156,6 +168,26 @@ impl Encoder<Core> for CoreCodec {
                     buf.extend_from_slice(&payload);
                 }
             }
+            // <len=0049><id=21><pieces root><base layer><index><length>
+            // <proof layers>
+            Core::HashRequest(req) => {
+                buf.put_u32(1 + 48);
+                buf.put_u8(CoreId::HashRequest as u8);
+                req.encode(buf)?;
+            }
+            Core::Hashes(req, hashes) => {
+                buf.put_u32(1 + 48 + 32 * hashes.len() as u32);
+                buf.put_u8(CoreId::Hashes as u8);
+                req.encode(buf)?;
+                for hash in hashes {
+                    buf.extend_from_slice(&hash);
+                }
+            }
+            Core::HashReject(req) => {
+                buf.put_u32(1 + 48);
+                buf.put_u8(CoreId::HashReject as u8);
+                req.encode(buf)?;
+            }
         }
         Ok(())
     }
@@ -259,6 +291,35 @@ impl Decoder for CoreCodec {
 
                 Core::Extended(ext_id, payload)
             }
+            // <len=0049><id=21><pieces root><base layer><index><length>
+            // <proof layers>
+            CoreId::HashRequest | CoreId::Hashes | CoreId::HashReject => {
+                if msg_len < 49 || !(msg_len - 49).is_multiple_of(32) {
+                    buf.advance(msg_len - 1);
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid hash message length",
+                    )
+                    .into());
+                }
+
+                let req = HashRequest::decode(buf);
+
+                match msg_id {
+                    CoreId::HashRequest => Core::HashRequest(req),
+                    CoreId::HashReject => Core::HashReject(req),
+                    _ => {
+                        let hashes = (0..(msg_len - 49) / 32)
+                            .map(|_| {
+                                let mut hash = [0; 32];
+                                buf.copy_to_slice(&mut hash);
+                                hash
+                            })
+                            .collect();
+                        Core::Hashes(req, hashes)
+                    }
+                }
+            }
         };
 
         Ok(Some(msg))
@@ -454,6 +515,58 @@ impl ExtensionTrait for CoreCodec {
                 // extended enum message to Core::Message just
                 // to send on the sink.
             }
+            Core::HashRequest(req) => {
+                debug!("{local} hash request from {remote}");
+
+                let msg = match peer.piece_layer_proof(req).await {
+                    Some(hashes) => Core::Hashes(req.clone(), hashes),
+                    None => Core::HashReject(req.clone()),
+                };
+
+                let _ = sink.send(msg.into()).await;
+            }
+            Core::Hashes(req, hashes) => {
+                debug!("{local} {} hashes from {remote}", hashes.len());
+
+                let piece_length =
+                    peer.torrent_ctx.info.read().await.piece_length;
+
+                let mut piece_layers =
+                    peer.torrent_ctx.piece_layers.write().await;
+
+                if let Some(layer) = piece_layers.get_mut(&req.pieces_root) {
+                    let valid = merkle::verify_proof(
+                        &req.pieces_root,
+                        req.index as usize,
+                        req.length as usize,
+                        layer.len() / 32,
+                        hashes,
+                    );
+
+                    // we only ask for the piece layers
+                    if !valid
+                        || req.base_layer
+                            != merkle::piece_layer_index(piece_length)
+                    {
+                        warn!("{remote} sent invalid hashes");
+                        return Ok(());
+                    }
+
+                    let start = req.index as usize * 32;
+
+                    for (i, hash) in
+                        hashes[..req.length as usize].iter().enumerate()
+                    {
+                        let begin = start + i * 32;
+                        if let Some(v) = layer.get_mut(begin..begin + 32) {
+                            v.copy_from_slice(hash);
+                        }
+                    }
+                }
+            }
+            Core::HashReject(req) => {
+                debug!("{local} {remote} rejected the hash request {req:?}");
+            }
         }
 
         Ok(())
@@ -477,7 +590,7 @@ mod tests {
 
     use super::*;
     use bitvec::{bitvec, prelude::Msb0};
-    use bytes::{Buf, BytesMut};
+    use bytes::{Buf, BufMut, BytesMut};
     use tokio_util::codec::{Decoder, Encoder};
 
     #[test]
@@ -605,6 +718,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn hash_messages() {
+        let req = HashRequest {
+            pieces_root: [3; 32],
+            base_layer: 2,
+            index: 4,
+            length: 2,
+            proof_layers: 1,
+        };
+
+        let mut buf = BytesMut::new();
+        CoreCodec.encode(Core::HashRequest(req.clone()), &mut buf).unwrap();
+
+        assert_eq!(buf.len(), 4 + 49);
+        assert_eq!(buf.get_u32(), 49);
+        assert_eq!(buf.get_u8(), CoreId::HashRequest as u8);
+        assert_eq!(buf.chunk()[..32], [3; 32]);
+
+        let msgs = [
+            Core::HashRequest(req.clone()),
+            Core::Hashes(req.clone(), vec![[1; 32], [2; 32], [3; 32]]),
+            Core::HashReject(req.clone()),
+        ];
+
+        for msg in msgs {
+            let mut buf = BytesMut::new();
+            CoreCodec.encode(msg.clone(), &mut buf).unwrap();
+            assert_eq!(CoreCodec.decode(&mut buf).unwrap().unwrap(), msg);
+            assert!(buf.is_empty());
+        }
+
+        // the hashes must be 32 bytes
+        let mut buf = BytesMut::new();
+        buf.put_u32(49 + 31);
+        buf.put_u8(CoreId::Hashes as u8);
+        buf.extend_from_slice(&[0; 48 + 31]);
+        assert!(CoreCodec.decode(&mut buf).is_err());
+        assert!(buf.is_empty());
+    }
+
     #[test]
     fn reserved_bytes() {
         let reserved = Bitfield::from_vec(vec![0, 0, 0, 0, 0, 16, 0, 0]);
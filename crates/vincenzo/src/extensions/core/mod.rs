@@ -130,3 +130,48 @@ impl BlockInfo {
         self.len <= BLOCK_LEN && self.begin <= BLOCK_LEN && self.len > 0
     }
 }
+
+/// A request for the hashes of a layer of the merkle tree of a file, of the
+/// "hash request", "hashes" and "hash reject" messages, BEP 52.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HashRequest {
+    /// The root of the merkle tree of the file.
+    pub pieces_root: [u8; 32],
+    /// The layer of the requested hashes, 0 are the leaves, which are the
+    /// hashes of the 16 KiB blocks.
+    pub base_layer: u32,
+    /// The index of the first hash in the layer.
+    pub index: u32,
+    /// How many hashes are requested, a power of two.
+    pub length: u32,
+    /// How many uncle hashes, above the requested ones, are requested.
+    pub proof_layers: u32,
+}
+
+impl HashRequest {
+    /// Encodes the hash request in the network binary protocol's format into
+    /// the given buffer.
+    pub fn encode(&self, buf: &mut BytesMut) -> io::Result<()> {
+        buf.extend_from_slice(&self.pieces_root);
+        buf.put_u32(self.base_layer);
+        buf.put_u32(self.index);
+        buf.put_u32(self.length);
+        buf.put_u32(self.proof_layers);
+        Ok(())
+    }
+
+    /// Decodes the hash request from the network binary protocol's format,
+    /// the buffer must have at least 48 bytes.
+    pub fn decode(buf: &mut impl bytes::Buf) -> Self {
+        let mut pieces_root = [0; 32];
+        buf.copy_to_slice(&mut pieces_root);
+
+        Self {
+            pieces_root,
+            base_layer: buf.get_u32(),
+            index: buf.get_u32(),
+            length: buf.get_u32(),
+            proof_layers: buf.get_u32(),
+        }
+    }
+}
@@ -121,11 +121,12 @@ impl ExtensionTrait for ExtendedCodec {
         );
         peer.extension = msg.0.clone();
 
+        // the inbound peer already sent our handshake when it connected
         if peer.ctx.direction == Direction::Outbound {
-            let metadata_size = peer.extension.metadata_size.unwrap();
-
             // create our Extension dict, that the local client supports.
-            let ext = Extension::supported(Some(metadata_size))
+            let ext = peer
+                .local_extension()
+                .await
                 .to_bencode()
                 .map_err(|_| Error::BencodeError)?;
 
@@ -133,9 +134,9 @@ impl ExtensionTrait for ExtendedCodec {
             let core = Core::Extended(Self::ID, ext);
 
             sink.send(core.into()).await?;
-
-            peer.try_request_info(sink).await?;
         }
+
+        peer.try_request_info(sink).await?;
         Ok(())
     }
 
@@ -78,6 +78,17 @@ impl Extension {
             metadata_size,
         }
     }
+
+    /// Remove the extensions that must not be used on private torrents,
+    /// BEP 27. Peers of private torrents are only found by the trackers, and
+    /// the info is not shared with them, so neither the peer exchange nor the
+    /// metadata exchange are advertised.
+    pub fn private(mut self) -> Self {
+        self.m.ut_metadata = None;
+        self.m.ut_pex = None;
+        self.metadata_size = None;
+        self
+    }
 }
 
 /// Messages of the Extension protocol
@@ -242,8 +253,9 @@ mod tests {
         let metainfo = MetaInfo::from_bencode(metainfo_bytes).unwrap();
 
         let info = metainfo.info;
+        let info_bytes = info.clone().to_bencode().unwrap();
         let metadata_data =
-            Metadata::data(0, &info.clone().to_bencode().unwrap()).unwrap();
+            Metadata::data(0, info_bytes.len() as u32, &info_bytes).unwrap();
 
         let mut r = b"d8:msg_typei1e5:piecei0e10:total_sizei5095ee".to_vec();
         r.extend_from_slice(&info.to_bencode().unwrap());
@@ -325,6 +337,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn private_extension() {
+        let ext = Extension::supported(Some(5205)).private();
+
+        assert_eq!(ext.m, M::default());
+        assert_eq!(ext.metadata_size, None);
+        assert_eq!(
+            ext.to_bencode().unwrap(),
+            b"d1:mde4:reqqi6e1:v14:Vincenzo 0.0.1e"
+        );
+    }
+
     #[test]
     fn metadata_msg_deserialization() {
         let buf = [
@@ -5,7 +5,6 @@ use crate::{
 };
 use bendy::encoding::ToBencode;
 use futures::SinkExt;
-use tokio::sync::oneshot;
 use tokio_util::codec::{Decoder, Encoder};
 use tracing::{debug, info};
 
@@ -111,7 +110,7 @@ impl TryInto<Core> for Metadata {
             ),
             Self::Response { metadata, payload } => {
                 let mut buff = metadata.to_bencode().unwrap();
-                buff.copy_from_slice(&payload);
+                buff.extend_from_slice(&payload);
                 Core::Extended(id, buff)
             }
         })
@@ -138,15 +137,23 @@ impl ExtensionTrait for MetadataCodec {
                 );
                 debug!("{metadata:?}");
 
-                let peer_ext_id = peer.extension.metadata_size.unwrap();
+                // the size must be the one of the peer's extended handshake
+                let Some(total) = peer
+                    .metadata_size()
+                    .filter(|size| metadata.total_size == Some(*size))
+                else {
+                    debug!("peer sent an info piece with an invalid size");
+                    return Ok(());
+                };
 
                 peer.torrent_ctx
                     .tx
-                    .send(TorrentMsg::DownloadedInfoPiece(
-                        peer_ext_id,
-                        metadata.piece,
-                        payload.clone(),
-                    ))
+                    .send(TorrentMsg::DownloadedInfoPiece {
+                        from: peer.ctx.id,
+                        total,
+                        index: metadata.piece,
+                        bytes: payload.clone(),
+                    })
                     .await?;
                 peer.torrent_ctx
                     .tx
@@ -163,27 +170,30 @@ impl ExtensionTrait for MetadataCodec {
                 );
                 debug!("piece = {piece:?}");
 
-                let (tx, rx) = oneshot::channel();
-                peer.torrent_ctx
-                    .tx
-                    .send(TorrentMsg::RequestInfoPiece(*piece, tx))
-                    .await?;
-
-                match rx.await? {
-                    Some(info_slice) => {
-                        info!("sending data with piece {:?}", piece);
-                        let payload = MetadataDict::data(*piece, &info_slice)?;
-                        sink.send(Core::Extended(Self::ID, payload).into())
-                            .await;
-                    }
-                    None => {
-                        info!("sending reject");
-                        let r = MetadataDict::reject(*piece)
-                            .to_bencode()
-                            .map_err(|_| Error::BencodeError)?;
-                        sink.send(Core::Extended(Self::ID, r).into()).await;
-                    }
-                }
+                // messages are sent with the id of the peer's handshake
+                let Some(ut_metadata) = peer.extension.m.ut_metadata else {
+                    return Ok(());
+                };
+
+                let payload =
+                    match peer.torrent_ctx.metadata_piece(*piece).await {
+                        Some(info_slice) => {
+                            info!("sending data with piece {:?}", piece);
+                            let total =
+                                peer.torrent_ctx.raw_info.read().await.len()
+                                    as u32;
+                            MetadataDict::data(*piece, total, &info_slice)?
+                        }
+                        None => {
+                            info!("sending reject");
+                            MetadataDict::reject(*piece)
+                                .to_bencode()
+                                .map_err(|_| Error::BencodeError)?
+                        }
+                    };
+                let _ = sink
+                    .send(Core::Extended(ut_metadata, payload).into())
+                    .await;
             }
             Metadata::Reject(piece) => {
                 debug!(
@@ -5,11 +5,15 @@
 pub mod codec;
 
 use bendy::{
-    decoding::{self, FromBencode, Object, ResultExt},
+    decoding::{self, Decoder, FromBencode, Object, ResultExt},
     encoding::ToBencode,
 };
 
-use super::super::error;
+use super::{super::error, core::BLOCK_LEN};
+
+/// The largest info that is accepted from peers, larger sizes are treated
+/// as invalid, to not let peers make us allocate unbounded memory.
+pub const MAX_METADATA_SIZE: u32 = 16 * 1024 * 1024;
 
 /// Metadata dict used in the Metadata protocol messages,
 /// this dict is used to request, reject, and send data (info).
@@ -61,21 +65,39 @@ impl Metadata {
         Self { msg_type: MetadataMsgType::Request, piece, total_size: None }
     }
 
-    pub fn data(piece: u32, info: &[u8]) -> Result<Vec<u8>, error::Error> {
+    /// A data message with the `piece` of an info of `total_size` bytes.
+    pub fn data(
+        piece: u32,
+        total_size: u32,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, error::Error> {
         let metadata = Self {
             msg_type: MetadataMsgType::Response,
             piece,
-            total_size: Some(info.len() as u32),
+            total_size: Some(total_size),
         };
 
         let mut bytes =
             metadata.to_bencode().map_err(|_| error::Error::BencodeError)?;
 
-        bytes.extend_from_slice(info);
+        bytes.extend_from_slice(payload);
 
         Ok(bytes)
     }
 
+    /// How many pieces an info of `total_size` bytes has.
+    pub fn pieces(total_size: u32) -> u32 {
+        total_size.div_ceil(BLOCK_LEN)
+    }
+
+    /// The length of the `piece` of an info of `total_size` bytes, all
+    /// pieces have 16 KiB, except for the last one. `None` if the piece does
+    /// not exist.
+    pub fn piece_len(total_size: u32, piece: u32) -> Option<u32> {
+        (piece < Self::pieces(total_size))
+            .then(|| (total_size - piece * BLOCK_LEN).min(BLOCK_LEN))
+    }
+
     pub fn reject(piece: u32) -> Self {
         Self { msg_type: MetadataMsgType::Reject, piece, total_size: None }
     }
@@ -87,18 +109,18 @@ impl Metadata {
     /// This function will return an error if the buffer is not a valid Data
     /// type of the metadata extension protocol
     pub fn extract(mut buf: Vec<u8>) -> Result<(Self, Vec<u8>), error::Error> {
-        // let mut info_buf = Vec::new();
-        let mut metadata_buf = Vec::new();
-
-        // find end of info dict, which is always the first "ee"
-        if let Some(i) = buf.windows(2).position(|w| w == b"ee") {
-            metadata_buf = buf.drain(..i + 2).collect();
-        }
+        // the payload of a data message comes right after the dict
+        let mut decoder = Decoder::new(&buf);
+        let Ok(Some(Object::Dict(dict))) = decoder.next_object() else {
+            return Err(error::Error::BencodeError);
+        };
+        let len =
+            dict.into_raw().map_err(|_| error::Error::BencodeError)?.len();
 
-        let metadata = Metadata::from_bencode(&metadata_buf)
+        let metadata = Metadata::from_bencode(&buf[..len])
             .map_err(|_| error::Error::BencodeError)?;
 
-        Ok((metadata, buf))
+        Ok((metadata, buf.split_off(len)))
     }
 }
 
@@ -155,3 +177,32 @@ impl ToBencode for Metadata {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn piece_len() {
+        assert_eq!(Metadata::pieces(BLOCK_LEN), 1);
+        assert_eq!(Metadata::pieces(BLOCK_LEN + 1), 2);
+        assert_eq!(Metadata::piece_len(BLOCK_LEN + 1, 0), Some(BLOCK_LEN));
+        assert_eq!(Metadata::piece_len(BLOCK_LEN + 1, 1), Some(1));
+        assert_eq!(Metadata::piece_len(BLOCK_LEN + 1, 2), None);
+        assert_eq!(Metadata::piece_len(0, 0), None);
+    }
+
+    #[test]
+    fn extract() {
+        // the payload may also contain "ee"
+        let buf = Metadata::data(1, BLOCK_LEN + 6, b"d1:aee").unwrap();
+        let (metadata, payload) = Metadata::extract(buf).unwrap();
+
+        assert_eq!(metadata.msg_type, MetadataMsgType::Response);
+        assert_eq!(metadata.piece, 1);
+        assert_eq!(metadata.total_size, Some(BLOCK_LEN + 6));
+        assert_eq!(payload, b"d1:aee");
+
+        assert!(Metadata::extract(b"i1e".to_vec()).is_err());
+    }
+}
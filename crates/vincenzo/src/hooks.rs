@@ -0,0 +1,242 @@
+//! User hooks that run on torrent lifecycle events.
+//!
+//! Each hook is the path of an executable, configured with `on_added`,
+//! `on_complete` and `on_error`. It runs asynchronously, without blocking the
+//! torrent, with information about the torrent on environment variables:
+//!
+//! - `VCZ_EVENT`: `added`, `complete` or `error`.
+//! - `VCZ_INFO_HASH`: info hash of the torrent, in hex.
+//! - `VCZ_NAME`: name of the torrent.
+//! - `VCZ_SAVE_PATH`: where the files of the torrent are, the root directory
+//!   of the torrent, or the file of single file torrents. It is not set
+//!   before the files are created.
+//! - `VCZ_SIZE`: size of the torrent in bytes, `0` if the metadata is not known
+//!   yet.
+//! - `VCZ_ERROR`: the reason of the error, only on `on_error`.
+//!
+//! A hook that takes longer than `hook_timeout` seconds is killed. Its output
+//! is logged line by line.
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use tokio::{process::Command, spawn, time::timeout};
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+
+/// Lifecycle event of a torrent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookEvent {
+    /// The torrent was added to the daemon.
+    Added,
+    /// All pieces of the torrent were downloaded.
+    Complete,
+    /// The torrent stopped because of an error.
+    Error,
+}
+
+impl HookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Complete => "complete",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Information about the torrent that is given to a hook.
+#[derive(Debug, Clone, Default)]
+pub struct HookEnv {
+    pub info_hash: [u8; 20],
+    pub name: String,
+    pub size: u64,
+    pub error: Option<String>,
+    /// The path of the files on the storage, if they were created.
+    pub save_path: Option<PathBuf>,
+}
+
+/// The configured hooks, cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    pub on_added: Option<PathBuf>,
+    pub on_complete: Option<PathBuf>,
+    pub on_error: Option<PathBuf>,
+    pub timeout: Duration,
+}
+
+impl From<&Config> for Hooks {
+    fn from(config: &Config) -> Self {
+        Self {
+            on_added: config.on_added.clone(),
+            on_complete: config.on_complete.clone(),
+            on_error: config.on_error.clone(),
+            timeout: Duration::from_secs(config.hook_timeout),
+        }
+    }
+}
+
+impl Hooks {
+    fn get(&self, event: HookEvent) -> Option<&PathBuf> {
+        match event {
+            HookEvent::Added => self.on_added.as_ref(),
+            HookEvent::Complete => self.on_complete.as_ref(),
+            HookEvent::Error => self.on_error.as_ref(),
+        }
+    }
+
+    /// Spawn the hook of `event` on the background, if there is one.
+    pub fn spawn(&self, event: HookEvent, env: HookEnv) {
+        if self.get(event).is_none() {
+            return;
+        }
+        let hooks = self.clone();
+        spawn(async move {
+            hooks.run(event, env).await;
+        });
+    }
+
+    /// Run the hook of `event` and wait for it to finish, or for the timeout.
+    /// Returns the exit code of the hook, if it exited.
+    pub async fn run(&self, event: HookEvent, env: HookEnv) -> Option<i32> {
+        let path = self.get(event)?;
+
+        let mut command = Command::new(path);
+        command
+            .env("VCZ_EVENT", event.as_str())
+            .env("VCZ_INFO_HASH", hex::encode(env.info_hash))
+            .env("VCZ_NAME", &env.name)
+            .env("VCZ_SIZE", env.size.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        if let Some(save_path) = &env.save_path {
+            command.env("VCZ_SAVE_PATH", save_path);
+        }
+        if let Some(e) = &env.error {
+            command.env("VCZ_ERROR", e);
+        }
+
+        info!("Running {} hook {path:?} for {:?}", event.as_str(), env.name);
+
+        let child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Could not run the hook {path:?}: {e}");
+                return None;
+            }
+        };
+
+        // the child is killed when the future is dropped on timeout.
+        let output = match timeout(self.timeout, child.wait_with_output()).await
+        {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => {
+                error!("Could not wait for the hook {path:?}: {e}");
+                return None;
+            }
+            Err(_) => {
+                warn!("The hook {path:?} timed out after {:?}", self.timeout);
+                return None;
+            }
+        };
+
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            info!("hook {path:?}: {line}");
+        }
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            warn!("hook {path:?}: {line}");
+        }
+
+        if !output.status.success() {
+            warn!("The hook {path:?} exited with {}", output.status);
+        }
+
+        output.status.code()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    use super::*;
+
+    fn tmp_path() -> PathBuf {
+        let mut rng = rand::thread_rng();
+        let name: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        std::env::temp_dir().join(name)
+    }
+
+    /// Write an executable script from another process. If the file was
+    /// written by the tests, a child forked at the same time by another test
+    /// would inherit the open file, and executing it would fail with ETXTBSY.
+    fn script(body: &str) -> PathBuf {
+        let path = tmp_path();
+
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(r#"printf '#!/bin/sh\n%s\n' "$1" > "$0" && chmod 755 "$0""#)
+            .arg(&path)
+            .arg(body)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        path
+    }
+
+    #[tokio::test]
+    async fn run_with_env() {
+        let out = tmp_path();
+        let hook = script(&format!(
+            "echo \"$VCZ_EVENT $VCZ_INFO_HASH $VCZ_NAME $VCZ_SAVE_PATH $VCZ_SIZE\" > {}",
+            out.display()
+        ));
+        let hooks = Hooks {
+            on_complete: Some(hook.clone()),
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let env = HookEnv {
+            info_hash: [1; 20],
+            name: "arch".to_owned(),
+            size: 10,
+            error: None,
+            save_path: Some("/downloads/arch-linux".into()),
+        };
+
+        // no hook for this event
+        assert_eq!(hooks.run(HookEvent::Added, env.clone()).await, None);
+
+        assert_eq!(hooks.run(HookEvent::Complete, env).await, Some(0));
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            format!(
+                "complete {} arch /downloads/arch-linux 10\n",
+                hex::encode([1; 20])
+            )
+        );
+
+        let _ = std::fs::remove_file(hook);
+        let _ = std::fs::remove_file(out);
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_hook() {
+        let hook = script("sleep 10");
+        let hooks = Hooks {
+            on_error: Some(hook.clone()),
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let env =
+            HookEnv { error: Some("bla".to_owned()), ..Default::default() };
+
+        assert_eq!(hooks.run(HookEvent::Error, env).await, None);
+
+        let _ = std::fs::remove_file(hook);
+    }
+}
@@ -20,6 +20,7 @@
 //!    use vincenzo::daemon::Daemon;
 //!    use vincenzo::daemon::DaemonMsg;
 //!    use vincenzo::magnet::Magnet;
+//!    use vincenzo::torrent::TorrentOptions;
 //!    use tokio::spawn;
 //!    use tokio::sync::oneshot;
 //!
@@ -39,7 +40,7 @@
 //!        // identifier of the torrent
 //!        let info_hash = magnet.parse_xt();
 //!
-//!        tx.send(DaemonMsg::NewTorrent(magnet)).await.unwrap();
+//!        tx.send(DaemonMsg::NewTorrent(magnet, TorrentOptions::default())).await.unwrap();
 //!
 //!        // get information about the torrent download
 //!        let (otx, orx) = oneshot::channel();
@@ -62,14 +63,20 @@ pub mod avg;
 pub mod bitfield;
 pub mod config;
 pub mod counter;
+pub mod create;
 pub mod daemon;
 pub mod daemon_wire;
 pub mod disk;
 pub mod error;
 pub mod extensions;
+pub mod hooks;
 pub mod magnet;
+pub mod merkle;
 pub mod metainfo;
 pub mod peer;
+pub mod rpc;
 pub mod torrent;
 pub mod tracker;
 pub mod utils;
+pub mod watch;
+pub mod webseed;
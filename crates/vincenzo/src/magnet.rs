@@ -1,31 +1,146 @@
 //! Handle magnet link
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, RangeInclusive};
 
 use magnet_url::Magnet as Magnet_;
 
-use crate::{error::Error, torrent::InfoHash};
+use crate::{error::Error, torrent::InfoHash, webseed::WebSeedKind};
+
+/// Prefix of the multihash of a SHA-256 digest of 32 bytes, which is the v2
+/// info hash on the `btmh` "xt", BEP 52.
+const SHA256_MULTIHASH: &str = "1220";
 
 #[derive(Debug, Clone, Hash)]
-pub struct Magnet(Magnet_);
+pub struct Magnet {
+    /// Boxed to keep the messages that carry a magnet small.
+    inner: Box<Magnet_>,
+    /// The info hashes of the `btih` and `btmh` "xt".
+    info_hash: InfoHash,
+    /// The web seeds of the `ws` and `x.hs` parameters, the inner magnet
+    /// only keeps the first `ws`.
+    web_seeds: Vec<(String, WebSeedKind)>,
+    /// Addresses of peers of the `x.pe` parameters, `host:port`.
+    peers: Vec<String>,
+    /// The indices of the files to download, from the `so` parameter,
+    /// BEP 53. `None` means all files.
+    select_only: Option<Vec<RangeInclusive<usize>>>,
+}
 
 impl Deref for Magnet {
     type Target = Magnet_;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.inner
     }
 }
 
 impl DerefMut for Magnet {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.inner
     }
 }
 
 impl Magnet {
     pub fn new(magnet_url: &str) -> Result<Self, Error> {
-        Ok(Self(
-            Magnet_::new(magnet_url).map_err(|_| Error::MagnetLinkInvalid)?,
-        ))
+        let inner =
+            Magnet_::new(magnet_url).map_err(|_| Error::MagnetLinkInvalid)?;
+
+        let mut info_hash = InfoHash::default();
+        let mut web_seeds = Vec::new();
+        let mut peers = Vec::new();
+        let mut select_only = None;
+
+        // some magnets are html escaped
+        let query = magnet_url
+            .split_once('?')
+            .map(|v| v.1.replace("&amp;", "&"))
+            .unwrap_or_default();
+
+        for (k, v) in query.split('&').filter_map(|v| v.split_once('=')) {
+            let v = urlencoding::decode(v)
+                .map_err(|_| Error::MagnetLinkInvalid)?
+                .into_owned();
+
+            match k {
+                "xt" => {
+                    if let Some(hash) = v.strip_prefix("urn:btih:") {
+                        info_hash.v1 = Some(Self::parse_btih(hash)?);
+                    } else if let Some(hash) = v.strip_prefix("urn:btmh:") {
+                        info_hash.v2 = Some(Self::parse_btmh_hash(hash)?);
+                    }
+                }
+                "ws" => web_seeds.push((v, WebSeedKind::UrlList)),
+                "x.hs" => web_seeds.push((v, WebSeedKind::HttpSeed)),
+                "x.pe" => {
+                    let port = v.rsplit_once(':').map(|v| v.1.parse::<u16>());
+                    if !matches!(port, Some(Ok(_))) {
+                        return Err(Error::MagnetPeerInvalid(v));
+                    }
+                    peers.push(v);
+                }
+                "so" => select_only = Some(Self::parse_select_only(&v)?),
+                _ => {}
+            }
+        }
+
+        if info_hash.v1.is_none() && info_hash.v2.is_none() {
+            return Err(Error::MagnetNoInfoHash);
+        }
+
+        Ok(Self {
+            inner: Box::new(inner),
+            info_hash,
+            web_seeds,
+            peers,
+            select_only,
+        })
+    }
+
+    /// A v1 info hash, which is either 40 hex characters or 32 base32
+    /// characters.
+    fn parse_btih(hash: &str) -> Result<[u8; 20], Error> {
+        let invalid = || Error::MagnetInfoHashInvalid(hash.to_owned());
+
+        let bytes = match hash.len() {
+            40 => hex::decode(hash).map_err(|_| invalid())?,
+            32 => base32_decode(hash).ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        };
+
+        bytes.try_into().map_err(|_| invalid())
+    }
+
+    /// A v2 info hash, which is a hex encoded multihash of SHA-256.
+    fn parse_btmh_hash(hash: &str) -> Result<[u8; 32], Error> {
+        let invalid = || Error::MagnetInfoHashInvalid(hash.to_owned());
+
+        let digest = hash.strip_prefix(SHA256_MULTIHASH).ok_or_else(invalid)?;
+        if digest.len() != 64 {
+            return Err(invalid());
+        }
+
+        hex::decode(digest)
+            .map_err(|_| invalid())?
+            .try_into()
+            .map_err(|_| invalid())
+    }
+
+    /// Parse the `so` parameter, a list of file indices and inclusive ranges
+    /// of indices, i.e. `0,2,4-6`.
+    fn parse_select_only(
+        so: &str,
+    ) -> Result<Vec<RangeInclusive<usize>>, Error> {
+        let invalid = || Error::MagnetSelectOnlyInvalid(so.to_owned());
+
+        so.split(',')
+            .map(|v| {
+                let (start, end) = v.split_once('-').unwrap_or((v, v));
+                let start: usize = start.parse().map_err(|_| invalid())?;
+                let end: usize = end.parse().map_err(|_| invalid())?;
+                if start > end {
+                    return Err(invalid());
+                }
+                Ok(start..=end)
+            })
+            .collect()
     }
 
     /// The name will come URL encoded, and it is also optional.
@@ -38,22 +153,47 @@ impl Magnet {
         "Unknown".to_string()
     }
 
-    /// Transform the "xt" field from hex, to a slice.
+    /// The info hash used on the wire. On v2 only magnets, this is the v2
+    /// info hash truncated to 20 bytes.
     pub fn parse_xt(&self) -> [u8; 20] {
-        let info_hash = hex::decode(self.xt.clone().unwrap()).unwrap();
-        let mut x = [0u8; 20];
+        self.info_hash.wire()
+    }
 
-        x[..20].copy_from_slice(&info_hash[..20]);
-        x
+    /// The v2 info hash, if the magnet has a `btmh` "xt".
+    pub fn parse_btmh(&self) -> Option<[u8; 32]> {
+        self.info_hash.v2
     }
 
-    /// Transform the "xt" field from hex, to a slice.
-    pub fn parse_xt_infohash(&self) -> InfoHash {
-        let info_hash = hex::decode(self.xt.clone().unwrap()).unwrap();
-        let mut x = [0u8; 20];
+    /// Web seeds of the `ws` parameters, the GetRight style of BEP 19,
+    /// and of the `x.hs` parameters, the Hoffman style of BEP 17.
+    pub fn parse_web_seeds(&self) -> &[(String, WebSeedKind)] {
+        &self.web_seeds
+    }
+
+    /// Addresses of the peers of the `x.pe` parameters, which are
+    /// `host:port`, `ipv4:port` or `[ipv6]:port`.
+    pub fn parse_peers(&self) -> &[String] {
+        &self.peers
+    }
+
+    /// The ranges of file indices of the `so` parameter, `None` if the
+    /// magnet does not have one, in which case all files are selected.
+    pub fn parse_so(&self) -> Option<&[RangeInclusive<usize>]> {
+        self.select_only.as_deref()
+    }
 
-        x[..20].copy_from_slice(&info_hash[..20]);
-        x.into()
+    /// If the file with the given index should be downloaded, according to
+    /// the `so` parameter.
+    pub fn is_file_selected(&self, index: usize) -> bool {
+        self.select_only
+            .as_ref()
+            .is_none_or(|so| so.iter().any(|v| v.contains(&index)))
+    }
+
+    /// All the info hashes of the magnet, v1 from the `btih` "xt" and v2
+    /// from the `btmh` "xt".
+    pub fn parse_xt_infohash(&self) -> InfoHash {
+        self.info_hash
     }
 
     /// Parse trackers so they can be used as socket addresses.
@@ -79,8 +219,145 @@ impl Magnet {
     }
 }
 
+/// Decode base32 without padding, RFC 4648, which is used by old magnets for
+/// the v1 info hash.
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer = 0_u64;
+    let mut bits = 0;
+
+    for c in s.bytes() {
+        let value = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
 #[cfg(test)]
 pub mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_btmh() {
+        let v2 = [7u8; 32];
+        let hybrid = format!(
+            "magnet:?xt=urn:btih:{}&xt=urn:btmh:1220{}&dn=a",
+            hex::encode([1u8; 20]),
+            hex::encode(v2)
+        );
+        let magnet = Magnet::new(&hybrid).unwrap();
+        assert_eq!(magnet.parse_btmh(), Some(v2));
+        assert_eq!(magnet.parse_xt(), [1; 20]);
+        assert_eq!(
+            magnet.parse_xt_infohash(),
+            InfoHash { v1: Some([1; 20]), v2: Some(v2) }
+        );
+
+        // v2 only, the wire hash is the truncated v2 hash
+        let magnet = Magnet::new(&format!(
+            "magnet:?xt=urn:btmh:1220{}",
+            hex::encode(v2)
+        ))
+        .unwrap();
+        assert_eq!(magnet.parse_xt(), [7; 20]);
+        assert_eq!(magnet.parse_xt_infohash().swarms(), vec![[7; 20]]);
+    }
+
+    #[test]
+    fn parse_web_seeds() {
+        let magnet = Magnet::new(&format!(
+            "magnet:?xt=urn:btih:{}&ws=http%3A%2F%2Fa.com%2F&ws=http%3A%2F%2Fb.com%2Ff&x.hs=http%3A%2F%2Fc.com%2Fseed.php",
+            hex::encode([1u8; 20]),
+        ))
+        .unwrap();
+        assert_eq!(
+            magnet.parse_web_seeds(),
+            [
+                ("http://a.com/".to_owned(), WebSeedKind::UrlList),
+                ("http://b.com/f".to_owned(), WebSeedKind::UrlList),
+                ("http://c.com/seed.php".to_owned(), WebSeedKind::HttpSeed),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_base32_and_invalid_hashes() {
+        let magnet =
+            Magnet::new("magnet:?xt=urn:btih:AAAQEAYEAUDAOCAJBIFQYDIOB4IBCEQT")
+                .unwrap();
+        let expected: Vec<u8> = (0..20).collect();
+        assert_eq!(magnet.parse_xt().to_vec(), expected);
+
+        // lowercase base32 is also accepted
+        let magnet =
+            Magnet::new("magnet:?xt=urn:btih:aeaqcaibaeaqcaibaeaqcaibaeaqcaib")
+                .unwrap();
+        assert_eq!(magnet.parse_xt(), [1; 20]);
+
+        assert!(matches!(
+            Magnet::new("magnet:?xt=urn:btih:123"),
+            Err(Error::MagnetInfoHashInvalid(_))
+        ));
+        assert!(matches!(
+            Magnet::new(&format!("magnet:?xt=urn:btih:{}", "z".repeat(40))),
+            Err(Error::MagnetInfoHashInvalid(_))
+        ));
+        // sha-1 multihash on btmh
+        assert!(matches!(
+            Magnet::new(&format!("magnet:?xt=urn:btmh:1114{}", "a".repeat(40))),
+            Err(Error::MagnetInfoHashInvalid(_))
+        ));
+        assert!(matches!(
+            Magnet::new("magnet:?dn=bla"),
+            Err(Error::MagnetNoInfoHash)
+        ));
+    }
+
+    #[test]
+    fn parse_peers_and_select_only() {
+        let magnet = Magnet::new(&format!(
+            "magnet:?xt=urn:btih:{}&x.pe=10.0.0.1:6881&x.pe=%5B%3A%3A1%5D%3A80&x.pe=example.com:51413&so=0,2,4-6",
+            hex::encode([1u8; 20]),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            magnet.parse_peers(),
+            ["10.0.0.1:6881", "[::1]:80", "example.com:51413"]
+        );
+        assert_eq!(magnet.parse_so(), Some(&[0..=0, 2..=2, 4..=6][..]));
+        let selected: Vec<usize> =
+            (0..8).filter(|i| magnet.is_file_selected(*i)).collect();
+        assert_eq!(selected, [0, 2, 4, 5, 6]);
+
+        // without `so` all files are selected
+        let magnet = Magnet::new(&format!(
+            "magnet:?xt=urn:btih:{}",
+            hex::encode([1u8; 20]),
+        ))
+        .unwrap();
+        assert!(magnet.parse_so().is_none());
+        assert!(magnet.is_file_selected(100));
+
+        for invalid in ["x.pe=10.0.0.1", "x.pe=a:b", "so=a", "so=3-1"] {
+            let r = Magnet::new(&format!(
+                "magnet:?xt=urn:btih:{}&{invalid}",
+                hex::encode([1u8; 20]),
+            ));
+            assert!(r.is_err(), "{invalid}");
+        }
+    }
+
     #[test]
     fn parse_string_to_magnet() {
         let mstr = "magnet:?xt=urn:btih:56BC861F42972DEA863AE853362A20E15C7BA07E&amp;dn=Rust%20for%20Rustaceans%3A%20Idiomatic%20Programming&amp;tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337&amp;tr=udp%3A%2F%2Fopen.stealth.si%3A80%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.torrent.eu.org%3A451%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.bittor.pw%3A1337%2Fannounce&amp;tr=udp%3A%2F%2Fpublic.popcorn-tracker.org%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Ftracker.dler.org%3A6969%2Fannounce&amp;tr=udp%3A%2F%2Fexodus.desync.com%3A6969&amp;tr=udp%3A%2F%2Fopen.demonii.com%3A1337%2Fannounce";
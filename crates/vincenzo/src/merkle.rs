@@ -0,0 +1,296 @@
+//! SHA-256 merkle trees of BitTorrent v2, BEP 52.
+//!
+//! Each file has its own tree, the leaves are the hashes of the 16 KiB blocks
+//! of the file, and the leaves after the end of the file are zeros, so that
+//! the number of leaves is a power of two. The root of the tree is the
+//! `pieces root` of the file, and the layer in which each hash covers exactly
+//! one piece is the "piece layer" of the file.
+//!
+//! The layers of a tree are counted from the leaves, the leaves are the layer
+//! 0, and the piece layer is `log2(piece_length / 16 KiB)`.
+use sha2::{Digest, Sha256};
+
+use crate::extensions::core::BLOCK_LEN;
+
+/// A node of a merkle tree.
+pub type Hash = [u8; 32];
+
+/// How many hashes a peer may ask in a single hash request.
+pub const MAX_HASHES: u32 = 512;
+
+/// SHA-256 of `data`.
+pub fn hash(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Hash of a subtree with `2^layer` leaves that are all zeros.
+pub fn pad_hash(layer: u32) -> Hash {
+    (0..layer).fold([0; 32], |acc, _| hash_pair(&acc, &acc))
+}
+
+/// The layer where each hash covers one piece of `piece_length`.
+pub fn piece_layer_index(piece_length: u32) -> u32 {
+    (piece_length / BLOCK_LEN).max(1).trailing_zeros()
+}
+
+/// Root of a tree whose bottom layer has `width` nodes, which must be a
+/// power of two. The nodes after the end of `hashes` are `pad`.
+pub fn root(hashes: &[Hash], width: usize, pad: Hash) -> Hash {
+    let mut layer = hashes.to_vec();
+    let mut pad = pad;
+    let mut width = width.max(1);
+
+    while width > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pad)))
+            .collect();
+        pad = hash_pair(&pad, &pad);
+        width /= 2;
+    }
+
+    layer.first().copied().unwrap_or(pad)
+}
+
+/// Hashes of the 16 KiB blocks of `data`, the last block may be smaller.
+pub fn block_hashes(data: &[u8]) -> Vec<Hash> {
+    data.chunks(BLOCK_LEN as usize).map(hash).collect()
+}
+
+/// Hash of a piece on the piece layer, `data` is smaller than `piece_length`
+/// only on the last piece of a file.
+pub fn piece_hash(data: &[u8], piece_length: u32) -> Hash {
+    let width = (piece_length / BLOCK_LEN).max(1) as usize;
+    root(&block_hashes(data), width, [0; 32])
+}
+
+/// The piece layer of a file, given all of its data.
+pub fn piece_layer(data: &[u8], piece_length: u32) -> Vec<Hash> {
+    data.chunks(piece_length as usize)
+        .map(|piece| piece_hash(piece, piece_length))
+        .collect()
+}
+
+/// The `pieces root` of a file, given all of its data. Empty files do not
+/// have one.
+pub fn file_root(data: &[u8]) -> Option<Hash> {
+    if data.is_empty() {
+        return None;
+    }
+    let leaves = block_hashes(data);
+    Some(root(&leaves, leaves.len().next_power_of_two(), [0; 32]))
+}
+
+/// The `pieces root` of a file, given its piece layer.
+pub fn root_from_piece_layer(layer: &[Hash], piece_length: u32) -> Hash {
+    let pad = pad_hash(piece_layer_index(piece_length));
+    root(layer, layer.len().next_power_of_two(), pad)
+}
+
+/// Split the bytes of a piece layer, as they are in the `piece layers` of a
+/// .torrent file, into hashes.
+pub fn split_hashes(bytes: &[u8]) -> Option<Vec<Hash>> {
+    if !bytes.len().is_multiple_of(32) {
+        return None;
+    }
+    Some(bytes.chunks(32).map(|v| v.try_into().unwrap()).collect())
+}
+
+/// Answer a hash request of `length` hashes of `layer`, starting at `index`,
+/// followed by the uncle hashes of `proof_layers` layers above them.
+///
+/// `pad` is the value of the nodes after the end of the layer. Returns `None`
+/// if the request is not valid.
+pub fn proof(
+    layer: &[Hash],
+    pad: Hash,
+    index: usize,
+    length: usize,
+    proof_layers: u32,
+) -> Option<Vec<Hash>> {
+    let width = layer.len().next_power_of_two();
+
+    if !length.is_power_of_two()
+        || length > width
+        || !index.is_multiple_of(length)
+        || index >= width
+    {
+        return None;
+    }
+
+    let mut nodes: Vec<Hash> =
+        (0..width).map(|i| layer.get(i).copied().unwrap_or(pad)).collect();
+    let mut hashes = nodes[index..index + length].to_vec();
+
+    // climb until the root of the requested hashes
+    let mut pos = index;
+    let mut span = 1;
+    while span < length {
+        nodes = nodes.chunks(2).map(|v| hash_pair(&v[0], &v[1])).collect();
+        pos /= 2;
+        span *= 2;
+    }
+
+    for _ in 0..proof_layers {
+        if nodes.len() < 2 {
+            break;
+        }
+        hashes.push(nodes[pos ^ 1]);
+        nodes = nodes.chunks(2).map(|v| hash_pair(&v[0], &v[1])).collect();
+        pos /= 2;
+    }
+
+    Some(hashes)
+}
+
+/// Verify the hashes of a hashes message, which are `length` hashes starting
+/// at `index` of a layer with `width` nodes, followed by the uncle hashes up
+/// to the root.
+///
+/// There must be exactly one uncle for each layer between the requested
+/// hashes and the root, otherwise a peer could send the top of the tree as
+/// the hashes of the layer.
+pub fn verify_proof(
+    root: &Hash,
+    index: usize,
+    length: usize,
+    width: usize,
+    hashes: &[Hash],
+) -> bool {
+    let width = width.next_power_of_two();
+
+    if !length.is_power_of_two()
+        || length > width
+        || !index.is_multiple_of(length)
+        || index >= width
+    {
+        return false;
+    }
+
+    let depth = (width / length).trailing_zeros() as usize;
+
+    if hashes.len() != length + depth {
+        return false;
+    }
+
+    let (base, uncles) = hashes.split_at(length);
+    let mut node = self::root(base, length, [0; 32]);
+    let mut pos = index / length;
+
+    for uncle in uncles {
+        node = if pos.is_multiple_of(2) {
+            hash_pair(&node, uncle)
+        } else {
+            hash_pair(uncle, &node)
+        };
+        pos /= 2;
+    }
+
+    node == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn small_file_root() {
+        // a single block, the root is the hash of the block
+        let a = data(100);
+        assert_eq!(file_root(&a), Some(hash(&a)));
+        assert_eq!(file_root(&[]), None);
+
+        // 3 blocks, the last leaf is padded with zeros
+        let b = data(BLOCK_LEN as usize * 2 + 10);
+        let leaves = block_hashes(&b);
+        let expected = hash_pair(
+            &hash_pair(&leaves[0], &leaves[1]),
+            &hash_pair(&leaves[2], &[0; 32]),
+        );
+        assert_eq!(file_root(&b), Some(expected));
+    }
+
+    #[test]
+    fn root_from_layer_matches_root_from_blocks() {
+        let piece_length = BLOCK_LEN * 4;
+
+        // 5 pieces and a bit, the layer is padded to 8 pieces
+        let file = data(piece_length as usize * 5 + 1000);
+        let layer = piece_layer(&file, piece_length);
+
+        assert_eq!(layer.len(), 6);
+        assert_eq!(piece_layer_index(piece_length), 2);
+        assert_eq!(pad_hash(2), piece_hash(&[], piece_length));
+        assert_eq!(
+            Some(root_from_piece_layer(&layer, piece_length)),
+            file_root(&file)
+        );
+    }
+
+    #[test]
+    fn proofs() {
+        let piece_length = BLOCK_LEN;
+        let file = data(piece_length as usize * 11);
+        let layer = piece_layer(&file, piece_length);
+        let root = root_from_piece_layer(&layer, piece_length);
+        let pad = pad_hash(0);
+
+        // the whole layer does not need uncles
+        let hashes = proof(&layer, pad, 0, 16, 0).unwrap();
+        assert_eq!(hashes.len(), 16);
+        assert!(verify_proof(&root, 0, 16, 11, &hashes));
+
+        // 4 hashes need 2 uncles to reach the root of 16 leaves
+        let hashes = proof(&layer, pad, 8, 4, 2).unwrap();
+        assert_eq!(hashes.len(), 6);
+        assert_eq!(&hashes[..3], &layer[8..11]);
+        assert_eq!(hashes[3], pad);
+        assert!(verify_proof(&root, 8, 4, 11, &hashes));
+
+        // tampered hashes are rejected
+        let mut bad = hashes.clone();
+        bad[0][0] ^= 1;
+        assert!(!verify_proof(&root, 8, 4, 11, &bad));
+
+        // not enough uncles
+        assert!(!verify_proof(&root, 8, 4, 11, &hashes[..5]));
+
+        // the index must be aligned to the length
+        assert!(!verify_proof(&root, 6, 4, 11, &hashes));
+        assert!(!verify_proof(&root, 16, 4, 11, &hashes));
+
+        // invalid requests
+        assert!(proof(&layer, pad, 2, 4, 0).is_none());
+        assert!(proof(&layer, pad, 0, 3, 0).is_none());
+        assert!(proof(&layer, pad, 16, 4, 0).is_none());
+    }
+
+    #[test]
+    fn proof_with_missing_uncles() {
+        let piece_length = BLOCK_LEN;
+        let file = data(piece_length as usize * 16);
+        let layer = piece_layer(&file, piece_length);
+        let root = root_from_piece_layer(&layer, piece_length);
+
+        // the children of the root hash to the root without any uncles, but
+        // they are not the hashes of the first 2 pieces.
+        let top = proof(&layer, pad_hash(0), 0, 16, 0)
+            .unwrap()
+            .chunks(8)
+            .map(|v| self::root(v, 8, [0; 32]))
+            .collect::<Vec<_>>();
+        assert_eq!(hash_pair(&top[0], &top[1]), root);
+        assert!(!verify_proof(&root, 0, 2, 16, &top));
+    }
+}
@@ -1,18 +1,28 @@
 //! Metainfo is a .torrent file with information about the Torrent.
 //! From the magnet link, we get the Metainfo from other peers.
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 
 use bendy::{
-    decoding::{self, FromBencode, Object, ResultExt},
+    decoding::{self, Decoder, FromBencode, Object, ResultExt},
     encoding::{self, AsString, Error, SingleItemEncoder, ToBencode},
 };
 use tracing::warn;
 
 use crate::{
+    bitfield::Bitfield,
     error,
     extensions::core::{BlockInfo, BLOCK_LEN},
+    magnet::Magnet,
+    merkle,
+    torrent::InfoHash,
 };
 
+/// The piece layers of a v2 torrent, BEP 52. The key is the `pieces root` of a
+/// file and the value is the concatenation of the hashes of its piece layer.
+///
+/// Files that are not larger than a piece do not have a piece layer.
+pub type PieceLayers = BTreeMap<[u8; 32], Vec<u8>>;
+
 /// Metainfo is a .torrent file with information about the Torrent.
 /// From the magnet link, we get the Metainfo from other peers.
 #[derive(Debug, PartialEq, Clone, Default)]
@@ -22,7 +32,163 @@ pub struct MetaInfo {
     pub info: Info,
     pub comment: Option<String>,
     pub creation_date: Option<u32>,
+    /// Web seeds in the Hoffman style, BEP 17.
     pub http_seeds: Option<Vec<String>>,
+    /// Web seeds in the GetRight style, BEP 19.
+    pub url_list: Option<Vec<String>>,
+    /// Only on v2 and hybrid torrents.
+    pub piece_layers: Option<PieceLayers>,
+}
+
+impl MetaInfo {
+    /// Compute the info hash of a .torrent file, which is the SHA1 of the
+    /// bencoded `info` dictionary, exactly as it appears on the file.
+    pub fn info_hash(buf: &[u8]) -> Result<[u8; 20], error::Error> {
+        Self::with_raw_info(buf, |raw| {
+            sha1_smol::Sha1::from(raw).digest().bytes()
+        })
+    }
+
+    /// Compute the v2 info hash of a .torrent file, which is the SHA-256 of
+    /// the bencoded `info` dictionary, BEP 52.
+    pub fn info_hash_v2(buf: &[u8]) -> Result<[u8; 32], error::Error> {
+        Self::with_raw_info(buf, merkle::hash)
+    }
+
+    /// Compute the info hashes of a .torrent file, according to its version.
+    pub fn info_hashes(buf: &[u8]) -> Result<InfoHash, error::Error> {
+        Self::with_raw_info(buf, |raw| {
+            let info = Info::from_bencode(raw)
+                .map_err(|_| error::Error::BencodeError)?;
+            let v1 = sha1_smol::Sha1::from(raw).digest().bytes();
+            let v2 = merkle::hash(raw);
+
+            Ok(match info.version() {
+                Version::V1 => InfoHash { v1: Some(v1), v2: None },
+                Version::V2 => InfoHash { v1: None, v2: Some(v2) },
+                Version::Hybrid => InfoHash { v1: Some(v1), v2: Some(v2) },
+            })
+        })?
+    }
+
+    /// The magnet link of a .torrent file, with its trackers and web seeds.
+    pub fn magnet(buf: &[u8]) -> Result<Magnet, error::Error> {
+        let info_hash = Self::info_hashes(buf)?;
+        let metainfo =
+            Self::from_bencode(buf).map_err(|_| error::Error::BencodeError)?;
+
+        Magnet::new(&metainfo.to_magnet_uri(&info_hash))
+    }
+
+    /// The bencoded `info` dictionary of a .torrent file, exactly as it
+    /// appears on the file.
+    pub fn raw_info(buf: &[u8]) -> Result<Vec<u8>, error::Error> {
+        Self::with_raw_info(buf, |raw| raw.to_vec())
+    }
+
+    /// Bencode the .torrent file, with the `info` dictionary being exactly
+    /// `raw_info`. Encoding [`Info`] again could drop keys that it does not
+    /// know about and change the info hash.
+    pub fn to_bencode_with_raw_info(
+        &self,
+        raw_info: &[u8],
+    ) -> Result<Vec<u8>, error::Error> {
+        let buf = self.to_bencode().map_err(|_| error::Error::BencodeError)?;
+
+        // position of the encoded `info` inside of `buf`
+        let (start, len) = Self::with_raw_info(&buf, |raw| {
+            (raw.as_ptr() as usize - buf.as_ptr() as usize, raw.len())
+        })?;
+
+        let mut torrent = Vec::with_capacity(buf.len() - len + raw_info.len());
+        torrent.extend_from_slice(&buf[..start]);
+        torrent.extend_from_slice(raw_info);
+        torrent.extend_from_slice(&buf[start + len..]);
+
+        Ok(torrent)
+    }
+
+    /// Call `f` with the bencoded `info` dictionary, exactly as it appears on
+    /// the file.
+    fn with_raw_info<T>(
+        buf: &[u8],
+        f: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, error::Error> {
+        let mut decoder = Decoder::new(buf);
+
+        let Ok(Some(Object::Dict(mut dict))) = decoder.next_object() else {
+            return Err(error::Error::BencodeError);
+        };
+
+        while let Some((key, value)) =
+            dict.next_pair().map_err(|_| error::Error::BencodeError)?
+        {
+            if key != b"info" {
+                continue;
+            }
+            let Object::Dict(info) = value else {
+                return Err(error::Error::BencodeError);
+            };
+            let raw =
+                info.into_raw().map_err(|_| error::Error::BencodeError)?;
+
+            return Ok(f(raw));
+        }
+
+        Err(error::Error::BencodeError)
+    }
+
+    /// All trackers of the torrent, from `announce` and `announce-list`,
+    /// without duplicates.
+    pub fn trackers(&self) -> Vec<String> {
+        let mut trackers = vec![self.announce.clone()];
+
+        for tier in self.announce_list.iter().flatten() {
+            for tracker in tier {
+                if !trackers.contains(tracker) {
+                    trackers.push(tracker.clone());
+                }
+            }
+        }
+
+        trackers.retain(|v| !v.is_empty());
+        trackers
+    }
+
+    /// Build a magnet link for this torrent, with its name, trackers and web
+    /// seeds. Hybrid torrents have both the v1 and v2 info hashes.
+    pub fn to_magnet_uri(&self, info_hash: &InfoHash) -> String {
+        let mut uri = "magnet:?".to_owned();
+
+        if let Some(v1) = &info_hash.v1 {
+            uri.push_str(&format!("xt=urn:btih:{}&", hex::encode(v1)));
+        }
+        if let Some(v2) = &info_hash.v2 {
+            // multihash of sha2-256, with 32 bytes
+            uri.push_str(&format!("xt=urn:btmh:1220{}&", hex::encode(v2)));
+        }
+
+        uri.push_str("dn=");
+        uri.push_str(&urlencoding::encode(&self.info.name));
+
+        for tracker in self.trackers() {
+            uri.push_str("&tr=");
+            uri.push_str(&urlencoding::encode(&tracker));
+        }
+
+        for seed in self.url_list.iter().flatten() {
+            uri.push_str("&ws=");
+            uri.push_str(&urlencoding::encode(seed));
+        }
+
+        // there is no standard parameter for BEP 17 seeds
+        for seed in self.http_seeds.iter().flatten() {
+            uri.push_str("&x.hs=");
+            uri.push_str(&urlencoding::encode(seed));
+        }
+
+        uri
+    }
 }
 
 /// File related information (Single-file format)
@@ -40,8 +206,24 @@ pub struct Info {
     /// name of the file
     pub name: String,
     /// length - bytes of the entire file
-    pub file_length: Option<u32>,
+    pub file_length: Option<u64>,
     pub files: Option<Vec<File>>,
+    /// If set to 1, peers should only be obtained from the trackers, BEP 27.
+    pub private: Option<u8>,
+    /// 2 on v2 and hybrid torrents, BEP 52.
+    pub meta_version: Option<u8>,
+    /// The files of v2 and hybrid torrents, BEP 52.
+    pub file_tree: Option<FileTree>,
+}
+
+/// Version of the protocol of a torrent, BEP 52.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+    /// A torrent with both the v1 and v2 metadata, that participates in both
+    /// swarms.
+    Hybrid,
 }
 
 impl Info {
@@ -49,13 +231,33 @@ impl Info {
         self.name = name;
         self
     }
-    /// Calculate how many pieces there are.
+    /// Calculate how many pieces there are. v2 torrents do not have the
+    /// hashes of the pieces, the pieces are counted from the size of the
+    /// files, after [`Info::align_v2_files`].
     pub fn pieces(&self) -> u32 {
+        if self.version() == Version::V2 {
+            if self.piece_length == 0 {
+                return 0;
+            }
+            return self.get_size().div_ceil(self.piece_length as u64) as u32;
+        }
         self.pieces.len() as u32 / 20
     }
+    pub fn version(&self) -> Version {
+        match (self.meta_version, self.pieces.is_empty()) {
+            (Some(2), true) => Version::V2,
+            (Some(2), false) => Version::Hybrid,
+            _ => Version::V1,
+        }
+    }
+    /// If the torrent is private, BEP 27. The peers of private torrents must
+    /// only come from their trackers.
+    pub fn is_private(&self) -> bool {
+        self.private == Some(1)
+    }
     /// Calculate how many blocks there are in the entire torrent.
     pub fn blocks_len(&self) -> u32 {
-        self.blocks_per_piece() * (self.pieces.len() as u32 / 20)
+        self.blocks_per_piece() * self.pieces()
     }
     /// Calculate how many blocks there are per piece
     pub fn blocks_per_piece(&self) -> u32 {
@@ -65,10 +267,11 @@ impl Info {
     /// Returns an Err if the Info is malformed, if it does not have `files` or
     /// `file_length`.
     pub fn get_block_infos(&self) -> Result<VecDeque<BlockInfo>, error::Error> {
-        let total_size = self.get_size() as u32;
+        let total_size = self.get_size();
+        let piece_length = self.piece_length as u64;
         let mut block_infos = Vec::new();
-        let mut processed_bytes = 0;
-        let mut offset_within_file = 0;
+        let mut processed_bytes = 0_u64;
+        let mut offset_within_file = 0_u64;
         let mut file_index = 0;
 
         while processed_bytes < total_size {
@@ -78,18 +281,20 @@ impl Info {
                 });
             let len = [
                 remaining_in_file,
-                self.piece_length - processed_bytes % self.piece_length,
-                BLOCK_LEN,
+                piece_length - processed_bytes % piece_length,
+                BLOCK_LEN as u64,
             ]
             .iter()
             .cloned()
             .min()
             .unwrap();
 
+            // the index, begin and len are u32 on the wire, the begin and
+            // len are always smaller than a piece.
             block_infos.push(BlockInfo {
-                index: (processed_bytes / self.piece_length),
-                begin: processed_bytes % self.piece_length,
-                len,
+                index: (processed_bytes / piece_length) as u32,
+                begin: (processed_bytes % piece_length) as u32,
+                len: len as u32,
             });
 
             processed_bytes += len;
@@ -111,32 +316,234 @@ impl Info {
     pub fn get_size(&self) -> u64 {
         // multi file torrent
         if let Some(files) = &self.files {
-            return files.iter().fold(0, |acc, x| acc + x.length as u64);
+            return files.iter().fold(0, |acc, x| acc + x.length);
         }
 
         // single file torrent
         if let Some(f) = self.file_length {
-            return f as u64;
+            return f;
+        }
+
+        // v2 torrent
+        if let Some(file_tree) = &self.file_tree {
+            return file_tree.files().iter().fold(0, |acc, x| acc + x.length);
         }
 
         warn!("tried to call get_size of malformed Info {self:#?}");
         0
     }
 
+    /// The piece layers of the files of v2 and hybrid torrents, with all the
+    /// hashes unknown, which are zeros. Files that are not larger than a piece
+    /// do not have a piece layer, the `pieces root` is the hash of the piece.
+    pub fn empty_piece_layers(&self) -> PieceLayers {
+        let Some(file_tree) = &self.file_tree else {
+            return PieceLayers::new();
+        };
+        let piece_length = self.piece_length as u64;
+
+        file_tree
+            .files()
+            .into_iter()
+            .filter(|f| f.length > piece_length)
+            .filter_map(|f| {
+                let hashes = f.length.div_ceil(piece_length) as usize;
+                Some((f.pieces_root?, vec![0; hashes * 32]))
+            })
+            .collect()
+    }
+
+    /// The pieces that have bytes of the files for which `selected` returns
+    /// true, given the index of the file. Single file torrents only have the
+    /// file 0, and padding files are never selected.
+    pub fn selected_pieces(
+        &self,
+        selected: impl Fn(usize) -> bool,
+    ) -> Bitfield {
+        let pieces = self.pieces() as usize;
+        let piece_length = self.piece_length as u64;
+
+        let Some(files) = &self.files else {
+            return Bitfield::repeat(selected(0), pieces);
+        };
+
+        let mut bitfield = Bitfield::repeat(false, pieces);
+        let mut offset = 0;
+
+        for (i, file) in files.iter().enumerate() {
+            if piece_length > 0
+                && file.length > 0
+                && !file.attr.padding
+                && selected(i)
+            {
+                let first = (offset / piece_length) as usize;
+                let last = ((offset + file.length - 1) / piece_length) as usize;
+                if first < pieces {
+                    bitfield[first..=last.min(pieces - 1)].fill(true);
+                }
+            }
+            offset += file.length;
+        }
+
+        bitfield
+    }
+
     /// Get the size (in bytes) of a piece.
     pub fn piece_size(&self, piece_index: usize) -> u32 {
-        let total_size = self.get_size() as u32;
+        let total_size = self.get_size();
         if piece_index == self.pieces() as usize - 1 {
-            let remainder = total_size % self.piece_length;
+            let remainder = total_size % self.piece_length as u64;
             if remainder == 0 {
                 self.piece_length
             } else {
-                remainder
+                remainder as u32
             }
         } else {
             self.piece_length
         }
     }
+
+    /// Make the paths of the torrent safe to be joined onto the download
+    /// directory, since they come from untrusted sources.
+    ///
+    /// Separators and control characters are replaced by `_`, reserved names
+    /// are prefixed with `_`, overlong names are truncated, and empty or `.`
+    /// components are removed. The same is done to the keys of the file tree,
+    /// to keep them matching the files.
+    ///
+    /// # Errors
+    ///
+    /// If a path has a `..` component, or if a path or the name would be
+    /// empty.
+    pub fn sanitize(&mut self) -> Result<(), error::Error> {
+        self.name = match sanitize_segment(&self.name)? {
+            Some(name) => name,
+            None => return Err(error::Error::UnsafePath(self.name.clone())),
+        };
+
+        for file in self.files.iter_mut().flatten() {
+            file.path = sanitize_path(&file.path)?;
+            if let Some(target) = &mut file.symlink_path {
+                *target = sanitize_path(target)?;
+            }
+        }
+
+        if let Some(file_tree) = &mut self.file_tree {
+            file_tree.sanitize()?;
+        }
+
+        Ok(())
+    }
+
+    /// The files of v2 only torrents are only on the file tree, and each one
+    /// starts at the beginning of a piece. Write them to `file_length` or
+    /// `files`, with pad files between them, so that the torrent has the
+    /// layout of a hybrid torrent, which is used by the disk.
+    ///
+    /// Does nothing on v1 and hybrid torrents. The info hash is still the one
+    /// of the original bytes of the info.
+    pub fn align_v2_files(&mut self) {
+        if self.version() != Version::V2
+            || self.files.is_some()
+            || self.file_length.is_some()
+        {
+            return;
+        }
+        let Some(file_tree) = &self.file_tree else { return };
+        let tree_files = file_tree.files();
+
+        // a single file torrent has only one file, named after the torrent
+        if let [file] = tree_files.as_slice() {
+            if file.path == [self.name.clone()] {
+                self.file_length = Some(file.length);
+                return;
+            }
+        }
+
+        let piece_length = self.piece_length as u64;
+        let mut files = Vec::with_capacity(tree_files.len() * 2);
+        let last = tree_files.len().saturating_sub(1);
+
+        for (i, file) in tree_files.into_iter().enumerate() {
+            let remainder = file.length % piece_length.max(1);
+
+            files.push(File {
+                length: file.length,
+                path: file.path,
+                ..Default::default()
+            });
+
+            if i != last && remainder != 0 {
+                let pad = piece_length - remainder;
+                files.push(File {
+                    length: pad,
+                    path: vec![".pad".to_owned(), pad.to_string()],
+                    attr: FileAttr { padding: true, ..Default::default() },
+                    ..Default::default()
+                });
+            }
+        }
+
+        self.files = Some(files);
+    }
+}
+
+/// The longest name of a file or directory on most filesystems, in bytes.
+const MAX_SEGMENT_LEN: usize = 255;
+
+/// Names that can't be used as a file name on Windows, with any extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6",
+    "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6",
+    "LPT7", "LPT8", "LPT9",
+];
+
+/// Sanitize a single component of a path, `None` if the component must be
+/// removed.
+fn sanitize_segment(segment: &str) -> Result<Option<String>, error::Error> {
+    match segment {
+        "" | "." => return Ok(None),
+        ".." => return Err(error::Error::UnsafePath(segment.to_owned())),
+        _ => {}
+    }
+
+    let mut segment: String = segment
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect();
+
+    let stem = segment.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        segment.insert(0, '_');
+    }
+
+    if segment.len() > MAX_SEGMENT_LEN {
+        // keep the extension of the file, if it is not overlong itself
+        let ext = match segment.rfind('.') {
+            Some(i) if segment.len() - i <= 16 => segment.split_off(i),
+            _ => String::new(),
+        };
+        let mut end = MAX_SEGMENT_LEN - ext.len();
+        while !segment.is_char_boundary(end) {
+            end -= 1;
+        }
+        segment.truncate(end);
+        segment.push_str(&ext);
+    }
+
+    Ok(Some(segment))
+}
+
+/// Sanitize all components of a path.
+fn sanitize_path(path: &[String]) -> Result<Vec<String>, error::Error> {
+    let mut sanitized = Vec::with_capacity(path.len());
+    for segment in path {
+        sanitized.extend(sanitize_segment(segment)?);
+    }
+    if sanitized.is_empty() {
+        return Err(error::Error::UnsafePath(path.join("/")));
+    }
+    Ok(sanitized)
 }
 
 /// Files in the [`Info`] are relative to the root folder name,
@@ -144,25 +551,229 @@ impl Info {
 #[derive(Debug, PartialEq, Clone, Default, Hash, Eq)]
 pub struct File {
     /// Length of the file in bytes.
-    pub length: u32,
+    pub length: u64,
     /// Path of the file, excluding the parent name.
     pub path: Vec<String>,
+    /// Attributes of the file, BEP 47.
+    pub attr: FileAttr,
+    /// Path of the target of a symlink, relative to the root folder of the
+    /// torrent, BEP 47.
+    pub symlink_path: Option<Vec<String>>,
+    /// SHA1 of the file, BEP 47.
+    pub sha1: Option<[u8; 20]>,
+}
+
+/// Attributes of a [`File`], the `attr` string of BEP 47.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Hash, Eq)]
+pub struct FileAttr {
+    /// `p`: a padding file, which is only zeros, to align the next file to a
+    /// piece. It is never written to disk.
+    pub padding: bool,
+    /// `x`: the file is executable.
+    pub executable: bool,
+    /// `h`: the file is hidden.
+    pub hidden: bool,
+    /// `l`: the file is a symlink to `symlink path`.
+    pub symlink: bool,
+}
+
+impl FileAttr {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl From<&str> for FileAttr {
+    /// Unknown attributes are ignored.
+    fn from(value: &str) -> Self {
+        Self {
+            padding: value.contains('p'),
+            executable: value.contains('x'),
+            hidden: value.contains('h'),
+            symlink: value.contains('l'),
+        }
+    }
+}
+
+impl std::fmt::Display for FileAttr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (set, c) in [
+            (self.padding, 'p'),
+            (self.executable, 'x'),
+            (self.hidden, 'h'),
+            (self.symlink, 'l'),
+        ] {
+            if set {
+                write!(f, "{c}")?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl File {
     /// Get the len of the given piece in the file, in bytes..
     pub fn get_piece_len(&self, piece: u32, piece_length: u32) -> u32 {
-        let b = (piece * piece_length) + piece_length;
+        let b = (piece as u64 * piece_length as u64) + piece_length as u64;
         if b <= self.length {
             piece_length
         } else {
-            self.length % piece_length
+            (self.length % piece_length as u64) as u32
         }
     }
     /// Return the number of pieces in the file, rounded up.
     pub fn pieces(&self, piece_length: u32) -> u32 {
-        let pieces = self.length as f32 / piece_length as f32;
-        pieces.ceil() as u32
+        self.length.div_ceil(piece_length as u64) as u32
+    }
+}
+
+/// A node of the `file tree` of a v2 torrent, BEP 52. The root is always a
+/// directory.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub enum FileTree {
+    Dir(BTreeMap<String, FileTree>),
+    File {
+        length: u64,
+        /// Root of the merkle tree of the file, empty files do not have one.
+        pieces_root: Option<[u8; 32]>,
+    },
+}
+
+impl Default for FileTree {
+    fn default() -> Self {
+        Self::Dir(BTreeMap::new())
+    }
+}
+
+/// A file of a [`FileTree`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct TreeFile {
+    /// Path of the file, excluding the name of the torrent on multi file
+    /// torrents.
+    pub path: Vec<String>,
+    pub length: u64,
+    pub pieces_root: Option<[u8; 32]>,
+}
+
+impl FileTree {
+    /// All the files of the tree, in the order of the tree.
+    pub fn files(&self) -> Vec<TreeFile> {
+        let mut files = Vec::new();
+        self.collect_files(&mut Vec::new(), &mut files);
+        files
+    }
+
+    /// Sanitize the names of the tree, like [`Info::sanitize`].
+    fn sanitize(&mut self) -> Result<(), error::Error> {
+        let Self::Dir(entries) = self else { return Ok(()) };
+
+        for (name, mut node) in std::mem::take(entries) {
+            node.sanitize()?;
+            match sanitize_segment(&name)? {
+                Some(name) => {
+                    entries.insert(name, node);
+                }
+                None => return Err(error::Error::UnsafePath(name)),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn collect_files(&self, path: &mut Vec<String>, files: &mut Vec<TreeFile>) {
+        match self {
+            Self::File { length, pieces_root } => files.push(TreeFile {
+                path: path.clone(),
+                length: *length,
+                pieces_root: *pieces_root,
+            }),
+            Self::Dir(entries) => {
+                for (name, node) in entries {
+                    path.push(name.clone());
+                    node.collect_files(path, files);
+                    path.pop();
+                }
+            }
+        }
+    }
+}
+
+impl ToBencode for FileTree {
+    /// Deepest directory supported in a file tree.
+    const MAX_DEPTH: usize = 64;
+
+    fn encode(&self, encoder: SingleItemEncoder) -> Result<(), Error> {
+        match self {
+            Self::Dir(entries) => encoder.emit_dict(|mut e| {
+                for (name, node) in entries {
+                    e.emit_pair(name.as_bytes(), node)?;
+                }
+                Ok(())
+            }),
+            Self::File { length, pieces_root } => encoder.emit_dict(|mut e| {
+                e.emit_pair_with(b"", |e| {
+                    e.emit_dict(|mut e| {
+                        e.emit_pair(b"length", length)?;
+                        if let Some(root) = pieces_root {
+                            e.emit_pair(b"pieces root", AsString(root))?;
+                        }
+                        Ok(())
+                    })
+                })
+            }),
+        }
+    }
+}
+
+impl FromBencode for FileTree {
+    fn decode_bencode_object(object: Object) -> Result<Self, decoding::Error>
+    where
+        Self: Sized,
+    {
+        let mut entries = BTreeMap::new();
+        let mut dict_dec = object.try_into_dictionary()?;
+
+        while let Some((key, value)) = dict_dec.next_pair()? {
+            // the empty key marks a file
+            if key.is_empty() {
+                let mut length = 0;
+                let mut pieces_root = None;
+                let mut file_dec = value.try_into_dictionary()?;
+
+                while let Some(pair) = file_dec.next_pair()? {
+                    match pair {
+                        (b"length", value) => {
+                            length = u64::decode_bencode_object(value)
+                                .context("length")?;
+                        }
+                        (b"pieces root", value) => {
+                            let root = AsString::decode_bencode_object(value)
+                                .context("pieces root")?
+                                .0;
+                            pieces_root = Some(root.try_into().map_err(
+                                |v: Vec<u8>| {
+                                    decoding::Error::unexpected_token(
+                                        "32 bytes",
+                                        format!("{} bytes", v.len()),
+                                    )
+                                    .context("pieces root")
+                                },
+                            )?);
+                        }
+                        _ => {}
+                    }
+                }
+
+                return Ok(Self::File { length, pieces_root });
+            }
+
+            let name = String::decode_bencode_object(Object::Bytes(key))
+                .context("file tree")?;
+            let node = FileTree::decode_bencode_object(value).context(&name)?;
+            entries.insert(name, node);
+        }
+
+        Ok(Self::Dir(entries))
     }
 }
 
@@ -171,8 +782,18 @@ impl ToBencode for File {
 
     fn encode(&self, encoder: SingleItemEncoder) -> Result<(), Error> {
         encoder.emit_dict(|mut e| {
+            if !self.attr.is_empty() {
+                e.emit_pair(b"attr", self.attr.to_string())?;
+            }
             e.emit_pair(b"length", self.length)?;
-            e.emit_pair(b"path", &self.path)
+            e.emit_pair(b"path", &self.path)?;
+            if let Some(sha1) = &self.sha1 {
+                e.emit_pair(b"sha1", AsString(sha1))?;
+            }
+            if let Some(symlink_path) = &self.symlink_path {
+                e.emit_pair(b"symlink path", symlink_path)?;
+            }
+            Ok(())
         })?;
         Ok(())
     }
@@ -186,12 +807,34 @@ impl FromBencode for File {
         let mut dict_dec = object.try_into_dictionary()?;
         let mut length = 0;
         let mut path: Vec<String> = vec![];
+        let mut attr = FileAttr::default();
+        let mut symlink_path = None;
+        let mut sha1 = None;
 
         while let Some(pair) = dict_dec.next_pair()? {
             match pair {
+                (b"attr", value) => {
+                    attr = String::decode_bencode_object(value)
+                        .context("attr")?
+                        .as_str()
+                        .into();
+                }
+                (b"sha1", value) => {
+                    let bytes = AsString::decode_bencode_object(value)
+                        .context("sha1")?
+                        .0;
+                    // a sha1 with the wrong length is ignored
+                    sha1 = <[u8; 20]>::try_from(bytes).ok();
+                }
+                (b"symlink path", value) => {
+                    symlink_path = Some(
+                        Vec::<String>::decode_bencode_object(value)
+                            .context("symlink path")?,
+                    );
+                }
                 (b"length", value) => {
                     length =
-                        u32::decode_bencode_object(value).context("length")?;
+                        u64::decode_bencode_object(value).context("length")?;
                 }
                 (b"path", value) => {
                     path = Vec::<String>::decode_bencode_object(value)
@@ -201,19 +844,22 @@ impl FromBencode for File {
             }
         }
 
-        Ok(Self { length, path })
+        Ok(Self { length, path, attr, symlink_path, sha1 })
     }
 }
 
 impl ToBencode for MetaInfo {
-    const MAX_DEPTH: usize = 5;
+    const MAX_DEPTH: usize = <Info as ToBencode>::MAX_DEPTH + 1;
 
     fn encode(
         &self,
         encoder: SingleItemEncoder,
     ) -> Result<(), encoding::Error> {
         encoder.emit_dict(|mut e| {
-            e.emit_pair(b"announce", &self.announce)?;
+            // trackerless torrents do not have an announce
+            if !self.announce.is_empty() {
+                e.emit_pair(b"announce", &self.announce)?;
+            }
 
             if let Some(announce_list) = &self.announce_list {
                 e.emit_pair(b"announce-list", announce_list)?;
@@ -231,7 +877,24 @@ impl ToBencode for MetaInfo {
                 e.emit_pair(b"httpseeds", seeds)?;
             }
 
-            e.emit_pair(b"info", &self.info)
+            e.emit_pair(b"info", &self.info)?;
+
+            if let Some(piece_layers) = &self.piece_layers {
+                e.emit_pair_with(b"piece layers", |e| {
+                    e.emit_dict(|mut e| {
+                        for (root, layer) in piece_layers {
+                            e.emit_pair(root, AsString(layer))?;
+                        }
+                        Ok(())
+                    })
+                })?;
+            }
+
+            if let Some(url_list) = &self.url_list {
+                e.emit_pair(b"url-list", url_list)?;
+            }
+
+            Ok(())
         })?;
 
         Ok(())
@@ -239,19 +902,32 @@ impl ToBencode for MetaInfo {
 }
 
 impl ToBencode for Info {
-    const MAX_DEPTH: usize = 5;
+    const MAX_DEPTH: usize = <FileTree as ToBencode>::MAX_DEPTH + 1;
 
     fn encode(&self, encoder: SingleItemEncoder) -> Result<(), Error> {
         encoder.emit_dict(|mut e| {
+            if let Some(file_tree) = &self.file_tree {
+                e.emit_pair(b"file tree", file_tree)?;
+            }
             if let Some(file_length) = &self.file_length {
                 e.emit_pair(b"length", file_length)?;
             }
             if let Some(files) = &self.files {
                 e.emit_pair(b"files", files)?;
             }
+            if let Some(meta_version) = &self.meta_version {
+                e.emit_pair(b"meta version", meta_version)?;
+            }
             e.emit_pair(b"name", &self.name)?;
             e.emit_pair(b"piece length", self.piece_length)?;
-            e.emit_pair(b"pieces", AsString(&self.pieces))
+            // v2 torrents do not have v1 pieces
+            if self.version() != Version::V2 {
+                e.emit_pair(b"pieces", AsString(&self.pieces))?;
+            }
+            if let Some(private) = &self.private {
+                e.emit_pair(b"private", private)?;
+            }
+            Ok(())
         })?;
         Ok(())
     }
@@ -267,7 +943,9 @@ impl FromBencode for MetaInfo {
         let mut comment = None;
         let mut creation_date = None;
         let mut http_seeds = None;
+        let mut url_list = None;
         let mut info = None;
+        let mut piece_layers = None;
 
         let mut dict_dec = object.try_into_dictionary()?;
         while let Some(pair) = dict_dec.next_pair()? {
@@ -302,12 +980,43 @@ impl FromBencode for MetaInfo {
                         .context("info")
                         .map(Some)?;
                 }
+                (b"piece layers", value) => {
+                    let mut layers = PieceLayers::new();
+                    let mut dict_dec = value.try_into_dictionary()?;
+
+                    while let Some((root, layer)) = dict_dec.next_pair()? {
+                        let root = root.try_into().map_err(|_| {
+                            decoding::Error::unexpected_token(
+                                "32 bytes",
+                                format!("{} bytes", root.len()),
+                            )
+                            .context("piece layers")
+                        })?;
+                        let layer = AsString::decode_bencode_object(layer)
+                            .context("piece layers")?
+                            .0;
+                        layers.insert(root, layer);
+                    }
+
+                    piece_layers = Some(layers);
+                }
+                (b"url-list", value) => {
+                    // it can be a single url or a list of urls
+                    url_list = match value {
+                        Object::List(_) => Vec::decode_bencode_object(value)
+                            .context("url-list")
+                            .map(Some)?,
+                        value => String::decode_bencode_object(value)
+                            .context("url-list")
+                            .map(|v| Some(vec![v]))?,
+                    };
+                }
                 _ => {}
             }
         }
 
-        let announce = announce
-            .ok_or_else(|| decoding::Error::missing_field("announce"))?;
+        // trackerless torrents do not have an announce
+        let announce = announce.unwrap_or_default();
         let info =
             info.ok_or_else(|| decoding::Error::missing_field("info"))?;
 
@@ -318,6 +1027,8 @@ impl FromBencode for MetaInfo {
             comment,
             creation_date,
             http_seeds,
+            url_list,
+            piece_layers,
         })
     }
 }
@@ -332,17 +1043,25 @@ impl FromBencode for Info {
         let mut name = None;
         let mut piece_length = None;
         let mut pieces = None;
+        let mut private = None;
+        let mut meta_version = None;
+        let mut file_tree = None;
 
         let mut dict_dec = object.try_into_dictionary()?;
         while let Some(pair) = dict_dec.next_pair()? {
             match pair {
+                (b"file tree", value) => {
+                    file_tree = FileTree::decode_bencode_object(value)
+                        .context("file tree")
+                        .map(Some)?;
+                }
                 (b"files", value) => {
                     files = Vec::<File>::decode_bencode_object(value)
                         .context("files")
                         .map(Some)?;
                 }
                 (b"length", value) => {
-                    file_length = u32::decode_bencode_object(value)
+                    file_length = u64::decode_bencode_object(value)
                         .context("file.length")
                         .map(Some)?;
                 }
@@ -361,6 +1080,16 @@ impl FromBencode for Info {
                         .context("pieces")
                         .map(|bytes| Some(bytes.0))?;
                 }
+                (b"private", value) => {
+                    private = u8::decode_bencode_object(value)
+                        .context("private")
+                        .map(Some)?;
+                }
+                (b"meta version", value) => {
+                    meta_version = u8::decode_bencode_object(value)
+                        .context("meta version")
+                        .map(Some)?;
+                }
                 _ => {}
             }
         }
@@ -369,17 +1098,31 @@ impl FromBencode for Info {
             name.ok_or_else(|| decoding::Error::missing_field("name"))?;
         let piece_length = piece_length
             .ok_or_else(|| decoding::Error::missing_field("piece_length"))?;
-        let pieces =
-            pieces.ok_or_else(|| decoding::Error::missing_field("pieces"))?;
+        // v2 torrents only have the `file tree`
+        let pieces = match (pieces, meta_version) {
+            (Some(pieces), _) => pieces,
+            (None, Some(2)) if file_tree.is_some() => Vec::new(),
+            _ => return Err(decoding::Error::missing_field("pieces")),
+        };
 
         // Check that we discovered all necessary fields
-        Ok(Info { files, file_length, name, piece_length, pieces })
+        Ok(Info {
+            files,
+            file_length,
+            name,
+            piece_length,
+            pieces,
+            private,
+            meta_version,
+            file_tree,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::magnet::Magnet;
 
     /// piece_length: 15
     /// -------------------
@@ -441,6 +1184,7 @@ mod tests {
             files: Some(vec![File {
                 length: 32768,
                 path: vec!["a.txt".to_owned()],
+                ..Default::default()
             }]),
             piece_length: BLOCK_LEN,
             pieces: vec![0; 40],
@@ -468,6 +1212,7 @@ mod tests {
             files: Some(vec![File {
                 length: 32768,
                 path: vec!["a.txt".to_owned()],
+                ..Default::default()
             }]),
             piece_length: 32668,
             pieces: vec![0; 40],
@@ -494,9 +1239,21 @@ mod tests {
     fn get_block_infos_file_boundary() {
         let info = Info {
             files: Some(vec![
-                File { length: BLOCK_LEN, path: vec!["a.txt".to_owned()] },
-                File { length: 12384, path: vec!["b.txt".to_owned()] },
-                File { length: BLOCK_LEN, path: vec!["c.txt".to_owned()] },
+                File {
+                    length: BLOCK_LEN as u64,
+                    path: vec!["a.txt".to_owned()],
+                    ..Default::default()
+                },
+                File {
+                    length: 12384,
+                    path: vec!["b.txt".to_owned()],
+                    ..Default::default()
+                },
+                File {
+                    length: BLOCK_LEN as u64,
+                    path: vec!["c.txt".to_owned()],
+                    ..Default::default()
+                },
             ]),
             piece_length: 45152,
             pieces: vec![0; 20],
@@ -531,10 +1288,15 @@ mod tests {
     fn get_block_infos_odd_pre() {
         let info = Info {
             files: Some(vec![
-                File { length: 10, path: vec!["".to_owned()] },
+                File {
+                    length: 10,
+                    path: vec!["".to_owned()],
+                    ..Default::default()
+                },
                 File {
                     length: 32768, // 2 blocks
                     path: vec!["".to_owned()],
+                    ..Default::default()
                 },
             ]),
             piece_length: 32668, // -100 of block_len
@@ -571,8 +1333,13 @@ mod tests {
                 File {
                     length: 32768, // 2 blocks
                     path: vec!["".to_owned()],
+                    ..Default::default()
+                },
+                File {
+                    length: 10,
+                    path: vec!["".to_owned()],
+                    ..Default::default()
                 },
-                File { length: 10, path: vec!["".to_owned()] },
             ]),
             piece_length: 32668, // -100 of block_len
             pieces: vec![0u8; 40],
@@ -685,7 +1452,8 @@ mod tests {
         println!("--- piece 25, block 1610 (last of file) ---");
         println!("{block:#?}");
 
-        let bytes_so_far = bi.iter().take(1611).fold(0, |acc, x| acc + x.len);
+        let bytes_so_far =
+            bi.iter().take(1611).fold(0, |acc, x| acc + x.len as u64);
         assert_eq!(bytes_so_far, file0.length);
 
         assert_eq!(*block, BlockInfo { index: 25, begin: 163840, len: 5920 });
@@ -784,6 +1552,67 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn info_hash_and_magnet() {
+        let bytes = include_bytes!("../../../test-files/debian.torrent");
+        let info_hash = MetaInfo::info_hash(bytes).unwrap();
+
+        assert_eq!(
+            hex::encode(info_hash),
+            "7431a969b347e14bba641b3517c024f7b40dfb7f"
+        );
+
+        let torrent = MetaInfo::from_bencode(bytes).unwrap();
+        let magnet =
+            Magnet::new(&torrent.to_magnet_uri(&info_hash.into())).unwrap();
+
+        assert_eq!(magnet.parse_xt(), info_hash);
+        assert_eq!(magnet.parse_dn(), torrent.info.name);
+        assert_eq!(magnet.tr.len(), torrent.trackers().len());
+        assert!(MetaInfo::info_hash(b"d4:spam4:eggse").is_err());
+    }
+
+    #[test]
+    fn private_flag() {
+        let public =
+            b"d6:lengthi5e4:name3:foo12:piece lengthi16384e6:pieces0:e";
+        let private =
+            b"d6:lengthi5e4:name3:foo12:piece lengthi16384e6:pieces0:7:privatei1ee";
+
+        let info = Info::from_bencode(private).unwrap();
+        assert!(info.is_private());
+        assert!(!Info::from_bencode(public).unwrap().is_private());
+
+        // the flag is part of the info, and so, of the info hash
+        assert_eq!(info.to_bencode().unwrap(), private);
+    }
+
+    #[test]
+    fn encode_with_raw_info() {
+        // `x-custom` is not a field of Info, and would be lost by encoding it
+        let mut raw =
+            b"d6:lengthi5e4:name3:foo12:piece lengthi16384e6:pieces20:"
+                .to_vec();
+        raw.extend_from_slice(&[7; 20]);
+        raw.extend_from_slice(b"8:x-customi1ee");
+
+        let metainfo = MetaInfo {
+            announce: "udp://tracker.example:1337".to_owned(),
+            info: Info::from_bencode(&raw).unwrap(),
+            ..Default::default()
+        };
+        assert_ne!(metainfo.info.to_bencode().unwrap(), raw);
+
+        let torrent = metainfo.to_bencode_with_raw_info(&raw).unwrap();
+
+        assert_eq!(MetaInfo::raw_info(&torrent).unwrap(), raw);
+        assert_eq!(
+            MetaInfo::info_hash(&torrent).unwrap(),
+            sha1_smol::Sha1::from(&raw).digest().bytes()
+        );
+        assert_eq!(MetaInfo::from_bencode(&torrent).unwrap(), metainfo);
+    }
+
     #[tokio::test]
     async fn utility_functions_complex_single() -> Result<(), Error> {
         //
@@ -863,12 +1692,24 @@ mod tests {
                 File {
                     length: 5034059,
                     path: vec!["dir".to_string(), "file_a.pdf".to_string()],
+                    ..Default::default()
                 },
                 // 1 block
-                File { length: 62, path: vec!["file_1.txt".to_string()] },
+                File {
+                    length: 62,
+                    path: vec!["file_1.txt".to_string()],
+                    ..Default::default()
+                },
                 // 1 block
-                File { length: 237, path: vec!["file_2.txt".to_string()] },
+                File {
+                    length: 237,
+                    path: vec!["file_2.txt".to_string()],
+                    ..Default::default()
+                },
             ]),
+            private: None,
+            meta_version: None,
+            file_tree: None,
         };
 
         let block_infos = info.get_block_infos().unwrap();
@@ -983,9 +1824,15 @@ mod tests {
                     files: Some(vec![File {
                         length: 4092334,
                         path: vec!["book.pdf".to_owned()],
+                        ..Default::default()
                     }]),
                     file_length: None,
+                    private: None,
+                    meta_version: None,
+                    file_tree: None,
                 },
+                url_list: None,
+                piece_layers: None,
             }
         });
 
@@ -1015,7 +1862,12 @@ mod tests {
                     name: "debian-9.4.0-amd64-netinst.iso".to_owned(),
                     files: None,
                     file_length: Some(305_135_616),
+                    private: None,
+                    meta_version: None,
+                    file_tree: None,
                 },
+                url_list: None,
+                piece_layers: None,
             }
         });
 
@@ -1043,7 +1895,12 @@ mod tests {
                 name: "debian-9.4.0-amd64-netinst.iso".to_owned(),
                 files: None,
                 file_length: Some(305_135_616),
+                private: None,
+                meta_version: None,
+                file_tree: None,
             },
+            url_list: None,
+            piece_layers: None,
         };
 
         let data = torrent.to_bencode()?;
@@ -1060,6 +1917,7 @@ mod tests {
         let file = File {
             path: ["a".to_owned(), "b".to_owned(), "c.txt".to_owned()].into(),
             length: 222,
+            ..Default::default()
         };
 
         let data = file.to_bencode()?;
@@ -1086,9 +1944,414 @@ mod tests {
                 path: ["a".to_owned(), "b".to_owned(), "c.txt".to_owned()]
                     .into(),
                 length: 222,
+                ..Default::default()
             }
         );
 
         Ok(())
     }
+
+    #[test]
+    fn selected_pieces() {
+        let file = |length, attr: &str| File {
+            length,
+            attr: attr.into(),
+            ..Default::default()
+        };
+        let info = Info {
+            piece_length: 10,
+            pieces: vec![0; 20 * 4],
+            files: Some(vec![
+                file(15, ""),
+                file(5, "p"),
+                file(12, ""),
+                file(8, ""),
+            ]),
+            ..Default::default()
+        };
+
+        let pieces = |files: &[usize]| {
+            let selected = info.selected_pieces(|i| files.contains(&i));
+            selected.iter_ones().collect::<Vec<_>>()
+        };
+
+        assert_eq!(pieces(&[0]), [0, 1]);
+        // padding files are never selected
+        assert_eq!(pieces(&[1]), Vec::<usize>::new());
+        assert_eq!(pieces(&[2]), [2, 3]);
+        assert_eq!(pieces(&[3]), [3]);
+        assert_eq!(pieces(&[0, 1, 2, 3]), [0, 1, 2, 3]);
+
+        let single = Info {
+            piece_length: 10,
+            pieces: vec![0; 20 * 2],
+            file_length: Some(20),
+            ..Default::default()
+        };
+        assert!(single.selected_pieces(|i| i == 0).all());
+        assert!(single.selected_pieces(|_| false).not_any());
+    }
+
+    /// The attributes of BEP 47 survive a round trip, and unknown
+    /// attributes are ignored.
+    #[test]
+    fn file_attrs() -> Result<(), decoding::Error> {
+        let data = b"d4:attr3:xlz6:lengthi0e4:pathl4:linke4:sha120:aaaaaaaaaaaaaaaaaaaa12:symlink pathl1:a5:b.txtee";
+
+        let file = File::from_bencode(data)?;
+
+        assert_eq!(
+            file,
+            File {
+                length: 0,
+                path: vec!["link".to_owned()],
+                attr: FileAttr {
+                    executable: true,
+                    symlink: true,
+                    ..Default::default()
+                },
+                symlink_path: Some(vec!["a".to_owned(), "b.txt".to_owned()]),
+                sha1: Some([b'a'; 20]),
+            }
+        );
+        assert_eq!(file.attr.to_string(), "xl");
+        assert_eq!(File::from_bencode(&file.to_bencode().unwrap())?, file);
+
+        let pad = File { length: 6, attr: "p".into(), ..Default::default() };
+        assert!(pad.attr.padding);
+        assert!(pad
+            .to_bencode()
+            .unwrap()
+            .starts_with(b"d4:attr1:p6:lengthi6e"));
+
+        Ok(())
+    }
+
+    /// piece_length: 16 MiB
+    /// ---------------------------------------
+    /// | f: 4 GiB + 10         | f: 1 GiB - 10 |
+    /// p------...------p-------f---------------|
+    /// | b: 16384 ... | b: 10 | b: 16384 ...  |
+    /// ---------------------------------------|
+    #[test]
+    fn get_block_infos_larger_than_4_gib() {
+        const GIB: u64 = 1024 * 1024 * 1024;
+        let piece_length = 16 * 1024 * 1024;
+        let info = Info {
+            files: Some(vec![
+                File {
+                    length: 4 * GIB + 10,
+                    path: vec!["a.bin".to_owned()],
+                    ..Default::default()
+                },
+                File {
+                    length: GIB - 10,
+                    path: vec!["b.bin".to_owned()],
+                    ..Default::default()
+                },
+            ]),
+            piece_length,
+            pieces: vec![0; 320 * 20],
+            ..Default::default()
+        };
+
+        assert_eq!(info.get_size(), 5 * GIB);
+        assert_eq!(info.piece_size(319), piece_length);
+
+        let blocks = info.get_block_infos().unwrap();
+
+        // the piece that starts at 4 GiB has one more block, it is split at
+        // the end of the first file.
+        assert_eq!(blocks.len(), 320 * 1024 + 1);
+        assert_eq!(
+            blocks[256 * 1024 - 1],
+            BlockInfo {
+                index: 255,
+                begin: piece_length - BLOCK_LEN,
+                len: BLOCK_LEN
+            }
+        );
+        assert_eq!(
+            blocks[256 * 1024],
+            BlockInfo { index: 256, begin: 0, len: 10 }
+        );
+        assert_eq!(
+            blocks[256 * 1024 + 1],
+            BlockInfo { index: 256, begin: 10, len: BLOCK_LEN }
+        );
+        assert_eq!(
+            blocks[257 * 1024],
+            BlockInfo {
+                index: 256,
+                begin: piece_length - BLOCK_LEN + 10,
+                len: BLOCK_LEN - 10
+            }
+        );
+        assert_eq!(
+            *blocks.back().unwrap(),
+            BlockInfo {
+                index: 319,
+                begin: piece_length - BLOCK_LEN,
+                len: BLOCK_LEN
+            }
+        );
+        assert_eq!(blocks.iter().fold(0, |acc, x| acc + x.len as u64), 5 * GIB);
+    }
+
+    /// Sizes of a layout of a few terabytes, which are only representable
+    /// in u64.
+    #[test]
+    fn multi_terabyte_layout() {
+        const TIB: u64 = 1024 * 1024 * 1024 * 1024;
+        let piece_length = 16 * 1024 * 1024;
+        let files = vec![
+            File {
+                length: 2 * TIB,
+                path: vec!["a.bin".to_owned()],
+                ..Default::default()
+            },
+            File {
+                length: TIB + 1,
+                path: vec!["b.bin".to_owned()],
+                ..Default::default()
+            },
+            File {
+                length: 3,
+                path: vec!["c.bin".to_owned()],
+                ..Default::default()
+            },
+        ];
+        let info = Info {
+            name: "big".to_owned(),
+            files: Some(files.clone()),
+            piece_length,
+            pieces: vec![0; 196_609 * 20],
+            ..Default::default()
+        };
+
+        assert_eq!(info.get_size(), 3 * TIB + 4);
+        assert_eq!(info.pieces(), 196_609);
+        assert_eq!(info.piece_size(0), piece_length);
+        assert_eq!(info.piece_size(196_608), 4);
+
+        assert_eq!(files[0].pieces(piece_length), 131_072);
+        assert_eq!(files[1].pieces(piece_length), 65_537);
+        assert_eq!(files[1].get_piece_len(0, piece_length), piece_length);
+        assert_eq!(files[1].get_piece_len(65_536, piece_length), 1);
+
+        // the lengths survive a round trip through bencode
+        let bytes = info.to_bencode().unwrap();
+        let needle = b"6:lengthi2199023255552e";
+        assert!(bytes.windows(needle.len()).any(|w| w == needle));
+        assert_eq!(Info::from_bencode(&bytes).unwrap(), info);
+
+        let info = Info {
+            file_length: Some(5 * TIB),
+            files: None,
+            pieces: vec![0; 327_680 * 20],
+            ..info
+        };
+        let bytes = info.to_bencode().unwrap();
+        assert_eq!(Info::from_bencode(&bytes).unwrap(), info);
+        assert_eq!(info.get_size(), 5 * TIB);
+    }
+
+    /// A hybrid torrent with a file larger than a piece, that has a piece
+    /// layer, and an empty file that is only on the file tree.
+    #[test]
+    fn hybrid_and_v2_metainfo() {
+        let piece_length = BLOCK_LEN;
+        let a = vec![1u8; BLOCK_LEN as usize * 3];
+        let b = vec![2u8; 10];
+        let layer = merkle::piece_layer(&a, piece_length);
+
+        let mut tree = BTreeMap::new();
+        tree.insert(
+            "a".to_owned(),
+            FileTree::File {
+                length: 3 * 16384,
+                pieces_root: merkle::file_root(&a),
+            },
+        );
+        tree.insert(
+            "b".to_owned(),
+            FileTree::File { length: 10, pieces_root: merkle::file_root(&b) },
+        );
+        let mut dir = BTreeMap::new();
+        dir.insert(
+            "e".to_owned(),
+            FileTree::File { length: 0, pieces_root: None },
+        );
+        tree.insert("d".to_owned(), FileTree::Dir(dir));
+
+        let info = Info {
+            name: "hybrid".to_owned(),
+            piece_length,
+            pieces: vec![0; 4 * 20],
+            files: Some(vec![
+                File {
+                    length: 3 * 16384,
+                    path: vec!["a".to_owned()],
+                    ..Default::default()
+                },
+                File {
+                    length: 10,
+                    path: vec!["b".to_owned()],
+                    ..Default::default()
+                },
+            ]),
+            meta_version: Some(2),
+            file_tree: Some(FileTree::Dir(tree)),
+            ..Default::default()
+        };
+        let pieces_root = merkle::file_root(&a).unwrap();
+        let metainfo = MetaInfo {
+            info: info.clone(),
+            piece_layers: Some(BTreeMap::from([(pieces_root, layer.concat())])),
+            ..Default::default()
+        };
+
+        assert_eq!(info.version(), Version::Hybrid);
+        assert_eq!(
+            info.file_tree.as_ref().unwrap().files()[2].path,
+            vec!["d".to_owned(), "e".to_owned()]
+        );
+        assert_eq!(
+            info.empty_piece_layers(),
+            BTreeMap::from([(pieces_root, vec![0; 3 * 32])])
+        );
+
+        let bytes = metainfo.to_bencode().unwrap();
+        assert_eq!(MetaInfo::from_bencode(&bytes).unwrap(), metainfo);
+
+        let info_bytes = info.to_bencode().unwrap();
+        let info_hash = MetaInfo::info_hashes(&bytes).unwrap();
+        assert_eq!(
+            info_hash.v1,
+            Some(sha1_smol::Sha1::from(&info_bytes).digest().bytes())
+        );
+        assert_eq!(info_hash.v2, Some(MetaInfo::info_hash_v2(&bytes).unwrap()));
+        assert!(info_hash.verify(&info_bytes));
+        assert_eq!(info_hash.swarms().len(), 2);
+
+        let magnet = Magnet::new(&metainfo.to_magnet_uri(&info_hash)).unwrap();
+        assert_eq!(magnet.parse_xt_infohash(), info_hash);
+
+        // without the v1 metadata it is a v2 only torrent
+        let info = Info { pieces: Vec::new(), files: None, ..info };
+        let metainfo = MetaInfo { info: info.clone(), ..metainfo };
+        let bytes = metainfo.to_bencode().unwrap();
+
+        assert_eq!(info.version(), Version::V2);
+        assert_eq!(info.get_size(), 3 * 16384 + 10);
+        assert_eq!(MetaInfo::from_bencode(&bytes).unwrap(), metainfo);
+
+        let info_hash = MetaInfo::info_hashes(&bytes).unwrap();
+        assert_eq!(info_hash.v1, None);
+        assert_eq!(info_hash.wire(), info_hash.v2.unwrap()[..20]);
+    }
+
+    /// The files of a v2 only torrent are aligned to the pieces with pad
+    /// files, except the last one.
+    #[test]
+    fn align_v2_files() {
+        let piece_length = BLOCK_LEN;
+        let file = |length| FileTree::File {
+            length,
+            pieces_root: Some([length as u8; 32]),
+        };
+        let tree = BTreeMap::from([
+            ("a".to_owned(), file(piece_length as u64 + 10)),
+            ("b".to_owned(), file(piece_length as u64)),
+            ("c".to_owned(), FileTree::File { length: 0, pieces_root: None }),
+            ("d".to_owned(), file(20)),
+        ]);
+        let mut info = Info {
+            name: "v2".to_owned(),
+            piece_length,
+            meta_version: Some(2),
+            file_tree: Some(FileTree::Dir(tree)),
+            ..Default::default()
+        };
+
+        info.align_v2_files();
+
+        let files = info.files.clone().unwrap();
+        let lengths: Vec<(u64, bool)> =
+            files.iter().map(|f| (f.length, f.attr.padding)).collect();
+        assert_eq!(
+            lengths,
+            vec![
+                (piece_length as u64 + 10, false),
+                (piece_length as u64 - 10, true),
+                (piece_length as u64, false),
+                (0, false),
+                (20, false),
+            ]
+        );
+        assert_eq!(files[4].path, vec!["d".to_owned()]);
+        assert_eq!(info.pieces(), 4);
+        assert_eq!(info.piece_size(3), 20);
+        assert_eq!(info.get_block_infos().unwrap().len(), 5);
+
+        // a single file
+        let tree = BTreeMap::from([("v2".to_owned(), file(10))]);
+        let mut info =
+            Info { file_tree: Some(FileTree::Dir(tree)), files: None, ..info };
+        info.align_v2_files();
+        assert_eq!(info.files, None);
+        assert_eq!(info.file_length, Some(10));
+        assert_eq!(info.pieces(), 1);
+    }
+
+    #[test]
+    fn sanitize_hostile_paths() {
+        let info = |bytes: &[u8]| MetaInfo::from_bencode(bytes).unwrap().info;
+
+        let mut absolute = info(include_bytes!(
+            "../../../test-files/hostile/absolute.torrent"
+        ));
+        absolute.sanitize().unwrap();
+        assert_eq!(absolute.name, "_tmp");
+        let files = absolute.files.unwrap();
+        assert_eq!(files[0].path, ["_etc", "cron.d", "evil"]);
+        assert_eq!(files[1].path, ["C:_Windows", "evil.dll"]);
+
+        let mut reserved = info(include_bytes!(
+            "../../../test-files/hostile/reserved.torrent"
+        ));
+        reserved.sanitize().unwrap();
+        let files = reserved.files.unwrap();
+        assert_eq!(files[0].path, ["_con.txt"]);
+        assert_eq!(files[1].path, ["sub", "_AUX"]);
+        assert_eq!(files[2].path[0].len(), MAX_SEGMENT_LEN);
+        assert!(files[2].path[0].ends_with("aaa.txt"));
+        assert_eq!(files[3].path, ["new_line"]);
+
+        for bytes in [
+            &include_bytes!("../../../test-files/hostile/traversal.torrent")[..],
+            include_bytes!("../../../test-files/hostile/symlink.torrent"),
+            include_bytes!("../../../test-files/hostile/empty.torrent"),
+            include_bytes!("../../../test-files/hostile/name.torrent"),
+        ] {
+            assert!(matches!(
+                info(bytes).sanitize(),
+                Err(error::Error::UnsafePath(_))
+            ));
+        }
+
+        // safe torrents are not changed
+        let bytes = include_bytes!("../../../test-files/book.torrent");
+        let mut book = info(bytes);
+        book.sanitize().unwrap();
+        assert_eq!(book, info(bytes));
+
+        // the keys of the file tree are sanitized like the files
+        let mut tree = FileTree::Dir(BTreeMap::from([(
+            "a/b".to_owned(),
+            FileTree::File { length: 1, pieces_root: None },
+        )]));
+        tree.sanitize().unwrap();
+        assert_eq!(tree.files()[0].path, ["a_b"]);
+    }
 }
@@ -32,12 +32,13 @@ use crate::{
     error::Error,
     extensions::{
         core::{
-            Block, BlockInfo, Core, CoreId, Handshake, HandshakeCodec, Message,
-            BLOCK_LEN,
+            Block, BlockInfo, Core, CoreId, Handshake, HandshakeCodec,
+            HashRequest, Message,
         },
         extended::Extension,
-        metadata::Metadata,
+        metadata::{Metadata, MAX_METADATA_SIZE},
     },
+    merkle,
     peer::session::ConnectionState,
     torrent::{TorrentCtx, TorrentMsg},
 };
@@ -74,6 +75,9 @@ pub enum PeerMsg {
     CancelMetadata(u32),
     /// Sent when the torrent has downloaded the entire info of the torrent.
     HaveInfo,
+    /// Sent when the info downloaded from peers was invalid, and the pieces
+    /// of the info must be requested again.
+    RequestInfo,
     /// Sent when the torrent is paused, it makes the peer pause downloads and
     /// uploads
     Pause,
@@ -187,12 +191,16 @@ impl Peer {
     /// peer in order to "exist". Only in this moment it gains it's peer_id
     /// and other data.
     ///
+    /// On inbound connections, the peer may use any of the swarms of the
+    /// torrent, i.e. the v1 or v2 info hash of hybrid torrents, and our
+    /// handshake answers with the same info hash.
+    ///
     /// The right order to create and run a Peer is the following:
     /// handshake -> new -> run
     pub async fn handshake(
         socket: TcpStream,
         direction: Direction,
-        info_hash: [u8; 20],
+        torrent_ctx: &TorrentCtx,
         local_peer_id: [u8; 20],
     ) -> Result<(Framed<TcpStream, MessageCodec>, Handshake), Error>
 // where
@@ -202,9 +210,15 @@ impl Peer {
         let local = socket.local_addr()?;
         let remote = socket.peer_addr()?;
         let mut socket = Framed::new(socket, HandshakeCodec);
-        let our_handshake = Handshake::new(info_hash, local_peer_id);
+        let mut our_handshake =
+            Handshake::new(torrent_ctx.info_hash, local_peer_id);
         let peer_handshake: Handshake;
 
+        // we support the v2 protocol, bit 60 from the left
+        if torrent_ctx.info_hashes.v2.is_some() {
+            our_handshake.reserved[7] |= 0x10;
+        }
+
         // we are connecting, send the first handshake
         if direction == Direction::Outbound {
             debug!("{local} sending the first handshake to {remote}");
@@ -216,6 +230,15 @@ impl Peer {
             debug!("{local} received their handshake {remote}");
             peer_handshake = their_handshake.clone();
 
+            if direction == Direction::Inbound
+                && torrent_ctx
+                    .info_hashes
+                    .swarms()
+                    .contains(&their_handshake.info_hash)
+            {
+                our_handshake.info_hash = their_handshake.info_hash;
+            }
+
             if !their_handshake.validate(&our_handshake) {
                 return Err(Error::HandshakeInvalid);
             }
@@ -270,7 +293,7 @@ impl Peer {
             pieces: RwLock::new(Bitfield::new()),
             id: handshake.peer_id,
             tx,
-            info_hash: handshake.info_hash,
+            info_hash: torrent_ctx.info_hash,
             local_addr,
         });
 
@@ -304,6 +327,12 @@ impl Peer {
     {
         self.session.state.connection = ConnectionState::Connecting;
         let local = self.ctx.local_addr;
+
+        // the info may have been downloaded, or loaded from the cache, before
+        // this peer connected.
+        if self.torrent_ctx.info.read().await.piece_length > 0 {
+            self.have_info = true;
+        }
         let remote = self.ctx.remote_addr;
 
         // if they are connecting, answer with our extended handshake
@@ -314,25 +343,17 @@ impl Peer {
             if self.reserved[43] {
                 debug!("{local} sending extended handshake to {remote}");
 
-                // we need to have the info downloaded in order to send the
-                // extended message, because it contains the metadata_size
-                if self.have_info {
-                    let info = self.torrent_ctx.info.read().await;
-                    let metadata_size = info
-                        .to_bencode()
-                        .map_err(|_| Error::BencodeError)?
-                        .len();
-                    drop(info);
-
-                    let ext = Extension::supported(Some(metadata_size as u32))
-                        .to_bencode()
-                        .map_err(|_| Error::BencodeError)?;
+                // the metadata_size is only sent if we have the info,
+                // we request the info when the peer sends its handshake.
+                let ext = self
+                    .local_extension()
+                    .await
+                    .to_bencode()
+                    .map_err(|_| Error::BencodeError)?;
 
-                    let extended = Core::Extended(0, ext);
+                let extended = Core::Extended(0, ext);
 
-                    socket.send(extended.into()).await?;
-                    self.try_request_info(&mut socket).await?;
-                }
+                socket.send(extended.into()).await?;
             }
         }
 
@@ -528,6 +549,11 @@ impl Peer {
                                 debug!("{local} requesting blocks");
                                 self.request_block_infos(&mut sink).await?;
                             }
+
+                            self.request_piece_layers(&mut sink).await?;
+                        }
+                        PeerMsg::RequestInfo => {
+                            self.try_request_info(&mut sink).await?;
                         }
                     }
                 }
@@ -804,9 +830,32 @@ impl Peer {
             .await;
     }
 
-    /// Maybe request an info piece from this Peer if:
+    /// The extended handshake that the local client sends to this peer.
+    pub async fn local_extension(&self) -> Extension {
+        let raw_info = self.torrent_ctx.raw_info.read().await;
+        let metadata_size =
+            (!raw_info.is_empty()).then_some(raw_info.len() as u32);
+        drop(raw_info);
+
+        let ext = Extension::supported(metadata_size);
+        if self.torrent_ctx.info.read().await.is_private() {
+            return ext.private();
+        }
+        ext
+    }
+
+    /// The size of the info that the peer sent in its extended handshake,
+    /// `None` if it was not sent or if it is not a valid size.
+    pub fn metadata_size(&self) -> Option<u32> {
+        self.extension
+            .metadata_size
+            .filter(|size| *size > 0 && *size <= MAX_METADATA_SIZE)
+    }
+
+    /// Maybe request all info pieces from this Peer if:
     /// - The peer supports the "ut_metadata" extension from the extension
     ///   protocol
+    /// - The peer sent a valid "metadata_size"
     /// - We do not have the info downloaded
     #[tracing::instrument(skip(self, sink))]
     pub async fn try_request_info<T, M>(
@@ -826,9 +875,11 @@ impl Peer {
                     "peer supports ut_metadata {ut_metadata}, sending request"
                 );
 
-                let t = self.extension.metadata_size.unwrap();
-                let pieces = t as f32 / BLOCK_LEN as f32;
-                let pieces = pieces.ceil() as u32;
+                let Some(size) = self.metadata_size() else {
+                    debug!("peer sent an invalid metadata_size");
+                    return Ok(());
+                };
+                let pieces = Metadata::pieces(size);
                 debug!("this info has {pieces} pieces");
 
                 for i in 0..pieces {
@@ -846,6 +897,86 @@ impl Peer {
         Ok(())
     }
 
+    /// Request the hashes of the piece layers that we do not know yet, if
+    /// the peer supports the v2 protocol, BEP 52.
+    ///
+    /// The hashes are requested with enough uncles to verify them against
+    /// the `pieces root` of the file, and at most [`merkle::MAX_HASHES`] at
+    /// a time.
+    pub async fn request_piece_layers<T, M>(
+        &mut self,
+        sink: &mut T,
+    ) -> Result<(), Error>
+    where
+        M: Into<Message> + From<Core>,
+        T: SinkExt<M> + Sized + std::marker::Unpin,
+    {
+        // bit 60 from the left
+        if !self.reserved[59] || self.torrent_ctx.info_hashes.v2.is_none() {
+            return Ok(());
+        }
+
+        let piece_length = self.torrent_ctx.info.read().await.piece_length;
+        let base_layer = merkle::piece_layer_index(piece_length);
+        let piece_layers = self.torrent_ctx.piece_layers.read().await;
+
+        for (pieces_root, layer) in piece_layers.iter() {
+            let hashes = layer.len() / 32;
+            let width = hashes.next_power_of_two();
+            let length = width.min(merkle::MAX_HASHES as usize);
+            let proof_layers = (width / length).trailing_zeros();
+
+            for index in (0..hashes).step_by(length) {
+                let end = (index + length).min(hashes) * 32;
+                // all of these hashes are known
+                if layer[index * 32..end].chunks(32).all(|v| v != [0; 32]) {
+                    continue;
+                }
+
+                let req = HashRequest {
+                    pieces_root: *pieces_root,
+                    base_layer,
+                    index: index as u32,
+                    length: length as u32,
+                    proof_layers,
+                };
+                let _ = sink.send(Core::HashRequest(req).into()).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answer a hash request from the piece layers that we know, returns
+    /// `None` if we can't.
+    pub async fn piece_layer_proof(
+        &self,
+        req: &HashRequest,
+    ) -> Option<Vec<[u8; 32]>> {
+        let piece_length = self.torrent_ctx.info.read().await.piece_length;
+        let base_layer = merkle::piece_layer_index(piece_length);
+
+        if req.base_layer != base_layer || req.length > merkle::MAX_HASHES {
+            return None;
+        }
+
+        let piece_layers = self.torrent_ctx.piece_layers.read().await;
+        let layer = merkle::split_hashes(piece_layers.get(&req.pieces_root)?)?;
+
+        // we can only answer with the layer complete
+        if layer.contains(&[0; 32]) {
+            return None;
+        }
+
+        merkle::proof(
+            &layer,
+            merkle::pad_hash(base_layer),
+            req.index as usize,
+            req.length as usize,
+            req.proof_layers,
+        )
+    }
+
     /// If this Peer has a piece that the local Peer (client)
     /// does not have.
     pub async fn has_piece_not_in_local(&self) -> bool {
@@ -0,0 +1,952 @@
+//! Transmission RPC compatibility layer.
+//!
+//! An optional HTTP listener that speaks the JSON-RPC protocol of
+//! Transmission, so that its ecosystem of clients (web UIs, mobile apps, *arr
+//! tools) can drive the [`Daemon`](crate::daemon::Daemon).
+//!
+//! Only the core methods are implemented, and they are mapped onto
+//! [`DaemonMsg`]:
+//! - `torrent-add`: [`DaemonMsg::NewTorrent`] for magnet links, or
+//!   [`DaemonMsg::NewTorrentFile`] for a base64 `metainfo`, with the
+//!   `download-dir`, `paused`, `files-wanted` and `labels` options.
+//! - `torrent-get`: reads the [`TorrentState`] of the torrents.
+//! - `torrent-start` and `torrent-stop`: [`DaemonMsg::TogglePause`], starting
+//!   a torrent with an error retries it.
+//! - `torrent-remove`: [`DaemonMsg::RemoveTorrent`].
+//! - `session-get`: information about the daemon.
+//!
+//! Transmission identifies torrents by a numeric id, which is assigned here
+//! in the order that the torrents are first seen.
+//!
+//! Only the addresses of the whitelist can use the RPC, by default loopback,
+//! and HTTP basic authentication is required when credentials are set.
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::{
+    body::Body,
+    header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::{net::TcpListener, spawn, sync::RwLock};
+use tracing::{debug, info, warn};
+
+use crate::{
+    daemon::{Daemon, DaemonCtx, DaemonMsg},
+    error::Error,
+    magnet::Magnet,
+    metainfo::MetaInfo,
+    torrent::{TorrentOptions, TorrentState, TorrentStatus},
+};
+
+/// Path of the RPC endpoint, the same one used by Transmission.
+pub const RPC_PATH: &str = "/transmission/rpc";
+
+/// Header used by Transmission to protect against CSRF.
+pub const SESSION_ID_HEADER: &str = "X-Transmission-Session-Id";
+
+/// Version of the Transmission RPC protocol that is implemented.
+const RPC_VERSION: u32 = 17;
+
+/// Oldest version of the Transmission RPC protocol that is compatible.
+const RPC_VERSION_MINIMUM: u32 = 14;
+
+/// Maximum size of a request body, enough for the metainfo of big torrents.
+const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    method: String,
+    #[serde(default)]
+    arguments: Value,
+    tag: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcResponse {
+    result: String,
+    arguments: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<Value>,
+}
+
+/// The Transmission RPC server.
+pub struct Rpc {
+    ctx: Arc<DaemonCtx>,
+    download_dir: String,
+    /// Random token that clients must echo back on [`SESSION_ID_HEADER`].
+    session_id: String,
+    /// Info hashes indexed by their Transmission id minus one.
+    ids: RwLock<Vec<[u8; 20]>>,
+    /// Username and password of the HTTP basic authentication.
+    auth: Option<(String, String)>,
+    /// Addresses that can use the RPC, see [`Rpc::whitelist`].
+    whitelist: Vec<String>,
+    /// Where torrents can be saved, see [`Daemon::check_save_path`].
+    allowed_save_dirs: Vec<PathBuf>,
+}
+
+impl Rpc {
+    pub fn new(ctx: Arc<DaemonCtx>, download_dir: String) -> Self {
+        let session_id = rand::thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(48)
+            .map(char::from)
+            .collect();
+
+        Self {
+            ctx,
+            session_id,
+            ids: RwLock::new(Vec::new()),
+            auth: None,
+            whitelist: vec!["127.0.0.1".to_owned(), "::1".to_owned()],
+            allowed_save_dirs: vec![PathBuf::from(&download_dir)],
+            download_dir,
+        }
+    }
+
+    /// Require HTTP basic authentication with these credentials.
+    pub fn auth(mut self, username: String, password: String) -> Self {
+        self.auth = Some((username, password));
+        self
+    }
+
+    /// Addresses that can use the RPC, `*` matches any number of an IPv4
+    /// address, e.g `192.168.*.*`, or any address when used alone.
+    pub fn whitelist(mut self, whitelist: Vec<String>) -> Self {
+        self.whitelist = whitelist;
+        self
+    }
+
+    /// Directories where torrents can be saved with `download-dir`, only the
+    /// `download_dir` by default.
+    pub fn allowed_save_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.allowed_save_dirs = dirs;
+        self
+    }
+
+    /// Accept HTTP connections on `addr` until the task is aborted.
+    pub async fn run(self, addr: SocketAddr) -> Result<(), Error> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Transmission RPC listening on: {addr}");
+        self.serve(listener).await
+    }
+
+    /// Accept HTTP connections on `listener` until the task is aborted.
+    pub async fn serve(self, listener: TcpListener) -> Result<(), Error> {
+        let rpc = Arc::new(self);
+
+        loop {
+            let (socket, remote) = listener.accept().await?;
+            let rpc = rpc.clone();
+
+            spawn(async move {
+                let service = service_fn(|req| {
+                    let rpc = rpc.clone();
+                    async move {
+                        Ok::<_, Infallible>(rpc.handle(remote.ip(), req).await)
+                    }
+                });
+
+                if let Err(e) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(socket), service)
+                    .await
+                {
+                    debug!("RPC connection with {remote} failed: {e}");
+                }
+            });
+        }
+    }
+
+    /// Handle an HTTP request of `remote`, enforcing the whitelist, the
+    /// authentication and the session id handshake.
+    async fn handle<B>(
+        &self,
+        remote: IpAddr,
+        req: Request<B>,
+    ) -> Response<Full<Bytes>>
+    where
+        B: Body<Data = Bytes>,
+        B::Error: std::error::Error + Send + Sync + 'static,
+    {
+        if !self.is_whitelisted(remote) {
+            warn!("RPC request from {remote} is not whitelisted");
+            return Self::response(StatusCode::FORBIDDEN, Bytes::new());
+        }
+
+        if !self.is_authorized(&req) {
+            let mut res =
+                Self::response(StatusCode::UNAUTHORIZED, Bytes::new());
+            res.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"Transmission\""),
+            );
+            return res;
+        }
+
+        if req.uri().path() != RPC_PATH {
+            return Self::response(StatusCode::NOT_FOUND, Bytes::new());
+        }
+
+        let session_id =
+            req.headers().get(SESSION_ID_HEADER).and_then(|v| v.to_str().ok());
+
+        // the first request of a client is expected to fail,
+        // it will then read the session id and retry with it.
+        if session_id != Some(&self.session_id) {
+            let mut res = Self::response(
+                StatusCode::CONFLICT,
+                Bytes::from_static(b"Invalid session id"),
+            );
+            if let Ok(v) = HeaderValue::from_str(&self.session_id) {
+                res.headers_mut().insert(SESSION_ID_HEADER, v);
+            }
+            return res;
+        }
+
+        if req.method() != Method::POST {
+            return Self::response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                Bytes::new(),
+            );
+        }
+
+        let body = match Limited::new(req.into_body(), MAX_BODY_SIZE)
+            .collect()
+            .await
+        {
+            Ok(body) => body.to_bytes(),
+            Err(e) if e.is::<LengthLimitError>() => {
+                return Self::response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    Bytes::new(),
+                )
+            }
+            Err(_) => {
+                return Self::response(StatusCode::BAD_REQUEST, Bytes::new())
+            }
+        };
+
+        let Ok(request) = serde_json::from_slice::<RpcRequest>(&body) else {
+            return Self::response(StatusCode::BAD_REQUEST, Bytes::new());
+        };
+
+        let res = self.call(request).await;
+
+        match serde_json::to_vec(&res) {
+            Ok(body) => Self::response(StatusCode::OK, body.into()),
+            Err(_) => {
+                Self::response(StatusCode::INTERNAL_SERVER_ERROR, Bytes::new())
+            }
+        }
+    }
+
+    fn is_whitelisted(&self, remote: IpAddr) -> bool {
+        let remote = remote.to_canonical();
+        let octets = remote.to_string();
+
+        self.whitelist.iter().any(|entry| {
+            if entry == "*" {
+                return true;
+            }
+            if let Ok(ip) = entry.parse::<IpAddr>() {
+                return ip == remote;
+            }
+            let entry: Vec<&str> = entry.split('.').collect();
+            let octets: Vec<&str> = octets.split('.').collect();
+            remote.is_ipv4()
+                && entry.len() == octets.len()
+                && entry.iter().zip(&octets).all(|(e, o)| *e == "*" || e == o)
+        })
+    }
+
+    fn is_authorized<B>(&self, req: &Request<B>) -> bool {
+        let Some((username, password)) = &self.auth else {
+            return true;
+        };
+
+        req.headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Basic "))
+            .and_then(|v| STANDARD.decode(v.trim()).ok())
+            .is_some_and(|v| v == format!("{username}:{password}").as_bytes())
+    }
+
+    fn response(status: StatusCode, body: Bytes) -> Response<Full<Bytes>> {
+        let mut res = Response::new(Full::new(body));
+        *res.status_mut() = status;
+        res
+    }
+
+    /// Run an RPC method, errors are reported on the `result` field.
+    async fn call(&self, req: RpcRequest) -> RpcResponse {
+        debug!("rpc {} {}", req.method, req.arguments);
+
+        let r = match req.method.as_str() {
+            "torrent-add" => self.torrent_add(&req.arguments).await,
+            "torrent-get" => self.torrent_get(&req.arguments).await,
+            "torrent-start" | "torrent-start-now" => {
+                self.torrent_pause(&req.arguments, false).await
+            }
+            "torrent-stop" => self.torrent_pause(&req.arguments, true).await,
+            "torrent-remove" => self.torrent_remove(&req.arguments).await,
+            "session-get" => Ok(self.session_get()),
+            method => Err(format!("method name not recognized: {method}")),
+        };
+
+        let (result, arguments) = match r {
+            Ok(arguments) => ("success".to_owned(), arguments),
+            Err(e) => (e, json!({})),
+        };
+
+        RpcResponse { result, arguments, tag: req.tag }
+    }
+
+    async fn torrent_add(&self, args: &Value) -> Result<Value, String> {
+        let metainfo = match args.get("metainfo").and_then(Value::as_str) {
+            Some(metainfo) => Some(
+                STANDARD
+                    .decode(metainfo.trim())
+                    .map_err(|_| "invalid or corrupt torrent file")?,
+            ),
+            None => None,
+        };
+
+        let magnet = match &metainfo {
+            Some(metainfo) => MetaInfo::magnet(metainfo)
+                .map_err(|_| "invalid or corrupt torrent file")?,
+            None => {
+                let filename = args
+                    .get("filename")
+                    .and_then(Value::as_str)
+                    .ok_or("missing filename")?;
+                Magnet::new(filename).map_err(|e| e.to_string())?
+            }
+        };
+
+        let info_hash = magnet.parse_xt();
+        let name = magnet.parse_dn();
+
+        if self.ctx.torrent_states.read().await.contains_key(&info_hash) {
+            let id = self.id(info_hash).await;
+            return Ok(json!({
+                "torrent-duplicate": {
+                    "id": id,
+                    "name": name,
+                    "hashString": hex::encode(info_hash),
+                }
+            }));
+        }
+
+        let options = TorrentOptions {
+            save_path: args
+                .get("download-dir")
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+            paused: args.get("paused").and_then(Value::as_bool) == Some(true),
+            files: args.get("files-wanted").and_then(Value::as_array).map(
+                |files| {
+                    files
+                        .iter()
+                        .filter_map(Value::as_u64)
+                        .map(|i| i as u32)
+                        .collect()
+                },
+            ),
+            labels: args
+                .get("labels")
+                .and_then(Value::as_array)
+                .map(|labels| {
+                    labels
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+
+        if let Some(save_path) = &options.save_path {
+            Daemon::check_save_path(
+                Path::new(save_path),
+                &self.allowed_save_dirs,
+            )
+            .map_err(|e| e.to_string())?;
+        }
+
+        let msg = match metainfo {
+            Some(metainfo) => DaemonMsg::NewTorrentFile(metainfo, options),
+            None => DaemonMsg::NewTorrent(magnet, options),
+        };
+
+        self.ctx.tx.send(msg).await.map_err(|e| e.to_string())?;
+
+        let id = self.id(info_hash).await;
+
+        Ok(json!({
+            "torrent-added": {
+                "id": id,
+                "name": name,
+                "hashString": hex::encode(info_hash),
+            }
+        }))
+    }
+
+    async fn torrent_get(&self, args: &Value) -> Result<Value, String> {
+        let fields: Vec<&str> = args
+            .get("fields")
+            .and_then(Value::as_array)
+            .ok_or("missing fields")?
+            .iter()
+            .filter_map(Value::as_str)
+            .collect();
+
+        let mut torrents = Vec::new();
+
+        for state in self.select(args).await {
+            let id = self.id(state.info_hash).await;
+            let mut torrent = Map::new();
+
+            for field in &fields {
+                if let Some(v) = self.field(id, &state, field) {
+                    torrent.insert((*field).to_owned(), v);
+                }
+            }
+
+            torrents.push(Value::Object(torrent));
+        }
+
+        Ok(json!({ "torrents": torrents }))
+    }
+
+    /// Transmission has explicit start and stop methods, but the daemon
+    /// toggles, so only toggle the torrents that are not in the desired
+    /// state already.
+    async fn torrent_pause(
+        &self,
+        args: &Value,
+        pause: bool,
+    ) -> Result<Value, String> {
+        for state in self.select(args).await {
+            let should_toggle = match state.status {
+                TorrentStatus::Paused | TorrentStatus::Error => !pause,
+                TorrentStatus::Downloading
+                | TorrentStatus::Seeding
+                | TorrentStatus::ConnectingTrackers
+                | TorrentStatus::DownloadingMetainfo => pause,
+                TorrentStatus::Moving => false,
+            };
+
+            if should_toggle {
+                self.ctx
+                    .tx
+                    .send(DaemonMsg::TogglePause(state.info_hash))
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        Ok(json!({}))
+    }
+
+    async fn torrent_remove(&self, args: &Value) -> Result<Value, String> {
+        if args.get("delete-local-data").and_then(Value::as_bool) == Some(true)
+        {
+            return Err("delete-local-data is not supported".to_owned());
+        }
+
+        for state in self.select(args).await {
+            self.ctx
+                .tx
+                .send(DaemonMsg::RemoveTorrent(state.info_hash))
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        Ok(json!({}))
+    }
+
+    fn session_get(&self) -> Value {
+        json!({
+            "version": format!("{} (vincenzo)", env!("CARGO_PKG_VERSION")),
+            "rpc-version": RPC_VERSION,
+            "rpc-version-minimum": RPC_VERSION_MINIMUM,
+            "session-id": self.session_id,
+            "rpc-authentication-required": self.auth.is_some(),
+            "download-dir": self.download_dir,
+            "dht-enabled": false,
+            "pex-enabled": false,
+            "utp-enabled": false,
+            "encryption": "tolerated",
+        })
+    }
+
+    /// Transmission id of the torrent, assigning a new one if needed.
+    async fn id(&self, info_hash: [u8; 20]) -> usize {
+        let mut ids = self.ids.write().await;
+
+        match ids.iter().position(|v| *v == info_hash) {
+            Some(i) => i + 1,
+            None => {
+                ids.push(info_hash);
+                ids.len()
+            }
+        }
+    }
+
+    /// States of the torrents selected by the `ids` argument, which can be
+    /// absent (all torrents), a single id, or a list of ids and hash strings.
+    async fn select(&self, args: &Value) -> Vec<TorrentState> {
+        let states = self.ctx.torrent_states.read().await;
+
+        let Some(ids) = args.get("ids") else {
+            let mut all: Vec<TorrentState> = states.values().cloned().collect();
+            drop(states);
+            // assign ids in a stable order
+            all.sort_by(|a, b| a.name.cmp(&b.name));
+            for state in &all {
+                self.id(state.info_hash).await;
+            }
+            return all;
+        };
+
+        let wanted: Vec<&Value> = match ids {
+            Value::Array(ids) => ids.iter().collect(),
+            Value::String(s) if s == "recently-active" => {
+                return states.values().cloned().collect();
+            }
+            id => vec![id],
+        };
+
+        let known = self.ids.read().await;
+        let mut selected = Vec::new();
+
+        for id in wanted {
+            let info_hash = match id {
+                Value::Number(n) => n
+                    .as_u64()
+                    .and_then(|n| known.get((n as usize).wrapping_sub(1)))
+                    .copied(),
+                Value::String(s) => {
+                    hex::decode(s).ok().and_then(|v| v.try_into().ok())
+                }
+                _ => None,
+            };
+
+            if let Some(state) = info_hash.and_then(|v| states.get(&v)) {
+                selected.push(state.clone());
+            }
+        }
+
+        selected
+    }
+
+    /// Value of a field of `torrent-get`, unknown fields are ignored.
+    fn field(
+        &self,
+        id: usize,
+        state: &TorrentState,
+        field: &str,
+    ) -> Option<Value> {
+        let left = state.size.saturating_sub(state.downloaded);
+
+        let v = match field {
+            "id" => json!(id),
+            "hashString" => json!(hex::encode(state.info_hash)),
+            "name" => json!(state.name),
+            "status" => json!(Self::status(&state.status)),
+            "totalSize" | "sizeWhenDone" => json!(state.size),
+            "leftUntilDone" => json!(left),
+            "haveValid" | "downloadedEver" => json!(state.downloaded),
+            "uploadedEver" => json!(state.uploaded),
+            "rateDownload" => json!(state.download_rate),
+            "rateUpload" => json!(state.upload_rate),
+            "percentDone" => {
+                if state.size == 0 {
+                    json!(0.0)
+                } else {
+                    json!(state.downloaded as f64 / state.size as f64)
+                }
+            }
+            "isFinished" => json!(state.status == TorrentStatus::Seeding),
+            "eta" => match state.download_rate {
+                0 => json!(-1),
+                rate => json!(left / rate),
+            },
+            "error" => match state.status {
+                // local error
+                TorrentStatus::Error => json!(3),
+                _ => json!(0),
+            },
+            "errorString" => match state.status {
+                TorrentStatus::Error => json!(state
+                    .error
+                    .clone()
+                    .unwrap_or_else(|| String::from(state.status.clone()))),
+                _ => json!(""),
+            },
+            "seeders" => json!(state.stats.seeders),
+            "leechers" => json!(state.stats.leechers),
+            "downloadDir" => match &state.save_path {
+                Some(save_path) => json!(save_path),
+                None => json!(self.download_dir),
+            },
+            "labels" => json!(state.labels),
+            _ => {
+                warn!("rpc field not supported: {field}");
+                return None;
+            }
+        };
+
+        Some(v)
+    }
+
+    /// Map the status to the `tr_torrent_activity` enum of Transmission.
+    fn status(status: &TorrentStatus) -> u8 {
+        match status {
+            TorrentStatus::Paused | TorrentStatus::Error => 0,
+            // Transmission has no status for moving, the files can't be used
+            // while they are moved, as when they are checked.
+            TorrentStatus::Moving => 2,
+            TorrentStatus::ConnectingTrackers
+            | TorrentStatus::DownloadingMetainfo => 3,
+            TorrentStatus::Downloading => 4,
+            TorrentStatus::Seeding => 6,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hashbrown::HashMap;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::mpsc,
+    };
+
+    use super::*;
+
+    fn rpc() -> (Rpc, mpsc::Receiver<DaemonMsg>) {
+        let (tx, rx) = mpsc::channel(10);
+        let ctx = Arc::new(DaemonCtx {
+            tx,
+            torrent_states: RwLock::new(HashMap::new()),
+        });
+        (Rpc::new(ctx, "/downloads".to_owned()), rx)
+    }
+
+    fn request(method: &str, arguments: Value) -> RpcRequest {
+        RpcRequest { method: method.to_owned(), arguments, tag: Some(json!(7)) }
+    }
+
+    async fn insert(rpc: &Rpc, info_hash: [u8; 20], status: TorrentStatus) {
+        rpc.ctx.torrent_states.write().await.insert(
+            info_hash,
+            TorrentState {
+                name: "arch".to_owned(),
+                info_hash,
+                status,
+                size: 100,
+                downloaded: 25,
+                download_rate: 5,
+                upload_rate: 3,
+                ..Default::default()
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn torrent_add() {
+        let (rpc, mut rx) = rpc();
+        let rpc = rpc.allowed_save_dirs(vec![PathBuf::from("/mnt/other")]);
+        let magnet = "magnet:?xt=urn:btih:48aac768a865798307ddd4284be77644368dd2c7&dn=Kerkour";
+
+        let args = json!({
+            "filename": magnet,
+            "download-dir": "/mnt/other",
+            "paused": true,
+            "files-wanted": [0, 2],
+            "labels": ["linux"],
+        });
+        let res = rpc.call(request("torrent-add", args)).await;
+
+        assert_eq!(res.result, "success");
+        assert_eq!(res.tag, Some(json!(7)));
+        assert_eq!(res.arguments["torrent-added"]["id"], json!(1));
+        assert_eq!(
+            res.arguments["torrent-added"]["hashString"],
+            json!("48aac768a865798307ddd4284be77644368dd2c7")
+        );
+        let Some(DaemonMsg::NewTorrent(_, options)) = rx.recv().await else {
+            panic!("the torrent was not added");
+        };
+        assert_eq!(
+            options,
+            TorrentOptions {
+                save_path: Some("/mnt/other".to_owned()),
+                paused: true,
+                files: Some(vec![0, 2]),
+                labels: vec!["linux".to_owned()],
+                ..Default::default()
+            }
+        );
+
+        let res = rpc
+            .call(request("torrent-add", json!({ "filename": "bla" })))
+            .await;
+        assert_ne!(res.result, "success");
+
+        // the torrent is not added outside of the allowed dirs
+        for dir in ["other", "/mnt/other/../../etc", "/mnt/another", "/etc"] {
+            let args = json!({ "filename": magnet, "download-dir": dir });
+            let res = rpc.call(request("torrent-add", args)).await;
+            assert_ne!(res.result, "success");
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn torrent_get() {
+        let (rpc, _rx) = rpc();
+        insert(&rpc, [1; 20], TorrentStatus::Downloading).await;
+
+        let res = rpc
+            .call(request(
+                "torrent-get",
+                json!({
+                    "fields": ["id", "name", "status", "percentDone", "eta",
+                        "rateUpload", "xyz"]
+                }),
+            ))
+            .await;
+
+        assert_eq!(res.result, "success");
+        assert_eq!(
+            res.arguments["torrents"],
+            json!([{
+                "id": 1,
+                "name": "arch",
+                "status": 4,
+                "percentDone": 0.25,
+                "eta": 15,
+                "rateUpload": 3,
+            }])
+        );
+
+        let res = rpc
+            .call(request(
+                "torrent-get",
+                json!({ "ids": [hex::encode([1; 20])], "fields": ["id"] }),
+            ))
+            .await;
+        assert_eq!(res.arguments["torrents"], json!([{ "id": 1 }]));
+
+        let res = rpc
+            .call(request("torrent-get", json!({ "ids": 2, "fields": ["id"] })))
+            .await;
+        assert_eq!(res.arguments["torrents"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn torrent_start_stop_remove() {
+        let (rpc, mut rx) = rpc();
+        insert(&rpc, [1; 20], TorrentStatus::Downloading).await;
+        insert(&rpc, [2; 20], TorrentStatus::Paused).await;
+
+        // only the torrent that is downloading is toggled
+        let res = rpc.call(request("torrent-stop", json!({}))).await;
+        assert_eq!(res.result, "success");
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(DaemonMsg::TogglePause(v)) if v == [1; 20]
+        ));
+        assert!(rx.try_recv().is_err());
+
+        let res = rpc.call(request("torrent-start", json!({}))).await;
+        assert_eq!(res.result, "success");
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(DaemonMsg::TogglePause(v)) if v == [2; 20]
+        ));
+        assert!(rx.try_recv().is_err());
+
+        // a magnet that is still downloading its info can be stopped
+        insert(&rpc, [3; 20], TorrentStatus::DownloadingMetainfo).await;
+        let ids = json!({ "ids": [hex::encode([3; 20])] });
+        rpc.call(request("torrent-stop", ids)).await;
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(DaemonMsg::TogglePause(v)) if v == [3; 20]
+        ));
+
+        let ids = json!({ "ids": [hex::encode([2; 20])] });
+        let res = rpc.call(request("torrent-remove", ids)).await;
+        assert_eq!(res.result, "success");
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(DaemonMsg::RemoveTorrent(v)) if v == [2; 20]
+        ));
+    }
+
+    #[tokio::test]
+    async fn torrent_start_retries_error() {
+        let (rpc, mut rx) = rpc();
+        insert(&rpc, [1; 20], TorrentStatus::Error).await;
+        rpc.ctx.torrent_states.write().await.get_mut(&[1; 20]).unwrap().error =
+            Some("No space left on device".to_owned());
+
+        let args = json!({ "fields": ["error", "errorString"] });
+        let res = rpc.call(request("torrent-get", args)).await;
+        assert_eq!(
+            res.arguments["torrents"],
+            json!([{ "error": 3, "errorString": "No space left on device" }])
+        );
+
+        // stopping a torrent with an error does nothing
+        rpc.call(request("torrent-stop", json!({}))).await;
+        assert!(rx.try_recv().is_err());
+
+        rpc.call(request("torrent-start", json!({}))).await;
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(DaemonMsg::TogglePause(v)) if v == [1; 20]
+        ));
+    }
+
+    #[tokio::test]
+    async fn torrent_add_metainfo() {
+        let (rpc, mut rx) = rpc();
+        let torrent = include_bytes!("../../../test-files/debian.torrent");
+
+        let args = json!({ "metainfo": STANDARD.encode(torrent) });
+        let res = rpc.call(request("torrent-add", args)).await;
+
+        assert_eq!(res.result, "success");
+        assert_eq!(
+            res.arguments["torrent-added"]["hashString"],
+            json!("7431a969b347e14bba641b3517c024f7b40dfb7f")
+        );
+        let Some(DaemonMsg::NewTorrentFile(v, _)) = rx.recv().await else {
+            panic!("the torrent file was not added");
+        };
+        assert_eq!(v, torrent);
+
+        let args = json!({ "metainfo": STANDARD.encode(b"d4:infoi1ee") });
+        let res = rpc.call(request("torrent-add", args)).await;
+        assert_eq!(res.result, "invalid or corrupt torrent file");
+    }
+
+    #[tokio::test]
+    async fn whitelist_and_auth() {
+        let (rpc, _rx) = rpc();
+        let rpc = rpc
+            .whitelist(vec!["::1".to_owned(), "192.168.*.*".to_owned()])
+            .auth("user".to_owned(), "pass".to_owned());
+        let session_id = rpc.session_id.clone();
+
+        let send = |ip: &str, auth: Option<&str>| {
+            let mut req = Request::post(RPC_PATH)
+                .header(SESSION_ID_HEADER, session_id.as_str());
+            if let Some(auth) = auth {
+                req = req.header(
+                    AUTHORIZATION,
+                    format!("Basic {}", STANDARD.encode(auth)),
+                );
+            }
+            let req = req
+                .body(Full::new(Bytes::from_static(
+                    br#"{"method":"session-get"}"#,
+                )))
+                .unwrap();
+            rpc.handle(ip.parse().unwrap(), req)
+        };
+
+        let res = send("10.0.0.1", Some("user:pass")).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = send("192.168.0.2", None).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(res.headers().contains_key(WWW_AUTHENTICATE));
+
+        let res = send("192.168.0.2", Some("user:wrong")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // IPv4 clients of a dual stack listener are mapped to IPv6
+        let res = send("::ffff:192.168.0.2", Some("user:pass")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body)
+            .contains(r#""rpc-authentication-required":true"#));
+
+        let res = send("::1", Some("user:pass")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn body_limit() {
+        let (rpc, _rx) = rpc();
+
+        let req = Request::post(RPC_PATH)
+            .header(SESSION_ID_HEADER, rpc.session_id.as_str())
+            .body(Full::new(Bytes::from(vec![b' '; MAX_BODY_SIZE + 1])))
+            .unwrap();
+        let res = rpc.handle([127, 0, 0, 1].into(), req).await;
+
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn session_id_handshake() {
+        let (rpc, _rx) = rpc();
+        let session_id = rpc.session_id.clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        spawn(rpc.serve(listener));
+
+        let send = |session_id: Option<String>| async move {
+            let body = r#"{"method":"session-get"}"#;
+            let header = session_id
+                .map(|v| format!("{SESSION_ID_HEADER}: {v}\r\n"))
+                .unwrap_or_default();
+            let req = format!(
+                "POST {RPC_PATH} HTTP/1.1\r\nHost: localhost\r\n{header}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+
+            let mut socket = TcpStream::connect(addr).await.unwrap();
+            socket.write_all(req.as_bytes()).await.unwrap();
+            let mut res = String::new();
+            socket.read_to_string(&mut res).await.unwrap();
+            res
+        };
+
+        let res = send(None).await;
+        assert!(res.starts_with("HTTP/1.1 409"));
+        assert!(res.to_lowercase().contains(
+            &format!("{SESSION_ID_HEADER}: {session_id}").to_lowercase()
+        ));
+
+        let res = send(Some(session_id)).await;
+        assert!(res.starts_with("HTTP/1.1 200"));
+        assert!(res.contains(r#""result":"success""#));
+        assert!(res.contains(r#""rpc-version":17"#));
+    }
+}
@@ -2,13 +2,19 @@
 use crate::{
     bitfield::Bitfield,
     daemon::DaemonMsg,
-    disk::DiskMsg,
+    disk::{DiskMsg, PieceStrategy, Preallocation},
     error::Error,
-    extensions::core::{BlockInfo, Message, CoreCodec},
+    extensions::{
+        core::{BlockInfo, Message, CoreCodec, BLOCK_LEN},
+        metadata::Metadata,
+    },
+    hooks::{HookEnv, HookEvent, Hooks},
     magnet::Magnet,
-    metainfo::Info,
+    merkle,
+    metainfo::{Info, MetaInfo, PieceLayers, Version},
     peer::{session::ConnectionState, Direction, Peer, PeerCtx, PeerMsg},
     tracker::{event::Event, Tracker, TrackerCtx, TrackerMsg},
+    webseed::{WebSeed, WebSeedKind},
 };
 use bendy::decoding::FromBencode;
 use bitvec::{bitvec, prelude::Msb0};
@@ -17,17 +23,27 @@ use speedy::{Readable, Writable};
 use std::{
     collections::BTreeMap,
     net::SocketAddr,
+    path::PathBuf,
     sync::{atomic::AtomicBool, Arc},
     time::Duration,
 };
 use tokio::{
-    net::{TcpListener, TcpStream},
+    fs,
+    net::{lookup_host, TcpListener, TcpStream},
     select, spawn,
     sync::{mpsc, oneshot, RwLock},
     time::{interval, interval_at, Instant},
 };
 use tracing::{debug, info, warn};
 
+/// How many sizes of the info are downloaded at once, see
+/// [`Torrent::add_info_piece`].
+const MAX_INFO_SIZES: usize = 4;
+
+/// The pieces of an info of one size, with the peer that sent them.
+/// k: index, v: (piece, peer id)
+pub type InfoPieces = BTreeMap<u32, (Vec<u8>, [u8; 20])>;
+
 #[derive(Debug)]
 pub enum TorrentMsg {
     /// Message when one of the peers have downloaded
@@ -35,6 +51,8 @@ pub enum TorrentMsg {
     /// that don't have it and update the UI with stats.
     DownloadedPiece(usize),
     PeerConnected([u8; 20], Arc<PeerCtx>),
+    /// The disk verified all wanted pieces of the torrent, and is moving its
+    /// files to their final location, see [`TorrentMsg::StorageComplete`].
     DownloadComplete,
     /// When in endgame mode, the first peer that receives this info,
     /// sends this message to send Cancel's to all other peers.
@@ -53,23 +71,93 @@ pub enum TorrentMsg {
     /// When a peer downloads an info piece,
     /// we need to mutate `info_dict` and maybe
     /// generate the entire info.
-    /// total, metadata.index, bytes
-    DownloadedInfoPiece(u32, u32, Vec<u8>),
-    /// When a peer request a piece of the info
-    /// index, recipient
-    RequestInfoPiece(u32, oneshot::Sender<Option<Vec<u8>>>),
+    DownloadedInfoPiece {
+        /// The peer that sent the piece.
+        from: [u8; 20],
+        /// The size of the info, from the extended handshake of the peer.
+        total: u32,
+        index: u32,
+        bytes: Vec<u8>,
+    },
     IncrementDownloaded(u32),
     IncrementUploaded(u32),
-    /// Toggle pause torrent and send Pause/Resume message to all Peers
+    /// Toggle pause torrent and send Pause/Resume message to all Peers, or
+    /// retry the torrent if it failed on disk.
     TogglePause,
     /// When we can't do a TCP connection with the ip of the Peer.
     FailedPeer(SocketAddr),
+    /// Ask for the .torrent file of the torrent, with the info and the
+    /// trackers. `None` if the info was not downloaded yet.
+    ExportTorrent(oneshot::Sender<Option<Vec<u8>>>),
+    /// Ask for a magnet link of the torrent, which is available even before
+    /// the info is downloaded.
+    ExportMagnet(oneshot::Sender<String>),
+    /// The disk could not create, read, write or move the files of the
+    /// torrent, i.e. there is no space for them, with the reason.
+    DiskError(String),
+    /// The disk retried the I/O of the torrent that failed, and it can be
+    /// used again.
+    DiskReady,
+    /// Move the files of the torrent to another directory.
+    MoveStorage(PathBuf),
+    /// How many bytes of the files the disk moved so far, they are only
+    /// sent when the files are copied to another filesystem.
+    MoveProgress(u64),
+    /// The disk moved the files of the torrent to the directory.
+    StorageMoved(PathBuf),
+    /// The disk has the files of the complete torrent at their final
+    /// location, after [`TorrentMsg::DownloadComplete`].
+    StorageComplete,
+    /// Where the disk has the files of the torrent, sent when they are
+    /// created, moved or completed.
+    BasePath(PathBuf),
     /// When torrent is being gracefully shutdown
     Quit,
 }
 
-#[derive(Debug, Clone)]
-pub struct InfoHash([u8; 20]);
+/// The info hashes of a torrent. v1 torrents only have the SHA-1 hash, v2
+/// torrents only have the SHA-256 hash, and hybrid torrents have both, BEP 52.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct InfoHash {
+    pub v1: Option<[u8; 20]>,
+    pub v2: Option<[u8; 32]>,
+}
+
+impl InfoHash {
+    /// The hash used on the wire and as the key of the torrent, which is the
+    /// v1 hash or, on v2 torrents, the v2 hash truncated to 20 bytes.
+    pub fn wire(&self) -> [u8; 20] {
+        self.v1.or_else(|| self.v2.map(Self::truncate)).unwrap_or_default()
+    }
+
+    /// The hashes of all the swarms that the torrent participates in, the
+    /// first is the [`Self::wire`] hash.
+    pub fn swarms(&self) -> Vec<[u8; 20]> {
+        let mut swarms = vec![self.wire()];
+        if let Some(v2) = self.v2.map(Self::truncate) {
+            if !swarms.contains(&v2) {
+                swarms.push(v2);
+            }
+        }
+        swarms
+    }
+
+    /// Check that the bencoded `info` dictionary matches all of the hashes.
+    pub fn verify(&self, info: &[u8]) -> bool {
+        let v1 = self
+            .v1
+            .is_none_or(|v| sha1_smol::Sha1::from(info).digest().bytes() == v);
+        let v2 = self.v2.is_none_or(|v| merkle::hash(info) == v);
+
+        (self.v1.is_some() || self.v2.is_some()) && v1 && v2
+    }
+
+    fn truncate(v2: [u8; 32]) -> [u8; 20] {
+        let mut hash = [0; 20];
+        hash.copy_from_slice(&v2[..20]);
+        hash
+    }
+}
 
 impl TryInto<InfoHash> for String {
     type Error = String;
@@ -82,25 +170,36 @@ impl TryInto<InfoHash> for String {
 
 impl Into<String> for InfoHash {
     fn into(self) -> String {
-        hex::encode(self.0)
+        match (self.v1, self.v2) {
+            (Some(v1), _) => hex::encode(v1),
+            (None, Some(v2)) => hex::encode(v2),
+            (None, None) => String::new(),
+        }
     }
 }
 
 impl From<[u8; 20]> for InfoHash {
     fn from(value: [u8; 20]) -> Self {
-        Self(value)
+        Self { v1: Some(value), v2: None }
+    }
+}
+
+impl From<[u8; 32]> for InfoHash {
+    fn from(value: [u8; 32]) -> Self {
+        Self { v1: None, v2: Some(value) }
     }
 }
 
 impl TryFrom<Vec<u8>> for InfoHash {
     type Error = &'static str;
     fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
-        if value.len() != 20 {
-            return Err("The infohash must have exactly 20 bytes");
+        if let Ok(v1) = <[u8; 20]>::try_from(value.as_slice()) {
+            return Ok(v1.into());
+        }
+        if let Ok(v2) = <[u8; 32]>::try_from(value.as_slice()) {
+            return Ok(v2.into());
         }
-        let mut buff = [0u8; 20];
-        buff[..20].copy_from_slice(&value[..20]);
-        Ok(InfoHash(buff))
+        Err("The infohash must have exactly 20 or 32 bytes")
     }
 }
 
@@ -114,18 +213,55 @@ pub struct Torrent {
     /// key: peer_id
     pub peer_ctxs: HashMap<[u8; 20], Arc<PeerCtx>>,
     pub failed_peers: Vec<SocketAddr>,
+    /// Peers that only came from the `x.pe` of the magnet, and not from the
+    /// trackers. They are dropped if the torrent turns out to be private.
+    pub magnet_only_peers: Vec<SocketAddr>,
+    /// Peers that sent an info that does not match the info hash, they are
+    /// not connected to again.
+    pub banned_peers: Vec<SocketAddr>,
     /// If using a Magnet link, the info will be downloaded in pieces
     /// and those pieces may come in different order,
     /// hence the HashMap (dictionary), and not a vec.
     /// After the dict is complete, it will be decoded into [`info`]
-    pub info_pieces: BTreeMap<u32, Vec<u8>>,
+    ///
+    /// Peers may lie about the size of the info, so the pieces are kept by
+    /// the size that their peer advertised, with the peer that sent them.
+    /// k: size of the info
+    pub info_pieces: BTreeMap<u32, InfoPieces>,
+    /// The pieces of the infos that did not match the info hash, with the
+    /// peer that sent them. Once the info is valid, the peers that sent a
+    /// piece that differs from it are banned.
+    pub suspect_info_pieces: Vec<(u32, Vec<u8>, [u8; 20])>,
     pub have_info: bool,
     /// How many bytes we have uploaded to other peers.
     pub uploaded: u64,
     /// How many bytes we have downloaded from other peers.
     pub downloaded: u64,
+    /// If the disk verified all wanted pieces, so that it only completes
+    /// once.
+    completed: bool,
     pub daemon_tx: mpsc::Sender<DaemonMsg>,
+    /// Hooks that run on lifecycle events of the torrent.
+    pub hooks: Hooks,
+    /// Directory where the info of the torrent is cached as a .torrent file,
+    /// so that it is not downloaded from peers again when the torrent is
+    /// added another time.
+    pub metadata_dir: Option<PathBuf>,
     pub status: TorrentStatus,
+    /// The status of the torrent before it started moving, which is restored
+    /// when the files are moved.
+    status_before_move: Option<TorrentStatus>,
+    /// How many bytes were moved, while the status is
+    /// [`TorrentStatus::Moving`].
+    moved: u64,
+    /// Where the files of the torrent are, the root directory or the file of
+    /// single file torrents. `None` until the disk creates them.
+    pub base_path: Option<PathBuf>,
+    /// Directory where the torrent is saved, if not the download dir. It is
+    /// the one of the options, until the torrent is moved.
+    pub save_path: Option<String>,
+    /// Why the status of the torrent is [`TorrentStatus::Error`].
+    pub error: Option<String>,
     /// Stats of the current Torrent, returned from tracker on announce
     /// requests.
     pub stats: Stats,
@@ -135,9 +271,17 @@ pub struct Torrent {
     pub last_second_downloaded: u64,
     /// The download rate of the torrent, in bytes
     pub download_rate: u64,
+    /// The uploaded bytes of the previous second, like
+    /// `last_second_downloaded`.
+    pub last_second_uploaded: u64,
+    /// The upload rate of the torrent, in bytes
+    pub upload_rate: u64,
     /// The total size of the torrent files, in bytes,
     /// this is a cache of ctx.info.get_size()
     pub size: u64,
+    /// How many bytes must be downloaded for the torrent to be complete,
+    /// which is less than `size` when only some files are selected.
+    pub wanted_size: u64,
     pub name: String,
 }
 
@@ -150,8 +294,34 @@ pub struct TorrentState {
     pub downloaded: u64,
     pub download_rate: u64,
     pub uploaded: u64,
+    pub upload_rate: u64,
     pub size: u64,
     pub info_hash: [u8; 20],
+    pub labels: Vec<String>,
+    /// Directory where the torrent is saved, if not the download dir.
+    pub save_path: Option<String>,
+    /// Why the status is [`TorrentStatus::Error`].
+    pub error: Option<String>,
+    /// How many bytes were moved, while the status is
+    /// [`TorrentStatus::Moving`].
+    pub moved: u64,
+}
+
+/// Options of a [`Torrent`], given when it is added to the daemon.
+#[derive(Debug, Clone, Default, PartialEq, Readable, Writable)]
+pub struct TorrentOptions {
+    /// Directory where the torrent is saved, instead of the download dir.
+    pub save_path: Option<String>,
+    /// If the torrent is paused after its info is downloaded.
+    pub paused: bool,
+    /// Indices of the files that are downloaded, instead of the ones
+    /// selected by the magnet. All files if `None`.
+    pub files: Option<Vec<u32>>,
+    /// Labels to organize the torrents, they are only used by clients.
+    pub labels: Vec<String>,
+    pub piece_strategy: PieceStrategy,
+    /// How the files are allocated, if not the default of the disk.
+    pub preallocation: Option<Preallocation>,
 }
 
 /// Context of [`Torrent`] that can be shared between other types
@@ -160,12 +330,51 @@ pub struct TorrentCtx {
     pub disk_tx: mpsc::Sender<DiskMsg>,
     pub tx: mpsc::Sender<TorrentMsg>,
     pub magnet: Magnet,
+    pub options: TorrentOptions,
+    /// The hash used on the wire, see [`InfoHash::wire`].
     pub info_hash: [u8; 20],
+    pub info_hashes: InfoHash,
+    /// Piece layers of the files of v2 and hybrid torrents, from the
+    /// `pieces root` of each file to its piece layer. Hashes that are not
+    /// known yet are zeros.
+    pub piece_layers: RwLock<PieceLayers>,
     pub bitfield: RwLock<Bitfield>,
     pub info: RwLock<Info>,
+    /// The bencoded info, exactly as the info hash is computed from it.
+    /// Empty until the info is downloaded.
+    pub raw_info: RwLock<Vec<u8>>,
     pub has_at_least_one_piece: AtomicBool,
 }
 
+impl TorrentCtx {
+    /// If only some files of the torrent are downloaded.
+    pub fn selects_files(&self) -> bool {
+        self.options.files.is_some() || self.magnet.parse_so().is_some()
+    }
+
+    /// If the file at `index` of the info is downloaded.
+    pub fn is_file_selected(&self, index: usize) -> bool {
+        match &self.options.files {
+            Some(files) => files.contains(&(index as u32)),
+            None => self.magnet.is_file_selected(index),
+        }
+    }
+
+    /// A piece of the bencoded info, to be sent to a peer that requested it,
+    /// BEP 9. `None` if we do not have the info yet, if the piece does not
+    /// exist, or if the torrent is private.
+    pub async fn metadata_piece(&self, index: u32) -> Option<Vec<u8>> {
+        if self.info.read().await.is_private() {
+            return None;
+        }
+        let raw_info = self.raw_info.read().await;
+        let len = Metadata::piece_len(raw_info.len() as u32, index)?;
+        let start = (index * BLOCK_LEN) as usize;
+
+        Some(raw_info[start..start + len as usize].to_vec())
+    }
+}
+
 /// Status of the current Torrent, updated at every announce request.
 #[derive(Clone, Debug, PartialEq, Default, Readable, Writable)]
 pub struct Stats {
@@ -175,11 +384,25 @@ pub struct Stats {
 }
 
 impl Torrent {
-    #[tracing::instrument(skip(disk_tx, daemon_tx), name = "torrent::new")]
     pub fn new(
         disk_tx: mpsc::Sender<DiskMsg>,
         daemon_tx: mpsc::Sender<DaemonMsg>,
         magnet: Magnet,
+    ) -> Self {
+        Self::with_options(
+            disk_tx,
+            daemon_tx,
+            magnet,
+            TorrentOptions::default(),
+        )
+    }
+
+    #[tracing::instrument(skip(disk_tx, daemon_tx), name = "torrent::new")]
+    pub fn with_options(
+        disk_tx: mpsc::Sender<DiskMsg>,
+        daemon_tx: mpsc::Sender<DaemonMsg>,
+        magnet: Magnet,
+        options: TorrentOptions,
     ) -> Self {
         let name = magnet.parse_dn();
         let bitfield = RwLock::new(Bitfield::default());
@@ -188,34 +411,53 @@ impl Torrent {
         let tracker_ctx = Arc::new(TrackerCtx::default());
 
         let (tx, rx) = mpsc::channel::<TorrentMsg>(300);
+        let save_path = options.save_path.clone();
 
         let ctx = Arc::new(TorrentCtx {
             tx: tx.clone(),
             disk_tx,
             info_hash: magnet.parse_xt(),
+            info_hashes: magnet.parse_xt_infohash(),
+            piece_layers: RwLock::new(PieceLayers::new()),
             bitfield,
             magnet,
+            options,
             info,
+            raw_info: RwLock::new(Vec::new()),
             has_at_least_one_piece: AtomicBool::new(false),
         });
 
         Self {
             name,
             size: 0,
+            wanted_size: 0,
             last_second_downloaded: 0,
             download_rate: 0,
+            last_second_uploaded: 0,
+            upload_rate: 0,
             status: TorrentStatus::default(),
             stats: Stats::default(),
             daemon_tx,
+            hooks: Hooks::default(),
+            metadata_dir: None,
+            status_before_move: None,
+            moved: 0,
+            base_path: None,
+            save_path,
+            completed: false,
+            error: None,
             uploaded: 0,
             downloaded: 0,
             info_pieces,
+            suspect_info_pieces: Vec::new(),
             tracker_ctx,
             ctx,
             rx,
             peer_ctxs: HashMap::new(),
             have_info: false,
             failed_peers: Vec::new(),
+            magnet_only_peers: Vec::new(),
+            banned_peers: Vec::new(),
         }
     }
 
@@ -226,10 +468,52 @@ impl Torrent {
         &mut self,
         listen: Option<SocketAddr>,
     ) -> Result<Vec<SocketAddr>, Error> {
-        let mut tracker =
-            Tracker::connect(self.ctx.magnet.parse_trackers()).await?;
+        // private torrents only use the peers of their trackers, BEP 27
+        let magnet_peers = if self.ctx.info.read().await.is_private() {
+            Vec::new()
+        } else {
+            self.magnet_peers().await
+        };
+
+        // without trackers, the peers of the magnet are the only ones
+        let trackers = self.ctx.magnet.parse_trackers();
+        let mut tracker = match Tracker::connect(trackers).await {
+            Ok(tracker) => tracker,
+            Err(e) if !magnet_peers.is_empty() => {
+                warn!("{e}, connecting only to the peers of the magnet");
+                if let Some(listen) = listen {
+                    let tracker_ctx = Arc::make_mut(&mut self.tracker_ctx);
+                    tracker_ctx.local_peer_addr.set_port(listen.port());
+                }
+                self.magnet_only_peers.clone_from(&magnet_peers);
+                return Ok(magnet_peers);
+            }
+            Err(e) => return Err(e),
+        };
         let info_hash = self.ctx.clone().info_hash;
-        let (res, peers) = tracker.announce_exchange(info_hash, listen).await?;
+        let (res, mut peers) =
+            tracker.announce_exchange(info_hash, listen).await?;
+
+        for peer in magnet_peers {
+            if !peers.contains(&peer) {
+                peers.push(peer);
+                self.magnet_only_peers.push(peer);
+            }
+        }
+
+        // hybrid torrents are also on the swarm of the v2 info hash
+        for info_hash in self.ctx.info_hashes.swarms().into_iter().skip(1) {
+            match tracker.announce_exchange(info_hash, listen).await {
+                Ok((_, swarm_peers)) => {
+                    for peer in swarm_peers {
+                        if !peers.contains(&peer) {
+                            peers.push(peer);
+                        }
+                    }
+                }
+                Err(e) => warn!("could not announce to the v2 swarm: {e}"),
+            }
+        }
 
         self.stats = Stats {
             interval: res.interval,
@@ -252,12 +536,28 @@ impl Torrent {
         Ok(peers)
     }
 
+    /// Resolve the `x.pe` peers of the magnet, the ones that can't be
+    /// resolved are ignored.
+    async fn magnet_peers(&self) -> Vec<SocketAddr> {
+        let mut peers = Vec::new();
+
+        for peer in self.ctx.magnet.parse_peers() {
+            match lookup_host(peer.as_str()).await {
+                Ok(mut addrs) => peers.extend(addrs.next()),
+                Err(e) => warn!("could not resolve the peer {peer}: {e}"),
+            }
+        }
+
+        peers
+    }
+
     /// Start the Torrent and immediately spawns all the event loops.
     #[tracing::instrument(skip(self), name = "torrent::start_and_run")]
     pub async fn start_and_run(
         &mut self,
         listen: Option<SocketAddr>,
     ) -> Result<(), Error> {
+        self.load_metadata().await?;
         let peers = self.start(listen).await?;
 
         self.spawn_outbound_peers(peers).await?;
@@ -308,8 +608,7 @@ impl Torrent {
         direction: Direction,
     ) -> Result<Peer, Error> {
         let (socket, handshake) =
-            Peer::handshake(socket, direction, ctx.info_hash, local_peer_id)
-                .await?;
+            Peer::handshake(socket, direction, &ctx, local_peer_id).await?;
 
         let local = socket.get_ref().local_addr()?;
         let remote = socket.get_ref().peer_addr()?;
@@ -410,6 +709,17 @@ impl Torrent {
                         TorrentMsg::PeerConnected(id, ctx) => {
                             debug!("{} connected with {}", ctx.local_addr, ctx.remote_addr);
 
+                            if self.is_forbidden_peer(&ctx).await {
+                                debug!("dropping {}, it is not allowed", ctx.remote_addr);
+                                let _ = ctx.tx.send(PeerMsg::Quit).await;
+                                continue;
+                            }
+
+                            // a torrent that starts paused does not download from its peers
+                            if self.status == TorrentStatus::Paused {
+                                let _ = ctx.tx.send(PeerMsg::Pause).await;
+                            }
+
                             self.peer_ctxs.insert(id, ctx.clone());
 
                             let _ = self
@@ -418,11 +728,18 @@ impl Torrent {
                                 .send(DiskMsg::NewPeer(ctx))
                                 .await;
                         }
+                        // the disk may verify the pieces again after an error
+                        TorrentMsg::DownloadComplete if self.completed => {}
                         TorrentMsg::DownloadComplete => {
                             info!("Downloaded torrent {:?}", self.name);
+                            self.completed = true;
                             let (otx, orx) = oneshot::channel();
 
-                            self.status = TorrentStatus::Seeding;
+                            // a paused, moving or failed torrent starts
+                            // seeding after it is resumed, moved or retried
+                            if self.status == TorrentStatus::Downloading {
+                                self.status = TorrentStatus::Seeding;
+                            }
 
                             if let Some(tracker_tx) = &tracker_tx {
                                 let _ = tracker_tx.send(
@@ -436,6 +753,7 @@ impl Torrent {
                                     })
                                 .await;
                             }
+                            self.announce_other_swarms(Event::Completed, 0).await;
 
                             if let Ok(Ok(r)) = orx.await {
                                 debug!("announced completion with success {r:#?}");
@@ -470,126 +788,179 @@ impl Torrent {
                                 let _ = peer.tx.send(PeerMsg::RequestBlockInfos(block_infos.clone())).await;
                             }
                         }
-                        TorrentMsg::DownloadedInfoPiece(total, index, bytes) => {
+                        TorrentMsg::DownloadedInfoPiece { from, total, index, bytes } => {
                             debug!("received DownloadedInfoPiece");
 
+                            // a peer may still send pieces after we have the
+                            // info, or while the torrent is stopped
+                            if self.have_info
+                                || self.status == TorrentStatus::Paused
+                                || self.status == TorrentStatus::Error
+                            {
+                                continue;
+                            }
+
                             if self.status == TorrentStatus::ConnectingTrackers {
                                 self.status = TorrentStatus::DownloadingMetainfo;
                             }
 
-                            self.info_pieces.insert(index, bytes);
-
-                            let info_len = self.info_pieces.values().fold(0, |acc, b| {
-                                acc + b.len()
-                            });
-
-                            let have_all_pieces = info_len as u32 >= total;
-
-                            if have_all_pieces {
-                                // info has a valid bencode format
-                                let info_bytes = self.info_pieces.values().fold(Vec::new(), |mut acc, b| {
-                                    acc.extend_from_slice(b);
-                                    acc
-                                });
-                                let info = Info::from_bencode(&info_bytes).map_err(|_| Error::BencodeError)?;
-
-                                let m_info = self.ctx.magnet.xt.clone().unwrap();
-
-                                let mut hash = sha1_smol::Sha1::new();
-                                hash.update(&info_bytes);
-
-                                let hash = hash.digest().bytes();
-
-                                // validate the hash of the downloaded info
-                                // against the hash of the magnet link
-                                let hash = hex::encode(hash);
-
-                                if hash.to_uppercase() == m_info.to_uppercase() {
-                                    debug!("the hash of the downloaded info matches the hash of the magnet link");
-
-                                    // with the info fully downloaded, we now know the pieces len,
-                                    // this will update the bitfield of the torrent
-                                    let mut bitfield = self.ctx.bitfield.write().await;
-                                    *bitfield = bitvec![u8, Msb0; 0; info.pieces() as usize];
-
-                                    // remove excess bits
-                                    if (info.pieces() as usize) < bitfield.len() {
-                                        unsafe {
-                                            bitfield.set_len(info.pieces() as usize);
-                                        }
-                                    }
-
-                                    debug!("local_bitfield is now of len {:?}", bitfield.len());
-
-                                    self.size = info.get_size();
-                                    self.have_info = true;
-
-                                    let mut info_l = self.ctx.info.write().await;
-
-                                    debug!("new info piece length {:?}", info.piece_length);
-                                    debug!("new info pieces_len {:?}", info.pieces.len());
-                                    debug!("new info pieces_len {:?}", info.pieces.len());
-                                    debug!("new info file_length {:?}", info.file_length);
-                                    debug!("new info files {:#?}", info.files);
-
-                                    *info_l = info;
-                                    drop(info_l);
-
-                                    self.status = TorrentStatus::Downloading;
-                                    self.ctx.disk_tx.send(DiskMsg::NewTorrent(self.ctx.clone())).await?;
-                                } else {
-                                    warn!("a peer sent a valid Info, but the hash does not match the hash of the provided magnet link, panicking");
-                                    return Err(Error::PieceInvalid);
-                                }
-                            }
-                        }
-                        TorrentMsg::RequestInfoPiece(index, recipient) => {
-                            debug!("received RequestInfoPiece {index}");
-                            let bytes = self.info_pieces.get(&index).cloned();
-                            let _ = recipient.send(bytes);
+                            self.add_info_piece(from, total, index, bytes).await?;
                         }
                         TorrentMsg::IncrementDownloaded(n) => {
                             self.downloaded += n as u64;
-
-                            // check if the torrent download is complete
-                            let is_download_complete = self.downloaded >= self.size;
                             debug!("IncrementDownloaded {:?}", self.downloaded);
-                            debug!("size is {}", self.size);
-
-                            if is_download_complete {
-                                self.ctx.tx.send(TorrentMsg::DownloadComplete).await?;
-                            }
                         }
                         TorrentMsg::IncrementUploaded(n) => {
                             self.uploaded += n as u64;
                             debug!("IncrementUploaded {}", self.uploaded);
                         }
+                        TorrentMsg::TogglePause if self.status == TorrentStatus::Error && self.have_info => {
+                            // resuming a torrent that failed on disk tries it again
+                            info!("Retrying torrent {:?}", self.name);
+                            let _ = self.ctx.disk_tx.send(DiskMsg::Retry(self.ctx.info_hash)).await;
+                        }
                         TorrentMsg::TogglePause => {
                             debug!("torrent TogglePause");
-                            // can only pause if the torrent is not connecting, or not erroring
-                            if self.status == TorrentStatus::Downloading || self.status == TorrentStatus::Seeding || self.status == TorrentStatus::Paused {
-                                info!("Paused torrent {:?}", self.name);
-                                if self.status == TorrentStatus::Paused {
-                                    if self.downloaded >= self.size {
-                                        self.status = TorrentStatus::Seeding;
-                                    } else {
-                                        self.status = TorrentStatus::Downloading;
-                                    }
-                                } else {
-                                    self.status = TorrentStatus::Paused;
-                                }
-                                for (_, peer) in &self.peer_ctxs {
-                                    if self.status == TorrentStatus::Paused {
-                                        let _ = peer.tx.send(PeerMsg::Pause).await;
-                                    } else {
-                                        let _ = peer.tx.send(PeerMsg::Resume).await;
-                                    }
+                            use TorrentStatus::*;
+                            let complete = self.completed;
+                            // can't pause while erroring or moving
+                            self.status = match self.status {
+                                Paused if !self.have_info => DownloadingMetainfo,
+                                Paused if complete => Seeding,
+                                Paused => Downloading,
+                                Downloading | Seeding | ConnectingTrackers
+                                | DownloadingMetainfo => Paused,
+                                Moving | Error => continue,
+                            };
+                            info!("{:?} is {:?}", self.name, self.status);
+
+                            for peer in self.peer_ctxs.values() {
+                                let msg = match self.status {
+                                    Paused => PeerMsg::Pause,
+                                    _ => PeerMsg::Resume,
+                                };
+                                let _ = peer.tx.send(msg).await;
+                                // the info pieces were ignored while paused
+                                if self.status == DownloadingMetainfo {
+                                    let _ = peer
+                                        .tx
+                                        .send(PeerMsg::RequestInfo)
+                                        .await;
                                 }
                             }
+                            if self.status == Paused && self.have_info {
+                                let _ = self
+                                    .ctx
+                                    .disk_tx
+                                    .send(DiskMsg::CloseFiles(
+                                        self.ctx.info_hash,
+                                    ))
+                                    .await;
+                            }
+                        }
+                        TorrentMsg::DiskError(e) => {
+                            warn!("{:?} failed on disk: {e}", self.name);
+                            self.error = Some(e.clone());
+
+                            // blocks that were in flight fail again
+                            if self.status == TorrentStatus::Error {
+                                continue;
+                            }
+
+                            self.status = TorrentStatus::Error;
+                            self.status_before_move = None;
+                            for (_, peer) in &self.peer_ctxs {
+                                let _ = peer.tx.send(PeerMsg::Pause).await;
+                            }
+                            self.hooks.spawn(HookEvent::Error, HookEnv {
+                                info_hash: self.ctx.info_hash,
+                                name: self.name.clone(),
+                                size: self.size,
+                                error: Some(e),
+                                save_path: self.base_path.clone(),
+                            });
+                        }
+                        TorrentMsg::DiskReady => {
+                            // the torrent may have been retried twice
+                            if self.status != TorrentStatus::Error {
+                                continue;
+                            }
+                            info!("Resumed torrent {:?} after disk error", self.name);
+                            self.error = None;
+                            self.status = if self.completed {
+                                TorrentStatus::Seeding
+                            } else {
+                                TorrentStatus::Downloading
+                            };
+                            for (_, peer) in &self.peer_ctxs {
+                                let _ = peer.tx.send(PeerMsg::Resume).await;
+                            }
+                        }
+                        TorrentMsg::MoveStorage(dir) => {
+                            use TorrentStatus::*;
+                            // can only move if the torrent has its files, and
+                            // is not moving already
+                            if !matches!(
+                                self.status,
+                                Downloading | Seeding | Paused
+                            ) {
+                                warn!(
+                                    "{:?} can't be moved while it is {:?}",
+                                    self.name, self.status
+                                );
+                                continue;
+                            }
+                            info!("Moving torrent {:?} to {dir:?}", self.name);
+                            let status =
+                                std::mem::replace(&mut self.status, Moving);
+                            self.status_before_move = Some(status);
+                            self.moved = 0;
+                            let _ = self
+                                .ctx
+                                .disk_tx
+                                .send(DiskMsg::MoveStorage {
+                                    info_hash: self.ctx.info_hash,
+                                    dir,
+                                })
+                                .await;
+                        }
+                        TorrentMsg::MoveProgress(bytes) => {
+                            self.moved = bytes;
+                        }
+                        TorrentMsg::StorageMoved(dir) => {
+                            info!("Moved torrent {:?} to {dir:?}", self.name);
+                            self.save_path = Some(dir.to_string_lossy().into_owned());
+                            // the download may have completed while moving
+                            let complete = self.completed;
+                            self.status = match self.status_before_move.take() {
+                                Some(TorrentStatus::Paused) => {
+                                    TorrentStatus::Paused
+                                }
+                                _ if complete => TorrentStatus::Seeding,
+                                _ => TorrentStatus::Downloading,
+                            };
+                        }
+                        TorrentMsg::StorageComplete => {
+                            self.hooks.spawn(HookEvent::Complete, HookEnv {
+                                info_hash: self.ctx.info_hash,
+                                name: self.name.clone(),
+                                size: self.size,
+                                error: None,
+                                save_path: self.base_path.clone(),
+                            });
+                        }
+                        TorrentMsg::BasePath(path) => {
+                            self.base_path = Some(path);
                         }
                         TorrentMsg::FailedPeer(addr) => {
                             self.failed_peers.push(addr);
                         },
+                        TorrentMsg::ExportTorrent(recipient) => {
+                            let _ = recipient.send(self.export_torrent().await);
+                        }
+                        TorrentMsg::ExportMagnet(recipient) => {
+                            let _ = recipient.send(self.export_magnet().await);
+                        }
                         TorrentMsg::Quit => {
                             info!("Quitting torrent {:?}", self.name);
                             let (otx, orx) = oneshot::channel();
@@ -619,7 +990,13 @@ impl Torrent {
                                 });
                             }
 
-                            orx.await??;
+                            let _ = self.ctx.disk_tx.send(DiskMsg::CloseFiles(self.ctx.info_hash)).await;
+
+                            // torrents of magnets without trackers
+                            // don't have a tracker to answer
+                            if tracker_tx.is_some() {
+                                orx.await??;
+                            }
 
                             return Ok(());
                         }
@@ -627,6 +1004,7 @@ impl Torrent {
                 }
                 _ = frontend_interval.tick() => {
                     self.download_rate = self.downloaded - self.last_second_downloaded;
+                    self.upload_rate = self.uploaded - self.last_second_uploaded;
 
                     let torrent_state = TorrentState {
                         name: self.name.clone(),
@@ -636,10 +1014,16 @@ impl Torrent {
                         stats: self.stats.clone(),
                         status: self.status.clone(),
                         download_rate: self.download_rate,
+                        upload_rate: self.upload_rate,
                         info_hash: self.ctx.info_hash,
+                        labels: self.ctx.options.labels.clone(),
+                        save_path: self.save_path.clone(),
+                        error: self.error.clone(),
+                        moved: self.moved,
                     };
 
                     self.last_second_downloaded = self.downloaded;
+                    self.last_second_uploaded = self.uploaded;
                     // debug!(
                     //     "{} {} of {}. Download rate: {}",
                     //     self.name,
@@ -657,7 +1041,7 @@ impl Torrent {
                     let info = self.ctx.info.read().await;
 
                     // we know if the info is downloaded if the piece_length is > 0
-                    if info.piece_length > 0 {
+                    if info.piece_length > 0 && tracker_tx.is_some() {
                         debug!("sending periodic announce, interval {announce_interval:?}");
                         let left = if self.downloaded < info.get_size() { info.get_size() } else { self.downloaded - info.get_size() };
 
@@ -675,6 +1059,7 @@ impl Torrent {
                                 })
                             .await;
                         }
+                        self.announce_other_swarms(Event::None, left).await;
 
                         let r = orx.await??;
                         debug!("new stats {r:#?}");
@@ -706,6 +1091,502 @@ impl Torrent {
             }
         }
     }
+
+    /// Set the info of the torrent, given its bencoded bytes. The bitfield,
+    /// the size and the piece layers are updated, and the torrent is ready
+    /// to be downloaded.
+    ///
+    /// # Errors
+    ///
+    /// If the hash of the info does not match the info hash of the magnet,
+    /// or if it is a v2 only torrent, which the disk does not support.
+    async fn set_info(&mut self, info_bytes: &[u8]) -> Result<(), Error> {
+        // validate the hash of the downloaded info
+        // against the hashes of the magnet link
+        if !self.ctx.info_hashes.verify(info_bytes) {
+            return Err(Error::InfoInvalid);
+        }
+
+        // info has a valid bencode format
+        let mut info = Info::from_bencode(info_bytes)
+            .map_err(|_| Error::BencodeError)?;
+
+        // the paths of the files are joined onto the download dir
+        if let Err(e) = info.sanitize() {
+            self.unsafe_info(&e).await;
+            return Err(e);
+        }
+
+        // the disk layout is the one of v1 and hybrid torrents
+        info.align_v2_files();
+
+        debug!(
+            "the hash of the downloaded info matches the hash of the magnet link"
+        );
+
+        // with the info fully downloaded, we now know the pieces len,
+        // this will update the bitfield of the torrent
+        let mut bitfield = self.ctx.bitfield.write().await;
+        *bitfield = bitvec![u8, Msb0; 0; info.pieces() as usize];
+
+        // remove excess bits
+        if (info.pieces() as usize) < bitfield.len() {
+            unsafe {
+                bitfield.set_len(info.pieces() as usize);
+            }
+        }
+
+        debug!("local_bitfield is now of len {:?}", bitfield.len());
+        drop(bitfield);
+
+        self.size = info.get_size();
+        self.wanted_size = match self.ctx.selects_files() {
+            false => self.size,
+            true => info
+                .selected_pieces(|i| self.ctx.is_file_selected(i))
+                .iter_ones()
+                .map(|piece| info.piece_size(piece) as u64)
+                .sum(),
+        };
+        self.have_info = true;
+
+        let mut info_l = self.ctx.info.write().await;
+
+        debug!("new info piece length {:?}", info.piece_length);
+        debug!("new info pieces_len {:?}", info.pieces.len());
+        debug!("new info file_length {:?}", info.file_length);
+        debug!("new info files {:#?}", info.files);
+
+        *self.ctx.piece_layers.write().await = info.empty_piece_layers();
+        *self.ctx.raw_info.write().await = info_bytes.to_vec();
+        *info_l = info;
+        drop(info_l);
+
+        // the pieces of the infos of other sizes are from lying peers, or
+        // peers that sent an info of another torrent.
+        for (_, pieces) in std::mem::take(&mut self.info_pieces) {
+            for (index, (bytes, from)) in pieces {
+                self.suspect_info_pieces.push((index, bytes, from));
+            }
+        }
+
+        self.drop_forbidden_peers().await;
+
+        self.status = TorrentStatus::Downloading;
+        if self.ctx.options.paused {
+            info!("Paused torrent {:?}", self.name);
+            self.status = TorrentStatus::Paused;
+            for peer in self.peer_ctxs.values() {
+                let _ = peer.tx.send(PeerMsg::Pause).await;
+            }
+        }
+        self.ctx.disk_tx.send(DiskMsg::NewTorrent(self.ctx.clone())).await?;
+        self.spawn_web_seeds();
+
+        Ok(())
+    }
+
+    /// The info has a path that can't be saved, the torrent stays in
+    /// [`TorrentStatus::Error`] without downloading anything, until it is
+    /// removed.
+    async fn unsafe_info(&mut self, e: &Error) {
+        warn!("{:?} has an unsafe path: {e}", self.name);
+        self.status = TorrentStatus::Error;
+        self.error = Some(e.to_string());
+
+        for peer in self.peer_ctxs.values() {
+            let _ = peer.tx.send(PeerMsg::Pause).await;
+        }
+        self.hooks.spawn(
+            HookEvent::Error,
+            HookEnv {
+                info_hash: self.ctx.info_hash,
+                name: self.name.clone(),
+                size: self.size,
+                error: self.error.clone(),
+                save_path: self.base_path.clone(),
+            },
+        );
+    }
+
+    /// Add a piece of the info sent by a peer, and set the info of the
+    /// torrent when all of its pieces are downloaded.
+    ///
+    /// The pieces are downloaded by the size of the info that each peer
+    /// advertised, so a peer that lies about the size can't stop the honest
+    /// peers from completing the info. Only [`MAX_INFO_SIZES`] sizes are
+    /// downloaded at once, the size with the fewest pieces is dropped to
+    /// make room for another one.
+    ///
+    /// If the info does not match the info hash, it is downloaded again, see
+    /// [`Self::discard_info`].
+    async fn add_info_piece(
+        &mut self,
+        from: [u8; 20],
+        total: u32,
+        index: u32,
+        bytes: Vec<u8>,
+    ) -> Result<(), Error> {
+        if Metadata::piece_len(total, index) != Some(bytes.len() as u32) {
+            debug!("ignoring invalid info piece {index} of size {total}");
+            return Ok(());
+        }
+
+        if !self.info_pieces.contains_key(&total)
+            && self.info_pieces.len() >= MAX_INFO_SIZES
+        {
+            let minority = self
+                .info_pieces
+                .iter()
+                .min_by_key(|(_, pieces)| pieces.len())
+                .map(|(size, pieces)| (*size, pieces.len()));
+
+            match minority {
+                Some((size, len)) if len <= 1 => {
+                    debug!("dropping the info pieces of size {size}");
+                    let pieces =
+                        self.info_pieces.remove(&size).unwrap_or_default();
+                    for (index, (bytes, from)) in pieces {
+                        self.suspect_info_pieces.push((index, bytes, from));
+                    }
+                }
+                _ => {
+                    self.suspect_info_pieces.push((index, bytes, from));
+                    return Ok(());
+                }
+            }
+        }
+
+        let pieces = self.info_pieces.entry(total).or_default();
+        pieces.insert(index, (bytes, from));
+
+        if pieces.len() < Metadata::pieces(total) as usize {
+            return Ok(());
+        }
+
+        let pieces = self.info_pieces.remove(&total).unwrap_or_default();
+        let info_bytes = Self::info_bytes(&pieces);
+
+        match self.set_info(&info_bytes).await {
+            Ok(()) => {
+                self.ban_info_offenders(&info_bytes).await;
+                if let Err(e) = self.save_metadata().await {
+                    warn!("could not cache the info of {:?}: {e}", self.name);
+                }
+                Ok(())
+            }
+            Err(Error::InfoInvalid | Error::BencodeError) => {
+                warn!(
+                    "the info of {:?} sent by peers does not match the info \
+                     hash, downloading it again",
+                    self.name
+                );
+                self.discard_info(pieces).await;
+                Ok(())
+            }
+            // the torrent is in error, see `unsafe_info`
+            Err(Error::UnsafePath(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Discard the pieces of an invalid info and request the info again,
+    /// preferably to the peers that did not send any of its pieces.
+    ///
+    /// We don't know which piece is wrong, so the pieces are kept as
+    /// suspects until the info is valid, see [`Self::ban_info_offenders`].
+    async fn discard_info(&mut self, pieces: InfoPieces) {
+        let senders: Vec<[u8; 20]> =
+            pieces.values().map(|(_, from)| *from).collect();
+
+        for (index, (bytes, from)) in pieces {
+            self.suspect_info_pieces.push((index, bytes, from));
+        }
+
+        let others: Vec<&PeerCtx> = self
+            .peer_ctxs
+            .values()
+            .filter(|peer| !senders.contains(&peer.id))
+            .map(|peer| peer.as_ref())
+            .collect();
+
+        let peers = if others.is_empty() {
+            self.peer_ctxs.values().map(|peer| peer.as_ref()).collect()
+        } else {
+            others
+        };
+
+        for peer in peers {
+            let _ = peer.tx.send(PeerMsg::RequestInfo).await;
+        }
+    }
+
+    /// Ban the peers that sent a piece of an invalid info that differs from
+    /// the same piece of the valid `info_bytes`.
+    async fn ban_info_offenders(&mut self, info_bytes: &[u8]) {
+        let total = info_bytes.len() as u32;
+        let suspects = std::mem::take(&mut self.suspect_info_pieces);
+
+        for (index, bytes, from) in suspects {
+            let start = index as usize * BLOCK_LEN as usize;
+            let valid = Metadata::piece_len(total, index)
+                .map(|len| &info_bytes[start..start + len as usize]);
+
+            if valid == Some(bytes.as_slice()) {
+                continue;
+            }
+
+            if let Some(peer) = self.peer_ctxs.remove(&from) {
+                warn!("Banning {}, it sent an invalid info", peer.remote_addr);
+                self.banned_peers.push(peer.remote_addr);
+                let _ = peer.tx.send(PeerMsg::Quit).await;
+            }
+        }
+    }
+
+    /// If the peer must not be used, which are the banned peers and the peers
+    /// that only came from the magnet on private torrents, BEP 27. Inbound
+    /// peers are allowed on private torrents, since they found us through the
+    /// trackers.
+    async fn is_forbidden_peer(&self, peer: &PeerCtx) -> bool {
+        if self.banned_peers.contains(&peer.remote_addr) {
+            return true;
+        }
+        peer.direction == Direction::Outbound
+            && self.magnet_only_peers.contains(&peer.remote_addr)
+            && self.ctx.info.read().await.is_private()
+    }
+
+    /// Disconnect from the peers that must not be used now that we know
+    /// that the torrent is private, see [`Self::is_forbidden_peer`].
+    async fn drop_forbidden_peers(&mut self) {
+        if !self.ctx.info.read().await.is_private() {
+            return;
+        }
+        self.failed_peers.retain(|v| !self.magnet_only_peers.contains(v));
+
+        let mut forbidden = Vec::new();
+        for (id, peer) in &self.peer_ctxs {
+            if self.is_forbidden_peer(peer).await {
+                forbidden.push(*id);
+            }
+        }
+
+        for id in forbidden {
+            if let Some(peer) = self.peer_ctxs.remove(&id) {
+                info!("Dropping {}, the torrent is private", peer.remote_addr);
+                let _ = peer.tx.send(PeerMsg::Quit).await;
+            }
+        }
+    }
+
+    /// The bencoded info, joined from the pieces that were downloaded.
+    fn info_bytes(pieces: &InfoPieces) -> Vec<u8> {
+        pieces.values().fold(Vec::new(), |mut acc, (b, _)| {
+            acc.extend_from_slice(b);
+            acc
+        })
+    }
+
+    /// Path of the cached .torrent file of this torrent.
+    fn metadata_path(&self) -> Option<PathBuf> {
+        let name = format!("{}.torrent", hex::encode(self.ctx.info_hash));
+        self.metadata_dir.as_ref().map(|dir| dir.join(name))
+    }
+
+    /// Write the .torrent file of the torrent to `metadata_dir`.
+    async fn save_metadata(&self) -> Result<(), Error> {
+        let (Some(path), Some(torrent)) =
+            (self.metadata_path(), self.export_torrent().await)
+        else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+
+        // write to a temporary file first, so that a crash does not leave
+        // an incomplete file behind
+        let tmp = path.with_extension("torrent.tmp");
+        fs::write(&tmp, torrent).await?;
+        fs::rename(&tmp, &path).await?;
+
+        debug!("cached the info of {:?} on {path:?}", self.name);
+
+        Ok(())
+    }
+
+    /// Use the info cached on `metadata_dir` by a previous download of this
+    /// torrent, if there is one, instead of downloading it from peers.
+    ///
+    /// A cached file that is invalid is ignored.
+    pub async fn load_metadata(&mut self) -> Result<(), Error> {
+        // the info was given by a .torrent file
+        if self.have_info {
+            return Ok(());
+        }
+
+        let Some(path) = self.metadata_path() else { return Ok(()) };
+        let Ok(torrent) = fs::read(&path).await else { return Ok(()) };
+
+        let info_bytes = match MetaInfo::raw_info(&torrent) {
+            Ok(info_bytes) if self.ctx.info_hashes.verify(&info_bytes) => {
+                info_bytes
+            }
+            _ => {
+                warn!("ignoring the invalid cached info on {path:?}");
+                return Ok(());
+            }
+        };
+
+        info!("Using the cached info of {:?}", self.name);
+        match self.set_info(&info_bytes).await {
+            // the torrent is in error, see `unsafe_info`
+            Err(Error::UnsafePath(_)) => Ok(()),
+            r => r,
+        }
+    }
+
+    /// Use the info of a .torrent file of this torrent, instead of
+    /// downloading it from peers.
+    pub async fn set_metainfo(&mut self, torrent: &[u8]) -> Result<(), Error> {
+        let info_bytes = MetaInfo::raw_info(torrent)?;
+
+        if !self.ctx.info_hashes.verify(&info_bytes) {
+            return Err(Error::InfoInvalid);
+        }
+
+        match self.set_info(&info_bytes).await {
+            Ok(()) => {}
+            // the torrent is in error, see `unsafe_info`
+            Err(Error::UnsafePath(_)) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+
+        // the file is not needed again to add the torrent another time
+        if let Err(e) = self.save_metadata().await {
+            warn!("could not cache the info of {:?}: {e}", self.name);
+        }
+
+        Ok(())
+    }
+
+    /// The [`MetaInfo`] of the torrent, from the info and the trackers and
+    /// web seeds of the magnet.
+    async fn metainfo(&self) -> MetaInfo {
+        let magnet = &self.ctx.magnet;
+
+        let trackers: Vec<String> = magnet
+            .tr
+            .iter()
+            .map(|v| urlencoding::decode(v).map_or(v.clone(), |v| v.into()))
+            .collect();
+
+        let seeds = |kind: WebSeedKind| {
+            let seeds: Vec<String> = magnet
+                .parse_web_seeds()
+                .iter()
+                .filter(|(_, k)| *k == kind)
+                .map(|(url, _)| url.clone())
+                .collect();
+            (!seeds.is_empty()).then_some(seeds)
+        };
+
+        let info = self.ctx.info.read().await.clone();
+
+        // the piece layers are only exported when all of them are known
+        let piece_layers = self.ctx.piece_layers.read().await;
+        let piece_layers = (info.version() != Version::V1
+            && piece_layers
+                .values()
+                .flat_map(|layer| layer.chunks(32))
+                .all(|hash| hash.iter().any(|b| *b != 0)))
+        .then(|| piece_layers.clone());
+
+        MetaInfo {
+            announce: trackers.first().cloned().unwrap_or_default(),
+            announce_list: (trackers.len() > 1)
+                .then(|| trackers.iter().map(|v| vec![v.clone()]).collect()),
+            info,
+            url_list: seeds(WebSeedKind::UrlList),
+            http_seeds: seeds(WebSeedKind::HttpSeed),
+            piece_layers,
+            ..Default::default()
+        }
+    }
+
+    /// The .torrent file of the torrent, with the info exactly as it was
+    /// downloaded. `None` if the info was not downloaded yet.
+    pub async fn export_torrent(&self) -> Option<Vec<u8>> {
+        if !self.have_info {
+            return None;
+        }
+        let info_bytes = self.ctx.raw_info.read().await.clone();
+
+        match self.metainfo().await.to_bencode_with_raw_info(&info_bytes) {
+            Ok(torrent) => Some(torrent),
+            Err(e) => {
+                warn!("could not encode the .torrent of {:?}: {e}", self.name);
+                None
+            }
+        }
+    }
+
+    /// A magnet link of the torrent, with all of its info hashes, name,
+    /// trackers and web seeds, in the same format for all torrents.
+    pub async fn export_magnet(&self) -> String {
+        let mut metainfo = self.metainfo().await;
+        metainfo.info.name.clone_from(&self.name);
+        metainfo.to_magnet_uri(&self.ctx.info_hashes)
+    }
+
+    /// Spawn an event loop for each web seed of the magnet.
+    ///
+    /// # Important
+    /// Must only be called after the torrent has the Info downloaded.
+    fn spawn_web_seeds(&self) {
+        for (url, kind) in self.ctx.magnet.parse_web_seeds() {
+            let ctx = self.ctx.clone();
+            let (url, kind) = (url.clone(), *kind);
+
+            spawn(async move {
+                let mut web_seed =
+                    match WebSeed::new(url.clone(), kind, ctx).await {
+                        Ok(web_seed) => web_seed,
+                        Err(e) => {
+                            warn!("could not use the web seed {url}: {e}");
+                            return;
+                        }
+                    };
+                if let Err(e) = web_seed.run().await {
+                    debug!("web seed {url} stopped due to an error: {e}");
+                }
+            });
+        }
+    }
+
+    /// Announce to the swarms of the torrent other than the one of
+    /// `ctx.info_hash`, which are the v2 swarm of hybrid torrents.
+    ///
+    /// The `Stopped` event is not sent, since the tracker stops after the
+    /// first one.
+    async fn announce_other_swarms(&self, event: Event, left: u64) {
+        let Some(tracker_tx) = &self.tracker_ctx.tx else { return };
+
+        for info_hash in self.ctx.info_hashes.swarms().into_iter().skip(1) {
+            let _ = tracker_tx
+                .send(TrackerMsg::Announce {
+                    event: event.clone(),
+                    info_hash,
+                    downloaded: self.downloaded,
+                    uploaded: self.uploaded,
+                    left,
+                    recipient: None,
+                })
+                .await;
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Readable, Writable)]
@@ -716,6 +1597,8 @@ pub enum TorrentStatus {
     Downloading,
     Seeding,
     Paused,
+    /// The files of the torrent are being moved to another directory.
+    Moving,
     Error,
 }
 
@@ -728,6 +1611,7 @@ impl<'a> From<TorrentStatus> for &'a str {
             Downloading => "Downloading",
             Seeding => "Seeding",
             Paused => "Paused",
+            Moving => "Moving",
             Error => "Error",
         }
     }
@@ -742,6 +1626,7 @@ impl From<TorrentStatus> for String {
             Downloading => "Downloading".to_owned(),
             Seeding => "Seeding".to_owned(),
             Paused => "Paused".to_owned(),
+            Moving => "Moving".to_owned(),
             Error => "Error".to_owned(),
         }
     }
@@ -756,7 +1641,465 @@ impl From<&str> for TorrentStatus {
             "Downloading" => Downloading,
             "Seeding" => Seeding,
             "Paused" => Paused,
+            "Moving" => Moving,
             _ => Error,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    use super::*;
+
+    fn tmp_dir() -> PathBuf {
+        let mut rng = rand::thread_rng();
+        let name: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        std::env::temp_dir().join(name)
+    }
+
+    /// Wait for the torrent to report `status` to the daemon.
+    async fn wait_status(
+        rx: &mut mpsc::Receiver<DaemonMsg>,
+        status: TorrentStatus,
+    ) {
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                DaemonMsg::TorrentState(state) if state.status == status => {
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // a magnet can be stopped while it downloads its info, and the info is
+    // requested again when it is started.
+    #[tokio::test]
+    async fn pause_before_the_info() {
+        let (disk_tx, _disk_rx) = mpsc::channel(10);
+        let (daemon_tx, mut daemon_rx) = mpsc::channel(10);
+        let magnet = Magnet::new(
+            "magnet:?xt=urn:btih:9999999999999999999999999999999999999999",
+        )
+        .unwrap();
+        let mut torrent = Torrent::new(disk_tx, daemon_tx, magnet);
+        torrent.status = TorrentStatus::DownloadingMetainfo;
+
+        let (peer_tx, mut peer_rx) = mpsc::channel(10);
+        let peer = Arc::new(PeerCtx {
+            direction: Direction::Outbound,
+            tx: peer_tx,
+            pieces: RwLock::new(Bitfield::default()),
+            id: [1; 20],
+            remote_addr: "10.0.0.2:6881".parse().unwrap(),
+            local_addr: "127.0.0.1:6881".parse().unwrap(),
+            info_hash: torrent.ctx.info_hash,
+        });
+        torrent.peer_ctxs.insert([1; 20], peer);
+
+        let tx = torrent.ctx.tx.clone();
+        let handle = spawn(async move { torrent.run().await });
+
+        tx.send(TorrentMsg::TogglePause).await.unwrap();
+        assert!(matches!(peer_rx.recv().await, Some(PeerMsg::Pause)));
+        wait_status(&mut daemon_rx, TorrentStatus::Paused).await;
+
+        tx.send(TorrentMsg::TogglePause).await.unwrap();
+        assert!(matches!(peer_rx.recv().await, Some(PeerMsg::Resume)));
+        assert!(matches!(peer_rx.recv().await, Some(PeerMsg::RequestInfo)));
+        wait_status(&mut daemon_rx, TorrentStatus::DownloadingMetainfo).await;
+
+        handle.abort();
+    }
+
+    // an info with an unsafe path is reported, and the torrent keeps
+    // running in error.
+    #[tokio::test]
+    async fn unsafe_info_keeps_the_torrent_in_error() {
+        let info = Info {
+            piece_length: BLOCK_LEN,
+            pieces: vec![0; 20],
+            name: "..".to_owned(),
+            file_length: Some(5),
+            ..Default::default()
+        };
+        let info_bytes = bendy::encoding::ToBencode::to_bencode(&info).unwrap();
+        let info_hash = sha1_smol::Sha1::from(&info_bytes).digest().bytes();
+        let uri = format!("magnet:?xt=urn:btih:{}", hex::encode(info_hash));
+        let total = info_bytes.len() as u32;
+
+        let (disk_tx, mut disk_rx) = mpsc::channel(10);
+        let (daemon_tx, mut daemon_rx) = mpsc::channel(10);
+        let mut torrent =
+            Torrent::new(disk_tx, daemon_tx, Magnet::new(&uri).unwrap());
+
+        let (peer_tx, mut peer_rx) = mpsc::channel(10);
+        let peer = Arc::new(PeerCtx {
+            direction: Direction::Outbound,
+            tx: peer_tx,
+            pieces: RwLock::new(Bitfield::default()),
+            id: [1; 20],
+            remote_addr: "10.0.0.2:6881".parse().unwrap(),
+            local_addr: "127.0.0.1:6881".parse().unwrap(),
+            info_hash,
+        });
+        torrent.peer_ctxs.insert([1; 20], peer);
+
+        let tx = torrent.ctx.tx.clone();
+        let handle = spawn(async move { torrent.run().await });
+
+        let msg = TorrentMsg::DownloadedInfoPiece {
+            from: [1; 20],
+            total,
+            index: 0,
+            bytes: info_bytes,
+        };
+        tx.send(msg).await.unwrap();
+
+        assert!(matches!(peer_rx.recv().await, Some(PeerMsg::Pause)));
+        wait_status(&mut daemon_rx, TorrentStatus::Error).await;
+        wait_status(&mut daemon_rx, TorrentStatus::Error).await;
+        assert!(!handle.is_finished());
+        assert!(disk_rx.try_recv().is_err());
+
+        handle.abort();
+    }
+
+    // the state reports where the torrent is, after it is moved.
+    #[tokio::test]
+    async fn report_the_save_path_after_a_move() {
+        let (disk_tx, mut disk_rx) = mpsc::channel(10);
+        let (daemon_tx, mut daemon_rx) = mpsc::channel(10);
+        let magnet = Magnet::new(
+            "magnet:?xt=urn:btih:9999999999999999999999999999999999999999",
+        )
+        .unwrap();
+        let options = TorrentOptions {
+            save_path: Some("/mnt/a".to_owned()),
+            ..Default::default()
+        };
+        let mut torrent =
+            Torrent::with_options(disk_tx, daemon_tx, magnet, options);
+        torrent.status = TorrentStatus::Downloading;
+
+        let tx = torrent.ctx.tx.clone();
+        let handle = spawn(async move { torrent.run().await });
+
+        let dir = PathBuf::from("/mnt/b");
+        tx.send(TorrentMsg::MoveStorage(dir.clone())).await.unwrap();
+        assert!(matches!(
+            disk_rx.recv().await,
+            Some(DiskMsg::MoveStorage { dir: d, .. }) if d == dir
+        ));
+        wait_status(&mut daemon_rx, TorrentStatus::Moving).await;
+
+        tx.send(TorrentMsg::StorageMoved(dir)).await.unwrap();
+        let state = loop {
+            match daemon_rx.recv().await.unwrap() {
+                DaemonMsg::TorrentState(state)
+                    if state.status == TorrentStatus::Downloading =>
+                {
+                    break state;
+                }
+                _ => {}
+            }
+        };
+        assert_eq!(state.save_path.as_deref(), Some("/mnt/b"));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn export_and_cache_metadata() {
+        let bytes = include_bytes!("../../../test-files/debian.torrent");
+        let info_hash = MetaInfo::info_hashes(bytes).unwrap();
+        let metainfo = MetaInfo::from_bencode(bytes).unwrap();
+        let uri = metainfo.to_magnet_uri(&info_hash);
+
+        let dir = tmp_dir();
+        let (disk_tx, mut disk_rx) = mpsc::channel(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel(10);
+
+        // without the info, only the magnet can be exported
+        let mut torrent = Torrent::new(
+            disk_tx.clone(),
+            daemon_tx.clone(),
+            Magnet::new(&uri).unwrap(),
+        );
+        torrent.metadata_dir = Some(dir.clone());
+        torrent.load_metadata().await.unwrap();
+
+        assert!(!torrent.have_info);
+        assert_eq!(torrent.export_torrent().await, None);
+        assert_eq!(torrent.export_magnet().await, uri);
+
+        // a downloaded info is cached
+        let info_bytes = MetaInfo::raw_info(bytes).unwrap();
+        torrent.set_info(&info_bytes).await.unwrap();
+        torrent.save_metadata().await.unwrap();
+
+        let exported = torrent.export_torrent().await.unwrap();
+        assert_eq!(MetaInfo::info_hashes(&exported).unwrap(), info_hash);
+        assert_eq!(
+            MetaInfo::from_bencode(&exported).unwrap().trackers(),
+            metainfo.trackers()
+        );
+        assert_eq!(torrent.export_magnet().await, uri);
+        assert!(matches!(disk_rx.try_recv(), Ok(DiskMsg::NewTorrent(_))));
+
+        // and used when the torrent is added again
+        let mut torrent =
+            Torrent::new(disk_tx, daemon_tx, Magnet::new(&uri).unwrap());
+        torrent.metadata_dir = Some(dir.clone());
+        torrent.load_metadata().await.unwrap();
+
+        assert!(torrent.have_info);
+        assert_eq!(torrent.size, metainfo.info.get_size());
+        assert_eq!(torrent.status, TorrentStatus::Downloading);
+        assert_eq!(torrent.export_torrent().await.unwrap(), exported);
+        assert!(matches!(disk_rx.try_recv(), Ok(DiskMsg::NewTorrent(_))));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn private_torrents_only_use_tracker_peers() {
+        let info = Info {
+            piece_length: BLOCK_LEN,
+            pieces: vec![0; 20],
+            name: "private".to_owned(),
+            file_length: Some(5),
+            private: Some(1),
+            ..Default::default()
+        };
+        let info_bytes = bendy::encoding::ToBencode::to_bencode(&info).unwrap();
+        let info_hash = sha1_smol::Sha1::from(&info_bytes).digest().bytes();
+        let uri = format!("magnet:?xt=urn:btih:{}", hex::encode(info_hash));
+
+        let (disk_tx, _disk_rx) = mpsc::channel(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel(10);
+        let mut torrent =
+            Torrent::new(disk_tx, daemon_tx, Magnet::new(&uri).unwrap());
+
+        // one peer from the magnet and one from the trackers
+        let magnet_peer: SocketAddr = "10.0.0.1:6881".parse().unwrap();
+        let tracker_peer: SocketAddr = "10.0.0.2:6881".parse().unwrap();
+        torrent.magnet_only_peers.push(magnet_peer);
+        torrent.failed_peers.push(magnet_peer);
+
+        let mut rxs = Vec::new();
+        for (id, remote_addr) in [(1, magnet_peer), (2, tracker_peer)] {
+            let (tx, rx) = mpsc::channel(10);
+            let ctx = Arc::new(PeerCtx {
+                direction: Direction::Outbound,
+                tx,
+                pieces: RwLock::new(Bitfield::default()),
+                id: [id; 20],
+                remote_addr,
+                local_addr: "127.0.0.1:6881".parse().unwrap(),
+                info_hash,
+            });
+            torrent.peer_ctxs.insert([id; 20], ctx);
+            rxs.push(rx);
+        }
+
+        torrent.set_info(&info_bytes).await.unwrap();
+
+        assert!(matches!(rxs[0].try_recv(), Ok(PeerMsg::Quit)));
+        assert!(rxs[1].try_recv().is_err());
+        assert_eq!(
+            torrent.peer_ctxs.keys().collect::<Vec<_>>(),
+            vec![&[2; 20]]
+        );
+        assert!(torrent.failed_peers.is_empty());
+
+        // the info is not shared with peers
+        assert_eq!(torrent.ctx.metadata_piece(0).await, None);
+    }
+
+    #[tokio::test]
+    async fn ban_peers_that_send_an_invalid_info() {
+        let info = Info {
+            piece_length: BLOCK_LEN,
+            pieces: vec![0; 20],
+            name: "a".repeat(BLOCK_LEN as usize),
+            file_length: Some(5),
+            ..Default::default()
+        };
+        let info_bytes = bendy::encoding::ToBencode::to_bencode(&info).unwrap();
+        let info_hash = sha1_smol::Sha1::from(&info_bytes).digest().bytes();
+        let uri = format!("magnet:?xt=urn:btih:{}", hex::encode(info_hash));
+        let total = info_bytes.len() as u32;
+        assert_eq!(Metadata::pieces(total), 2);
+
+        let (disk_tx, mut disk_rx) = mpsc::channel(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel(10);
+        let mut torrent =
+            Torrent::new(disk_tx, daemon_tx, Magnet::new(&uri).unwrap());
+
+        let mut rxs = Vec::new();
+        for id in 1..=3 {
+            let (tx, rx) = mpsc::channel(10);
+            let ctx = Arc::new(PeerCtx {
+                direction: Direction::Outbound,
+                tx,
+                pieces: RwLock::new(Bitfield::default()),
+                id: [id; 20],
+                remote_addr: format!("10.0.0.{id}:6881").parse().unwrap(),
+                local_addr: "127.0.0.1:6881".parse().unwrap(),
+                info_hash,
+            });
+            torrent.peer_ctxs.insert([id; 20], ctx);
+            rxs.push(rx);
+        }
+
+        let (first, last) = info_bytes.split_at(BLOCK_LEN as usize);
+
+        // pieces of the wrong length are ignored
+        torrent.add_info_piece([3; 20], total, 1, vec![0; 3]).await.unwrap();
+        torrent.add_info_piece([3; 20], total + 1, 0, vec![]).await.unwrap();
+        assert!(torrent.info_pieces.is_empty());
+
+        // peer 2 sends a corrupted piece
+        let mut corrupted = last.to_vec();
+        corrupted[0] ^= 1;
+        let first_piece = first.to_vec();
+        torrent.add_info_piece([1; 20], total, 0, first_piece).await.unwrap();
+        torrent.add_info_piece([2; 20], total, 1, corrupted).await.unwrap();
+
+        // the info is requested again to the peer that did not send it
+        assert!(!torrent.have_info);
+        assert!(torrent.info_pieces.is_empty());
+        assert!(rxs[0].try_recv().is_err());
+        assert!(rxs[1].try_recv().is_err());
+        assert!(matches!(rxs[2].try_recv(), Ok(PeerMsg::RequestInfo)));
+        assert!(torrent.banned_peers.is_empty());
+
+        // the other peer sends the valid info
+        torrent.add_info_piece([3; 20], total, 1, last.to_vec()).await.unwrap();
+        let first_piece = first.to_vec();
+        torrent.add_info_piece([3; 20], total, 0, first_piece).await.unwrap();
+
+        // only the peer whose piece differs from the valid info is banned
+        assert!(torrent.have_info);
+        assert!(rxs[0].try_recv().is_err());
+        assert!(matches!(rxs[1].try_recv(), Ok(PeerMsg::Quit)));
+        assert_eq!(torrent.peer_ctxs.len(), 2);
+        assert!(!torrent.peer_ctxs.contains_key(&[2; 20]));
+        assert_eq!(
+            torrent.banned_peers,
+            vec!["10.0.0.2:6881".parse::<SocketAddr>().unwrap()]
+        );
+        assert_eq!(*torrent.ctx.raw_info.read().await, info_bytes);
+        assert!(matches!(disk_rx.try_recv(), Ok(DiskMsg::NewTorrent(_))));
+
+        // and it is served to other peers
+        assert_eq!(torrent.ctx.metadata_piece(0).await.unwrap(), first);
+        assert_eq!(torrent.ctx.metadata_piece(1).await.unwrap(), last);
+        assert_eq!(torrent.ctx.metadata_piece(2).await, None);
+    }
+
+    #[tokio::test]
+    async fn ignore_peers_that_lie_about_the_info_size() {
+        let info = Info {
+            piece_length: BLOCK_LEN,
+            pieces: vec![0; 20],
+            name: "a".repeat(BLOCK_LEN as usize),
+            file_length: Some(5),
+            ..Default::default()
+        };
+        let info_bytes = bendy::encoding::ToBencode::to_bencode(&info).unwrap();
+        let info_hash = sha1_smol::Sha1::from(&info_bytes).digest().bytes();
+        let uri = format!("magnet:?xt=urn:btih:{}", hex::encode(info_hash));
+        let total = info_bytes.len() as u32;
+
+        let (disk_tx, _disk_rx) = mpsc::channel(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel(10);
+        let mut torrent =
+            Torrent::new(disk_tx, daemon_tx, Magnet::new(&uri).unwrap());
+
+        let mut rxs = Vec::new();
+        for id in 1..=2 {
+            let (tx, rx) = mpsc::channel(10);
+            let ctx = Arc::new(PeerCtx {
+                direction: Direction::Outbound,
+                tx,
+                pieces: RwLock::new(Bitfield::default()),
+                id: [id; 20],
+                remote_addr: format!("10.0.0.{id}:6881").parse().unwrap(),
+                local_addr: "127.0.0.1:6881".parse().unwrap(),
+                info_hash,
+            });
+            torrent.peer_ctxs.insert([id; 20], ctx);
+            rxs.push(rx);
+        }
+
+        // peer 1 lies about the size and sends the first piece
+        let lie = 3 * BLOCK_LEN;
+        let fake = vec![0; BLOCK_LEN as usize];
+        torrent.add_info_piece([1; 20], lie, 0, fake).await.unwrap();
+
+        // the honest peer sends the full info
+        for (i, piece) in info_bytes.chunks(BLOCK_LEN as usize).enumerate() {
+            let (index, piece) = (i as u32, piece.to_vec());
+            torrent.add_info_piece([2; 20], total, index, piece).await.unwrap();
+        }
+
+        assert!(torrent.have_info);
+        assert!(torrent.info_pieces.is_empty());
+        assert_eq!(*torrent.ctx.raw_info.read().await, info_bytes);
+
+        // and the liar is banned
+        assert!(matches!(rxs[0].try_recv(), Ok(PeerMsg::Quit)));
+        assert!(rxs[1].try_recv().is_err());
+        assert_eq!(
+            torrent.banned_peers,
+            vec!["10.0.0.1:6881".parse::<SocketAddr>().unwrap()]
+        );
+
+        // only a few sizes are downloaded at once
+        let mut torrent = Torrent::new(
+            mpsc::channel(10).0,
+            mpsc::channel(10).0,
+            Magnet::new(&uri).unwrap(),
+        );
+        for i in 0..=MAX_INFO_SIZES as u32 {
+            let size = 2 * BLOCK_LEN + i;
+            let piece = vec![0; BLOCK_LEN as usize];
+            torrent.add_info_piece([1; 20], size, 0, piece).await.unwrap();
+        }
+        assert_eq!(torrent.info_pieces.len(), MAX_INFO_SIZES);
+        assert!(!torrent.info_pieces.contains_key(&(2 * BLOCK_LEN)));
+        assert_eq!(torrent.suspect_info_pieces.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ignore_invalid_cached_metadata() {
+        let bytes = include_bytes!("../../../test-files/debian.torrent");
+        let dir = tmp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // the .torrent of another torrent
+        let uri = "magnet:?xt=urn:btih:48aac768a865798307ddd4284be77644368dd2c7";
+        std::fs::write(
+            dir.join("48aac768a865798307ddd4284be77644368dd2c7.torrent"),
+            bytes,
+        )
+        .unwrap();
+
+        let (disk_tx, _disk_rx) = mpsc::channel(10);
+        let (daemon_tx, _daemon_rx) = mpsc::channel(10);
+        let mut torrent =
+            Torrent::new(disk_tx, daemon_tx, Magnet::new(uri).unwrap());
+        torrent.metadata_dir = Some(dir.clone());
+        torrent.load_metadata().await.unwrap();
+
+        assert!(!torrent.have_info);
+        assert!(torrent.info_pieces.is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
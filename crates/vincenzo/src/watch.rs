@@ -0,0 +1,226 @@
+//! Watch directory for automatic torrent ingestion.
+//!
+//! The directory is scanned periodically, and any `.magnet` text file or
+//! `.torrent` file found there is added to the [`Daemon`] using
+//! [`DaemonMsg::NewTorrent`] or [`DaemonMsg::NewTorrentFile`], so the info
+//! of a `.torrent` file is not downloaded again from peers. After that, the file is moved to the processed
+//! directory or, if there is none, renamed with an `.added` extension.
+//! Files that could not be parsed are reported and renamed with an
+//! `.invalid` extension, so they are not tried again. A file that cannot be
+//! renamed is reported and does not stop the scan of the other files.
+//!
+//! Tools that drop files in the directory should write them somewhere else
+//! and then move them into it, to avoid reading a file that is incomplete.
+//!
+//! [`Daemon`]: crate::daemon::Daemon
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{fs, sync::mpsc, time::interval};
+use tracing::{error, info, warn};
+
+use crate::{
+    daemon::DaemonMsg, error::Error, magnet::Magnet, metainfo::MetaInfo,
+    torrent::TorrentOptions,
+};
+
+/// How often the watch directory is scanned.
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct WatchDir {
+    dir: PathBuf,
+    /// Where to move the files that were added, if `None`, they are renamed
+    /// in place to `.added`.
+    processed_dir: Option<PathBuf>,
+    daemon_tx: mpsc::Sender<DaemonMsg>,
+}
+
+impl WatchDir {
+    pub fn new(
+        dir: PathBuf,
+        processed_dir: Option<PathBuf>,
+        daemon_tx: mpsc::Sender<DaemonMsg>,
+    ) -> Self {
+        Self { dir, processed_dir, daemon_tx }
+    }
+
+    /// Scan the directory every [`SCAN_INTERVAL`] until the task is aborted.
+    pub async fn run(self) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir).await?;
+        if let Some(processed_dir) = &self.processed_dir {
+            fs::create_dir_all(processed_dir).await?;
+        }
+
+        info!("Watching directory: {:?}", self.dir);
+
+        let mut scan_interval = interval(SCAN_INTERVAL);
+
+        loop {
+            scan_interval.tick().await;
+            if let Err(e) = self.scan().await {
+                error!("Could not scan the watch directory: {e}");
+            }
+        }
+    }
+
+    /// Add all the torrents that are in the directory, returns how many
+    /// were added.
+    pub async fn scan(&self) -> Result<usize, Error> {
+        let mut entries = fs::read_dir(&self.dir).await?;
+        let mut added = 0;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+
+            let msg = match path.extension().and_then(|v| v.to_str()) {
+                Some("magnet") => Self::read_magnet(&path).await,
+                Some("torrent") => Self::read_torrent(&path).await,
+                _ => continue,
+            };
+
+            let msg = match msg {
+                Ok(msg) => msg,
+                Err(e) => {
+                    error!("Invalid file on the watch directory {path:?}: {e}");
+                    Self::rename(&path, "invalid").await;
+                    continue;
+                }
+            };
+
+            info!("Adding torrent from the watch directory: {path:?}");
+            let _ = self.daemon_tx.send(msg).await;
+            added += 1;
+
+            match &self.processed_dir {
+                Some(processed_dir) => {
+                    // the file name always exists, since it has an extension
+                    let to = processed_dir.join(path.file_name().unwrap());
+                    if let Err(e) = fs::rename(&path, &to).await {
+                        warn!("Could not move {path:?} to {to:?}: {e}");
+                        Self::rename(&path, "added").await;
+                    }
+                }
+                None => Self::rename(&path, "added").await,
+            }
+        }
+
+        Ok(added)
+    }
+
+    async fn read_magnet(path: &Path) -> Result<DaemonMsg, Error> {
+        let magnet = Magnet::new(fs::read_to_string(path).await?.trim())?;
+        Ok(DaemonMsg::NewTorrent(magnet, TorrentOptions::default()))
+    }
+
+    async fn read_torrent(path: &Path) -> Result<DaemonMsg, Error> {
+        let buf = fs::read(path).await?;
+        // validate the file here, so that it can be renamed to `.invalid`
+        MetaInfo::magnet(&buf)?;
+        Ok(DaemonMsg::NewTorrentFile(buf, TorrentOptions::default()))
+    }
+
+    /// Append `ext` to the file name, i.e. `a.torrent` -> `a.torrent.added`
+    async fn rename(path: &Path, ext: &str) {
+        let mut to = path.as_os_str().to_owned();
+        to.push(".");
+        to.push(ext);
+        if let Err(e) = fs::rename(path, &to).await {
+            error!("Could not rename {path:?} to {to:?}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    use super::*;
+
+    fn tmp_dir() -> PathBuf {
+        let mut rng = rand::thread_rng();
+        let name: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        std::env::temp_dir().join(name)
+    }
+
+    #[tokio::test]
+    async fn add_and_rename_files() {
+        let dir = tmp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        std::fs::write(
+            dir.join("a.magnet"),
+            "magnet:?xt=urn:btih:48aac768a865798307ddd4284be77644368dd2c7&dn=Kerkour\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("b.torrent"),
+            include_bytes!("../../../test-files/debian.torrent"),
+        )
+        .unwrap();
+        std::fs::write(dir.join("c.magnet"), "not a magnet").unwrap();
+        std::fs::write(dir.join("d.txt"), "ignored").unwrap();
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let watch = WatchDir::new(dir.clone(), None, tx);
+
+        let added = watch.scan().await.unwrap();
+
+        let mut magnets = Vec::new();
+        let mut torrents = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            match msg {
+                DaemonMsg::NewTorrent(magnet, _) => {
+                    magnets.push(hex::encode(magnet.parse_xt()))
+                }
+                DaemonMsg::NewTorrentFile(torrent, _) => torrents.push(torrent),
+                _ => panic!("unexpected message"),
+            }
+        }
+
+        assert_eq!(added, 2);
+        assert_eq!(magnets, vec!["48aac768a865798307ddd4284be77644368dd2c7"]);
+        // the info of the file is added directly, not only its magnet
+        assert_eq!(
+            torrents,
+            vec![include_bytes!("../../../test-files/debian.torrent").to_vec()]
+        );
+        assert!(dir.join("a.magnet.added").exists());
+        assert!(dir.join("b.torrent.added").exists());
+        assert!(dir.join("c.magnet.invalid").exists());
+        assert!(dir.join("d.txt").exists());
+
+        // nothing is added twice
+        assert_eq!(watch.scan().await.unwrap(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn move_to_processed_dir() {
+        let dir = tmp_dir();
+        let processed_dir = dir.join("processed");
+        std::fs::create_dir_all(&processed_dir).unwrap();
+
+        std::fs::write(
+            dir.join("a.magnet"),
+            "magnet:?xt=urn:btih:48aac768a865798307ddd4284be77644368dd2c7",
+        )
+        .unwrap();
+
+        let (tx, _rx) = mpsc::channel(10);
+        let watch = WatchDir::new(dir.clone(), Some(processed_dir.clone()), tx);
+
+        assert_eq!(watch.scan().await.unwrap(), 1);
+        assert!(!dir.join("a.magnet").exists());
+        assert!(processed_dir.join("a.magnet").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,985 @@
+//! Web seeds, HTTP servers that have the files of a torrent.
+//!
+//! There are two kinds of web seeds:
+//!
+//! - GetRight style, BEP 19, from the `url-list` of the .torrent or the `ws` of
+//!   the magnet. The url points to the files themselves, and a piece is
+//!   downloaded with an HTTP range request for each file that it spans.
+//! - Hoffman style, BEP 17, from the `httpseeds` of the .torrent or the `x.hs`
+//!   of the magnet. The url points to a script that is asked for a range of a
+//!   piece with `?info_hash=..&piece=..&ranges=..`.
+//!
+//! A web seed works like a peer that has all the pieces: it is registered on
+//! the torrent with a [`PeerCtx`], so the [`Disk`] picks blocks for it in the
+//! same way as for any other peer, and the downloaded blocks are written with
+//! [`DiskMsg::WriteBlock`].
+//!
+//! Only plain `http` urls are supported.
+//!
+//! [`Disk`]: crate::disk::Disk
+use std::{collections::VecDeque, net::SocketAddr, sync::Arc, time::Duration};
+
+use bitvec::{bitvec, prelude::Msb0};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{lookup_host, TcpStream},
+    select,
+    sync::{mpsc, oneshot, RwLock},
+    time::{interval, timeout, Instant},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    disk::DiskMsg,
+    error::Error,
+    extensions::core::{Block, BlockInfo, BLOCK_LEN},
+    metainfo::Info,
+    peer::{Direction, PeerCtx, PeerMsg},
+    torrent::{TorrentCtx, TorrentMsg},
+};
+
+/// How long a single HTTP request may take.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many redirects are followed for a single request.
+const MAX_REDIRECTS: usize = 5;
+
+/// The longest a web seed waits after a failed request.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// The style of a web seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WebSeedKind {
+    /// GetRight style, BEP 19.
+    UrlList,
+    /// Hoffman style, BEP 17.
+    HttpSeed,
+}
+
+/// A web seed of a torrent, see the module docs.
+#[derive(Debug)]
+pub struct WebSeed {
+    pub url: String,
+    pub kind: WebSeedKind,
+    pub torrent_ctx: Arc<TorrentCtx>,
+    /// Context that is registered on the torrent, as if this was a peer.
+    pub ctx: Arc<PeerCtx>,
+    pub rx: mpsc::Receiver<PeerMsg>,
+    /// The info of the torrent, which is known before the web seed starts.
+    info: Info,
+    paused: bool,
+    /// How many requests failed in a row.
+    failures: u32,
+    /// When the next request may be sent, after a failure.
+    retry_at: Instant,
+}
+
+impl WebSeed {
+    /// Create a web seed for a torrent whose info is already downloaded.
+    pub async fn new(
+        url: String,
+        kind: WebSeedKind,
+        torrent_ctx: Arc<TorrentCtx>,
+    ) -> Result<Self, Error> {
+        let http_url = HttpUrl::parse(&url)?;
+        let remote_addr = lookup_host((http_url.host.as_str(), http_url.port))
+            .await?
+            .next()
+            .ok_or(Error::PeerSocketAddr)?;
+
+        let info = torrent_ctx.info.read().await.clone();
+        let (tx, rx) = mpsc::channel::<PeerMsg>(300);
+
+        let ctx = Arc::new(PeerCtx {
+            direction: Direction::Outbound,
+            tx,
+            // a web seed has all the pieces
+            pieces: RwLock::new(bitvec![u8, Msb0; 1; info.pieces() as usize]),
+            id: sha1_smol::Sha1::from(&url).digest().bytes(),
+            remote_addr,
+            local_addr: SocketAddr::from(([0, 0, 0, 0], 0)),
+            info_hash: torrent_ctx.info_hash,
+        });
+
+        Ok(Self {
+            url,
+            kind,
+            torrent_ctx,
+            ctx,
+            rx,
+            info,
+            paused: false,
+            failures: 0,
+            retry_at: Instant::now(),
+        })
+    }
+
+    /// Register the web seed on the torrent and download blocks until the
+    /// torrent is complete or quits.
+    #[tracing::instrument(skip_all, name = "webseed::run")]
+    pub async fn run(&mut self) -> Result<(), Error> {
+        self.torrent_ctx
+            .tx
+            .send(TorrentMsg::PeerConnected(self.ctx.id, self.ctx.clone()))
+            .await?;
+
+        let mut download_interval = interval(Duration::from_secs(1));
+
+        loop {
+            select! {
+                Some(msg) = self.rx.recv() => {
+                    if self.handle_msg(msg) {
+                        return Ok(());
+                    }
+                }
+                _ = download_interval.tick(),
+                    if !self.paused && Instant::now() >= self.retry_at =>
+                {
+                    // download while there are blocks to request, checking
+                    // the messages of the torrent between requests.
+                    loop {
+                        match self.download().await {
+                            Ok(0) => break,
+                            Ok(_) => self.failures = 0,
+                            Err(e) => {
+                                self.backoff(&e);
+                                break;
+                            }
+                        }
+                        while let Ok(msg) = self.rx.try_recv() {
+                            if self.handle_msg(msg) {
+                                return Ok(());
+                            }
+                        }
+                        if self.paused {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handle a message of the torrent, returns true if the web seed must
+    /// stop.
+    fn handle_msg(&mut self, msg: PeerMsg) -> bool {
+        match msg {
+            PeerMsg::Pause => self.paused = true,
+            PeerMsg::Resume => self.paused = false,
+            // a web seed does not upload, so there is nothing left to do
+            PeerMsg::SeedOnly | PeerMsg::Quit => return true,
+            _ => {}
+        }
+        false
+    }
+
+    fn backoff(&mut self, e: &Error) {
+        let delay = match e {
+            Error::WebSeedBusy(secs) => Duration::from_secs(*secs),
+            _ => {
+                self.failures += 1;
+                (Duration::from_secs(5) * 2_u32.pow(self.failures.min(6)))
+                    .min(MAX_BACKOFF)
+            }
+        };
+        warn!("web seed {} failed: {e}, retrying in {delay:?}", self.url);
+        self.retry_at = Instant::now() + delay;
+    }
+
+    /// Request blocks to the disk, download them and send them to be
+    /// written. Returns how many blocks were downloaded.
+    ///
+    /// If a request fails, the blocks that were not downloaded are given
+    /// back to the disk, so that other peers can download them.
+    async fn download(&mut self) -> Result<usize, Error> {
+        let info_hash = self.torrent_ctx.info_hash;
+        let disk_tx = &self.torrent_ctx.disk_tx;
+
+        // about one piece per request
+        let qnt = (self.info.piece_length / BLOCK_LEN).max(1) as usize;
+
+        let (otx, orx) = oneshot::channel();
+        disk_tx
+            .send(DiskMsg::RequestBlocks {
+                info_hash,
+                peer_id: self.ctx.id,
+                recipient: otx,
+                qnt,
+            })
+            .await?;
+        let block_infos = orx.await?;
+        let len = block_infos.len();
+
+        let mut spans = VecDeque::from(spans(block_infos));
+
+        while let Some(span) = spans.pop_front() {
+            let bytes = match self.fetch(&span).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    let pending =
+                        span.into_iter().chain(spans.into_iter().flatten());
+                    let _ = disk_tx
+                        .send(DiskMsg::ReturnBlockInfos(
+                            info_hash,
+                            pending.collect(),
+                        ))
+                        .await;
+                    return Err(e);
+                }
+            };
+
+            let mut offset = 0;
+            for block_info in span {
+                let end = offset + block_info.len as usize;
+                let block = Block {
+                    index: block_info.index as usize,
+                    begin: block_info.begin,
+                    block: bytes[offset..end].to_vec(),
+                };
+                offset = end;
+                disk_tx.send(DiskMsg::WriteBlock { info_hash, block }).await?;
+            }
+        }
+
+        debug!("web seed {} downloaded {len} blocks", self.url);
+
+        Ok(len)
+    }
+
+    /// Download the bytes of a span of contiguous blocks of a piece.
+    async fn fetch(&self, span: &[BlockInfo]) -> Result<Vec<u8>, Error> {
+        let index = span[0].index;
+        let begin = span[0].begin;
+        let len: u32 = span.iter().map(|v| v.len).sum();
+
+        let bytes = match self.kind {
+            WebSeedKind::HttpSeed => {
+                let url = piece_url(
+                    &self.url,
+                    &self.torrent_ctx.info_hash,
+                    index,
+                    begin,
+                    len,
+                );
+                get(&url, None).await?
+            }
+            WebSeedKind::UrlList => {
+                let offset =
+                    index as u64 * self.info.piece_length as u64 + begin as u64;
+                let mut bytes = Vec::with_capacity(len as usize);
+
+                for slice in file_slices(&self.info, offset, len as u64) {
+                    // padding files are not on the server, BEP 47
+                    if slice.padding {
+                        bytes.resize(bytes.len() + slice.len as usize, 0);
+                        continue;
+                    }
+                    let url = file_url(&self.url, &self.info, &slice.path);
+                    let range = (slice.offset, slice.offset + slice.len - 1);
+                    bytes.extend(get(&url, Some(range)).await?);
+                }
+                bytes
+            }
+        };
+
+        if bytes.len() != len as usize {
+            return Err(Error::WebSeedResponse(format!(
+                "expected {len} bytes, got {}",
+                bytes.len()
+            )));
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// Group block infos in spans of contiguous blocks of the same piece, which
+/// are downloaded with a single request.
+fn spans(block_infos: VecDeque<BlockInfo>) -> Vec<Vec<BlockInfo>> {
+    let mut block_infos = Vec::from(block_infos);
+    block_infos.sort_by_key(|v| (v.index, v.begin));
+
+    let mut spans: Vec<Vec<BlockInfo>> = Vec::new();
+
+    for block_info in block_infos {
+        match spans.last_mut() {
+            Some(span)
+                if span.last().is_some_and(|last| {
+                    last.index == block_info.index
+                        && last.begin + last.len == block_info.begin
+                }) =>
+            {
+                span.push(block_info)
+            }
+            _ => spans.push(vec![block_info]),
+        }
+    }
+
+    spans
+}
+
+/// A part of a file that a range of the torrent is on.
+#[derive(Debug, Clone, PartialEq)]
+struct FileSlice {
+    /// Path of the file inside the torrent, empty on single file torrents.
+    path: Vec<String>,
+    padding: bool,
+    /// Offset in the file.
+    offset: u64,
+    len: u64,
+}
+
+/// Map a range of the torrent, starting at the absolute `offset`, to the
+/// files that it spans.
+fn file_slices(info: &Info, offset: u64, len: u64) -> Vec<FileSlice> {
+    let Some(files) = &info.files else {
+        return vec![FileSlice {
+            path: Vec::new(),
+            padding: false,
+            offset,
+            len,
+        }];
+    };
+
+    let end = offset + len;
+    let mut slices = Vec::new();
+    let mut file_start = 0;
+
+    for file in files {
+        let file_end = file_start + file.length;
+        let start = offset.max(file_start);
+        let stop = end.min(file_end);
+
+        if start < stop {
+            slices.push(FileSlice {
+                path: file.path.clone(),
+                padding: file.attr.padding,
+                offset: start - file_start,
+                len: stop - start,
+            });
+        }
+
+        if file_end >= end {
+            break;
+        }
+        file_start = file_end;
+    }
+
+    slices
+}
+
+/// Url of a file on a GetRight style web seed, BEP 19.
+///
+/// On single file torrents, the url is of the file itself, unless it ends
+/// with a `/`, in which case the name of the torrent is appended. On multi
+/// file torrents, the name of the torrent and the path of the file are
+/// always appended.
+pub fn file_url(base: &str, info: &Info, path: &[String]) -> String {
+    let mut url = base.to_owned();
+
+    if info.files.is_none() {
+        if url.ends_with('/') {
+            url.push_str(&urlencoding::encode(&info.name));
+        }
+        return url;
+    }
+
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    url.push_str(&urlencoding::encode(&info.name));
+    for part in path {
+        url.push('/');
+        url.push_str(&urlencoding::encode(part));
+    }
+
+    url
+}
+
+/// Url of a range of a piece on a Hoffman style web seed, BEP 17. The range
+/// is inclusive and relative to the start of the piece.
+pub fn piece_url(
+    base: &str,
+    info_hash: &[u8; 20],
+    piece: u32,
+    begin: u32,
+    len: u32,
+) -> String {
+    let separator = if base.contains('?') { '&' } else { '?' };
+    format!(
+        "{base}{separator}info_hash={}&piece={piece}&ranges={begin}-{}",
+        urlencoding::encode_binary(info_hash),
+        begin + len - 1
+    )
+}
+
+/// The parts of an `http` url that are needed to send a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpUrl {
+    /// The host and port, as they were written on the url.
+    pub authority: String,
+    pub host: String,
+    pub port: u16,
+    /// The path and query.
+    pub path: String,
+}
+
+impl HttpUrl {
+    pub fn parse(url: &str) -> Result<Self, Error> {
+        let invalid = || Error::WebSeedUrlInvalid(url.to_owned());
+
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => {
+                (&rest[..i], format!("/{}", &rest[i..]))
+            }
+            Some(i) => (&rest[..i], rest[i..].to_owned()),
+            None => (rest, "/".to_owned()),
+        };
+
+        // ipv6 hosts are in brackets, i.e. `[::1]:8080`
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !authority.ends_with(']') => {
+                (host, port.parse().map_err(|_| invalid())?)
+            }
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            authority: authority.to_owned(),
+            host: host.to_owned(),
+            port,
+            path,
+        })
+    }
+
+    /// Resolve the `Location` of a redirect.
+    fn join(&self, location: &str) -> Result<Self, Error> {
+        if location.starts_with('/') {
+            return Self::parse(&format!(
+                "http://{}{location}",
+                self.authority
+            ));
+        }
+        Self::parse(location)
+    }
+}
+
+/// A response of an HTTP request.
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    location: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Send a GET request and return the body of the response, following
+/// redirects.
+///
+/// `range` is an inclusive range of bytes. If the server does not support
+/// range requests and answers with the whole file, only the range is
+/// returned.
+pub async fn get(
+    url: &str,
+    range: Option<(u64, u64)>,
+) -> Result<Vec<u8>, Error> {
+    let mut url = HttpUrl::parse(url)?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let res = timeout(REQUEST_TIMEOUT, request(&url, range))
+            .await
+            .map_err(|_| Error::Timeout)??;
+
+        match res.status {
+            200 => {
+                let Some((start, end)) = range else {
+                    return Ok(res.body);
+                };
+                return res
+                    .body
+                    .get(start as usize..=end as usize)
+                    .map(|v| v.to_vec())
+                    .ok_or(Error::WebSeedResponse(
+                        "the range is out of the file".to_owned(),
+                    ));
+            }
+            206 => return Ok(res.body),
+            301 | 302 | 303 | 307 | 308 => {
+                let location = res.location.ok_or(Error::WebSeedResponse(
+                    "redirect without a location".to_owned(),
+                ))?;
+                url = url.join(&location)?;
+            }
+            // BEP 17 seeds answer with how many seconds to wait
+            503 => {
+                let secs = String::from_utf8_lossy(&res.body)
+                    .trim()
+                    .parse()
+                    .unwrap_or(30);
+                return Err(Error::WebSeedBusy(secs));
+            }
+            status => {
+                return Err(Error::WebSeedResponse(format!("status {status}")))
+            }
+        }
+    }
+
+    Err(Error::WebSeedResponse("too many redirects".to_owned()))
+}
+
+async fn request(
+    url: &HttpUrl,
+    range: Option<(u64, u64)>,
+) -> Result<Response, Error> {
+    let mut socket = TcpStream::connect((url.host.as_str(), url.port)).await?;
+
+    let mut req = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: vincenzo/{}\r\nConnection: close\r\n",
+        url.path,
+        url.authority,
+        env!("CARGO_PKG_VERSION"),
+    );
+    if let Some((start, end)) = range {
+        req.push_str(&format!("Range: bytes={start}-{end}\r\n"));
+    }
+    req.push_str("\r\n");
+
+    socket.write_all(req.as_bytes()).await?;
+
+    let mut buf = Vec::new();
+    socket.read_to_end(&mut buf).await?;
+
+    parse_response(&buf)
+}
+
+fn parse_response(buf: &[u8]) -> Result<Response, Error> {
+    let invalid = |e: &str| Error::WebSeedResponse(e.to_owned());
+
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut res = httparse::Response::new(&mut headers);
+
+    let len = match res.parse(buf).map_err(|e| invalid(&e.to_string()))? {
+        httparse::Status::Complete(len) => len,
+        httparse::Status::Partial => return Err(invalid("incomplete headers")),
+    };
+
+    let header = |name: &str| {
+        res.headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| String::from_utf8_lossy(h.value).trim().to_owned())
+    };
+
+    let body = &buf[len..];
+    let body = if header("transfer-encoding")
+        .is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+    {
+        dechunk(body)?
+    } else if let Some(content_length) = header("content-length") {
+        let content_length: usize =
+            content_length.parse().map_err(|_| invalid("content length"))?;
+        body.get(..content_length).ok_or(invalid("incomplete body"))?.to_vec()
+    } else {
+        body.to_vec()
+    };
+
+    Ok(Response {
+        status: res.code.unwrap_or_default(),
+        location: header("location"),
+        body,
+    })
+}
+
+/// Decode a body with the chunked transfer encoding.
+fn dechunk(mut buf: &[u8]) -> Result<Vec<u8>, Error> {
+    let invalid = || Error::WebSeedResponse("invalid chunk".to_owned());
+    let mut body = Vec::new();
+
+    loop {
+        let (start, size) = match httparse::parse_chunk_size(buf) {
+            Ok(httparse::Status::Complete(v)) => v,
+            _ => return Err(invalid()),
+        };
+        if size == 0 {
+            return Ok(body);
+        }
+        let end = start + size as usize;
+        body.extend_from_slice(buf.get(start..end).ok_or_else(invalid)?);
+        // skip the CRLF after the chunk
+        buf = buf.get(end + 2..).ok_or_else(invalid)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{distributions::Alphanumeric, Rng};
+    use tokio::{net::TcpListener, spawn, time::sleep};
+
+    use super::*;
+    use crate::{
+        bitfield::Bitfield,
+        daemon::DaemonMsg,
+        disk::Disk,
+        magnet::Magnet,
+        metainfo::{self, File},
+        torrent::Torrent,
+    };
+
+    type Handler =
+        Arc<dyn Fn(&str, Option<(usize, usize)>) -> String + Send + Sync>;
+
+    /// Run an HTTP server that answers each request with the response
+    /// returned by `handler`, given the path and the range of the request.
+    async fn serve(handler: Handler) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let handler = handler.clone();
+                spawn(async move {
+                    let mut buf = vec![0; 4096];
+                    let n = socket.read(&mut buf).await.unwrap();
+
+                    let mut headers = [httparse::EMPTY_HEADER; 16];
+                    let mut req = httparse::Request::new(&mut headers);
+                    req.parse(&buf[..n]).unwrap();
+
+                    let range =
+                        req.headers.iter().find(|h| h.name == "Range").map(
+                            |h| {
+                                let v = std::str::from_utf8(h.value).unwrap();
+                                let (a, b) = v
+                                    .strip_prefix("bytes=")
+                                    .unwrap()
+                                    .split_once('-')
+                                    .unwrap();
+                                (a.parse().unwrap(), b.parse().unwrap())
+                            },
+                        );
+
+                    let res = handler(req.path.unwrap(), range);
+                    socket.write_all(res.as_bytes()).await.unwrap();
+                });
+            }
+        });
+
+        format!("http://{addr}")
+    }
+
+    fn response(status: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[test]
+    fn parse_urls() {
+        let url = HttpUrl::parse("http://a.com:8080/b/c?d=e").unwrap();
+        assert_eq!(url.host, "a.com");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/b/c?d=e");
+
+        let url = HttpUrl::parse("http://[::1]").unwrap();
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.port, 80);
+        assert_eq!(url.path, "/");
+
+        assert_eq!(
+            url.join("/x").unwrap(),
+            HttpUrl::parse("http://[::1]/x").unwrap()
+        );
+
+        assert!(HttpUrl::parse("https://a.com/").is_err());
+        assert!(HttpUrl::parse("http://a.com:x/").is_err());
+    }
+
+    #[test]
+    fn web_seed_urls() {
+        let single = Info {
+            name: "a b.iso".to_owned(),
+            file_length: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            file_url("http://a.com/", &single, &[]),
+            "http://a.com/a%20b.iso"
+        );
+        assert_eq!(
+            file_url("http://a.com/x.iso", &single, &[]),
+            "http://a.com/x.iso"
+        );
+
+        let multi = Info {
+            name: "dir".to_owned(),
+            files: Some(vec![]),
+            ..Default::default()
+        };
+        let path = ["sub".to_owned(), "f.txt".to_owned()];
+        assert_eq!(
+            file_url("http://a.com/seed", &multi, &path),
+            "http://a.com/seed/dir/sub/f.txt"
+        );
+
+        assert_eq!(
+            piece_url("http://a.com/seed.php", &[b'a'; 20], 3, 16, 4),
+            format!(
+                "http://a.com/seed.php?info_hash={}&piece=3&ranges=16-19",
+                "a".repeat(20)
+            )
+        );
+        assert_eq!(
+            piece_url("http://a.com/s?x=1", &[b'a'; 20], 0, 0, 1),
+            format!(
+                "http://a.com/s?x=1&info_hash={}&piece=0&ranges=0-0",
+                "a".repeat(20)
+            )
+        );
+    }
+
+    #[test]
+    fn map_ranges_to_files() {
+        let info = Info {
+            name: "dir".to_owned(),
+            piece_length: 8,
+            files: Some(vec![
+                File {
+                    length: 5,
+                    path: vec!["a".to_owned()],
+                    ..Default::default()
+                },
+                File {
+                    length: 3,
+                    path: vec!["pad".to_owned()],
+                    attr: "p".into(),
+                    ..Default::default()
+                },
+                File {
+                    length: 10,
+                    path: vec!["b".to_owned()],
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            file_slices(&info, 3, 8),
+            vec![
+                FileSlice {
+                    path: vec!["a".to_owned()],
+                    padding: false,
+                    offset: 3,
+                    len: 2,
+                },
+                FileSlice {
+                    path: vec!["pad".to_owned()],
+                    padding: true,
+                    offset: 0,
+                    len: 3,
+                },
+                FileSlice {
+                    path: vec!["b".to_owned()],
+                    padding: false,
+                    offset: 0,
+                    len: 3,
+                },
+            ]
+        );
+        assert_eq!(file_slices(&info, 10, 8).len(), 1);
+
+        let blocks = VecDeque::from([
+            BlockInfo { index: 1, begin: 4, len: 4 },
+            BlockInfo { index: 0, begin: 0, len: 4 },
+            BlockInfo { index: 1, begin: 0, len: 4 },
+            BlockInfo { index: 2, begin: 4, len: 4 },
+        ]);
+        let spans = spans(blocks);
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[1].len(), 2);
+    }
+
+    #[tokio::test]
+    async fn http_get() {
+        let base = serve(Arc::new(|path, range| {
+            let file = "0123456789";
+            match (path, range) {
+                ("/file", Some((a, b))) => {
+                    response("206 Partial Content", &file[a..=b])
+                }
+                ("/file", None) | ("/no-range", _) => response("200 OK", file),
+                ("/moved", _) => {
+                    "HTTP/1.1 302 Found\r\nLocation: /file\r\nContent-Length: 0\r\n\r\n".to_owned()
+                }
+                ("/chunked", _) => "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n012\r\n2\r\n34\r\n0\r\n\r\n".to_owned(),
+                ("/busy", _) => response("503 Service Unavailable", "7"),
+                _ => response("404 Not Found", ""),
+            }
+        }))
+        .await;
+
+        let url = |path: &str| format!("{base}{path}");
+
+        assert_eq!(get(&url("/file"), None).await.unwrap(), b"0123456789");
+        assert_eq!(get(&url("/file"), Some((2, 4))).await.unwrap(), b"234");
+        assert_eq!(get(&url("/no-range"), Some((8, 9))).await.unwrap(), b"89");
+        assert_eq!(get(&url("/moved"), Some((0, 1))).await.unwrap(), b"01");
+        assert_eq!(get(&url("/chunked"), None).await.unwrap(), b"01234");
+        assert!(matches!(
+            get(&url("/busy"), None).await,
+            Err(Error::WebSeedBusy(7))
+        ));
+        assert!(matches!(
+            get(&url("/nope"), None).await,
+            Err(Error::WebSeedResponse(_))
+        ));
+    }
+
+    /// Download all the pieces of a torrent from web seeds of both styles,
+    /// going through the disk in the same way as a peer.
+    #[tokio::test]
+    async fn download_from_web_seeds() {
+        let a: Vec<u8> = (0..20).collect();
+        let b: Vec<u8> = (20..50).collect();
+        let data: Vec<u8> = a.iter().chain(b.iter()).copied().collect();
+        let piece_length = 16;
+
+        let name = "webseed";
+        let info = Info {
+            name: name.to_owned(),
+            piece_length,
+            pieces: data
+                .chunks(piece_length as usize)
+                .flat_map(|v| sha1_smol::Sha1::from(v).digest().bytes())
+                .collect(),
+            files: Some(vec![
+                metainfo::File {
+                    length: a.len() as u64,
+                    path: vec!["a".to_owned()],
+                    ..Default::default()
+                },
+                metainfo::File {
+                    length: b.len() as u64,
+                    path: vec!["b".to_owned()],
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+
+        let files = Arc::new((a.clone(), b.clone(), data.clone()));
+        let base = serve(Arc::new(move |path, range| {
+            let (a, b, data) = &*files;
+            let file = match path.split('?').next().unwrap() {
+                "/files/webseed/a" => a,
+                "/files/webseed/b" => b,
+                "/seed" => {
+                    let query = path.split_once('?').unwrap().1;
+                    let param = |k: &str| {
+                        query
+                            .split('&')
+                            .find_map(|v| v.strip_prefix(k))
+                            .unwrap()
+                            .to_owned()
+                    };
+                    let piece: usize = param("piece=").parse().unwrap();
+                    let ranges = param("ranges=");
+                    let (start, end) = ranges.split_once('-').unwrap();
+                    let start = piece * 16 + start.parse::<usize>().unwrap();
+                    let end = piece * 16 + end.parse::<usize>().unwrap();
+                    let body = &data[start..=end];
+                    return format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                        body.len()
+                    ) + &String::from_utf8_lossy(body);
+                }
+                _ => return response("404 Not Found", ""),
+            };
+            let (start, end) = range.unwrap();
+            let body = &file[start..=end];
+            format!(
+                "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            ) + &String::from_utf8_lossy(body)
+        }))
+        .await;
+
+        let mut rng = rand::thread_rng();
+        let download_dir: String =
+            (0..20).map(|_| rng.sample(Alphanumeric) as char).collect();
+        let download_dir = std::env::temp_dir().join(download_dir);
+
+        let (disk_tx, disk_rx) = mpsc::channel::<DiskMsg>(300);
+        let mut disk =
+            Disk::new(disk_rx, download_dir.to_string_lossy().into_owned());
+
+        let (daemon_tx, _daemon_rx) = mpsc::channel::<DaemonMsg>(300);
+        let magnet = Magnet::new(&format!(
+            "magnet:?xt=urn:btih:{}&dn={name}",
+            hex::encode([5u8; 20])
+        ))
+        .unwrap();
+        let mut torrent = Torrent::new(disk_tx.clone(), daemon_tx, magnet);
+        *torrent.ctx.info.write().await = info;
+        *torrent.ctx.bitfield.write().await = Bitfield::repeat(false, 4);
+
+        disk_tx.send(DiskMsg::NewTorrent(torrent.ctx.clone())).await.unwrap();
+        spawn(async move { disk.run().await });
+
+        let mut seeds = Vec::new();
+        for (url, kind) in [
+            (format!("{base}/files/"), WebSeedKind::UrlList),
+            (format!("{base}/seed"), WebSeedKind::HttpSeed),
+        ] {
+            let mut seed =
+                WebSeed::new(url, kind, torrent.ctx.clone()).await.unwrap();
+            seeds.push(seed.ctx.tx.clone());
+            spawn(async move { seed.run().await });
+        }
+
+        // forward the messages that `Torrent::run` would handle
+        let mut downloaded = 0;
+        while downloaded < 4 {
+            let msg = timeout(Duration::from_secs(10), torrent.rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            match msg {
+                TorrentMsg::PeerConnected(_, ctx) => {
+                    disk_tx.send(DiskMsg::NewPeer(ctx)).await.unwrap();
+                }
+                TorrentMsg::DownloadedPiece(_) => downloaded += 1,
+                _ => {}
+            }
+        }
+
+        for tx in seeds {
+            let _ = tx.send(PeerMsg::Quit).await;
+        }
+
+        let base_path = download_dir.join(name);
+        assert!(torrent.ctx.bitfield.read().await.all());
+
+        // the pieces are written after they are announced
+        let read = |file| tokio::fs::read(base_path.join(file));
+        timeout(Duration::from_secs(10), async {
+            while read("a").await.unwrap() != a || read("b").await.unwrap() != b
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(tokio::fs::read(base_path.join("a")).await.unwrap(), a);
+        assert_eq!(tokio::fs::read(base_path.join("b")).await.unwrap(), b);
+
+        tokio::fs::remove_dir_all(&download_dir).await.unwrap();
+    }
+}